@@ -6,6 +6,7 @@ use std::error::Error;
 use std::ffi::OsString;
 
 pub struct SystemApps;
+pub struct MimeApps;
 pub struct DesktopEntry {
     pub name: String,
 }
@@ -23,3 +24,27 @@ impl SystemApps {
         .into_iter())
     }
 }
+
+impl DesktopEntry {
+    pub fn is_application(&self) -> bool {
+        true
+    }
+
+    pub fn supports(&self, _mime: &str) -> bool {
+        true
+    }
+
+    pub fn is_shown_in(&self, _desktops: &[String]) -> bool {
+        true
+    }
+}
+
+pub fn desktops_from_env() -> Vec<String> {
+    Vec::new()
+}
+
+impl MimeApps {
+    pub fn describe_user_handlers(_mime: &str) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
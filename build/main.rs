@@ -1,3 +1,5 @@
+// Only the command definitions are needed here, so helper methods go unused
+#[allow(dead_code)]
 mod cli {
     include!("../src/cli.rs");
 }
@@ -0,0 +1,311 @@
+use crate::{
+    common::{locales, DesktopAction, DesktopEntry, EntryType},
+    error::Result,
+    utils,
+};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// Bump whenever the format of the cache changes so that old caches are discarded
+const CACHE_VERSION: u32 = 9;
+
+/// On-disk cache of parsed desktop entries
+/// Entries are keyed by path and are reparsed whenever their file's modification time changes
+#[derive(Debug, Default)]
+pub struct EntryCache {
+    /// Where the cache is saved, if anywhere
+    path: Option<PathBuf>,
+    /// Cached data
+    data: CacheData,
+    /// Whether the cache has changed since it was loaded
+    dirty: bool,
+}
+
+/// Serialized contents of the cache
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheData {
+    version: u32,
+    /// Locales used to pick localized values when the entries were parsed
+    locales: Vec<String>,
+    entries: BTreeMap<PathBuf, CachedEntry>,
+}
+
+/// A single cached desktop entry file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedEntry {
+    /// Modification time of the file when it was parsed
+    modified: SystemTime,
+    /// Parsed fields, or nothing if the file is not a valid desktop entry
+    entry: Option<CachedFields>,
+}
+
+/// The fields of a desktop entry that handlr cares about
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedFields {
+    name: String,
+    generic_name: Option<String>,
+    icon: Option<String>,
+    exec: String,
+    try_exec: Option<String>,
+    terminal: bool,
+    mime_type: Vec<String>,
+    categories: Vec<String>,
+    keywords: Vec<String>,
+    extra: BTreeMap<String, String>,
+    entry_type: EntryType,
+    single_main_window: bool,
+    dbus_activatable: bool,
+    actions: Vec<DesktopAction>,
+    no_display: bool,
+    hidden: bool,
+    only_show_in: Vec<String>,
+    not_show_in: Vec<String>,
+    working_dir: Option<String>,
+}
+
+impl From<&DesktopEntry> for CachedFields {
+    fn from(entry: &DesktopEntry) -> Self {
+        Self {
+            name: entry.name.clone(),
+            generic_name: entry.generic_name.clone(),
+            icon: entry.icon.clone(),
+            exec: entry.exec.clone(),
+            try_exec: entry.try_exec.clone(),
+            terminal: entry.terminal,
+            mime_type: entry.mime_type.iter().map(|m| m.to_string()).collect(),
+            categories: entry.categories.clone(),
+            keywords: entry.keywords.clone(),
+            extra: entry.extra.clone(),
+            entry_type: entry.entry_type.clone(),
+            single_main_window: entry.single_main_window,
+            dbus_activatable: entry.dbus_activatable,
+            actions: entry.actions.clone(),
+            no_display: entry.no_display,
+            hidden: entry.hidden,
+            only_show_in: entry.only_show_in.clone(),
+            not_show_in: entry.not_show_in.clone(),
+            working_dir: entry.working_dir.clone(),
+        }
+    }
+}
+
+impl CachedFields {
+    /// Turn cached fields back into a desktop entry for the file at the given path
+    fn to_entry(&self, path: &Path) -> Option<DesktopEntry> {
+        Some(DesktopEntry {
+            name: self.name.clone(),
+            generic_name: self.generic_name.clone(),
+            icon: self.icon.clone(),
+            exec: self.exec.clone(),
+            try_exec: self.try_exec.clone(),
+            file_name: path.file_name()?.to_owned(),
+            path: Some(path.to_owned()),
+            working_dir: self.working_dir.clone(),
+            terminal: self.terminal,
+            mime_type: self
+                .mime_type
+                .iter()
+                .filter_map(|m| Mime::from_str(m).ok())
+                .collect(),
+            categories: self.categories.clone(),
+            keywords: self.keywords.clone(),
+            extra: self.extra.clone(),
+            entry_type: self.entry_type.clone(),
+            single_main_window: self.single_main_window,
+            dbus_activatable: self.dbus_activatable,
+            term_args: None,
+            actions: self.actions.clone(),
+            no_display: self.no_display,
+            hidden: self.hidden,
+            only_show_in: self.only_show_in.clone(),
+            not_show_in: self.not_show_in.clone(),
+        })
+    }
+}
+
+impl EntryCache {
+    /// Load the cache from `$XDG_CACHE_HOME/handlr/`
+    /// Starts with an empty cache if it does not exist or cannot be read
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn load(base_dirs: &xdg::BaseDirectories) -> Self {
+        match base_dirs.place_cache_file("handlr/desktop_entries.json") {
+            Ok(path) => Self::load_from(path),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Load the cache from the given path
+    fn load_from(path: PathBuf) -> Self {
+        let data = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<CacheData>(&data).ok())
+            // Discard caches that are outdated or were made with different locales
+            .filter(|data| {
+                data.version == CACHE_VERSION && data.locales == locales()
+            })
+            .unwrap_or_else(|| CacheData {
+                version: CACHE_VERSION,
+                locales: locales().to_vec(),
+                entries: BTreeMap::new(),
+            });
+
+        Self {
+            path: Some(path),
+            data,
+            dirty: false,
+        }
+    }
+
+    /// Get the desktop entry at the given path
+    /// Only parses the file if it is not cached or has been modified since it was cached
+    pub fn get_or_parse(&mut self, path: &Path) -> Option<DesktopEntry> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        if let Some(cached) = self.data.entries.get(path) {
+            if Some(cached.modified) == modified {
+                return cached.entry.as_ref()?.to_entry(path);
+            }
+        }
+
+        let entry = DesktopEntry::try_from(path.to_owned()).ok();
+
+        if let Some(modified) = modified {
+            self.data.entries.insert(
+                path.to_owned(),
+                CachedEntry {
+                    modified,
+                    entry: entry.as_ref().map(CachedFields::from),
+                },
+            );
+            self.dirty = true;
+        }
+
+        entry
+    }
+
+    /// Forget about every cached file that is not in the given list of paths
+    pub fn retain_paths(&mut self, paths: &[PathBuf]) {
+        let before = self.data.entries.len();
+        self.data.entries.retain(|path, _| paths.contains(path));
+        self.dirty |= self.data.entries.len() != before;
+    }
+
+    /// Save the cache if it has changed
+    pub fn save(&self) -> Result<()> {
+        match &self.path {
+            Some(path) if self.dirty => {
+                utils::write_atomic(path, &serde_json::to_vec(&self.data)?)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    // Helper function to get a unique path for a cache file
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("handlr-cache-{name}-{}.json", std::process::id()))
+    }
+
+    #[test]
+    fn cache_round_trip() -> Result<()> {
+        let path = cache_path("round-trip");
+        let entry_path = PathBuf::from("tests/Helix.desktop");
+
+        let mut cache = EntryCache::load_from(path.clone());
+        let entry = cache.get_or_parse(&entry_path);
+        assert_eq!(entry, DesktopEntry::try_from(entry_path.clone()).ok());
+        assert!(cache.dirty);
+        cache.save()?;
+
+        // Entries should now come from the cache without changing it
+        let mut cache = EntryCache::load_from(path.clone());
+        assert!(cache.data.entries.contains_key(&entry_path));
+        assert_eq!(cache.get_or_parse(&entry_path), entry);
+        assert!(!cache.dirty);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_invalid_entry() {
+        let mut cache = EntryCache::load_from(cache_path("invalid"));
+        let entry_path = PathBuf::from("tests/empty_exec.desktop");
+
+        assert_eq!(cache.get_or_parse(&entry_path), None);
+        // Invalid entries are cached too, so they are not parsed every time
+        assert!(cache.data.entries[&entry_path].entry.is_none());
+    }
+
+    #[test]
+    fn cache_stale_entry() {
+        let mut cache = EntryCache::load_from(cache_path("stale"));
+        let entry_path = PathBuf::from("tests/Helix.desktop");
+
+        // Pretend the file was cached before it was last modified
+        cache.data.entries.insert(
+            entry_path.clone(),
+            CachedEntry {
+                modified: SystemTime::UNIX_EPOCH,
+                entry: Some(CachedFields {
+                    name: "Outdated".into(),
+                    generic_name: None,
+                    icon: None,
+                    exec: "outdated".into(),
+                    try_exec: None,
+                    terminal: false,
+                    mime_type: Vec::new(),
+                    categories: Vec::new(),
+                    keywords: Vec::new(),
+                    extra: BTreeMap::new(),
+                    entry_type: EntryType::Application,
+                    single_main_window: false,
+                    dbus_activatable: false,
+                    actions: Vec::new(),
+                    no_display: false,
+                    hidden: false,
+                    only_show_in: Vec::new(),
+                    not_show_in: Vec::new(),
+                    working_dir: None,
+                }),
+            },
+        );
+
+        assert_eq!(
+            cache.get_or_parse(&entry_path),
+            DesktopEntry::try_from(entry_path.clone()).ok()
+        );
+        assert!(cache.dirty);
+    }
+
+    #[test]
+    fn cache_removed_files() {
+        let mut cache = EntryCache::load_from(cache_path("removed"));
+        let helix = PathBuf::from("tests/Helix.desktop");
+        let cmus = PathBuf::from("tests/cmus.desktop");
+
+        cache.get_or_parse(&helix);
+        cache.get_or_parse(&cmus);
+        cache.dirty = false;
+
+        cache.retain_paths(std::slice::from_ref(&helix));
+
+        assert!(cache.data.entries.contains_key(&helix));
+        assert!(!cache.data.entries.contains_key(&cmus));
+        assert!(cache.dirty);
+    }
+}
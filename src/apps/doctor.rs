@@ -0,0 +1,583 @@
+use crate::{
+    apps::{layout::Layout, MimeApps},
+    cli::OutputFormat,
+    common::{
+        applications_dirs, extra_applications_dirs, find_desktop_file,
+        write_rows, DesktopEntry, DesktopHandler, Row, TableConfig, TableStyle,
+    },
+    config::ConfigFile,
+    error::{Error, Result},
+    utils::Output,
+};
+use itertools::Itertools;
+use mime::Mime;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::Display,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Sections of mimeapps.list that handlr reads associations from
+const SECTIONS: [&str; 3] = [
+    "Default Applications",
+    "Added Associations",
+    "Removed Associations",
+];
+
+/// How serious a problem found by `handlr doctor` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something that is probably a mistake, but does not break anything
+    Warning,
+    /// Something that stops handlr or a handler from working
+    Error,
+}
+
+/// The kinds of problems `handlr doctor` looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Check {
+    /// A handler whose desktop file cannot be found
+    MissingHandler,
+    /// A handler whose desktop file cannot be used, e.g. because it is not an application
+    BadHandler,
+    /// A handler whose `TryExec` or `Exec` program cannot be found
+    MissingProgram,
+    /// A handler that is only found in one of `extra_data_dirs`, rather than the XDG data directories
+    ExtraDataDir,
+    /// A key that is not a valid mime
+    InvalidMime,
+    /// A handler listed more than once for the same mime
+    DuplicateHandler,
+    /// A regex handler whose patterns do not compile
+    BadRegex,
+    /// Anything else in the config file that stops handlr from loading it
+    BadConfig,
+}
+
+impl Check {
+    /// Get how serious problems of this kind are
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::DuplicateHandler | Self::ExtraDataDir => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::MissingHandler => "missing-handler",
+            Self::BadHandler => "bad-handler",
+            Self::MissingProgram => "missing-program",
+            Self::ExtraDataDir => "extra-data-dir",
+            Self::InvalidMime => "invalid-mime",
+            Self::DuplicateHandler => "duplicate-handler",
+            Self::BadRegex => "bad-regex",
+            Self::BadConfig => "bad-config",
+        })
+    }
+}
+
+/// A problem found by `handlr doctor`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub check: Check,
+    /// Where the problem is, e.g. `mimeapps.list [Default Applications] text/plain`
+    pub location: String,
+    pub problem: String,
+}
+
+impl Finding {
+    fn new(check: Check, location: &str, problem: String) -> Self {
+        Self {
+            severity: check.severity(),
+            check,
+            location: location.to_owned(),
+            problem,
+        }
+    }
+}
+
+impl Row for Finding {
+    const HEADERS: &'static [&'static str] =
+        &["severity", "check", "location", "problem"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.severity.to_string(),
+            self.check.to_string(),
+            self.location.clone(),
+            self.problem.clone(),
+        ]
+    }
+}
+
+/// Check the user's config file and mimeapps.list files for problems and write what was found, without changing anything
+/// Neither needs to be loadable, since finding out why they are not is the point
+/// Errors if any error-level problems were found, after everything is written
+#[mutants::skip] // Cannot test directly, depends on system state
+pub fn doctor(
+    mut writer: Output,
+    format: OutputFormat,
+    plain: bool,
+) -> Result<()> {
+    // Read the config file directly, so that it is not created if it does not exist
+    let contents = match std::fs::read_to_string(ConfigFile::path()?) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (mut config, mut findings) = parse_config(&contents);
+    config.apply_env();
+    if plain {
+        config.table.style = TableStyle::Blank;
+    }
+
+    // Handlers are usually associated with many mimes, so only check each once
+    let search_path = std::env::var_os("PATH").unwrap_or_default();
+    let base_dirs = xdg::BaseDirectories::new()?;
+    let dirs = applications_dirs(&base_dirs, &config.extra_data_dirs);
+    let extra_dirs =
+        extra_applications_dirs(&base_dirs, &config.extra_data_dirs);
+    let mut checked = BTreeMap::new();
+    let mut check = |name: &str| {
+        checked
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                check_handler(
+                    name,
+                    &search_path,
+                    config.check_try_exec,
+                    &dirs,
+                    &extra_dirs,
+                )
+            })
+            .clone()
+    };
+
+    for path in MimeApps::user_paths()? {
+        if !path.is_file() {
+            continue;
+        }
+        let source = path.file_name().unwrap_or_default().to_string_lossy();
+        let contents = std::fs::read_to_string(&path)?;
+        findings.extend(check_mime_apps(&source, &contents, &mut check));
+    }
+
+    write_findings(&mut writer, &findings, format, &config.table)?;
+    writer.finish()?;
+
+    match findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count()
+    {
+        0 => Ok(()),
+        errors => Err(Error::DoctorFailed(errors)),
+    }
+}
+
+/// Parse the contents of the config file and check them like `check_config`
+/// Contents that cannot be parsed are reported, and the default config is used in their place
+pub fn parse_config(contents: &str) -> (ConfigFile, Vec<Finding>) {
+    match toml::from_str::<ConfigFile>(contents) {
+        Ok(mut config) => {
+            let findings = check_config(&mut config);
+            (config, findings)
+        }
+        Err(e) => (
+            ConfigFile::default(),
+            vec![Finding::new(Check::BadConfig, "handlr.toml", e.to_string())],
+        ),
+    }
+}
+
+/// Check the parts of the config file that would stop it from loading, i.e. regex handlers,
+/// mime overrides, the selector, and launch options
+pub fn check_config(config: &mut ConfigFile) -> Vec<Finding> {
+    let location = "handlr.toml";
+    let limits = config.regex_limits();
+
+    let mut findings = config
+        .handlers
+        .compile_all(&limits)
+        .into_iter()
+        .map(|e| {
+            let check = match e {
+                Error::DuplicateRegexHandlerName { .. }
+                | Error::ExecOrHandler(_) => Check::BadConfig,
+                _ => Check::BadRegex,
+            };
+            Finding::new(check, location, e.to_string())
+        })
+        .collect_vec();
+
+    if let Err(e) = config.mime_overrides.validate() {
+        let check = match e {
+            Error::BadMimeOverride { .. } => Check::InvalidMime,
+            _ => Check::BadConfig,
+        };
+        findings.push(Finding::new(check, location, e.to_string()));
+    }
+
+    if let Err(e) = config.selector.validate() {
+        findings.push(Finding::new(Check::BadConfig, location, e.to_string()));
+    }
+
+    findings.extend(config.launch.iter().filter_map(|(handler, options)| {
+        let e = options.validate(handler).err()?;
+        Some(Finding::new(Check::BadConfig, location, e.to_string()))
+    }));
+
+    findings
+}
+
+/// Check the contents of a mimeapps.list file for invalid mimes, repeated handlers,
+/// and handlers that `check_handler` finds a problem with
+/// The contents do not need to be valid, since every line is checked as it was written
+pub fn check_mime_apps<F>(
+    source: &str,
+    contents: &str,
+    mut check_handler: F,
+) -> Vec<Finding>
+where
+    F: FnMut(&str) -> Option<(Check, String)>,
+{
+    let mut findings = Vec::new();
+
+    for (section, key, value) in Layout::parse(contents).raw_entries() {
+        if !SECTIONS.contains(&section) {
+            continue;
+        }
+        let location = format!("{source} [{section}] {key}");
+
+        if Mime::from_str(key).is_err() {
+            findings.push(Finding::new(
+                Check::InvalidMime,
+                &location,
+                format!("'{key}' is not a valid mime"),
+            ));
+        }
+
+        let names = value.split(';').filter(|name| !name.is_empty());
+
+        findings.extend(names.clone().duplicates().map(|name| {
+            Finding::new(
+                Check::DuplicateHandler,
+                &location,
+                format!("'{name}' is listed more than once"),
+            )
+        }));
+
+        // Removed associations only hide handlers, so it does not matter whether they can be used
+        if section == "Removed Associations" {
+            continue;
+        }
+
+        findings.extend(names.unique().filter_map(|name| {
+            let (check, problem) = check_handler(name)?;
+            Some(Finding::new(check, &location, problem))
+        }));
+    }
+
+    findings
+}
+
+/// Get what is wrong with a handler in mimeapps.list, if anything
+/// Programs without a path are looked for in `search_path`, which is in the form of `$PATH`
+/// With `check_try_exec`, a missing `TryExec` program is reported as the reason the handler is skipped
+/// Handlers are looked for in `dirs`, and ones that are only found in one of `extra_dirs` are reported,
+/// since other programs may not find them
+fn check_handler(
+    name: &str,
+    search_path: &OsStr,
+    check_try_exec: bool,
+    dirs: &[PathBuf],
+    extra_dirs: &[PathBuf],
+) -> Option<(Check, String)> {
+    let found = DesktopHandler::from_str(name).and_then(|_| {
+        let path = find_desktop_file(dirs, OsStr::new(name))
+            .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+        let entry =
+            DesktopEntry::try_from(path.clone())?.check_application()?;
+        Ok((path, entry))
+    });
+
+    match found {
+        Ok((path, entry)) => match entry.missing_try_exec(search_path) {
+            Some(program) if check_try_exec => Some((
+                Check::MissingProgram,
+                format!("'{name}' is skipped, since its TryExec program '{program}' cannot be found"),
+            )),
+            _ => match entry.missing_program(search_path) {
+                Ok(Some(program)) => Some((
+                    Check::MissingProgram,
+                    format!("'{name}' runs '{program}', which cannot be found"),
+                )),
+                Ok(None) => {
+                    let dir = extra_dirs.iter().find(|dir| path.starts_with(dir))?;
+                    Some((
+                        Check::ExtraDataDir,
+                        format!(
+                            "'{name}' is only found in '{}', which is not in $XDG_DATA_DIRS",
+                            dir.display()
+                        ),
+                    ))
+                }
+                // An `Exec` that cannot be split cannot be run at all
+                Err(e) => Some((Check::BadHandler, e.to_string())),
+            },
+        },
+        Err(Error::NotFound(_)) => Some((
+            Check::MissingHandler,
+            format!("'{name}' cannot be found in any applications directory"),
+        )),
+        Err(e) => Some((Check::BadHandler, e.to_string())),
+    }
+}
+
+/// Write findings as a table or JSON
+pub fn write_findings<W: Write>(
+    writer: &mut W,
+    findings: &[Finding],
+    format: OutputFormat,
+    table: &TableConfig,
+) -> Result<()> {
+    if findings.is_empty() && !format.is_json() {
+        writeln!(writer, "No problems found")?;
+        Ok(())
+    } else {
+        write_rows(writer, findings, format, table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn mime_apps_findings() {
+        let contents = "[Default Applications]
+text/plain=helix.desktop;missing.desktop;helix.desktop;
+text plain=helix.desktop;
+# comment=not.desktop;
+[Removed Associations]
+text/html=missing.desktop;
+[Unknown]
+not a mime=missing.desktop;
+";
+        let findings = check_mime_apps("mimeapps.list", contents, |name| {
+            (name == "missing.desktop")
+                .then(|| (Check::MissingHandler, format!("{name} is missing")))
+        });
+
+        assert_eq!(
+            findings
+                .iter()
+                .map(|f| (f.check, f.location.as_str()))
+                .collect_vec(),
+            [
+                (
+                    Check::DuplicateHandler,
+                    "mimeapps.list [Default Applications] text/plain"
+                ),
+                (
+                    Check::MissingHandler,
+                    "mimeapps.list [Default Applications] text/plain"
+                ),
+                (
+                    Check::InvalidMime,
+                    "mimeapps.list [Default Applications] text plain"
+                ),
+            ]
+        );
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[1].severity, Severity::Error);
+    }
+
+    #[test]
+    fn config_findings() -> Result<()> {
+        let mut config: ConfigFile = toml::from_str(
+            r#"
+            selector = ["rofi", "-dmenu", "-p", "{promt}"]
+
+            [mime_overrides]
+            "*.chm" = "chm"
+
+            [[handlers]]
+            exec = "mpv %u"
+            regexes = ['youtu\.be', '(unclosed']
+
+            [[handlers]]
+            exec = "firefox %u"
+            regexes = []
+            exclude_regexes = ['reddit']
+            "#,
+        )?;
+
+        let findings = check_config(&mut config);
+        assert_eq!(
+            findings.iter().map(|f| f.check).collect_vec(),
+            [
+                Check::BadRegex,
+                Check::BadRegex,
+                Check::InvalidMime,
+                Check::BadConfig
+            ]
+        );
+        assert!(findings[0].problem.contains("handlers[0].regexes"));
+
+        assert!(check_config(&mut ConfigFile::default()).is_empty());
+
+        // Syntax errors are found rather than stopping everything
+        let (config, findings) = parse_config("enable_selector = [");
+        assert_eq!(
+            findings.iter().map(|f| f.check).collect_vec(),
+            [Check::BadConfig]
+        );
+        assert!(!config.enable_selector);
+        assert!(parse_config("").1.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn handler_findings() -> Result<()> {
+        let root = TempDir::new("doctor")?;
+        let bin = root.join("bin");
+        std::fs::create_dir_all(&bin)?;
+        // Fixtures are found by their paths from the crate's root
+        let fixtures = [PathBuf::from(".")];
+        let check = |name: &str, check_try_exec: bool| {
+            check_handler(name, bin.as_os_str(), check_try_exec, &fixtures, &[])
+        };
+
+        // tests/Helix.desktop has `TryExec=hx`
+        assert!(matches!(
+            check("tests/Helix.desktop", true),
+            Some((Check::MissingProgram, problem))
+                if problem.contains("is skipped") && problem.contains("'hx'")
+        ));
+        assert!(matches!(
+            check("tests/Helix.desktop", false),
+            Some((Check::MissingProgram, problem)) if problem.contains("runs 'hx'")
+        ));
+
+        use std::os::unix::fs::PermissionsExt;
+        let install = |program: &str| -> Result<()> {
+            let path = bin.join(program);
+            std::fs::write(&path, "#!/bin/sh\n")?;
+            std::fs::set_permissions(
+                &path,
+                std::fs::Permissions::from_mode(0o755),
+            )?;
+            Ok(())
+        };
+        install("hx")?;
+        assert_eq!(check("tests/Helix.desktop", true), None);
+
+        assert!(matches!(
+            check("tests/link.desktop", true),
+            Some((Check::BadHandler, _))
+        ));
+        assert!(matches!(
+            check("tests/bad_exec.desktop", true),
+            Some((Check::BadHandler, problem)) if problem.contains("unterminated")
+        ));
+        assert!(matches!(
+            check("missing.desktop", true),
+            Some((Check::MissingHandler, _))
+        ));
+
+        // Handlers only found through `extra_data_dirs` still work, but are worth knowing about
+        let xdg_dir = root.join("share/applications");
+        let extra_dir = root.join("flatpak/exports/share/applications");
+        let dirs = [xdg_dir.clone(), extra_dir.clone()];
+        let check_app = || {
+            check_handler(
+                "app.desktop",
+                bin.as_os_str(),
+                true,
+                &dirs,
+                &dirs[1..],
+            )
+        };
+        install("app")?;
+        std::fs::create_dir_all(&extra_dir)?;
+        std::fs::write(
+            extra_dir.join("app.desktop"),
+            "[Desktop Entry]\nType=Application\nName=App\nExec=app %f\n",
+        )?;
+        assert_eq!(
+            check_app(),
+            Some((
+                Check::ExtraDataDir,
+                format!(
+                    "'app.desktop' is only found in '{}', which is not in $XDG_DATA_DIRS",
+                    extra_dir.display()
+                )
+            ))
+        );
+
+        // A copy in an XDG data directory comes first
+        std::fs::create_dir_all(&xdg_dir)?;
+        std::fs::copy(
+            extra_dir.join("app.desktop"),
+            xdg_dir.join("app.desktop"),
+        )?;
+        assert_eq!(check_app(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn findings_output() -> Result<()> {
+        let findings = [Finding::new(
+            Check::DuplicateHandler,
+            "mimeapps.list [Default Applications] text/plain",
+            "'helix.desktop' is listed more than once".into(),
+        )];
+
+        let write = |findings: &[Finding], format| -> Result<String> {
+            let mut buffer = Vec::new();
+            write_findings(
+                &mut buffer,
+                findings,
+                format,
+                &TableConfig::default(),
+            )?;
+            Ok(String::from_utf8(buffer)?)
+        };
+
+        assert_eq!(
+            write(&findings, OutputFormat::Tsv)?,
+            "severity\tcheck\tlocation\tproblem\nwarning\tduplicate-handler\tmimeapps.list [Default Applications] text/plain\t'helix.desktop' is listed more than once\n"
+        );
+        assert_eq!(
+            write(&findings, OutputFormat::Json)?,
+            "[{\"severity\":\"warning\",\"check\":\"duplicate-handler\",\"location\":\"mimeapps.list [Default Applications] text/plain\",\"problem\":\"'helix.desktop' is listed more than once\"}]\n"
+        );
+        assert_eq!(write(&[], OutputFormat::Tsv)?, "No problems found\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use crate::apps::DesktopList;
+use indexmap::IndexMap;
+use mime::Mime;
+use std::str::FromStr;
+
+/// The layout of a mimeapps.list file as it was read
+/// Used to write the file back without losing anything handlr does not understand,
+/// like comments, blank lines, unknown sections, line endings, and byte order marks
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Layout {
+    /// Lines before the first section
+    preamble: Vec<String>,
+    sections: Vec<Section>,
+    /// Whether most lines ended with `\r\n` rather than `\n`
+    crlf: bool,
+    /// Whether the file started with a UTF-8 byte order mark
+    bom: bool,
+}
+
+/// UTF-8 byte order mark
+const BOM: char = '\u{feff}';
+
+/// A section of a mimeapps.list file and the lines in it
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    /// Line the section starts with, e.g. `[Default Applications]`
+    header: String,
+    /// Name of the section, e.g. `Default Applications`
+    name: String,
+    lines: Vec<Line>,
+}
+
+/// A line in a section of a mimeapps.list file
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    /// An association of a mime to its handlers, along with the line it was read from
+    Entry(Mime, String),
+    /// Anything else, like comments and blank lines
+    Other(String),
+}
+
+impl Line {
+    /// Get the line as it was read
+    fn raw(&self) -> &str {
+        match self {
+            Line::Entry(_, raw) | Line::Other(raw) => raw,
+        }
+    }
+}
+
+impl Layout {
+    /// Get the layout of the contents of a mimeapps.list file
+    pub fn parse(contents: &str) -> Self {
+        let (bom, contents) = match contents.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, contents),
+        };
+
+        // Keep whichever line ending most lines use
+        let crlf_count = contents.matches("\r\n").count();
+        let lf_count = contents.matches('\n').count() - crlf_count;
+
+        let mut layout = Self {
+            crlf: crlf_count > lf_count,
+            bom,
+            ..Default::default()
+        };
+
+        for line in contents.lines() {
+            let trimmed = line.trim();
+
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                layout.sections.push(Section {
+                    header: line.to_owned(),
+                    name: trimmed[1..trimmed.len() - 1].to_owned(),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+
+            let Some(section) = layout.sections.last_mut() else {
+                layout.preamble.push(line.to_owned());
+                continue;
+            };
+
+            let entry = trimmed
+                .split_once('=')
+                .and_then(|(key, _)| Mime::from_str(key.trim()).ok());
+
+            section.lines.push(match entry {
+                Some(mime) => Line::Entry(mime, line.to_owned()),
+                None => Line::Other(line.to_owned()),
+            });
+        }
+
+        layout
+    }
+
+    /// Write the given known sections using this layout
+    /// Entries in known sections are written where they were read from, and new entries are added to the end of their section.
+    /// Everything else is kept as is, except for known sections that are left with nothing in them.
+    pub fn render(
+        &self,
+        known: &[(&str, &IndexMap<Mime, DesktopList>)],
+    ) -> String {
+        let mut lines = self.preamble.clone();
+        let mut written = Vec::new();
+
+        for (index, section) in self.sections.iter().enumerate() {
+            let Some(&(name, associations)) =
+                known.iter().find(|(name, _)| *name == section.name)
+            else {
+                // Sections handlr does not know about are kept exactly as they are
+                lines.push(section.header.clone());
+                lines.extend(section.lines.iter().map(|l| l.raw().to_owned()));
+                continue;
+            };
+
+            let mut section_lines = Vec::new();
+
+            for line in &section.lines {
+                match line {
+                    Line::Entry(mime, _) => {
+                        // Drop entries that were removed or that already appeared
+                        if let Some(handlers) = associations.get(mime) {
+                            if !written.contains(&(name, mime)) {
+                                written.push((name, mime));
+                                section_lines.push(entry_line(mime, handlers));
+                            }
+                        }
+                    }
+                    Line::Other(line) => section_lines.push(line.clone()),
+                }
+            }
+
+            // New entries go after the last non-blank line of the first section with a given name
+            if !self.sections[..index].iter().any(|s| s.name == name) {
+                let end = section_lines
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map_or(0, |pos| pos + 1);
+                let new = associations
+                    .iter()
+                    .filter(|(mime, _)| !self.contains(name, mime))
+                    .map(|(mime, handlers)| entry_line(mime, handlers))
+                    .collect::<Vec<_>>();
+                section_lines.splice(end..end, new);
+            }
+
+            // Leave out sections that would only be blank lines
+            if section_lines.iter().any(|line| !line.trim().is_empty()) {
+                lines.push(section.header.clone());
+                lines.extend(section_lines);
+            }
+        }
+
+        // Sections that were not in the file at all go at the end
+        for (name, associations) in known {
+            if associations.is_empty()
+                || self.sections.iter().any(|s| s.name == *name)
+            {
+                continue;
+            }
+
+            lines.push(format!("[{name}]"));
+            lines.extend(
+                associations
+                    .iter()
+                    .map(|(mime, handlers)| entry_line(mime, handlers)),
+            );
+        }
+
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let start = if self.bom {
+            BOM.to_string()
+        } else {
+            String::new()
+        };
+
+        lines
+            .into_iter()
+            .fold(start, |output, line| output + &line + newline)
+    }
+
+    /// Get the given contents of a mimeapps.list file the way serde_ini expects them,
+    /// i.e. without a byte order mark or carriage returns
+    pub fn normalize(contents: &str) -> String {
+        contents.trim_start_matches(BOM).replace("\r\n", "\n")
+    }
+
+    /// Get the handlers of every mime that has more than one entry in sections with the given name,
+    /// in the order they were read
+    pub fn repeated_entries(
+        &self,
+        name: &str,
+    ) -> Vec<(Mime, Vec<DesktopList>)> {
+        let mut entries = IndexMap::<&Mime, Vec<DesktopList>>::new();
+
+        for line in self
+            .sections
+            .iter()
+            .filter(|section| section.name == name)
+            .flat_map(|section| &section.lines)
+        {
+            if let Line::Entry(mime, raw) = line {
+                let value = raw.split_once('=').map_or("", |(_, v)| v.trim());
+                if let Ok(handlers) = DesktopList::from_str(value) {
+                    entries.entry(mime).or_default().push(handlers);
+                }
+            }
+        }
+
+        entries
+            .into_iter()
+            .filter(|(_, handlers)| handlers.len() > 1)
+            .map(|(mime, handlers)| (mime.clone(), handlers))
+            .collect()
+    }
+
+    /// Get every line that looks like an association as its section's name, key, and value,
+    /// without needing keys to be valid mimes or values to be valid lists of handlers
+    pub fn raw_entries(&self) -> Vec<(&str, &str, &str)> {
+        self.sections
+            .iter()
+            .flat_map(|section| {
+                section.lines.iter().filter_map(|line| {
+                    let trimmed = line.raw().trim();
+                    if trimmed.starts_with('#') {
+                        return None;
+                    }
+                    let (key, value) = trimmed.split_once('=')?;
+                    Some((section.name.as_str(), key.trim(), value.trim()))
+                })
+            })
+            .collect()
+    }
+
+    /// Check if a section with the given name has an entry for the given mime
+    fn contains(&self, name: &str, mime: &Mime) -> bool {
+        self.sections
+            .iter()
+            .filter(|section| section.name == name)
+            .flat_map(|section| &section.lines)
+            .any(|line| matches!(line, Line::Entry(m, _) if m == mime))
+    }
+}
+
+/// Get the line for an association of a mime to its handlers
+fn entry_line(mime: &Mime, handlers: &DesktopList) -> String {
+    format!("{mime}={handlers}")
+}
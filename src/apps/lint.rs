@@ -0,0 +1,259 @@
+use crate::{apps::MimeApps, common::DesktopHandler};
+use itertools::Itertools;
+use mime::Mime;
+use std::fmt::Display;
+use wildmatch::WildMatch;
+
+/// A potential problem with the associations in a mimeapps.list file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// Multiple entries in the same section that are the same mime once normalized
+    Duplicate {
+        section: &'static str,
+        mimes: Vec<String>,
+    },
+    /// A wildcard that never takes effect because a longer wildcard
+    /// matches everything it does and has the same handlers
+    ShadowedWildcard {
+        wildcard: String,
+        shadowed_by: String,
+    },
+    /// An association that does not change anything because its handler is the system default anyway
+    NoOp { mime: String, handler: String },
+}
+
+impl Lint {
+    /// Whether the lint is purely informational rather than a likely mistake
+    pub fn is_informational(&self) -> bool {
+        matches!(self, Self::NoOp { .. })
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate { section, mimes } => write!(
+                f,
+                "{} in [{section}] are the same mime",
+                mimes.iter().map(|m| format!("'{m}'")).join(", ")
+            ),
+            Self::ShadowedWildcard {
+                wildcard,
+                shadowed_by,
+            } => write!(
+                f,
+                "'{wildcard}' never takes effect because '{shadowed_by}' matches the same mimes with the same handlers"
+            ),
+            Self::NoOp { mime, handler } => write!(
+                f,
+                "'{mime}' is set to '{handler}', which is already the system default"
+            ),
+        }
+    }
+}
+
+/// Find potential problems with the associations in a mimeapps.list file
+/// `system_default` gets the handler a mime would have without any user associations
+pub fn lint_mime_apps<F: Fn(&Mime) -> Option<DesktopHandler>>(
+    mime_apps: &MimeApps,
+    system_default: F,
+) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    // Duplicate mimes
+    for (section, associations) in [
+        ("Default Applications", &mime_apps.default_apps),
+        ("Added Associations", &mime_apps.added_associations),
+    ] {
+        lints.extend(
+            associations
+                .keys()
+                .into_group_map_by(|mime| normalize(mime))
+                .into_values()
+                .filter(|mimes| mimes.len() > 1)
+                .sorted()
+                .map(|mimes| Lint::Duplicate {
+                    section,
+                    mimes: mimes.iter().map(|m| m.to_string()).collect(),
+                }),
+        );
+    }
+
+    // Wildcards shadowed by longer ones
+    let wildcards = mime_apps
+        .default_apps
+        .iter()
+        .filter(|(mime, _)| mime.as_ref().contains('*'))
+        .collect_vec();
+
+    for (wildcard, handlers) in wildcards.iter() {
+        if let Some((longer, _)) =
+            wildcards.iter().find(|(other, other_handlers)| {
+                other.as_ref().len() > wildcard.as_ref().len()
+                    && other_handlers == handlers
+                    // Every mime the shorter wildcard matches is matched by the longer one
+                    && WildMatch::new(other.as_ref()).matches(wildcard.as_ref())
+            })
+        {
+            lints.push(Lint::ShadowedWildcard {
+                wildcard: wildcard.to_string(),
+                shadowed_by: longer.to_string(),
+            })
+        }
+    }
+
+    // Associations that match the system default
+    for (mime, handlers) in mime_apps.default_apps.iter() {
+        match handlers.front() {
+            Some(handler)
+                if !mime.as_ref().contains('*')
+                    && system_default(mime).as_ref() == Some(handler) =>
+            {
+                lints.push(Lint::NoOp {
+                    mime: mime.to_string(),
+                    handler: handler.to_string(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    lints
+}
+
+/// Normalize a mime for comparison, ignoring case and any parameters
+fn normalize(mime: &Mime) -> String {
+    mime.essence_str().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apps::DesktopList, error::Result};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    // Helper function to build the default apps of a mimeapps.list
+    fn mime_apps(default_apps: &[(&str, &str)]) -> Result<MimeApps> {
+        let mut mime_apps = MimeApps::default();
+        for (mime, handlers) in default_apps {
+            mime_apps.default_apps.insert(
+                Mime::from_str(mime)?,
+                DesktopList::from_str(handlers)?,
+            );
+        }
+        Ok(mime_apps)
+    }
+
+    // Pretend every text/plain file would be opened with helix otherwise
+    fn system_default(mime: &Mime) -> Option<DesktopHandler> {
+        (mime == &mime::TEXT_PLAIN).then(|| {
+            DesktopHandler::assume_valid("helix.desktop".into())
+                .expect("Test handler names should be valid")
+        })
+    }
+
+    #[test]
+    fn lints() -> Result<()> {
+        // Default apps, followed by the lints expected from them
+        type Case<'a> = (&'a [(&'a str, &'a str)], &'a [Lint]);
+
+        let cases: &[Case] = &[
+            // Nothing wrong
+            (
+                &[("video/*", "mpv.desktop;"), ("text/plain", "nvim.desktop;")],
+                &[],
+            ),
+            // Duplicates after normalization
+            (
+                &[
+                    ("video/mp4", "mpv.desktop;"),
+                    ("video/mp4; codecs=avc1", "vlc.desktop;"),
+                ],
+                &[Lint::Duplicate {
+                    section: "Default Applications",
+                    mimes: vec![
+                        "video/mp4".into(),
+                        "video/mp4; codecs=avc1".into(),
+                    ],
+                }],
+            ),
+            // A shorter wildcard entirely covered by a longer one with the same handlers
+            (
+                &[("video/*", "mpv.desktop;"), ("video/**", "mpv.desktop;")],
+                &[Lint::ShadowedWildcard {
+                    wildcard: "video/*".into(),
+                    shadowed_by: "video/**".into(),
+                }],
+            ),
+            // Different handlers mean the wildcard is probably intentional
+            (
+                &[("video/*", "mpv.desktop;"), ("video/**", "vlc.desktop;")],
+                &[],
+            ),
+            // The longer wildcard only covers some of what the shorter one matches
+            (
+                &[("video/*", "mpv.desktop;"), ("video/x-*", "mpv.desktop;")],
+                &[],
+            ),
+            // Same handler as the system default
+            (
+                &[("text/plain", "helix.desktop;")],
+                &[Lint::NoOp {
+                    mime: "text/plain".into(),
+                    handler: "helix.desktop".into(),
+                }],
+            ),
+            // Only the first handler matters
+            (&[("text/plain", "nvim.desktop;helix.desktop;")], &[]),
+        ];
+
+        for (default_apps, expected) in cases {
+            assert_eq!(
+                lint_mime_apps(&mime_apps(default_apps)?, system_default),
+                expected.to_vec(),
+                "{default_apps:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_added_associations() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        for mime in ["text/html", "text/html;charset=utf-8"] {
+            mime_apps.added_associations.insert(
+                Mime::from_str(mime)?,
+                DesktopList::from_str("firefox.desktop;")?,
+            );
+        }
+
+        assert_eq!(
+            lint_mime_apps(&mime_apps, system_default),
+            vec![Lint::Duplicate {
+                section: "Added Associations",
+                mimes: vec![
+                    "text/html".into(),
+                    "text/html;charset=utf-8".into()
+                ],
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn informational_lints() {
+        assert!(Lint::NoOp {
+            mime: "text/plain".into(),
+            handler: "helix.desktop".into()
+        }
+        .is_informational());
+        assert!(!Lint::ShadowedWildcard {
+            wildcard: "video/*".into(),
+            shadowed_by: "video/**".into()
+        }
+        .is_informational());
+    }
+}
@@ -1,5 +1,15 @@
+mod cache;
+mod doctor;
+mod layout;
+mod lint;
 mod system;
 mod user;
 
+pub use doctor::doctor;
+pub use lint::lint_mime_apps;
 pub use system::SystemApps;
-pub use user::{DesktopList, MimeApps};
+#[cfg(test)]
+pub use user::PruneReport;
+pub use user::{
+    desktops_from_env, CleanReport, DesktopList, MimeApps, Reorder,
+};
@@ -1,10 +1,15 @@
+use super::cache::EntryCache;
 use crate::{
-    apps::DesktopList,
-    common::{DesktopEntry, DesktopHandler, Handleable},
-    error::Result,
+    apps::{desktops_from_env, DesktopList},
+    common::{
+        applications_dirs, desktop_files, extra_data_dirs, DesktopEntry,
+        DesktopHandler, Handleable,
+    },
+    error::{Error, Result},
 };
+use itertools::Itertools;
 use mime::Mime;
-use std::{collections::BTreeMap, convert::TryFrom, ffi::OsString};
+use std::{collections::BTreeMap, ffi::OsString};
 
 #[derive(Debug, Default, Clone)]
 pub struct SystemApps {
@@ -12,6 +17,8 @@ pub struct SystemApps {
     pub associations: BTreeMap<Mime, DesktopList>,
     /// Apps with no associated mime
     unassociated: DesktopList,
+    /// Associations of mimes and lists of apps that are only left out because they are hidden with `NoDisplay`
+    hidden: BTreeMap<Mime, DesktopList>,
 }
 
 impl SystemApps {
@@ -20,56 +27,176 @@ impl SystemApps {
         Some(self.associations.get(mime)?.clone())
     }
 
-    /// Get the primary of handler associated with a given mime
-    pub fn get_handler(&self, mime: &Mime) -> Option<DesktopHandler> {
-        Some(self.get_handlers(mime)?.front()?.clone())
+    /// Get the list of handlers hidden with `NoDisplay` that are associated with a given mime
+    pub fn get_hidden_handlers(&self, mime: &Mime) -> Option<DesktopList> {
+        Some(self.hidden.get(mime)?.clone())
     }
 
-    /// Get all system-level desktop entries on the system
+    /// Get the handlers that say they support a given mime, either exactly or through a wildcard like `text/*`,
+    /// without duplicates
+    pub fn get_candidates(&self, mime: &Mime) -> Vec<DesktopHandler> {
+        let wildcards = self.associations.iter().filter(|(m, _)| {
+            *m != mime
+                && m.as_ref().contains('*')
+                && wildmatch::WildMatch::new(m.as_ref()).matches(mime.as_ref())
+        });
+
+        self.associations
+            .get_key_value(mime)
+            .into_iter()
+            .chain(wildcards)
+            .flat_map(|(_, handlers)| handlers.iter().cloned())
+            .unique()
+            .collect()
+    }
+
+    /// Get all system-level desktop entries on the system along with their desktop file ids
+    /// Only the first of the files with the same id is used, e.g. the user's copy rather than the system's
+    /// Parsed entries are cached in `$XDG_CACHE_HOME/handlr/` between runs
     #[mutants::skip] // Cannot test directly, depends on system state
     pub fn get_entries(
     ) -> Result<impl Iterator<Item = (OsString, DesktopEntry)>> {
-        Ok(xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
+        let base_dirs = xdg::BaseDirectories::new()?;
+        let mut cache = EntryCache::load(&base_dirs);
+
+        let (ids, paths): (Vec<_>, Vec<_>) =
+            desktop_files(&applications_dirs(&base_dirs, extra_data_dirs()))
+                .into_iter()
+                .unzip();
+
+        let entries = ids
             .into_iter()
-            .filter(|p| {
-                p.extension().and_then(|x| x.to_str()) == Some("desktop")
-            })
-            .filter_map(|p| {
+            .zip(&paths)
+            .filter_map(|(id, path)| {
+                let entry = cache.get_or_parse(path)?;
+                // Entries in subdirectories are known by their id rather than their file name
                 Some((
-                    p.file_name()?.to_owned(),
-                    DesktopEntry::try_from(p.clone()).ok()?,
+                    id.clone(),
+                    DesktopEntry {
+                        file_name: id,
+                        ..entry
+                    },
                 ))
-            }))
+            })
+            .collect::<Vec<_>>();
+
+        // Failing to save the cache should never stop handlr from working
+        cache.retain_paths(&paths);
+        let _ = cache.save();
+
+        Ok(entries.into_iter())
+    }
+
+    /// Get the handler whose desktop entry runs the given command, e.g. `vlc`
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn handler_for_command(command: &str) -> Result<DesktopHandler> {
+        Self::handler_for_command_in(Self::get_entries()?, command)
+    }
+
+    /// Get the handler for the only application among the given desktop entries
+    /// whose `Exec` key runs the given command
+    /// Makes testing easier
+    fn handler_for_command_in(
+        entries: impl IntoIterator<Item = (OsString, DesktopEntry)>,
+        command: &str,
+    ) -> Result<DesktopHandler> {
+        let mut handlers = entries
+            .into_iter()
+            .filter(|(_, entry)| {
+                entry.is_application()
+                    && entry.program().is_some_and(|program| program == command)
+            })
+            // The same desktop file can be in more than one applications directory
+            .filter_map(|(file_name, _)| {
+                DesktopHandler::assume_valid(file_name).ok()
+            })
+            .unique()
+            .collect_vec();
+
+        match handlers.len() {
+            0 => Err(Error::NotFound(command.to_owned())),
+            1 => Ok(handlers.remove(0)),
+            _ => Err(Error::AmbiguousCommand(
+                command.to_owned(),
+                handlers.iter().map(|h| h.to_string()).sorted().collect(),
+            )),
+        }
     }
 
     /// Create a new instance of `SystemApps`
+    /// Unless `include_hidden` is set, entries that should not be shown on the current desktop are left out
     #[mutants::skip] // Cannot test directly, depends on system state
-    pub fn populate() -> Result<Self> {
+    pub fn populate(
+        check_try_exec: bool,
+        include_hidden: bool,
+    ) -> Result<Self> {
+        let search_path = std::env::var_os("PATH").unwrap_or_default();
+        Ok(Self::from_available_entries(
+            Self::get_entries()?
+                .map(|(_, entry)| entry)
+                .filter(|entry| {
+                    !check_try_exec
+                        || entry.missing_try_exec(&search_path).is_none()
+                }),
+            &desktops_from_env(),
+            include_hidden,
+        ))
+    }
+
+    /// Create a new instance of `SystemApps` from the given desktop entries,
+    /// leaving out the ones that should not be shown on any of the given desktops unless `include_hidden` is set
+    /// The ones only left out because of `NoDisplay` are kept apart, for when nothing else can open a mime
+    pub(crate) fn from_available_entries(
+        entries: impl IntoIterator<Item = DesktopEntry>,
+        desktops: &[String],
+        include_hidden: bool,
+    ) -> Self {
+        let (shown, hidden): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .filter(|entry| include_hidden || entry.is_available_in(desktops))
+            .partition(|entry| include_hidden || entry.is_shown_in(desktops));
+
+        Self {
+            hidden: Self::from_entries(hidden).associations,
+            ..Self::from_entries(shown)
+        }
+    }
+
+    /// Create a new instance of `SystemApps` from the given desktop entries
+    /// Entries that are not applications, like links and directories, are left out
+    fn from_entries(entries: impl IntoIterator<Item = DesktopEntry>) -> Self {
         let mut associations = BTreeMap::<Mime, DesktopList>::new();
         let mut unassociated = DesktopList::default();
 
-        Self::get_entries()?.for_each(|(_, entry)| {
-            let (file_name, mimes) = (entry.file_name, entry.mime_type);
-            let desktop_handler =
-                DesktopHandler::assume_valid(file_name.to_owned());
-
-            if mimes.is_empty() {
-                unassociated.push_back(desktop_handler);
-            } else {
-                mimes.into_iter().for_each(|mime| {
-                    associations
-                        .entry(mime)
-                        .or_default()
-                        .push_back(desktop_handler.clone());
-                });
-            }
-        });
+        entries
+            .into_iter()
+            .filter(|entry| entry.is_application())
+            .for_each(|entry| {
+                let (file_name, mimes) = (entry.file_name, entry.mime_type);
+                // Files whose names could not be used in mimeapps.list are left out
+                let Ok(desktop_handler) =
+                    DesktopHandler::assume_valid(file_name.to_owned())
+                else {
+                    return;
+                };
 
-        Ok(Self {
+                if mimes.is_empty() {
+                    unassociated.push_back(desktop_handler);
+                } else {
+                    mimes.into_iter().for_each(|mime| {
+                        associations
+                            .entry(mime)
+                            .or_default()
+                            .push_back(desktop_handler.clone());
+                    });
+                }
+            });
+
+        Self {
             associations,
             unassociated,
-        })
+            hidden: BTreeMap::new(),
+        }
     }
 
     /// Get an installed terminal emulator
@@ -80,6 +207,16 @@ impl SystemApps {
             .find(|h| h.is_terminal_emulator())
     }
 
+    /// Get an installed file manager, whether or not it is associated with any mimes
+    pub fn file_manager(&self) -> Option<DesktopHandler> {
+        self.associations
+            .values()
+            .flat_map(|handlers| handlers.iter())
+            .chain(self.unassociated.iter())
+            .find(|h| h.get_entry().is_ok_and(|e| e.is_file_manager()))
+            .cloned()
+    }
+
     #[cfg(test)]
     /// Internal helper function for testing
     pub fn add_unassociated(&mut self, handler: DesktopHandler) {
@@ -90,14 +227,16 @@ impl SystemApps {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
+    use std::{convert::TryFrom, path::PathBuf, str::FromStr};
 
     #[test]
     fn get_handlers() -> Result<()> {
         let mut expected_handlers = DesktopList::default();
         expected_handlers
-            .push_back(DesktopHandler::assume_valid("helix.desktop".into()));
+            .push_back(DesktopHandler::assume_valid("helix.desktop".into())?);
         expected_handlers
-            .push_back(DesktopHandler::assume_valid("nvim.desktop".into()));
+            .push_back(DesktopHandler::assume_valid("nvim.desktop".into())?);
 
         let mut associations: BTreeMap<Mime, DesktopList> = BTreeMap::new();
 
@@ -108,13 +247,6 @@ mod tests {
             ..Default::default()
         };
 
-        assert_eq!(
-            system_apps
-                .get_handler(&mime::TEXT_PLAIN)
-                .expect("Could not get handler")
-                .to_string(),
-            "helix.desktop"
-        );
         assert_eq!(
             system_apps
                 .get_handlers(&mime::TEXT_PLAIN)
@@ -124,4 +256,157 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn no_display_handlers() -> Result<()> {
+        let entry = DesktopEntry::try_from(PathBuf::from(
+            "tests/org.kde.kdeconnect.handler.desktop",
+        ))?;
+        let tel = Mime::from_str("x-scheme-handler/tel")?;
+
+        // Entries hidden from menus are not fallen back to, even on desktops they are meant for,
+        // but are kept apart in case nothing else can open their mimes
+        let system_apps = SystemApps::from_available_entries(
+            [entry.clone()],
+            &["kde".to_owned()],
+            false,
+        );
+        assert_eq!(system_apps.get_handlers(&tel), None);
+        assert_eq!(
+            system_apps.get_hidden_handlers(&tel),
+            Some(DesktopList::from_str("org.kde.kdeconnect.handler.desktop")?)
+        );
+
+        // Not even that on desktops they are not meant for
+        let system_apps = SystemApps::from_available_entries(
+            [entry.clone()],
+            &["gnome".to_owned()],
+            false,
+        );
+        assert_eq!(system_apps.get_hidden_handlers(&tel), None);
+
+        // Unless hidden entries are included
+        let system_apps =
+            SystemApps::from_available_entries([entry], &[], true);
+        assert_eq!(
+            system_apps.get_handlers(&tel),
+            Some(DesktopList::from_str("org.kde.kdeconnect.handler.desktop")?)
+        );
+        assert_eq!(system_apps.get_hidden_handlers(&tel), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn handler_for_command() -> Result<()> {
+        let entry = |file_name: &str, exec: &str| {
+            let mut entry = DesktopEntry::fake_entry(exec, false);
+            entry.file_name = file_name.into();
+            (entry.file_name.clone(), entry)
+        };
+        let entries = vec![
+            entry("vlc.desktop", "/usr/bin/vlc --started-from-file %U"),
+            entry("vlc-beta.desktop", "vlc-beta %U"),
+            entry("mpv.desktop", "mpv -- %U"),
+            // The same entry in another applications directory
+            entry("mpv.desktop", "mpv -- %U"),
+            entry("umpv.desktop", "mpv --player-operation-mode=pseudo-gui"),
+        ];
+
+        assert_eq!(
+            SystemApps::handler_for_command_in(entries.clone(), "vlc")?,
+            DesktopHandler::assume_valid("vlc.desktop".into())?
+        );
+        assert_eq!(
+            SystemApps::handler_for_command_in(entries.clone(), "vlc-beta")?,
+            DesktopHandler::assume_valid("vlc-beta.desktop".into())?
+        );
+        assert!(matches!(
+            SystemApps::handler_for_command_in(entries.clone(), "mpv"),
+            Err(Error::AmbiguousCommand(command, candidates))
+                if command == "mpv"
+                    && candidates == ["mpv.desktop", "umpv.desktop"]
+        ));
+        assert!(matches!(
+            SystemApps::handler_for_command_in(entries, "totem"),
+            Err(Error::NotFound(command)) if command == "totem"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_candidates() -> Result<()> {
+        let handler = |name: &str| {
+            DesktopHandler::assume_valid(name.into())
+                .expect("Test handler names should be valid")
+        };
+        let mut associations: BTreeMap<Mime, DesktopList> = BTreeMap::new();
+        associations.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("helix.desktop;nvim.desktop")?,
+        );
+        associations.insert(
+            Mime::from_str("text/*")?,
+            DesktopList::from_str("nvim.desktop;gedit.desktop")?,
+        );
+        associations
+            .insert(mime::TEXT_HTML, DesktopList::from_str("firefox.desktop")?);
+
+        let system_apps = SystemApps {
+            associations,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            system_apps.get_candidates(&mime::TEXT_PLAIN),
+            vec![
+                handler("helix.desktop"),
+                handler("nvim.desktop"),
+                handler("gedit.desktop")
+            ]
+        );
+        assert_eq!(
+            system_apps.get_candidates(&mime::TEXT_CSS),
+            vec![handler("nvim.desktop"), handler("gedit.desktop")]
+        );
+        assert!(system_apps.get_candidates(&mime::IMAGE_PNG).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_non_applications() -> Result<()> {
+        let entries = [
+            "tests/Helix.desktop",
+            "tests/org.wezfurlong.wezterm.desktop",
+            "tests/link.desktop",
+            "tests/directory.desktop",
+        ]
+        .into_iter()
+        .map(|path| DesktopEntry::try_from(std::path::PathBuf::from(path)))
+        .collect::<Result<Vec<_>>>()?;
+
+        let system_apps = SystemApps::from_entries(entries);
+
+        let handlers = system_apps
+            .associations
+            .values()
+            .flat_map(|handlers| handlers.iter())
+            .chain(system_apps.unassociated.iter())
+            .map(|handler| handler.to_string())
+            .unique()
+            .collect_vec();
+
+        assert_eq!(
+            handlers,
+            ["Helix.desktop", "org.wezfurlong.wezterm.desktop"]
+        );
+        // The link's URL does not make it a handler for anything
+        assert!(!system_apps
+            .associations
+            .contains_key(&Mime::from_str("x-scheme-handler/https")?));
+
+        Ok(())
+    }
 }
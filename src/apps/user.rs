@@ -1,9 +1,15 @@
 use crate::{
-    common::{mime_types, DesktopHandler, Handleable},
+    apps::layout::Layout,
+    common::{
+        mime_types, DesktopHandler, Handleable, HandlerSource, MimeOrExtension,
+        Trace,
+    },
     config::ConfigFile,
     error::{Error, Result},
+    utils,
 };
 use derive_more::{Deref, DerefMut};
+use indexmap::IndexMap;
 use itertools::Itertools;
 use mime::Mime;
 use serde::{Deserialize, Serialize};
@@ -11,11 +17,12 @@ use serde_with::{
     serde_as, DeserializeFromStr, DisplayFromStr, SerializeDisplay,
 };
 use std::{
-    collections::{BTreeMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fmt::Display,
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::Once,
 };
 use wildmatch::WildMatch;
 
@@ -26,13 +33,37 @@ use wildmatch::WildMatch;
 #[serde(default)]
 pub struct MimeApps {
     #[serde(rename = "Added Associations")]
-    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
-    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
-    pub added_associations: BTreeMap<Mime, DesktopList>,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    #[serde_as(as = "IndexMap<DisplayFromStr, _>")]
+    pub added_associations: IndexMap<Mime, DesktopList>,
+    #[serde(rename = "Removed Associations")]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    #[serde_as(as = "IndexMap<DisplayFromStr, _>")]
+    pub removed_associations: IndexMap<Mime, DesktopList>,
     #[serde(rename = "Default Applications")]
-    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
-    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
-    pub default_apps: BTreeMap<Mime, DesktopList>,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    #[serde_as(as = "IndexMap<DisplayFromStr, _>")]
+    pub default_apps: IndexMap<Mime, DesktopList>,
+    /// Desktop that this file is specific to, if any (i.e. `$desktop-mimeapps.list`)
+    #[serde(skip)]
+    desktop: Option<String>,
+    /// Desktop-specific associations, in order of precedence
+    /// These take priority over the associations in this file
+    #[serde(skip)]
+    desktop_apps: Vec<MimeApps>,
+    /// System-wide associations from `$XDG_CONFIG_DIRS` and `$XDG_DATA_DIRS`, in order of precedence
+    /// These are never written to and have lower priority than the associations in this file
+    #[serde(skip)]
+    system_layers: Vec<MimeApps>,
+    /// Path this file was read from
+    #[serde(skip)]
+    source: Option<PathBuf>,
+    /// Layout of the file when it was read, so that it can be written back without losing anything
+    #[serde(skip)]
+    layout: Layout,
+    /// Mimes that had more than one entry in the same section when this file was read
+    #[serde(skip)]
+    repeated_keys: Vec<(&'static str, Mime)>,
 }
 
 /// Helper struct for a list of `DesktopHandler`s
@@ -63,19 +94,140 @@ impl FromStr for DesktopList {
     }
 }
 
+impl DesktopList {
+    /// Insert a handler at the given index, or at the end if none is given or it is past the end
+    /// A handler that is already in the list is moved to the given index instead of being repeated,
+    /// or left where it is if none is given
+    pub fn insert_at(&mut self, handler: DesktopHandler, index: Option<usize>) {
+        if index.is_none() && self.contains(&handler) {
+            return;
+        }
+        self.retain(|h| *h != handler);
+        let index = index.map_or(self.len(), |index| index.min(self.len()));
+        self.insert(index, handler);
+    }
+}
+
 impl Display for DesktopList {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{};", self.iter().join(";"))
     }
 }
 
+/// Where to move a handler to within a mime's list of handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reorder {
+    /// To the given index, or to the end if it is past it
+    To(usize),
+    /// One place earlier, making it more preferred
+    Up,
+    /// One place later, making it less preferred
+    Down,
+}
+
+/// How a mime's default handlers differ from the ones it should have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftKind {
+    /// The mime has no default handlers, but should have some
+    Added,
+    /// The mime has default handlers, but should not have any
+    Removed,
+    /// The mime has the right handlers, but in the wrong order
+    Reordered,
+    /// The mime has different handlers
+    Changed,
+}
+
+/// A mime whose default handlers differ from the ones it should have
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    pub mime: String,
+    pub kind: DriftKind,
+    pub current: Vec<String>,
+    pub desired: Vec<String>,
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (current, desired) =
+            (self.current.join(";"), self.desired.join(";"));
+        match self.kind {
+            DriftKind::Added => write!(f, "+ {}: {desired};", self.mime),
+            DriftKind::Removed => write!(f, "- {}: {current};", self.mime),
+            DriftKind::Reordered => write!(
+                f,
+                "~ {}: {current}; -> {desired}; (reordered)",
+                self.mime
+            ),
+            DriftKind::Changed => {
+                write!(f, "~ {}: {current}; -> {desired};", self.mime)
+            }
+        }
+    }
+}
+
+/// Summary of the handlers pruned from a mime's default application association
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct PruneReport {
+    pub mime: String,
+    pub removed: Vec<String>,
+    pub remaining: Vec<String>,
+}
+
+/// Summary of the handlers pruned from a mime's association in a given section of mimeapps.list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CleanReport {
+    pub section: &'static str,
+    #[serde(flatten)]
+    pub report: PruneReport,
+}
+
 impl MimeApps {
-    /// Add a handler to an existing default application association
+    /// Add a handler to the end of an existing default application association,
+    /// unless it is already part of it
     pub fn add_handler(
         &mut self,
         mime: &Mime,
         handler: &DesktopHandler,
         expand_wildcards: bool,
+    ) -> Result<()> {
+        self.add_handler_at(mime, handler, expand_wildcards, None)
+    }
+
+    /// Add a handler to the start of an existing default application association,
+    /// making it the preferred one while keeping the rest as fallbacks
+    pub fn add_handler_front(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+        expand_wildcards: bool,
+    ) -> Result<()> {
+        self.add_handler_at(mime, handler, expand_wildcards, Some(0))
+    }
+
+    /// Add a handler to an existing default application association at the given index,
+    /// or at the end if the index is past it
+    pub fn insert_handler(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+        expand_wildcards: bool,
+        index: usize,
+    ) -> Result<()> {
+        self.add_handler_at(mime, handler, expand_wildcards, Some(index))
+    }
+
+    /// Add a handler to an existing default application association at the given index,
+    /// or at the end if none is given
+    /// If the handler is already associated with the mime, it is moved to the given index instead,
+    /// or left where it is if none is given
+    fn add_handler_at(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+        expand_wildcards: bool,
+        index: Option<usize>,
     ) -> Result<()> {
         if expand_wildcards {
             let wildcard = WildMatch::new(mime.as_ref());
@@ -86,14 +238,14 @@ impl MimeApps {
                     self.default_apps
                         .entry(Mime::from_str(mime)?)
                         .or_default()
-                        .push_back(handler.clone());
+                        .insert_at(handler.clone(), index);
                     Ok(())
                 })?
         } else {
             self.default_apps
                 .entry(mime.clone())
                 .or_default()
-                .push_back(handler.clone());
+                .insert_at(handler.clone(), index);
         }
         Ok(())
     }
@@ -126,10 +278,27 @@ impl MimeApps {
         Ok(())
     }
 
+    /// Move a handler to the front of a mime's default application association, making it the preferred one
+    /// Only an exact match for the mime is changed, and only if the handler is already in it
+    /// Returns whether anything changed
+    pub fn prioritize_handler(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+    ) -> bool {
+        match self.default_apps.get_mut(mime) {
+            Some(list) if list.contains(handler) && list[0] != *handler => {
+                list.insert_at(handler.clone(), Some(0));
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Entirely remove a given mime's default application association
     pub fn unset_handler(&mut self, mime: &Mime) -> Option<()> {
         // If exact match is found, remove it
-        self.default_apps.remove(mime).map_or_else(
+        self.default_apps.shift_remove(mime).map_or_else(
             || {
                 let wildcard = WildMatch::new(mime.as_ref());
                 // Otherwise, remove all wildcard matches
@@ -176,205 +345,1137 @@ impl MimeApps {
             )
     }
 
-    /// Get a list of handlers associated with a wildcard mime
-    fn get_from_wildcard(&self, mime: &Mime) -> Option<&DesktopList> {
-        // Get the handlers that wildcard match the given mime
-        let associations = self.default_apps.iter().filter(|(m, _)| {
-            wildmatch::WildMatch::new(m.as_ref()).matches(mime.as_ref())
-        });
+    /// Move a handler within a given mime's default application association
+    /// and get the handlers in their new order
+    pub fn reorder_handler(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+        to: Reorder,
+    ) -> Result<&DesktopList> {
+        let handlers = self
+            .default_apps
+            .get_mut(mime)
+            .ok_or_else(|| Error::NotFound(mime.to_string()))?;
+        let index = handlers
+            .iter()
+            .position(|h| h == handler)
+            .ok_or_else(|| Error::NotFound(format!("{handler} in {mime}")))?;
 
-        // Get the length of the longest wildcard that matches
-        // Assuming the longest match is the best match
-        // Inspired by how globs are handled in xdg spec
-        let biggest_wildcard_len = associations
-            .clone()
-            .map(|(ref m, _)| m.as_ref().len())
-            .max()?;
-
-        // Keep only the lists of handlers from associations with the longest wildcards
-        // And get the first one, assuming it takes precedence
-        // Loosely inspired by how globs are handled in xdg spec
-        associations
-            .filter(|(ref m, _)| m.as_ref().len() == biggest_wildcard_len)
-            .map(|(_, handlers)| handlers)
-            .collect_vec()
-            .first()
-            .cloned()
-    }
-
-    /// Get the handler associated with a given mime from mimeapps.list's default apps
-    #[mutants::skip] // Cannot entirely test, namely cannot test selector or filtering
-    pub fn get_handler_from_user(
+        let index = match to {
+            Reorder::To(index) => index,
+            Reorder::Up => index.saturating_sub(1),
+            Reorder::Down => index + 1,
+        };
+        handlers.insert_at(handler.clone(), Some(index));
+
+        Ok(handlers)
+    }
+
+    /// Get every mime whose default handlers differ from the ones it should have, sorted by mime
+    /// The order of handlers matters, and mimes that are not in `desired` are only compared if `replace` is true,
+    /// like with `Config::import`
+    pub fn drift(
+        &self,
+        desired: &IndexMap<Mime, DesktopList>,
+        replace: bool,
+    ) -> Vec<Drift> {
+        let names = |list: Option<&DesktopList>| {
+            list.into_iter()
+                .flat_map(|list| list.iter().map(|h| h.to_string()))
+                .collect_vec()
+        };
+
+        self.default_apps
+            .keys()
+            .filter(|mime| replace || desired.contains_key(*mime))
+            .chain(desired.keys())
+            .unique()
+            .sorted_by_key(|mime| mime.to_string())
+            .filter_map(|mime| {
+                let current = names(self.default_apps.get(mime));
+                let desired = names(desired.get(mime));
+
+                let kind = match (current.is_empty(), desired.is_empty()) {
+                    _ if current == desired => return None,
+                    (true, _) => DriftKind::Added,
+                    (_, true) => DriftKind::Removed,
+                    _ if current
+                        .iter()
+                        .sorted()
+                        .eq(desired.iter().sorted()) =>
+                    {
+                        DriftKind::Reordered
+                    }
+                    _ => DriftKind::Changed,
+                };
+
+                Some(Drift {
+                    mime: mime.to_string(),
+                    kind,
+                    current,
+                    desired,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove every handler whose desktop file cannot be found from a given mime's default file association
+    /// The association is removed entirely if no handlers remain
+    pub fn prune_mime(&mut self, mime: &Mime) -> PruneReport {
+        self.prune_mime_with(mime, DesktopHandler::exists)
+    }
+
+    /// Remove every handler that does not satisfy `keep` from a given mime's default file association
+    /// Makes testing easier
+    fn prune_mime_with<F: Fn(&DesktopHandler) -> bool>(
+        &mut self,
+        mime: &Mime,
+        keep: F,
+    ) -> PruneReport {
+        prune_with(&mut self.default_apps, mime, keep)
+    }
+
+    /// Remove every handler whose desktop file cannot be found from every default app and added association
+    /// and report what was removed from each section
+    pub fn clean(&mut self) -> Vec<CleanReport> {
+        self.clean_with(DesktopHandler::exists)
+    }
+
+    /// Remove every handler that does not satisfy `keep` from every default app and added association
+    /// Makes testing easier
+    fn clean_with<F: Fn(&DesktopHandler) -> bool>(
+        &mut self,
+        keep: F,
+    ) -> Vec<CleanReport> {
+        let mut reports = Vec::new();
+
+        for (section, associations) in [
+            ("default_apps", &mut self.default_apps),
+            ("added_associations", &mut self.added_associations),
+        ] {
+            for mime in associations.keys().cloned().collect_vec() {
+                let report = prune_with(associations, &mime, &keep);
+
+                if !report.removed.is_empty() {
+                    reports.push(CleanReport { section, report });
+                }
+            }
+        }
+
+        reports
+    }
+
+    /// Get the desktop-specific associations for the first desktop in `$XDG_CURRENT_DESKTOP`
+    pub fn desktop(&self) -> Result<&MimeApps> {
+        self.desktop_apps.first().ok_or(Error::NoDesktop)
+    }
+
+    /// Get the desktop-specific associations for the first desktop in `$XDG_CURRENT_DESKTOP`
+    pub fn desktop_mut(&mut self) -> Result<&mut MimeApps> {
+        self.desktop_apps.first_mut().ok_or(Error::NoDesktop)
+    }
+
+    /// Get every set of associations in order of precedence
+    /// i.e. desktop-specific files, then mimeapps.list, then system-wide files
+    fn layers(&self) -> impl Iterator<Item = &MimeApps> {
+        self.desktop_apps
+            .iter()
+            .chain(std::iter::once(self))
+            .chain(self.system_layers.iter())
+    }
+
+    /// Get the mimes that had more than one entry in the same section of any of the files associations were read from,
+    /// along with the file and section
+    pub fn repeated_keys(&self) -> Vec<(String, &'static str, &Mime)> {
+        self.layers()
+            .flat_map(|layer| {
+                layer
+                    .repeated_keys
+                    .iter()
+                    .map(|(section, mime)| (layer.source(), *section, mime))
+            })
+            .collect()
+    }
+
+    /// Get every set of associations other than the ones in mimeapps.list that are not empty,
+    /// in order of precedence
+    pub fn other_layers(&self) -> impl Iterator<Item = &MimeApps> {
+        self.desktop_apps
+            .iter()
+            .chain(self.system_layers.iter())
+            .filter(|layer| {
+                !(layer.default_apps.is_empty()
+                    && layer.added_associations.is_empty())
+            })
+    }
+
+    /// Get the list of default handlers associated with a given mime
+    /// Desktop-specific associations are checked before mimeapps.list,
+    /// which is checked before system-wide associations
+    pub fn get_default_handlers(
         &self,
         mime: &Mime,
         config_file: &ConfigFile,
-    ) -> Result<DesktopHandler> {
-        let error = Error::NotFound(mime.to_string());
-        // Check for an exact match first and then fall back to wildcard
-        match self
+    ) -> Option<&DesktopList> {
+        self.layers().find_map(|mime_apps| {
+            // Check for an exact match first and then fall back to wildcard
+            mime_apps
+                .default_apps
+                .get(mime)
+                .or_else(|| mime_apps.get_from_wildcard(mime, config_file))
+        })
+    }
+
+    /// Get every mime each handler is associated with in default apps or added associations
+    /// Every layer is included, regardless of precedence
+    pub fn handler_index(&self) -> BTreeMap<DesktopHandler, BTreeSet<Mime>> {
+        let mut index = BTreeMap::<DesktopHandler, BTreeSet<Mime>>::new();
+
+        for (mime, handlers) in self.layers().flat_map(|mime_apps| {
+            mime_apps
+                .default_apps
+                .iter()
+                .chain(mime_apps.added_associations.iter())
+        }) {
+            for handler in handlers.iter() {
+                index
+                    .entry(handler.clone())
+                    .or_default()
+                    .insert(mime.clone());
+            }
+        }
+
+        index
+    }
+
+    /// Get every association of a mime to a given handler in default apps or added associations,
+    /// along with where it comes from and the file it is in
+    /// Layers are listed in order of precedence, and wildcards are kept as they are
+    pub fn associations_of(
+        &self,
+        handler: &DesktopHandler,
+    ) -> Vec<(Mime, HandlerSource, String)> {
+        self.layers()
+            .flat_map(|layer| {
+                let source = layer.source();
+                let defaults = layer.default_apps.iter().map(|(mime, list)| {
+                    let kind = if mime.as_ref().contains('*') {
+                        HandlerSource::Wildcard
+                    } else {
+                        HandlerSource::Default
+                    };
+                    (mime, list, kind)
+                });
+                let added =
+                    layer.added_associations.iter().map(|(mime, list)| {
+                        (mime, list, HandlerSource::AddedAssociation)
+                    });
+
+                defaults
+                    .chain(added)
+                    .filter(|(_, list, _)| list.contains(handler))
+                    .map(move |(mime, _, kind)| {
+                        (mime.clone(), kind, source.clone())
+                    })
+                    .collect_vec()
+            })
+            .collect()
+    }
+
+    /// Get the default handler for a given mime from system-wide mimeapps.list files only
+    pub fn get_system_default_handler(
+        &self,
+        mime: &Mime,
+    ) -> Option<&DesktopHandler> {
+        self.system_layers
+            .iter()
+            .find_map(|layer| layer.default_apps.get(mime))
+            .and_then(|handlers| handlers.front())
+    }
+
+    /// Get every handler associated with exactly a given mime in mimeapps.list's default apps, in priority order,
+    /// along with whether it comes from a wildcard
+    /// Each file's exact match comes before its wildcard matches, and earlier files come before later ones
+    pub fn get_default_candidates(
+        &self,
+        mime: &Mime,
+        config_file: &ConfigFile,
+    ) -> Vec<(DesktopHandler, bool)> {
+        self.layers()
+            .flat_map(|mime_apps| {
+                [
+                    (mime_apps.default_apps.get(mime), false),
+                    (mime_apps.get_from_wildcard(mime, config_file), true),
+                ]
+            })
+            .filter_map(|(handlers, wildcard)| {
+                Some(handlers?.iter().map(move |h| (h.clone(), wildcard)))
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Get the list of added associations for a given mime
+    /// Layers are checked in the same order as with default handlers
+    pub fn get_added_handlers(&self, mime: &Mime) -> Option<&DesktopList> {
+        self.layers()
+            .find_map(|mime_apps| mime_apps.added_associations.get(mime))
+    }
+
+    /// Get the handlers associated with a given mime or extension in this mimeapps.list,
+    /// along with descriptions of them for completions
+    /// Default applications come first, then added associations, which are marked as such
+    /// Nothing is returned if the mime is invalid or has no associations
+    pub fn describe_handlers(&self, mime: &str) -> Vec<(String, String)> {
+        let Ok(MimeOrExtension(mime, _)) = MimeOrExtension::from_str(mime)
+        else {
+            return Vec::new();
+        };
+
+        let name = |handler: &DesktopHandler| {
+            handler.get_entry().map(|e| e.name).unwrap_or_default()
+        };
+
+        let defaults = self
             .default_apps
-            .get(mime)
-            .or_else(|| self.get_from_wildcard(mime))
-        {
-            Some(handlers) => {
-                // Prepares for selector and filters out apps that do not exist
-                let handlers = handlers
-                    .iter()
-                    .flat_map(|h| -> Result<(&DesktopHandler, String)> {
-                        // Filtering breaks testing, so treat every app as valid
-                        if cfg!(test) {
-                            Ok((h, h.to_string()))
-                        } else {
-                            Ok((h, h.get_entry()?.name))
+            .get(&mime)
+            .into_iter()
+            .flat_map(|l| l.iter());
+        let added = self
+            .added_associations
+            .get(&mime)
+            .into_iter()
+            .flat_map(|l| l.iter())
+            .filter(|handler| !defaults.clone().contains(handler));
+
+        defaults
+            .clone()
+            .map(|handler| (handler.to_string(), name(handler)))
+            .chain(added.map(|handler| {
+                (
+                    handler.to_string(),
+                    format!("{} (added association)", name(handler))
+                        .trim_start()
+                        .to_owned(),
+                )
+            }))
+            .collect()
+    }
+
+    /// Get descriptions of the handlers associated with a given mime or extension in the user's mimeapps.list
+    /// Used to complete handlers that can be removed from a mime
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn describe_user_handlers(mime: &str) -> Vec<(String, String)> {
+        Self::path(None)
+            .and_then(Self::read_user_file)
+            .map(|mime_apps| mime_apps.describe_handlers(mime))
+            .unwrap_or_default()
+    }
+
+    /// Hide a handler from a given mime's added and system associations
+    /// by adding it to the mime's removed associations
+    pub fn remove_association(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+    ) -> Option<()> {
+        let removed =
+            self.removed_associations.entry(mime.clone()).or_default();
+
+        if removed.contains(handler) {
+            None
+        } else {
+            removed.push_back(handler.clone());
+            Some(())
+        }
+    }
+
+    /// Check if a handler has been removed from a given mime's associations
+    pub fn is_removed(&self, mime: &Mime, handler: &DesktopHandler) -> bool {
+        self.layers().any(|mime_apps| {
+            mime_apps
+                .removed_associations
+                .get(mime)
+                .is_some_and(|removed| removed.contains(handler))
+        })
+    }
+
+    /// Get the associations in default apps whose wildcards match a given mime, other than the mime itself,
+    /// with the longest wildcards first
+    /// Assuming the longest match is the best match
+    /// Inspired by how globs are handled in xdg spec
+    fn wildcard_matches(&self, mime: &Mime) -> Vec<(&Mime, &DesktopList)> {
+        self.default_apps
+            .iter()
+            .filter(|(m, _)| {
+                *m != mime
+                    && wildmatch::WildMatch::new(m.as_ref())
+                        .matches(mime.as_ref())
+            })
+            // Sorting is stable, so the first of the longest wildcards takes precedence
+            .sorted_by_key(|(m, _)| std::cmp::Reverse(m.as_ref().len()))
+            .collect()
+    }
+
+    /// Get the handlers from the longest wildcard in default apps that matches a given mime
+    /// Wildcards are never used for protected mimes, see `ConfigFile::protected_mimes`
+    fn get_from_wildcard(
+        &self,
+        mime: &Mime,
+        config_file: &ConfigFile,
+    ) -> Option<&DesktopList> {
+        let (wildcard, handlers) =
+            self.wildcard_matches(mime).into_iter().next()?;
+
+        if config_file.is_protected(mime) {
+            warn_protected(wildcard, mime);
+            return None;
+        }
+
+        Some(handlers)
+    }
+
+    /// Get the handlers associated with a given mime from mimeapps.list's default apps, in the order they should be tried
+    /// If the selector is enabled, the selected handler comes first, followed by the rest
+    /// Handlers whose desktop files cannot be found are skipped
+    /// The selector is told the mime and, if there is one, the path being opened through
+    /// `HANDLR_MIME` and `HANDLR_PATH` in its environment
+    /// Also gets whether the handlers came from a wildcard, and records the steps taken to find them in `trace`
+    #[mutants::skip] // Cannot entirely test, namely cannot test selector
+    pub fn get_handlers_from_user(
+        &self,
+        mime: &Mime,
+        path: Option<&str>,
+        config_file: &ConfigFile,
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)> {
+        let env = std::iter::once(("HANDLR_MIME", mime.essence_str()))
+            .chain(path.map(|path| ("HANDLR_PATH", path)))
+            .collect_vec();
+
+        self.get_handlers_from_user_with(
+            mime,
+            config_file,
+            |handler| handler.check_usable(config_file.check_try_exec),
+            |lines, handlers| {
+                config_file
+                    .selector
+                    .args("Open With", mime)
+                    .and_then(|args| {
+                        utils::select(&args, lines.iter().cloned(), &env)
+                    })
+                    .or_else(|e| match e {
+                        // Fall back to asking without the selector if it cannot be run at all
+                        Error::SelectorNotFound(_) | Error::BadCmd(_) => {
+                            let options = lines
+                                .iter()
+                                .zip(handlers)
+                                .map(|(line, handler)| {
+                                    // Detailed lines already start with the id
+                                    let name = line
+                                        .split_once('\t')
+                                        .map_or(line.as_str(), |(_, name)| {
+                                            name
+                                        });
+                                    format!("{name} ({handler})")
+                                })
+                                .collect_vec();
+                            utils::choose_without_selector(
+                                &format!("Handler for {mime}?"),
+                                &options,
+                                &e,
+                            )
+                            .map(|choice| handlers[choice].to_string())
                         }
+                        e => Err(e),
                     })
-                    .collect_vec();
-
-                if config_file.enable_selector && handlers.len() > 1 {
-                    let handler = {
-                        let name = select(
-                            &config_file.selector,
-                            handlers.iter().map(|h| h.1.clone()),
-                        )?;
-
-                        handlers
-                            .into_iter()
-                            .find(|h| h.1 == name)
-                            .ok_or(error)?
-                            .0
-                            .clone()
-                    };
+            },
+            |layer, handler| {
+                let mut layer = layer.clone();
+                if layer.prioritize_handler(mime, handler) {
+                    layer.save(config_file.backup_mimeapps)?;
+                }
+                Ok(())
+            },
+            trace,
+        )
+    }
 
-                    Ok(handler)
+    /// Get the handlers associated with a given mime from mimeapps.list's default apps,
+    /// skipping handlers that `usable` fails for and choosing between them with `select`,
+    /// which is given the lines to show and the handlers they belong to
+    /// With `selector_persist`, the chosen handler is given to `remember` along with the file it was found in
+    /// Makes testing easier
+    fn get_handlers_from_user_with<F, S, R>(
+        &self,
+        mime: &Mime,
+        config_file: &ConfigFile,
+        usable: F,
+        select: S,
+        remember: R,
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)>
+    where
+        F: Fn(&DesktopHandler) -> Result<()>,
+        S: FnOnce(Vec<String>, &[&DesktopHandler]) -> Result<String>,
+        R: FnOnce(&MimeApps, &DesktopHandler) -> Result<()>,
+    {
+        let error = Error::NotFound(mime.to_string());
+        // System-wide files come last and are never written to
+        let writable = self.desktop_apps.len() + 1;
+
+        // Use the first list of handlers, including ones from wildcards, that has any handlers that exist
+        let (index, layer, (mut handlers, source)) = self
+            .layers()
+            .enumerate()
+            .find_map(|(index, layer)| {
+                // Check for an exact match first and then fall back to wildcard
+                layer
+                    .exact_candidate(mime, &usable, trace)
+                    .or_else(|| {
+                        layer.wildcard_candidate(
+                            mime,
+                            config_file,
+                            &usable,
+                            trace,
+                        )
+                    })
+                    .map(|candidate| (index, layer, candidate))
+            })
+            .ok_or_else(|| Error::NotFound(mime.to_string()))?;
+
+        // Only ask which handler to use if there is more than one to choose from
+        // after missing and duplicate handlers are filtered out
+        if config_file.should_select(mime) && handlers.len() > 1 {
+            // Prefer showing apps' names, but fall back to desktop file names
+            let names = handlers
+                .iter()
+                .map(|h| {
+                    h.get_entry().map_or_else(
+                        |_| h.to_string(),
+                        |e| e.format(&config_file.selector_format),
+                    )
+                })
+                .collect_vec();
+
+            trace.step(|| {
+                format!(
+                    "asking the selector to choose from {}",
+                    names.join(", ")
+                )
+            });
+            let lines = if config_file.selector_detailed {
+                handlers
+                    .iter()
+                    .zip(&names)
+                    .map(|(handler, name)| format!("{handler}\t{name}"))
+                    .collect_vec()
+            } else {
+                names.clone()
+            };
+            let choice = select(lines, &handlers)?;
+            trace.step(|| format!("selected {choice}"));
+
+            let selected =
+                find_selected(&handlers, &names, &choice).ok_or(error)?;
+
+            let handler = handlers.remove(selected);
+            handlers.insert(0, handler);
+
+            if config_file.selector_persist {
+                // Wildcards also cover other mimes, so they are never changed based on a choice for one of them
+                if source == HandlerSource::Default && index < writable {
+                    remember(layer, handler)?;
+                    trace.step(|| {
+                        format!(
+                            "{}: remembered {handler} as the default for '{mime}'",
+                            layer.source()
+                        )
+                    });
                 } else {
-                    Ok(handlers.first().ok_or(error)?.0.clone())
+                    trace.step(|| {
+                        format!("not remembering {handler}, since '{mime}' is not set exactly in a file handlr writes to")
+                    });
                 }
             }
-            None => Err(error),
         }
+
+        Ok((handlers.into_iter().cloned().collect(), source))
+    }
+
+    /// Internal helper function for `get_handlers_from_user_with`
+    /// Get the handlers that exist from this file's default apps for exactly a given mime
+    fn exact_candidate<F>(
+        &self,
+        mime: &Mime,
+        usable: &F,
+        trace: &mut Trace,
+    ) -> Option<(Vec<&DesktopHandler>, HandlerSource)>
+    where
+        F: Fn(&DesktopHandler) -> Result<()>,
+    {
+        let source = self.source();
+
+        let Some(list) = self.default_apps.get(mime) else {
+            trace.step(|| format!("{source}: no default for '{mime}'"));
+            return None;
+        };
+
+        let (handlers, skipped) = usable_handlers(list, usable, &source, trace);
+        trace.step(|| {
+            format!(
+                "{source}: '{mime}' is set to {list}{}",
+                missing_note(handlers.is_empty(), &skipped)
+            )
+        });
+
+        (!handlers.is_empty()).then_some((handlers, HandlerSource::Default))
+    }
+
+    /// Internal helper function for `get_handlers_from_user_with`
+    /// Get the handlers that exist from the longest wildcard in this file's default apps that matches a given mime
+    fn wildcard_candidate<F>(
+        &self,
+        mime: &Mime,
+        config_file: &ConfigFile,
+        usable: &F,
+        trace: &mut Trace,
+    ) -> Option<(Vec<&DesktopHandler>, HandlerSource)>
+    where
+        F: Fn(&DesktopHandler) -> Result<()>,
+    {
+        let matches = self.wildcard_matches(mime);
+        let source = self.source();
+
+        trace.step(|| {
+            if matches.is_empty() {
+                format!("{source}: no wildcards match '{mime}'")
+            } else {
+                format!(
+                    "{source}: wildcards matching '{mime}': {}",
+                    matches
+                        .iter()
+                        .map(|(m, _)| format!(
+                            "'{m}' (length {})",
+                            m.as_ref().len()
+                        ))
+                        .join(", ")
+                )
+            }
+        });
+
+        let (wildcard, list) = matches.first()?;
+
+        if config_file.is_protected(mime) {
+            trace.step(|| {
+                format!("{source}: '{mime}' is protected, so '{wildcard}' is not used")
+            });
+            warn_protected(wildcard, mime);
+            return None;
+        }
+
+        let (handlers, skipped) = usable_handlers(list, usable, &source, trace);
+        trace.step(|| {
+            format!(
+                "{source}: '{wildcard}' is set to {list}{}",
+                missing_note(handlers.is_empty(), &skipped)
+            )
+        });
+
+        (!handlers.is_empty()).then_some((handlers, HandlerSource::Wildcard))
     }
 
     /// Get the path to the user's mimeapps.list file
+    /// or to the desktop-specific one if a desktop is given
     #[mutants::skip] // Cannot test directly, depends on system state
-    fn path() -> Result<PathBuf> {
+    fn path(desktop: Option<&str>) -> Result<PathBuf> {
         let mut config = xdg::BaseDirectories::new()?.get_config_home();
-        config.push("mimeapps.list");
+        config.push(file_name(desktop));
         Ok(config)
     }
 
+    /// Get the paths of the user's mimeapps.list and the desktop-specific files for each desktop in `$XDG_CURRENT_DESKTOP`,
+    /// whether or not they exist
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn user_paths() -> Result<Vec<PathBuf>> {
+        let desktops = desktops_from_env();
+
+        std::iter::once(None)
+            .chain(desktops.iter().map(|desktop| Some(desktop.as_str())))
+            .map(Self::path)
+            .collect()
+    }
+
+    /// Get a description of where these associations came from
+    pub fn source(&self) -> String {
+        self.source.as_ref().map_or_else(
+            || file_name(self.desktop.as_deref()),
+            |path| path.display().to_string(),
+        )
+    }
+
     /// Read and parse mimeapps.list
+    /// along with the desktop-specific files for each desktop in `$XDG_CURRENT_DESKTOP`
+    /// and the system-wide files in `$XDG_CONFIG_DIRS` and `$XDG_DATA_DIRS`
     #[mutants::skip] // Cannot test directly, depends on system state
     pub fn read() -> Result<Self> {
-        let exists = std::path::Path::new(&Self::path()?).exists();
-
-        let file = std::fs::OpenOptions::new()
-            .write(!exists)
-            .create(!exists)
-            .read(true)
-            .open(Self::path()?)?;
+        let base_dirs = xdg::BaseDirectories::new()?;
+
+        // Locations of system-wide mimeapps.list files in order of precedence
+        let system_dirs = base_dirs
+            .get_config_dirs()
+            .into_iter()
+            .chain(
+                std::iter::once(base_dirs.get_data_home())
+                    .chain(base_dirs.get_data_dirs())
+                    .map(|dir| dir.join("applications")),
+            )
+            .collect_vec();
 
-        Self::read_from(file)
+        Self::read_in(
+            &base_dirs.get_config_home(),
+            &system_dirs,
+            &desktops_from_env(),
+        )
     }
 
-    /// Deserialize MimeApps from reader
-    /// Makes testing easier
-    fn read_from<R: Read>(reader: R) -> Result<Self> {
-        let mut mime_apps: MimeApps = serde_ini::de::from_read(reader)?;
+    /// Read and parse the mimeapps.list files in a given config directory like ~/.config
+    /// for no desktop and for each of the given desktops,
+    /// along with the system-wide files in the given directories, which are in order of precedence
+    /// Nothing is created if the files do not exist
+    pub fn read_in(
+        config_home: &Path,
+        system_dirs: &[PathBuf],
+        desktops: &[String],
+    ) -> Result<Self> {
+        let mut mime_apps =
+            Self::read_user_file(config_home.join(file_name(None)))?;
+
+        mime_apps.desktop_apps = desktops
+            .iter()
+            .map(|desktop| -> Result<Self> {
+                Ok(Self::read_layer(
+                    config_home.join(file_name(Some(desktop))),
+                    Some(desktop),
+                )?
+                .unwrap_or_else(|| Self {
+                    desktop: Some(desktop.clone()),
+                    ..Default::default()
+                }))
+            })
+            .collect::<Result<_>>()?;
+
+        for dir in system_dirs {
+            // Desktop-specific files take precedence over generic ones in the same directory
+            for desktop in
+                desktops.iter().map(Some).chain(std::iter::once(None))
+            {
+                if let Some(layer) = Self::read_layer(
+                    dir.join(file_name(desktop.map(String::as_str))),
+                    desktop,
+                )? {
+                    mime_apps.system_layers.push(layer)
+                }
+            }
+        }
 
-        // Remove empty entries
-        mime_apps
-            .default_apps
-            .retain(|_, handlers| !handlers.is_empty());
+        Ok(mime_apps)
+    }
 
+    /// Read and parse the user's mimeapps.list file
+    /// A missing file is treated as having no associations rather than being created,
+    /// so that commands that only read associations never change anything
+    fn read_user_file(path: PathBuf) -> Result<Self> {
+        let mut mime_apps =
+            Self::read_layer(path.clone(), None)?.unwrap_or_default();
+        mime_apps.source = Some(path);
         Ok(mime_apps)
     }
 
+    /// Read and parse a mimeapps.list file if it exists
+    fn read_layer(
+        path: PathBuf,
+        desktop: Option<&String>,
+    ) -> Result<Option<Self>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let mut layer = Self::read_from(std::fs::File::open(&path)?)?;
+        layer.desktop = desktop.cloned();
+        layer.source = Some(path);
+
+        Ok(Some(layer))
+    }
+
+    /// Deserialize MimeApps from reader
+    /// Makes testing easier
+    fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        Self::try_from(contents.as_str())
+    }
+
     /// Save associations to mimeapps.list
+    /// If `backup` is true, the previous version of the file is kept as mimeapps.list.bak
     #[mutants::skip] // Cannot test directly, alters system state
-    pub fn save(&mut self) -> Result<()> {
+    pub fn save(&mut self, backup: bool) -> Result<()> {
         if cfg!(test) {
             Ok(())
         } else {
-            let mut file = std::fs::OpenOptions::new()
-                .read(true)
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(Self::path()?)?;
-
-            self.save_to(&mut file)
+            self.save_to_path(&Self::path(self.desktop.as_deref())?, backup)
         }
     }
 
+    /// Save associations to the file at the given path
+    /// The file is replaced all at once, so it is never left partially written
+    fn save_to_path(&mut self, path: &Path, backup: bool) -> Result<()> {
+        // Replace the file a symlink points to rather than the symlink itself
+        let path = std::fs::canonicalize(path).unwrap_or(path.to_owned());
+
+        let mut contents = Vec::new();
+        self.save_to(&mut contents)?;
+
+        if backup && path.is_file() {
+            let mut backup_path = path.clone().into_os_string();
+            backup_path.push(".bak");
+            utils::write_atomic(
+                Path::new(&backup_path),
+                &std::fs::read(&path)?,
+            )?;
+        } else if let Some(parent) = path.parent() {
+            // The file may not exist yet, in which case neither might its directory
+            std::fs::create_dir_all(parent)?;
+        }
+
+        utils::write_atomic(&path, &contents)
+    }
+
     /// Serialize MimeApps and write to writer
     /// Makes testing easier
     fn save_to<W: Write>(&mut self, writer: &mut W) -> Result<()> {
         // Remove empty entries
         self.default_apps.retain(|_, handlers| !handlers.is_empty());
 
-        // Use Linefeed instead of default carriage return
-        let w = serde_ini::write::Writer::new(
-            writer,
-            serde_ini::write::LineEnding::Linefeed,
-        );
-        let mut ser = serde_ini::ser::Serializer::new(w);
-        self.serialize(&mut ser)?;
+        writer.write_all(self.to_string().as_bytes())?;
+        // Repeated entries are merged when written
+        self.repeated_keys.clear();
 
         Ok(())
     }
 }
 
-/// Run given selector command
-#[mutants::skip] // Cannot test directly, runs external command
-fn select<O: Iterator<Item = String>>(
-    selector: &str,
-    mut opts: O,
-) -> Result<String> {
-    use std::{
-        io::prelude::*,
-        process::{Command, Stdio},
-    };
-
-    let process = {
-        let mut split = shlex::split(selector)
-            .ok_or_else(|| Error::BadCmd(selector.to_string()))?;
-        let (cmd, args) = (split.remove(0), split);
-        Command::new(cmd)
-            .args(args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?
-    };
-
-    let output = {
-        process
-            .stdin
-            .ok_or_else(|| Error::Selector(selector.to_string()))?
-            .write_all(opts.join("\n").as_bytes())?;
-
-        let mut output = String::with_capacity(24);
+impl TryFrom<&str> for MimeApps {
+    type Error = Error;
+
+    /// Parse the contents of a mimeapps.list file
+    /// Only the associations and layout are set, as there is no file for the rest to come from
+    fn try_from(contents: &str) -> Result<Self> {
+        let mut mime_apps: MimeApps =
+            serde_ini::de::from_str(&Layout::normalize(contents))?;
+        mime_apps.layout = Layout::parse(contents);
+
+        // Merge the handlers of mimes with more than one entry in the same section,
+        // rather than only keeping one of them
+        for (section, associations) in [
+            ("Added Associations", &mut mime_apps.added_associations),
+            ("Removed Associations", &mut mime_apps.removed_associations),
+            ("Default Applications", &mut mime_apps.default_apps),
+        ] {
+            for (mime, lists) in mime_apps.layout.repeated_entries(section) {
+                associations.insert(
+                    mime.clone(),
+                    DesktopList(
+                        lists.into_iter().flat_map(|l| l.0).unique().collect(),
+                    ),
+                );
+                mime_apps.repeated_keys.push((section, mime));
+            }
+        }
 
-        process
-            .stdout
-            .ok_or_else(|| Error::Selector(selector.to_string()))?
-            .read_to_string(&mut output)?;
+        // Remove empty entries
+        mime_apps
+            .default_apps
+            .retain(|_, handlers| !handlers.is_empty());
 
-        output.trim_end().to_owned()
-    };
+        Ok(mime_apps)
+    }
+}
 
-    if output.is_empty() {
-        Err(Error::Cancelled)
-    } else {
-        Ok(output)
+impl Display for MimeApps {
+    /// Write the contents of the mimeapps.list file for these associations,
+    /// keeping the layout of the file they were read from, if any
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut default_apps = self.default_apps.clone();
+        default_apps.retain(|_, handlers| !handlers.is_empty());
+
+        f.write_str(&self.layout.render(&[
+            ("Added Associations", &self.added_associations),
+            ("Removed Associations", &self.removed_associations),
+            ("Default Applications", &default_apps),
+        ]))
     }
 }
 
+/// Builds a `MimeApps` from associations given as strings, without any file
+/// The associations go through the same parsing as mimeapps.list itself, e.g.
+///
+/// ```
+/// let mime_apps = MimeApps::builder()
+///     .default("image/png", ["feh.desktop"])
+///     .added("text/html", ["lynx.desktop"])
+///     .build()?;
+/// ```
+///
+/// Only used by tests, as handlr has no library target for anything else to use it from
 #[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone)]
+pub struct MimeAppsBuilder {
+    sections: [(&'static str, Vec<String>); 3],
+}
+
+#[cfg(test)]
+impl MimeApps {
+    /// Start building a `MimeApps`, see `MimeAppsBuilder`
+    pub fn builder() -> MimeAppsBuilder {
+        MimeAppsBuilder {
+            sections: [
+                ("Added Associations", Vec::new()),
+                ("Removed Associations", Vec::new()),
+                ("Default Applications", Vec::new()),
+            ],
+        }
+    }
+
+    /// Helper function for testing
+    pub fn push_system_layer(&mut self, source: &str, mut layer: MimeApps) {
+        layer.source = Some(source.into());
+        self.system_layers.push(layer);
+    }
+}
+
+#[cfg(test)]
+impl MimeAppsBuilder {
+    /// Add an entry to the section at the given index
+    fn entry<'a>(
+        mut self,
+        section: usize,
+        mime: &str,
+        handlers: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        self.sections[section]
+            .1
+            .push(format!("{mime}={};", handlers.into_iter().join(";")));
+        self
+    }
+
+    /// Add handlers to `[Added Associations]` for a mime
+    pub fn added<'a>(
+        self,
+        mime: &str,
+        handlers: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        self.entry(0, mime, handlers)
+    }
+
+    /// Add handlers to `[Removed Associations]` for a mime
+    pub fn removed<'a>(
+        self,
+        mime: &str,
+        handlers: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        self.entry(1, mime, handlers)
+    }
+
+    /// Add handlers to `[Default Applications]` for a mime
+    pub fn default<'a>(
+        self,
+        mime: &str,
+        handlers: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        self.entry(2, mime, handlers)
+    }
+
+    /// Parse the associations, failing on invalid mimes like reading mimeapps.list would
+    pub fn build(&self) -> Result<MimeApps> {
+        let contents = self
+            .sections
+            .iter()
+            .filter(|(_, entries)| !entries.is_empty())
+            .map(|(section, entries)| {
+                format!("[{section}]\n{}\n", entries.join("\n"))
+            })
+            // Sections are separated by blank lines like in files written by handlr
+            .join("\n");
+
+        MimeApps::try_from(contents.as_str())
+    }
+}
+
+/// Remove every handler that does not satisfy `keep` from a given mime's association in `associations`
+/// The association is removed entirely if no handlers remain
+fn prune_with<F: Fn(&DesktopHandler) -> bool>(
+    associations: &mut IndexMap<Mime, DesktopList>,
+    mime: &Mime,
+    keep: F,
+) -> PruneReport {
+    let mut report = PruneReport {
+        mime: mime.to_string(),
+        ..Default::default()
+    };
+
+    if let Some(handlers) = associations.get_mut(mime) {
+        let (remaining, removed): (VecDeque<_>, VecDeque<_>) =
+            handlers.drain(..).partition(|h| keep(h));
+
+        report.removed = removed.iter().map(|h| h.to_string()).collect();
+        report.remaining = remaining.iter().map(|h| h.to_string()).collect();
+
+        if remaining.is_empty() {
+            associations.shift_remove(mime);
+        } else {
+            handlers.0 = remaining;
+        }
+    }
+
+    report
+}
+
+/// Why handlers in a list were skipped
+#[derive(Default)]
+struct Skipped {
+    /// Whether any were skipped because their `TryExec` program cannot be found
+    try_exec: bool,
+    /// Whether any were skipped because they are not installed
+    missing: bool,
+}
+
+/// Get the handlers in a list that `usable` does not fail for, without repeats,
+/// along with why the rest were skipped
+/// Handlers skipped for reasons other than not being installed are recorded in `trace`
+fn usable_handlers<'a, F>(
+    list: &'a DesktopList,
+    usable: &F,
+    source: &str,
+    trace: &mut Trace,
+) -> (Vec<&'a DesktopHandler>, Skipped)
+where
+    F: Fn(&DesktopHandler) -> Result<()>,
+{
+    let mut skipped = Skipped::default();
+    let handlers = list
+        .iter()
+        .filter(|handler| match usable(handler) {
+            Ok(()) => true,
+            Err(Error::MissingTryExec { program, .. }) => {
+                trace.step(|| {
+                    format!("{source}: skipping {handler}, since its TryExec program '{program}' cannot be found")
+                });
+                skipped.try_exec = true;
+                false
+            }
+            Err(_) => {
+                skipped.missing = true;
+                false
+            }
+        })
+        .unique()
+        .collect_vec();
+
+    (handlers, skipped)
+}
+
+/// Get a note for traces about lists of handlers that are all skipped
+fn missing_note(all_skipped: bool, skipped: &Skipped) -> &'static str {
+    match (all_skipped, skipped.try_exec, skipped.missing) {
+        (false, ..) => "",
+        (true, true, true) => {
+            ", but some are not installed and the rest were skipped because of their TryExec programs"
+        }
+        (true, true, false) => {
+            ", but all of them were skipped because of their TryExec programs"
+        }
+        (true, false, _) => ", but none of them are installed",
+    }
+}
+
+/// Find which of the handlers was chosen from the selector's output
+/// It is matched against the handlers' desktop file ids first and then what is shown for them,
+/// so that handlers with the same name can still be told apart by their ids
+/// Only the id is needed from a detailed line, which selectors often print whole
+fn find_selected(
+    handlers: &[&DesktopHandler],
+    names: &[String],
+    choice: &str,
+) -> Option<usize> {
+    let id = choice.split_once('\t').map_or(choice, |(id, _)| id);
+    handlers
+        .iter()
+        .position(|handler| handler.to_string() == id)
+        .or_else(|| names.iter().position(|name| name == choice))
+}
+
+/// Warn that a wildcard matched a protected mime and was not used
+/// Only the first time is warned about, so that resolving many mimes does not repeat it
+fn warn_protected(wildcard: &Mime, mime: &Mime) {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        eprintln!(
+            "warning: wildcard '{wildcard}' matches '{mime}', which handlr manages itself, so it was skipped\n\
+             Set '{mime}' exactly to change its handler"
+        )
+    });
+}
+
+/// Get the name of the mimeapps.list file for a given desktop, if any
+fn file_name(desktop: Option<&str>) -> String {
+    match desktop {
+        Some(desktop) => format!("{desktop}-mimeapps.list"),
+        None => "mimeapps.list".to_string(),
+    }
+}
+
+/// Get the lowercased names of the desktops in `$XDG_CURRENT_DESKTOP`
+#[mutants::skip] // Cannot test directly, depends on system state
+pub fn desktops_from_env() -> Vec<String> {
+    current_desktops(&std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default())
+}
+
+/// Get the lowercased names of the desktops in a colon-separated list like `$XDG_CURRENT_DESKTOP`
+fn current_desktops(var: &str) -> Vec<String> {
+    var.split(':')
+        .filter(|desktop| !desktop.is_empty())
+        .map(|desktop| desktop.to_lowercase())
+        .unique()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::SelectorArgs, utils::TempDir};
     use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
     use std::{fs::File, str::FromStr};
 
+    proptest! {
+        #[test]
+        fn desktop_list_round_trip(
+            names in prop::collection::vec("[^;\r\n]+", 0..8)
+        ) {
+            // Parsing removes duplicates, so there are none to begin with
+            let list = DesktopList(
+                names
+                    .iter()
+                    .unique()
+                    .map(|name| DesktopHandler::from_str(name))
+                    .collect::<Result<_>>()?,
+            );
+            prop_assert_eq!(DesktopList::from_str(&list.to_string())?, list);
+        }
+    }
+
     // Helper function to test serializing and deserializing mimeapps.list files
     fn mimeapps_round_trip(
         input_path: &str,
@@ -412,6 +1513,11 @@ mod tests {
         mimeapps_round_trip_simple("./tests/mimeapps_no_added.list")
     }
 
+    #[test]
+    fn mimeapps_removed_round_trip() -> Result<()> {
+        mimeapps_round_trip_simple("./tests/mimeapps_removed.list")
+    }
+
     #[test]
     fn mimeapps_no_default_round_trip() -> Result<()> {
         mimeapps_round_trip_simple("./tests/mimeapps_no_default.list")
@@ -440,6 +1546,523 @@ mod tests {
         )
     }
 
+    #[test]
+    fn mimeapps_layout_round_trip() -> Result<()> {
+        mimeapps_round_trip_simple("./tests/mimeapps_layout.list")
+    }
+
+    // Helper function that changes a few entries of the layout fixtures
+    fn change_layout(mime_apps: &mut MimeApps) -> Result<()> {
+        mime_apps.set_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+            false,
+        )?;
+        mime_apps.unset_handler(&mime::APPLICATION_PDF);
+        mime_apps.set_handler(
+            &mime::IMAGE_PNG,
+            &DesktopHandler::assume_valid("imv.desktop".into())?,
+            false,
+        )?;
+        mime_apps.remove_association(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("wine-extension-txt.desktop".into())?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mimeapps_layout_changes() -> Result<()> {
+        // Changes should only touch the lines they need to
+        mimeapps_round_trip(
+            "./tests/mimeapps_layout.list",
+            "./tests/mimeapps_layout_changed.list",
+            change_layout,
+        )
+    }
+
+    #[test]
+    fn mimeapps_crlf() -> Result<()> {
+        mimeapps_round_trip_simple("./tests/mimeapps_layout_crlf.list")?;
+        // Changed lines should keep the same line endings as everything else
+        mimeapps_round_trip(
+            "./tests/mimeapps_layout_crlf.list",
+            "./tests/mimeapps_layout_crlf_changed.list",
+            change_layout,
+        )
+    }
+
+    #[test]
+    fn mimeapps_bom() -> Result<()> {
+        mimeapps_round_trip_simple("./tests/mimeapps_layout_bom.list")?;
+        mimeapps_round_trip(
+            "./tests/mimeapps_layout_bom.list",
+            "./tests/mimeapps_layout_bom_changed.list",
+            change_layout,
+        )?;
+
+        // The byte order mark should not end up in the first line
+        let mime_apps = MimeApps::read_from(File::open(
+            "./tests/mimeapps_layout_bom.list",
+        )?)?;
+        assert_eq!(
+            mime_apps.default_apps.keys().next().map(|m| m.to_string()),
+            Some("x-scheme-handler/https".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimeapps_repeated_keys() -> Result<()> {
+        mimeapps_round_trip(
+            "./tests/mimeapps_repeated.list",
+            "./tests/mimeapps_repeated_merged.list",
+            noop,
+        )?;
+
+        let mime_apps =
+            MimeApps::read_from(File::open("./tests/mimeapps_repeated.list")?)?;
+        assert_eq!(
+            mime_apps.default_apps[&mime::TEXT_HTML].to_string(),
+            "firefox.desktop;chromium.desktop;"
+        );
+        assert_eq!(
+            mime_apps.repeated_keys,
+            vec![("Default Applications", mime::TEXT_HTML)]
+        );
+
+        Ok(())
+    }
+
+    /// Check for handlers that are usable unless they are in `missing`
+    fn installed_unless<'a>(
+        missing: &'a [&str],
+    ) -> impl Fn(&DesktopHandler) -> Result<()> + 'a {
+        |h| match missing.contains(&h.to_string().as_str()) {
+            true => Err(Error::NotFound(h.to_string())),
+            false => Ok(()),
+        }
+    }
+
+    #[test]
+    fn skip_missing_handlers() -> Result<()> {
+        let mime_apps = MimeApps::builder()
+            .default(
+                "application/pdf",
+                ["org.pwmt.zathura.desktop", "evince.desktop"],
+            )
+            .default("video/mp4", ["uninstalled.desktop"])
+            .default("video/*", ["mpv.desktop"])
+            .default("text/plain", ["helix.desktop", "nvim.desktop"])
+            .build()?;
+        let config_file = ConfigFile::default();
+
+        let installed = |h: &DesktopHandler| match h.to_string().as_str() {
+            "helix.desktop" => Err(Error::MissingTryExec {
+                handler: h.to_string(),
+                program: "hx".into(),
+            }),
+            _ => installed_unless(&[
+                "org.pwmt.zathura.desktop",
+                "uninstalled.desktop",
+            ])(h),
+        };
+        let get = |mime: &str, trace: &mut Trace| -> Result<Vec<String>> {
+            Ok(mime_apps
+                .get_handlers_from_user_with(
+                    &Mime::from_str(mime)?,
+                    &config_file,
+                    installed,
+                    |_, _| unreachable!("The selector is disabled"),
+                    |_, _| Ok(()),
+                    trace,
+                )?
+                .0
+                .iter()
+                .map(|h| h.to_string())
+                .collect())
+        };
+
+        // Missing handlers are skipped in favor of the rest of the list
+        assert_eq!(
+            get("application/pdf", &mut Trace::default())?,
+            vec!["evince.desktop"]
+        );
+        // If every handler is missing, fall back to wildcards
+        assert_eq!(
+            get("video/mp4", &mut Trace::default())?,
+            vec!["mpv.desktop"]
+        );
+
+        // Handlers whose TryExec program is missing are skipped too, and say why
+        let mut trace = Trace::enabled();
+        assert_eq!(get("text/plain", &mut trace)?, vec!["nvim.desktop"]);
+        assert_eq!(
+            trace.steps()[0],
+            "mimeapps.list: skipping helix.desktop, since its TryExec program 'hx' cannot be found"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_handlers_without_try_exec() -> Result<()> {
+        let dir = TempDir::new("try-exec")?;
+        let bin = dir.join("bin");
+        std::fs::create_dir_all(&bin)?;
+
+        let desktop_file = dir.join("app.desktop");
+        std::fs::write(
+            &desktop_file,
+            "[Desktop Entry]\nType=Application\nName=App\nTryExec=handlr-test-app\nExec=handlr-test-app %f\n",
+        )?;
+        let handler = desktop_file.to_string_lossy().into_owned();
+        let mime_apps = MimeApps::builder()
+            .default("text/plain", [handler.as_str()])
+            .build()?;
+        let config_file = ConfigFile::default();
+
+        // `bin` stands in for `$PATH`
+        let get = |trace: &mut Trace| {
+            mime_apps.get_handlers_from_user_with(
+                &Mime::from_str("text/plain")?,
+                &config_file,
+                |h| h.check_usable_in(true, bin.as_os_str()),
+                |_, _| unreachable!("The selector is disabled"),
+                |_, _| Ok(()),
+                trace,
+            )
+        };
+
+        let mut trace = Trace::enabled();
+        assert!(matches!(get(&mut trace), Err(Error::NotFound(_))));
+        assert_eq!(
+            trace.steps()[..2],
+            [
+                format!("mimeapps.list: skipping {handler}, since its TryExec program 'handlr-test-app' cannot be found"),
+                format!("mimeapps.list: 'text/plain' is set to {handler};, but all of them were skipped because of their TryExec programs"),
+            ]
+        );
+
+        // Once the program is installed, the handler is used
+        let program = bin.join("handlr-test-app");
+        std::fs::write(&program, "")?;
+        std::fs::set_permissions(
+            &program,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )?;
+        assert_eq!(
+            get(&mut Trace::default())?.0,
+            [DesktopHandler::from_str(&handler)?]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_wildcards_and_selector() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let config_file = ConfigFile {
+            enable_selector: true,
+            ..Default::default()
+        };
+
+        for (mime, handlers) in [
+            ("video/mp4", "uninstalled.desktop;"),
+            ("video/*", "totem.desktop;"),
+            ("*/*", "xdg-open.desktop;"),
+            ("video/mp*", "mpv.desktop;vlc.desktop;"),
+        ] {
+            mime_apps.default_apps.insert(
+                Mime::from_str(mime)?,
+                DesktopList::from_str(handlers)?,
+            );
+        }
+
+        let mut trace = Trace::enabled();
+        let (handlers, source) = mime_apps.get_handlers_from_user_with(
+            &Mime::from_str("video/mp4")?,
+            &config_file,
+            installed_unless(&["uninstalled.desktop"]),
+            |names, _| Ok(names[1].clone()),
+            |_, _| Ok(()),
+            &mut trace,
+        )?;
+
+        assert_eq!(source, HandlerSource::Wildcard);
+        assert_eq!(
+            handlers.iter().map(|h| h.to_string()).collect_vec(),
+            vec!["vlc.desktop", "mpv.desktop"]
+        );
+        assert_eq!(
+            trace.steps(),
+            [
+                "mimeapps.list: 'video/mp4' is set to uninstalled.desktop;, but none of them are installed",
+                "mimeapps.list: wildcards matching 'video/mp4': 'video/mp*' (length 9), 'video/*' (length 7), '*/*' (length 3)",
+                "mimeapps.list: 'video/mp*' is set to mpv.desktop;vlc.desktop;",
+                "asking the selector to choose from mpv.desktop, vlc.desktop",
+                "selected vlc.desktop",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_per_mime() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let mut config_file = ConfigFile {
+            always_ask: vec!["x-scheme-handler/http".into(), "video/*".into()],
+            never_ask: vec!["video/webm".into()],
+            ..Default::default()
+        };
+
+        for mime in ["x-scheme-handler/http", "video/mp4", "video/webm"] {
+            mime_apps.default_apps.insert(
+                Mime::from_str(mime)?,
+                DesktopList::from_str("firefox.desktop;mpv.desktop;")?,
+            );
+        }
+        mime_apps.default_apps.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("Helix.desktop;nvim.desktop;")?,
+        );
+
+        // Gets whether the selector was asked
+        let asked = |config_file: &ConfigFile, mime: &str| -> Result<bool> {
+            let asked = std::cell::Cell::new(false);
+            mime_apps.get_handlers_from_user_with(
+                &Mime::from_str(mime)?,
+                config_file,
+                |_| Ok(()),
+                |names, _| {
+                    asked.set(true);
+                    Ok(names[0].clone())
+                },
+                |_, _| Ok(()),
+                &mut Trace::default(),
+            )?;
+            Ok(asked.get())
+        };
+
+        assert!(asked(&config_file, "x-scheme-handler/http")?);
+        assert!(asked(&config_file, "video/mp4")?);
+        // `never_ask` wins over `always_ask`
+        assert!(!asked(&config_file, "video/webm")?);
+        assert!(!asked(&config_file, "text/plain")?);
+
+        config_file.enable_selector = true;
+        assert!(asked(&config_file, "text/plain")?);
+        assert!(!asked(&config_file, "video/webm")?);
+
+        // Command line flags override the config file in both directions
+        config_file.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: false,
+            disable_selector: true,
+        });
+        assert!(!asked(&config_file, "x-scheme-handler/http")?);
+
+        config_file.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: true,
+            disable_selector: false,
+        });
+        assert!(asked(&config_file, "video/webm")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remember_selection() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let config_file = ConfigFile {
+            enable_selector: true,
+            selector_persist: true,
+            ..Default::default()
+        };
+
+        for (mime, handlers) in [
+            ("video/mp4", "mpv.desktop;vlc.desktop;"),
+            ("video/*", "mpv.desktop;vlc.desktop;"),
+        ] {
+            mime_apps.default_apps.insert(
+                Mime::from_str(mime)?,
+                DesktopList::from_str(handlers)?,
+            );
+        }
+
+        let mut system = MimeApps::default();
+        system.default_apps.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("Helix.desktop;nvim.desktop;")?,
+        );
+        mime_apps.push_system_layer("/etc/xdg/mimeapps.list", system);
+
+        // Gets the mimeapps.list that would be saved, if any
+        let remembered = |mime: &str,
+                          select: &dyn Fn(Vec<String>) -> Result<String>|
+         -> Result<Option<String>> {
+            let saved = std::cell::RefCell::new(None);
+            mime_apps.get_handlers_from_user_with(
+                &Mime::from_str(mime)?,
+                &config_file,
+                |_| Ok(()),
+                |names, _| select(names),
+                |layer, handler| {
+                    let mut layer = layer.clone();
+                    if layer.prioritize_handler(&Mime::from_str(mime)?, handler)
+                    {
+                        let mut buffer = Vec::new();
+                        layer.save_to(&mut buffer)?;
+                        *saved.borrow_mut() = Some(String::from_utf8(buffer)?);
+                    }
+                    Ok(())
+                },
+                &mut Trace::default(),
+            )?;
+            Ok(saved.into_inner())
+        };
+        let second = |names: Vec<String>| Ok(names[1].clone());
+
+        assert_eq!(
+            remembered("video/mp4", &second)?,
+            Some(
+                "[Default Applications]\nvideo/mp4=vlc.desktop;mpv.desktop;\nvideo/*=mpv.desktop;vlc.desktop;\n"
+                    .into()
+            )
+        );
+        // Choosing the handler that is already the default changes nothing
+        assert_eq!(
+            remembered("video/mp4", &|names| Ok(names[0].clone()))?,
+            None
+        );
+        // Wildcards are left alone
+        assert_eq!(remembered("video/webm", &second)?, None);
+        // So are system-wide files
+        assert_eq!(remembered("text/plain", &second)?, None);
+        // And nothing is remembered when the selection is cancelled
+        assert!(matches!(
+            remembered("video/mp4", &|_| Err(Error::Cancelled)),
+            Err(Error::Cancelled)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_selector_for_one_handler() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let config_file = ConfigFile {
+            enable_selector: true,
+            ..Default::default()
+        };
+
+        mime_apps.default_apps.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str(
+                "uninstalled.desktop;Helix.desktop;Helix.desktop;",
+            )?,
+        );
+        mime_apps.default_apps.insert(
+            mime::TEXT_HTML,
+            DesktopList::from_str(
+                "firefox.desktop;uninstalled.desktop;nvim.desktop;",
+            )?,
+        );
+
+        let installed = installed_unless(&["uninstalled.desktop"]);
+        let offered = std::cell::RefCell::new(Vec::new());
+        let get = |mime: &Mime| -> Result<Vec<String>> {
+            Ok(mime_apps
+                .get_handlers_from_user_with(
+                    mime,
+                    &config_file,
+                    &installed,
+                    |names, _| {
+                        offered.borrow_mut().push(names.clone());
+                        Ok(names[1].clone())
+                    },
+                    |_, _| Ok(()),
+                    &mut Trace::default(),
+                )?
+                .0
+                .iter()
+                .map(|h| h.to_string())
+                .collect())
+        };
+
+        // Only one handler is left after filtering, so there is nothing to choose
+        assert_eq!(get(&mime::TEXT_PLAIN)?, vec!["Helix.desktop"]);
+        assert!(offered.borrow().is_empty());
+
+        // Only handlers that are left are offered
+        assert_eq!(
+            get(&mime::TEXT_HTML)?,
+            vec!["nvim.desktop", "firefox.desktop"]
+        );
+        assert_eq!(
+            *offered.borrow(),
+            vec![vec![
+                "firefox.desktop".to_string(),
+                "nvim.desktop".to_string()
+            ]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_detailed() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let config_file = ConfigFile {
+            enable_selector: true,
+            selector_detailed: true,
+            ..Default::default()
+        };
+
+        mime_apps.default_apps.insert(
+            Mime::from_str("video/mp4")?,
+            DesktopList::from_str("mpv.desktop;vlc.desktop;")?,
+        );
+
+        let mut offered = Vec::new();
+        let (handlers, _) = mime_apps.get_handlers_from_user_with(
+            &Mime::from_str("video/mp4")?,
+            &config_file,
+            |_| Ok(()),
+            |lines, _| {
+                offered = lines.clone();
+                Ok(lines[1].clone())
+            },
+            |_, _| Ok(()),
+            &mut Trace::default(),
+        )?;
+
+        // Uninstalled handlers are shown by their desktop file names
+        assert_eq!(
+            offered,
+            ["mpv.desktop\tmpv.desktop", "vlc.desktop\tvlc.desktop"]
+        );
+        assert_eq!(handlers[0].to_string(), "vlc.desktop");
+
+        // Handlers with the same name can only be told apart by their ids
+        let foo =
+            DesktopHandler::assume_valid("org.example.Foo.desktop".into())?;
+        let other =
+            DesktopHandler::assume_valid("com.other.Foo.desktop".into())?;
+        let handlers = [&foo, &other];
+        let names = ["Foo".to_owned(), "Foo".to_owned()];
+        let find = |choice| find_selected(&handlers, &names, choice);
+        assert_eq!(find("com.other.Foo.desktop\tFoo"), Some(1));
+        assert_eq!(find("com.other.Foo.desktop"), Some(1));
+        assert_eq!(find("Foo"), Some(0));
+        assert_eq!(find("Bar"), None);
+
+        Ok(())
+    }
+
     #[test]
     fn mimeapps_empty_entry_fallback() -> Result<()> {
         let file = File::open("./tests/mimeapps_empty_entry.list")?;
@@ -448,7 +2071,13 @@ mod tests {
 
         assert_eq!(
             mime_apps
-                .get_handler_from_user(&mime::TEXT_PLAIN, &config_file)?
+                .get_handlers_from_user(
+                    &mime::TEXT_PLAIN,
+                    None,
+                    &config_file,
+                    &mut Trace::default()
+                )?
+                .0[0]
                 .to_string(),
             "nvim.desktop"
         );
@@ -492,20 +2121,20 @@ mod tests {
 
         mime_apps.set_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             true,
         )?;
 
         mime_apps.set_handler(
             &Mime::from_str("application/vnd.oasis.opendocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
             true,
         )?;
 
         // This should only add video/mp4
         mime_apps.set_handler(
             &Mime::from_str("video/mp4")?,
-            &DesktopHandler::assume_valid("mpv.desktop".into()),
+            &DesktopHandler::assume_valid("mpv.desktop".into())?,
             true,
         )?;
 
@@ -523,26 +2152,26 @@ mod tests {
 
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             true,
         )?;
 
         mime_apps.add_handler(
             &Mime::from_str("application/vnd.oasis.opendocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
             true,
         )?;
 
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
             true,
         )?;
 
         // This should only add video/mp4
         mime_apps.add_handler(
             &Mime::from_str("video/mp4")?,
-            &DesktopHandler::assume_valid("mpv.desktop".into()),
+            &DesktopHandler::assume_valid("mpv.desktop".into())?,
             true,
         )?;
 
@@ -554,6 +2183,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn add_handlers_at_positions() -> Result<()> {
+        let mut mime_apps = MimeApps::default();
+        let mime = mime::TEXT_PLAIN;
+        let handler = |name: &str| {
+            DesktopHandler::assume_valid(name.into())
+                .expect("Test handler names should be valid")
+        };
+
+        mime_apps.add_handler(&mime, &handler("helix.desktop"), false)?;
+        mime_apps.add_handler(&mime, &handler("nvim.desktop"), false)?;
+        mime_apps.add_handler_front(&mime, &handler("kate.desktop"), false)?;
+        mime_apps.insert_handler(&mime, &handler("vim.desktop"), false, 1)?;
+        // Past the end
+        mime_apps.insert_handler(&mime, &handler("emacs.desktop"), false, 9)?;
+        assert_eq!(
+            mime_apps.default_apps[&mime].to_string(),
+            "kate.desktop;vim.desktop;helix.desktop;nvim.desktop;emacs.desktop;"
+        );
+
+        // Handlers that are already there are moved rather than repeated
+        mime_apps.add_handler_front(&mime, &handler("nvim.desktop"), false)?;
+        mime_apps.insert_handler(&mime, &handler("emacs.desktop"), false, 2)?;
+        assert_eq!(
+            mime_apps.default_apps[&mime].to_string(),
+            "nvim.desktop;kate.desktop;emacs.desktop;vim.desktop;helix.desktop;"
+        );
+
+        // Without a position, handlers that are already there stay where they are
+        mime_apps.add_handler(&mime, &handler("kate.desktop"), false)?;
+        assert_eq!(
+            mime_apps.default_apps[&mime].to_string(),
+            "nvim.desktop;kate.desktop;emacs.desktop;vim.desktop;helix.desktop;"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn unset_handlers_expand_wildcards() -> Result<()> {
         let mut mime_apps = MimeApps::default();
@@ -561,14 +2228,14 @@ mod tests {
         // Just add text/*
         mime_apps.set_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             false,
         )?;
 
         // Add all the non-wildcard text mimes
         mime_apps.set_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             true,
         )?;
 
@@ -593,45 +2260,449 @@ mod tests {
         Ok(())
     }
 
+    // Helper function to set up a mime with a mix of existing and missing handlers
+    fn prune_mime_test(handlers: &[&str]) -> Result<(MimeApps, PruneReport)> {
+        let mut mime_apps = MimeApps::default();
+
+        for handler in handlers {
+            mime_apps.add_handler(
+                &mime::TEXT_PLAIN,
+                &DesktopHandler::assume_valid(handler.into())?,
+                false,
+            )?;
+        }
+
+        let report = mime_apps.prune_mime_with(&mime::TEXT_PLAIN, |h| {
+            !h.to_string().starts_with("missing")
+        });
+
+        Ok((mime_apps, report))
+    }
+
+    #[test]
+    fn prune_mime_keeps_survivors() -> Result<()> {
+        let (mime_apps, report) = prune_mime_test(&[
+            "missing-editor.desktop",
+            "helix.desktop",
+            "missing-viewer.desktop",
+            "nvim.desktop",
+        ])?;
+
+        assert_eq!(
+            report,
+            PruneReport {
+                mime: "text/plain".into(),
+                removed: vec![
+                    "missing-editor.desktop".into(),
+                    "missing-viewer.desktop".into()
+                ],
+                remaining: vec!["helix.desktop".into(), "nvim.desktop".into()],
+            }
+        );
+
+        assert_eq!(
+            mime_apps.default_apps.get(&mime::TEXT_PLAIN),
+            Some(&DesktopList(
+                vec![
+                    DesktopHandler::assume_valid("helix.desktop".into())?,
+                    DesktopHandler::assume_valid("nvim.desktop".into())?
+                ]
+                .into()
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_mime_becomes_empty() -> Result<()> {
+        let (mime_apps, report) = prune_mime_test(&[
+            "missing-editor.desktop",
+            "missing-viewer.desktop",
+        ])?;
+
+        assert_eq!(report.removed.len(), 2);
+        assert!(report.remaining.is_empty());
+        assert!(!mime_apps.default_apps.contains_key(&mime::TEXT_PLAIN));
+
+        Ok(())
+    }
+
+    #[test]
+    fn clean_every_section() -> Result<()> {
+        let mut mime_apps = MimeApps::builder()
+            .default("text/plain", ["missing-editor.desktop", "helix.desktop"])
+            .default("image/png", ["missing-viewer.desktop"])
+            .default("video/mp4", ["mpv.desktop"])
+            .added("text/html", ["missing-browser.desktop", "firefox.desktop"])
+            .build()?;
+
+        let reports =
+            mime_apps.clean_with(|h| !h.to_string().starts_with("missing"));
+
+        let summary = reports
+            .iter()
+            .map(|r| {
+                (r.section, r.report.mime.as_str(), r.report.removed.len())
+            })
+            .collect_vec();
+        assert_eq!(
+            summary,
+            vec![
+                ("default_apps", "text/plain", 1),
+                ("default_apps", "image/png", 1),
+                ("added_associations", "text/html", 1),
+            ]
+        );
+
+        // Empty associations are removed entirely
+        assert!(!mime_apps.default_apps.contains_key(&mime::IMAGE_PNG));
+        assert_eq!(
+            mime_apps.default_apps[&mime::TEXT_PLAIN].to_string(),
+            "helix.desktop;"
+        );
+        assert_eq!(
+            mime_apps.added_associations[&mime::TEXT_HTML].to_string(),
+            "firefox.desktop;"
+        );
+
+        // Nothing left to clean
+        assert!(mime_apps
+            .clean_with(|h| !h.to_string().starts_with("missing"))
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder() -> Result<()> {
+        let mime_apps = MimeApps::builder()
+            .default("image/png", ["feh.desktop"])
+            .added("text/html", ["lynx.desktop", "firefox.desktop"])
+            .removed("text/html", ["chromium.desktop"])
+            .build()?;
+
+        assert_eq!(
+            mime_apps.default_apps[&mime::IMAGE_PNG].to_string(),
+            "feh.desktop;"
+        );
+        assert_eq!(
+            mime_apps.added_associations[&mime::TEXT_HTML].to_string(),
+            "lynx.desktop;firefox.desktop;"
+        );
+        assert!(mime_apps.is_removed(
+            &mime::TEXT_HTML,
+            &DesktopHandler::assume_valid("chromium.desktop".into())?
+        ));
+        // Sections are written in a fixed order, regardless of which was added first
+        assert_eq!(
+            mime_apps.to_string(),
+            "[Added Associations]\n\
+             text/html=lynx.desktop;firefox.desktop;\n\
+             \n\
+             [Removed Associations]\n\
+             text/html=chromium.desktop;\n\
+             \n\
+             [Default Applications]\n\
+             image/png=feh.desktop;\n"
+        );
+
+        // Invalid mimes are rejected like in mimeapps.list
+        assert!(MimeApps::builder()
+            .default("not a mime", ["feh.desktop"])
+            .build()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_and_display() -> Result<()> {
+        let contents = "# Comment\n[Default Applications]\ntext/plain=helix.desktop;\nvideo/mp4=\n";
+        let mime_apps = MimeApps::try_from(contents)?;
+
+        assert_eq!(
+            mime_apps.get_default_handlers(
+                &mime::TEXT_PLAIN,
+                &ConfigFile::default()
+            ),
+            Some(&DesktopList::from_str("helix.desktop;")?)
+        );
+        // The layout is kept, but empty entries are not
+        assert_eq!(
+            mime_apps.to_string(),
+            "# Comment\n[Default Applications]\ntext/plain=helix.desktop;\n"
+        );
+        assert_eq!(
+            MimeApps::try_from(mime_apps.to_string().as_str())?.to_string(),
+            mime_apps.to_string()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_missing_file() -> Result<()> {
+        let root = TempDir::new("read-missing")?;
+        let dir = root.join("handlr");
+        let path = dir.join("mimeapps.list");
+
+        let mut mime_apps = MimeApps::read_user_file(path.clone())?;
+        assert!(mime_apps.default_apps.is_empty());
+        assert_eq!(mime_apps.source(), path.display().to_string());
+        // Reading should never create anything
+        assert!(!dir.exists());
+
+        // The file and its directory are only created once something is saved
+        mime_apps.add_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
+            false,
+        )?;
+        mime_apps.save_to_path(&path, false)?;
+        assert_eq!(
+            MimeApps::read_user_file(path.clone())?.default_apps,
+            mime_apps.default_apps
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_with_backup() -> Result<()> {
+        let dir = TempDir::new("save")?;
+        let path = dir.join("mimeapps.list");
+        let backup = dir.join("mimeapps.list.bak");
+
+        let mut mime_apps = MimeApps::default();
+        mime_apps.add_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
+            false,
+        )?;
+        mime_apps.save_to_path(&path, true)?;
+        let first = std::fs::read_to_string(&path)?;
+        // There was nothing to back up yet
+        assert!(!backup.exists());
+
+        mime_apps.add_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+            false,
+        )?;
+        mime_apps.save_to_path(&path, true)?;
+        assert_eq!(std::fs::read_to_string(&backup)?, first);
+        assert!(std::fs::read_to_string(&path)?.contains("nvim.desktop"));
+
+        // Only the latest backup is kept
+        let second = std::fs::read_to_string(&path)?;
+        mime_apps.unset_handler(&mime::TEXT_PLAIN);
+        mime_apps.save_to_path(&path, true)?;
+        assert_eq!(std::fs::read_to_string(&backup)?, second);
+
+        // Nothing is backed up unless asked for
+        std::fs::remove_file(&backup)?;
+        mime_apps.save_to_path(&path, false)?;
+        assert!(!backup.exists());
+
+        // No temporary files should be left behind
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn current_desktops_from_var() {
+        assert_eq!(current_desktops("GNOME"), vec!["gnome"]);
+        assert_eq!(
+            current_desktops("ubuntu:GNOME::ubuntu"),
+            vec!["ubuntu", "gnome"]
+        );
+        assert!(current_desktops("").is_empty());
+    }
+
+    #[test]
+    fn desktop_specific_precedence() -> Result<()> {
+        let config_file = ConfigFile::default();
+        let handler = |name: &str| {
+            DesktopHandler::assume_valid(name.into())
+                .expect("Test handler names should be valid")
+        };
+        let text_markdown = Mime::from_str("text/markdown")?;
+
+        let mut mime_apps = MimeApps::default();
+        mime_apps.set_handler(
+            &text_markdown,
+            &handler("nvim.desktop"),
+            false,
+        )?;
+        mime_apps.set_handler(
+            &mime::TEXT_PLAIN,
+            &handler("nvim.desktop"),
+            false,
+        )?;
+        mime_apps.set_handler(
+            &mime::TEXT_HTML,
+            &handler("nvim.desktop"),
+            false,
+        )?;
+        mime_apps.set_handler(
+            &Mime::from_str("video/mp4")?,
+            &handler("mpv.desktop"),
+            false,
+        )?;
+
+        let mut gnome = MimeApps {
+            desktop: Some("gnome".into()),
+            ..Default::default()
+        };
+        gnome.set_handler(
+            &mime::TEXT_PLAIN,
+            &handler("org.gnome.TextEditor.desktop"),
+            false,
+        )?;
+        gnome.set_handler(
+            &Mime::from_str("text/*")?,
+            &handler("org.gnome.TextEditor.desktop"),
+            false,
+        )?;
+
+        let mut ubuntu = MimeApps {
+            desktop: Some("ubuntu".into()),
+            ..Default::default()
+        };
+        ubuntu.set_handler(
+            &mime::TEXT_HTML,
+            &handler("firefox.desktop"),
+            false,
+        )?;
+
+        mime_apps.desktop_apps = vec![ubuntu, gnome];
+
+        let get = |mime: &Mime| -> Result<String> {
+            Ok(mime_apps
+                .get_handlers_from_user(
+                    mime,
+                    None,
+                    &config_file,
+                    &mut Trace::default(),
+                )?
+                .0[0]
+                .to_string())
+        };
+
+        // The first desktop takes precedence over later ones
+        assert_eq!(get(&mime::TEXT_HTML)?, "firefox.desktop");
+        // Desktop-specific files take precedence over mimeapps.list
+        assert_eq!(get(&mime::TEXT_PLAIN)?, "org.gnome.TextEditor.desktop");
+        // Including with wildcards
+        assert_eq!(get(&text_markdown)?, "org.gnome.TextEditor.desktop");
+        // Fall back to mimeapps.list
+        assert_eq!(get(&Mime::from_str("video/mp4")?)?, "mpv.desktop");
+
+        // Desktop-specific associations are never written to mimeapps.list
+        let mut buffer = Vec::new();
+        mime_apps.save_to(&mut buffer)?;
+        assert!(!String::from_utf8(buffer)?.contains("gnome"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn system_layer_precedence() -> Result<()> {
+        let config_file = ConfigFile::default();
+
+        let mut mime_apps = MimeApps::builder()
+            .default("text/plain", ["nvim.desktop"])
+            .build()?;
+
+        let etc = MimeApps::builder()
+            .default("text/html", ["chromium.desktop"])
+            .build()?;
+
+        let usr = MimeApps::builder()
+            .default("text/plain", ["org.gnome.TextEditor.desktop"])
+            .default("text/html", ["firefox.desktop"])
+            .default("image/png", ["feh.desktop"])
+            .added("image/png", ["org.gnome.Loupe.desktop"])
+            .build()?;
+
+        mime_apps.push_system_layer("/etc/xdg/mimeapps.list", etc);
+        mime_apps
+            .push_system_layer("/usr/share/applications/mimeapps.list", usr);
+
+        let get = |mime: &Mime| -> Result<String> {
+            Ok(mime_apps
+                .get_handlers_from_user(
+                    mime,
+                    None,
+                    &config_file,
+                    &mut Trace::default(),
+                )?
+                .0[0]
+                .to_string())
+        };
+
+        // User associations take precedence over system-wide ones
+        assert_eq!(get(&mime::TEXT_PLAIN)?, "nvim.desktop");
+        // Earlier system-wide files take precedence over later ones
+        assert_eq!(get(&mime::TEXT_HTML)?, "chromium.desktop");
+        assert_eq!(get(&mime::IMAGE_PNG)?, "feh.desktop");
+        assert_eq!(
+            mime_apps.get_added_handlers(&mime::IMAGE_PNG),
+            Some(&DesktopList::from_str("org.gnome.Loupe.desktop;")?)
+        );
+
+        // System-wide associations are never written to mimeapps.list
+        let mut buffer = Vec::new();
+        mime_apps.save_to(&mut buffer)?;
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "[Default Applications]\ntext/plain=nvim.desktop;\n"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn remove_handlers_expand_wildcards() -> Result<()> {
         let mut mime_apps = MimeApps::default();
         // Just add text/*
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             false,
         )?;
 
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
             false,
         )?;
 
         // Add all the non-wildcard text mimes
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
             true,
         )?;
 
         mime_apps.add_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
             true,
         )?;
 
         // Only remove from text/*
         mime_apps.remove_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
         );
 
         assert_eq!(
             mime_apps.default_apps.get(&Mime::from_str("text/*")?),
             Some(&DesktopList(
-                vec![DesktopHandler::assume_valid("nvim.desktop".into())]
+                vec![DesktopHandler::assume_valid("nvim.desktop".into())?]
                     .into()
             ))
         );
@@ -639,7 +2710,7 @@ mod tests {
         // Remove from the rest of the text mimes
         mime_apps.remove_handler(
             &Mime::from_str("text/*")?,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
         );
 
         let mut buffer = Vec::new();
@@ -1,10 +1,10 @@
-use std::fmt::Write;
+use std::{fmt::Write, path::PathBuf};
 
 use crate::{
-    apps::SystemApps,
+    apps::{desktops_from_env, MimeApps, SystemApps},
     common::{mime_types, DesktopHandler, MimeOrExtension, UserPath},
 };
-use clap::{builder::StyledStr, Args, Parser};
+use clap::{builder::StyledStr, Args, Parser, ValueEnum};
 use clap_complete::{
     engine::{ArgValueCompleter, CompletionCandidate},
     PathCompleter,
@@ -57,14 +57,53 @@ pub enum Cmd {
     /// }
     ///
     /// Where each top-level key has an array with the same scheme as the normal `--json` output
+    ///
+    /// When using `--orphans`, installed apps that support at least one mimetype,
+    /// but are not associated with any mimetype in mimeapps.list, are listed instead,
+    /// along with the mimetypes they support that are currently opened with other handlers
+    ///
+    /// When using `--handler`, every mimetype associated with the given handler is listed instead,
+    /// along with where each association comes from (`default`, `wildcard`, `added association`, or `system`),
+    /// the file it is in, and whether the mimetype currently opens with the handler.
+    /// Wildcards are listed as they are, unless `--expand` is used.
     #[clap(verbatim_doc_comment)]
     List {
+        /// Only list mimetypes matching this pattern
+        ///
+        /// Patterns containing `*` or `?` must match the whole mimetype, e.g. `video/*`,
+        /// anything else only has to be part of it, e.g. `text`. Case is ignored.
+        #[clap(conflicts_with_all = ["orphans", "handler", "handlers_of"])]
+        pattern: Option<String>,
         /// Output handler info as json
         #[clap(long)]
         json: bool,
         /// Expand wildcards in mimetypes and show global defaults
         #[clap(long, short)]
         all: bool,
+        /// List installed apps that no association points to
+        #[clap(long, conflicts_with = "all")]
+        orphans: bool,
+        /// Only show the desktop file ids of handlers, not their names
+        #[clap(long, conflicts_with_all = ["orphans", "handler", "handlers_of"])]
+        no_names: bool,
+        /// List the mimetypes associated with the given handler
+        #[clap(
+            long,
+            conflicts_with_all = ["all", "orphans"],
+            add = ArgValueCompleter::new(autocomplete_desktop_files)
+        )]
+        handler: Option<DesktopHandler>,
+        /// Replace wildcards with every mimetype they match when using `--handler`
+        #[clap(long, requires = "handler")]
+        expand: bool,
+        /// Include installed apps that are hidden or not meant for the current desktop
+        #[clap(long)]
+        include_hidden: bool,
+        /// List the handlers associated with a mime in mimeapps.list as `handler<TAB>name`, for completions
+        #[clap(long, hide = true, conflicts_with_all = ["all", "orphans", "handler", "json"])]
+        handlers_of: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Open a path/URL with its default handler
@@ -74,12 +113,71 @@ pub enum Cmd {
     /// If multiple handlers are set and `enable_selector` is set to true,
     /// you will be prompted to select one using `selector` from ~/.config/handlr/handlr.toml.
     /// Otherwise, the default handler will be opened.
+    ///
+    /// If any path's mime is in `confirm_mimes`, you will be asked to confirm before anything is opened.
+    ///
+    /// Paths that do not exist but contain `*`, `?`, or `[` are expanded like glob patterns,
+    /// for when they were not expanded by a shell.
     Open {
         /// Paths/URLs to open
         #[clap(required = true, add=ArgValueCompleter::new(PathCompleter::any()))]
         paths: Vec<UserPath>,
         #[command(flatten)]
         selector_args: SelectorArgs,
+        /// Open without asking for confirmation, even for mimes in `confirm_mimes`
+        #[clap(long, short, visible_alias = "no-confirm")]
+        yes: bool,
+        /// Tag the launched handlers so that window manager rules can target them
+        ///
+        /// The tag is exported as `HANDLR_TAG`, and terminal emulators are given the arguments in `tag_args`
+        #[clap(long)]
+        tag: Option<String>,
+        /// Open the URL of handlers that are Link desktop entries instead of failing
+        #[clap(long)]
+        follow_links: bool,
+        /// Never treat paths that do not exist as glob patterns
+        #[clap(long)]
+        no_glob: bool,
+        /// Open paths and URLs as many times as they are given
+        ///
+        /// Otherwise, only the first of the same files or URLs is opened
+        #[clap(long)]
+        keep_duplicates: bool,
+        /// Open every path with the given handler instead of the ones set for them
+        #[clap(long, add = ArgValueCompleter::new(autocomplete_desktop_files))]
+        with: Option<DesktopHandler>,
+        /// Run this command on paths that no handler can be found for, instead of failing
+        ///
+        /// Like a desktop entry's `Exec`, it is run once per path with `%f` or `%u`,
+        /// and is otherwise given every path at once, appended to the end if it has no field codes
+        #[clap(long, value_name = "COMMAND", conflicts_with = "with")]
+        if_no_handler: Option<String>,
+        /// Wait for handlers to exit and send a notification when each one does
+        ///
+        /// handlr keeps running until then and fails with the worst exit code of those that fail
+        #[clap(long)]
+        notify_done: bool,
+        /// Wait for handlers to exit instead of detaching from them
+        ///
+        /// handlr keeps running until then and fails with the worst exit code of those that fail
+        #[clap(long)]
+        wait: bool,
+        /// Print each handler followed by the paths it would be given, separated by tabs, without opening anything
+        ///
+        /// Fails if any path has no handler, after printing the rest.
+        #[clap(long)]
+        dry_run: bool,
+        /// Run this action from the handlers' desktop files, e.g. `new-window`, instead of their main command
+        ///
+        /// `handlr info` lists a handler's actions
+        #[clap(long)]
+        action: Option<String>,
+        /// Make the handler chosen with the selector the default for the mime, like `selector_persist`
+        #[clap(long)]
+        remember: bool,
+        /// Fall back to installed apps that are hidden or not meant for the current desktop
+        #[clap(long)]
+        include_hidden: bool,
     },
 
     /// Set the default handler for mime/extension
@@ -90,16 +188,39 @@ pub enum Cmd {
     /// When `expand_wildcards` is true in `~/.config/handlr/handlr.toml`,
     /// wildcards will be expanded into matching mimes rather than added verbatim
     ///
-    /// File extensions are converted into their respective mimetypes in mimeapps.list.
+    /// File extensions (anything starting with `.`) are converted into their respective mimetypes in mimeapps.list,
+    /// and the mimetype used for each one is printed.
+    ///
+    /// Several mimetypes/extensions can be given at once, followed by the handler,
+    /// in which case they are all set before mimeapps.list is saved.
+    ///
+    /// If only a single mimetype/extension is given, the handler is picked from the installed applications that support it,
+    /// with a numbered prompt in a terminal and with `selector` otherwise.
+    ///
+    /// With `--from-command`, every argument is a mimetype/extension
+    /// and the handler is the application whose desktop file runs the given command.
     ///
     /// Currently does not support regex handlers.
     Set {
-        /// Mimetype or file extension to operate on.
-        #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
-        mime: MimeOrExtension,
-        /// Desktop file of handler program
-        #[clap(add = ArgValueCompleter::new(autocomplete_desktop_files))]
-        handler: DesktopHandler,
+        /// Mimetypes or file extensions to operate on, followed by the desktop file of the handler program
+        #[clap(
+            required = true,
+            value_name = "MIMES_AND_HANDLER",
+            add = ArgValueCompleter::new(autocomplete_mimes_and_desktop_files)
+        )]
+        args: Vec<String>,
+        /// Use the application that runs the given command (e.g. `vlc`) as the handler
+        #[clap(long, value_name = "COMMAND")]
+        from_command: Option<String>,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+        /// Write mime aliases (e.g. `application/x-pdf`) as given rather than replacing them with their canonical mime
+        #[clap(long)]
+        raw: bool,
     },
 
     /// Unset the default handler for mime/extension
@@ -114,6 +235,12 @@ pub enum Cmd {
         /// Mimetype or file extension to unset the default handler of
         #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
         mime: MimeOrExtension,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
     },
 
     /// Launch the handler for specified extension/mime with optional arguments
@@ -125,14 +252,55 @@ pub enum Cmd {
     /// Otherwise, the default handler will be opened.
     Launch {
         /// Mimetype or file extension to launch the handler of
-        #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
-        mime: MimeOrExtension,
+        ///
+        /// Treated as the first argument to pass instead when `--regex` is given
+        #[clap(
+            required_unless_present = "regex",
+            add = ArgValueCompleter::new(autocomplete_mimes)
+        )]
+        mime: Option<String>,
+        /// Launch the regex handler with this `name` from ~/.config/handlr/handlr.toml instead,
+        /// regardless of what the arguments are
+        #[clap(long, value_name = "NAME")]
+        regex: Option<String>,
         /// Arguments to pass to handler program
         // Not necessarily a path, but completing as a path tends to be the expected "default" behavior
         #[clap(add=ArgValueCompleter::new(PathCompleter::any()))]
         args: Vec<String>,
         #[command(flatten)]
         selector_args: SelectorArgs,
+        /// Tag the launched handlers so that window manager rules can target them
+        ///
+        /// The tag is exported as `HANDLR_TAG`, and terminal emulators are given the arguments in `tag_args`
+        #[clap(long)]
+        tag: Option<String>,
+        /// Open the URL of handlers that are Link desktop entries instead of failing
+        #[clap(long)]
+        follow_links: bool,
+        /// Wait for the handler to exit and send a notification when it does
+        ///
+        /// handlr keeps running until then and fails with the handler's exit code if it fails
+        #[clap(long)]
+        notify_done: bool,
+        /// Wait for the handler to exit instead of detaching from it
+        ///
+        /// handlr keeps running until then and fails with the handler's exit code if it fails
+        #[clap(long)]
+        wait: bool,
+        /// Print the handler followed by the arguments it would be given, separated by tabs, without launching it
+        #[clap(long)]
+        dry_run: bool,
+        /// Run this action from the handler's desktop file, e.g. `new-private-window`, instead of its main command
+        ///
+        /// `handlr info` lists a handler's actions
+        #[clap(long)]
+        action: Option<String>,
+        /// Make the handler chosen with the selector the default for the mime, like `selector_persist`
+        #[clap(long)]
+        remember: bool,
+        /// Fall back to installed apps that are hidden or not meant for the current desktop
+        #[clap(long)]
+        include_hidden: bool,
     },
 
     /// Get handler for this mime/extension
@@ -143,14 +311,29 @@ pub enum Cmd {
     ///
     /// Note that regex handlers are not supported by this subcommand currently.
     ///
+    /// With `--all`, every handler that could be used is listed in priority order instead,
+    /// along with the mime it is associated with (which may be a parent of the given one)
+    /// and where it comes from: `default`, `wildcard`, `added association`, `system`, or `file manager`.
+    /// The selector is never used.
+    ///
+    /// With `--trace`, each step taken to find the handler is printed to stderr,
+    /// e.g. which mimes and wildcards were tried and whether the selector was used.
+    ///
     /// When using `--json`, output is in the form:
     ///
     /// {
     ///   "cmd": "helix",
     ///   "handler": "helix.desktop",
-    ///   "name": "Helix"
+    ///   "keywords": [
+    ///     "Text",
+    ///     "editor"
+    ///   ],
+    ///   "name": "Helix",
+    ///   "source": "default"
     /// }
     ///
+    /// where "source" is one of `default`, `wildcard`, `added_association`, `system`, or `file_manager`.
+    ///
     /// Note that when handlr is not being directly output to a terminal, and the handler is a terminal program,
     /// the "cmd" key in the json output will include the command of the `x-scheme-handler/terminal` handler.
     #[clap(verbatim_doc_comment)]
@@ -158,11 +341,25 @@ pub enum Cmd {
         /// Output handler info as json
         #[clap(long)]
         json: bool,
+        /// List every handler that could be used and where it comes from
+        #[clap(long, short)]
+        all: bool,
+        /// Print the steps taken to find the handler to stderr
+        #[clap(long, conflicts_with = "all")]
+        trace: bool,
         /// Mimetype to get the handler of
         #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
         mime: MimeOrExtension,
         #[command(flatten)]
         selector_args: SelectorArgs,
+        /// Make the handler chosen with the selector the default for the mime, like `selector_persist`
+        #[clap(long, conflicts_with = "all")]
+        remember: bool,
+        /// Fall back to installed apps that are hidden or not meant for the current desktop
+        #[clap(long)]
+        include_hidden: bool,
+        #[command(flatten)]
+        output: OutputArgs,
     },
 
     /// Add a handler for given mime/extension
@@ -174,28 +371,243 @@ pub enum Cmd {
     ///
     /// This subcommand adds secondary handlers that coexist with the default
     /// and does not overwrite existing handlers.
+    /// With `--prepend` or `--position`, the handler can be put before them instead.
+    /// Without them, a handler that is already there stays where it is.
+    ///
+    /// Like with `set`, several mimetypes/extensions can be given at once, followed by the handler.
+    /// With `--select`, the handler is picked from the installed applications if only a single mimetype/extension is given,
+    /// and `--from-command` finds the handler by the command it runs.
     Add {
-        /// Mimetype to add handler to
-        #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
-        mime: MimeOrExtension,
-        /// Desktop file of handler program
-        #[clap(add = ArgValueCompleter::new(autocomplete_desktop_files))]
-        handler: DesktopHandler,
+        /// Mimetypes or file extensions to add handler to, followed by the desktop file of the handler program
+        #[clap(
+            required = true,
+            value_name = "MIMES_AND_HANDLER",
+            add = ArgValueCompleter::new(autocomplete_mimes_and_desktop_files)
+        )]
+        args: Vec<String>,
+        /// Use the application that runs the given command (e.g. `vlc`) as the handler
+        #[clap(long, value_name = "COMMAND")]
+        from_command: Option<String>,
+        /// Add the handler before the existing ones, making it the default while keeping them as fallbacks
+        #[clap(long, conflicts_with = "position")]
+        prepend: bool,
+        /// Add the handler at the given index, counting from 0, instead of after the existing ones
+        ///
+        /// A handler that is already there is moved to that index.
+        #[clap(long, value_name = "N")]
+        position: Option<usize>,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+        /// Write mime aliases (e.g. `application/x-pdf`) as given rather than replacing them with their canonical mime
+        #[clap(long)]
+        raw: bool,
+        /// Pick the handler from the installed applications that support the mime when none is given
+        #[clap(long, conflicts_with = "from_command")]
+        select: bool,
     },
 
     /// Remove a given handler from a given mime/extension
     ///
-    /// Note that if a handler is not supplied,
-    ///
     /// Literal wildcards (e.g. `text/*`) will be favored over matching mimetypes if present.
     /// Otherwise, mimes matching wildcards (e.g. `text/plain`, etc.) will have their handlers removed.
+    ///
+    /// With `--missing`, every handler whose desktop file cannot be found is removed instead.
+    /// If no handlers remain, the association is removed entirely.
+    ///
+    /// With `--system`, the handler is instead added to the mime's removed associations,
+    /// so that it is no longer used from added associations or system-level desktop entries.
     Remove {
         /// Mimetype to remove handler from
         #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
         mime: MimeOrExtension,
         /// Desktop file of handler program to remove
-        #[clap(add = ArgValueCompleter::new(autocomplete_desktop_files))]
+        #[clap(
+            required_unless_present = "missing",
+            add = ArgValueCompleter::new(autocomplete_associated_handlers)
+        )]
+        handler: Option<DesktopHandler>,
+        /// Remove all handlers whose desktop files cannot be found
+        #[clap(long, conflicts_with = "handler")]
+        missing: bool,
+        /// Hide the handler from added and system associations by writing it to `[Removed Associations]`
+        #[clap(long, requires = "handler")]
+        system: bool,
+        /// Report what would be removed without changing mimeapps.list
+        #[clap(long, requires = "missing")]
+        dry_run: bool,
+        /// Output report as json
+        #[clap(long, requires = "missing")]
+        json: bool,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+
+    /// Move a handler within a mime's list of handlers, changing which one is preferred
+    ///
+    /// The handlers are printed in their new order afterwards, one per line.
+    Reorder {
+        /// Mimetype or file extension whose handlers to reorder
+        #[clap(add = ArgValueCompleter::new(autocomplete_mimes))]
+        mime: MimeOrExtension,
+        /// Desktop file of handler program to move
+        #[clap(add = ArgValueCompleter::new(autocomplete_associated_handlers))]
         handler: DesktopHandler,
+        /// Move the handler to the given index, counting from 0
+        #[clap(
+            long,
+            value_name = "N",
+            required_unless_present_any = ["up", "down"],
+            conflicts_with_all = ["up", "down"]
+        )]
+        to: Option<usize>,
+        /// Move the handler one place earlier
+        #[clap(long, conflicts_with = "down")]
+        up: bool,
+        /// Move the handler one place later
+        #[clap(long)]
+        down: bool,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+
+    /// Remove every handler whose desktop file cannot be found from mimeapps.list
+    ///
+    /// Both default apps and added associations are cleaned.
+    /// Associations that end up with no handlers are removed entirely.
+    ///
+    /// When using `--json`, output will be in the form:
+    ///
+    /// [
+    ///   {
+    ///     "section": "default_apps",
+    ///     "mime": "application/pdf",
+    ///     "removed": [
+    ///       "org.pwmt.zathura.desktop"
+    ///     ],
+    ///     "remaining": [
+    ///       "org.gnome.Evince.desktop"
+    ///     ]
+    ///   },
+    ///   ...
+    /// ]
+    #[clap(verbatim_doc_comment)]
+    Clean {
+        /// Report what would be removed without changing mimeapps.list
+        #[clap(long)]
+        dry_run: bool,
+        /// Output report as json
+        #[clap(long)]
+        json: bool,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Set default handlers from a file, e.g. one written by `export`
+    ///
+    /// The file maps mimes to lists of handlers, in order of preference:
+    ///
+    /// [default_apps]
+    /// "text/plain" = ["helix.desktop", "nvim.desktop"]
+    ///
+    /// It can also contain regex handlers like in `~/.config/handlr/handlr.toml`, which are checked but not imported.
+    /// Every mime and handler is checked before anything is changed, and every problem is reported at once.
+    Import {
+        /// File to read, or `-` for stdin
+        #[clap(add = ArgValueCompleter::new(PathCompleter::file()))]
+        file: PathBuf,
+        /// Keep the handlers of mimes that are not in the file (the default)
+        #[clap(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Remove the handlers of mimes that are not in the file
+        #[clap(long)]
+        replace: bool,
+        /// Write to the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+        /// Do not warn about potential problems with mimeapps.list after changing it
+        #[clap(long)]
+        no_lint: bool,
+    },
+
+    /// Write the default handlers and regex handlers in the form read by `import`
+    ///
+    /// Importing the output again changes nothing.
+    Export {
+        /// Read the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+    },
+
+    /// Check whether the default handlers have drifted from a file in the form read by `import`
+    ///
+    /// Nothing is changed. Each line starts with `+` for a mime that importing the file would add,
+    /// `-` for one it would remove (only with `--replace`), or `~` for one whose handlers it would change or reorder.
+    /// handlr exits with 0 if everything matches and 1 if anything differs.
+    Status {
+        /// File to compare against, or `-` for stdin
+        #[clap(
+            long,
+            value_name = "FILE",
+            add = ArgValueCompleter::new(PathCompleter::file())
+        )]
+        check: PathBuf,
+        /// Only compare the mimes in the file (the default)
+        #[clap(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Also report mimes that are not in the file, which `import --replace` would remove
+        #[clap(long)]
+        replace: bool,
+        /// Output the differences as json
+        #[clap(long)]
+        json: bool,
+        /// Read the mimeapps.list for the current desktop (e.g. `gnome-mimeapps.list`)
+        #[clap(long)]
+        desktop: bool,
+    },
+
+    /// Set the default handler for a custom URL scheme (e.g. `myapp://`)
+    ///
+    /// If given a command rather than a desktop file, a desktop file that runs the command
+    /// with the URL is generated in `$XDG_DATA_HOME/applications` and used as the handler.
+    ///
+    /// Registering the same scheme again is safe and replaces the previous registration.
+    RegisterScheme {
+        /// URL scheme to register, without `://`
+        scheme: String,
+        /// Command to open URLs with, or the desktop file of a handler program
+        handler: String,
+        /// Run `update-desktop-database` afterwards so other programs see the change
+        #[clap(long)]
+        update_database: bool,
+    },
+
+    /// Undo `register-scheme`
+    ///
+    /// Removes the default handler for the URL scheme along with any desktop file generated for it.
+    UnregisterScheme {
+        /// URL scheme to unregister, without `://`
+        scheme: String,
+        /// Run `update-desktop-database` afterwards so other programs see the change
+        #[clap(long)]
+        update_database: bool,
     },
 
     /// Get the mimetype of a given file/URL
@@ -215,6 +627,12 @@ pub enum Cmd {
     ///   },
     /// ...
     /// ]
+    ///
+    /// With `--provenance`, each object also says how a file's mime was detected:
+    /// "extension" is what its extension was considered to be,
+    /// "matched_glob" and "glob_weight" are the shared-mime-info pattern that matched its name and that pattern's weight,
+    /// and "sniffed" is whether its contents were used because its name was not enough.
+    /// Table output is unchanged.
     #[clap(verbatim_doc_comment)]
     Mime {
         /// File paths/URLs to get the mimetype of
@@ -223,13 +641,191 @@ pub enum Cmd {
         /// Output mimetype info as json
         #[clap(long)]
         json: bool,
+        /// Include how each mime was detected in json output
+        #[clap(long)]
+        provenance: bool,
+        #[command(flatten)]
+        output: OutputArgs,
     },
+
+    /// Check mimeapps.list and the config file for problems without changing anything
+    ///
+    /// Reports handlers whose desktop files or programs cannot be found, invalid mimes,
+    /// handlers listed more than once for a mime, and regex handlers that do not compile.
+    /// Exits with 1 if any problem is an error rather than a warning.
+    /// `handlr clean` removes handlers that cannot be found.
+    ///
+    /// Works even when the config file or mimeapps.list cannot otherwise be loaded.
+    Doctor {
+        /// Output problems as json
+        #[clap(long)]
+        json: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// List installed apps and the details of their desktop entries
+    ///
+    /// When using `--json`, output will be in the form:
+    ///
+    /// [
+    ///   {
+    ///     "handler": "Helix.desktop",
+    ///     "name": "Helix",
+    ///     "generic_name": "Text Editor",
+    ///     "icon": "helix",
+    ///     "terminal": true,
+    ///     "mime_type": [
+    ///       "text/plain",
+    ///       ...
+    ///     ]
+    ///   },
+    ///   ...
+    /// ]
+    ///
+    /// `generic_name` and `icon` are null when the desktop entry does not set them.
+    /// Desktop file names that are not valid UTF-8 have their invalid parts replaced with `�`.
+    #[clap(verbatim_doc_comment)]
+    Apps {
+        /// Output apps as json
+        #[clap(long)]
+        json: bool,
+        /// Include apps that are hidden or not meant for the current desktop
+        #[clap(long)]
+        include_hidden: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Print the command that would be run to open a path/URL, without running it
+    ///
+    /// The handler is found the same way as with `open`, including regex handlers and the selector.
+    ///
+    /// When using `--json`, output will be in the form:
+    ///
+    /// {
+    ///   "handler": "org.pwmt.zathura.desktop",
+    ///   "program": "zathura",
+    ///   "args": [
+    ///     "/home/user/doc.pdf"
+    ///   ]
+    /// }
+    #[clap(verbatim_doc_comment)]
+    Which {
+        /// Path/URL to find the command for
+        #[clap(add = ArgValueCompleter::new(PathCompleter::any()))]
+        path: UserPath,
+        #[command(flatten)]
+        selector_args: SelectorArgs,
+        /// Show the command of this action from the handler's desktop file, e.g. `new-window`, instead of its main command
+        #[clap(long)]
+        action: Option<String>,
+        /// Output the command as json
+        #[clap(long)]
+        json: bool,
+        /// Print the steps taken to find the handler to stderr
+        #[clap(long)]
+        trace: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Test a path/URL against every regex handler to see which one would be used
+    ///
+    /// Lists each pattern of each handler in the order they are in ~/.config/handlr/handlr.toml,
+    /// whether it matches, and whether its handler is the one that would be used,
+    /// i.e. the one with the highest `priority` whose `regexes` match and whose `exclude_regexes` do not.
+    ///
+    /// When using `--json`, output will be in the form:
+    ///
+    /// [
+    ///   {
+    ///     "handler": 0,
+    ///     "name": "youtube",
+    ///     "priority": 0,
+    ///     "field": "regexes",
+    ///     "pattern": "youtu\\.be",
+    ///     "matched": true,
+    ///     "used": true
+    ///   },
+    ///   ...
+    /// ]
+    #[clap(verbatim_doc_comment)]
+    TestRegex {
+        /// Path/URL to test
+        #[clap(add = ArgValueCompleter::new(PathCompleter::any()))]
+        path: UserPath,
+        /// Output as json
+        #[clap(long)]
+        json: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Show a handler's desktop entry as handlr sees it
+    ///
+    /// Lists the path of its desktop file, its name, its `Exec` key and what handlr does with each field code in it,
+    /// whether it runs in a terminal, whether handlr considers it a terminal emulator, the mimetypes it supports,
+    /// the actions that can be run with `--action`, and the mimetypes in mimeapps.list that currently open with it.
+    /// If the desktop file cannot be found, the directories it was looked for in are listed.
+    Info {
+        /// Desktop file of the handler
+        #[clap(add = ArgValueCompleter::new(autocomplete_desktop_files))]
+        handler: DesktopHandler,
+        /// Output info as json
+        #[clap(long)]
+        json: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Print a shell completion script to stdout
+    ///
+    /// The script calls back into handlr as `COMPLETE=<shell> handlr -- <args>` to get completions,
+    /// so mimes and desktop files are always up to date.
+    /// This is the same script printed by `COMPLETE=<shell> handlr`.
+    Completions {
+        /// Shell to generate the script for
+        shell: CompletionShell,
+    },
+}
+
+impl Cmd {
+    /// Get the arguments that override the configured selector, for the commands that take them
+    pub fn selector_args(&self) -> Option<&SelectorArgs> {
+        match self {
+            Self::Open { selector_args, .. }
+            | Self::Launch { selector_args, .. }
+            | Self::Get { selector_args, .. }
+            | Self::Which { selector_args, .. } => Some(selector_args),
+            _ => None,
+        }
+    }
+
+    /// Get whether `--remember` was given, for the commands that can remember selections
+    /// Dry runs never remember anything
+    pub fn remember(&self) -> Option<bool> {
+        match self {
+            Self::Open {
+                remember,
+                dry_run: false,
+                ..
+            }
+            | Self::Launch {
+                remember,
+                dry_run: false,
+                ..
+            }
+            | Self::Get { remember, .. } => Some(*remember),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Args)]
 pub struct SelectorArgs {
     /// Override the configured selector command
-    #[clap(long, short)]
+    #[clap(long, short, add = ArgValueCompleter::new(autocomplete_selectors))]
     pub selector: Option<String>,
     /// Enable selector, overrides `enable_selector`
     #[clap(long, short)]
@@ -240,6 +836,94 @@ pub struct SelectorArgs {
     pub disable_selector: bool,
 }
 
+#[derive(Clone, Args)]
+pub struct OutputArgs {
+    /// Output format, overrides `--json`
+    ///
+    /// Defaults to `table` when writing to a terminal and `tsv` otherwise.
+    /// `tsv` and `csv` have a header line and one line per row, with no padding or decoration
+    #[clap(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// Output JSON Lines, shorthand for `--format jsonl`
+    #[clap(long, conflicts_with = "format")]
+    pub jsonl: bool,
+    /// Draw tables without borders, regardless of `[table]` in ~/.config/handlr/handlr.toml
+    #[clap(long)]
+    pub plain: bool,
+    /// Write output to the given file instead of stdout, `-` means stdout
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+/// Shells that completion scripts can be generated for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Elvish,
+    Powershell,
+}
+
+impl CompletionShell {
+    /// Get the name `clap_complete` knows the shell by
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::Elvish => "elvish",
+            Self::Powershell => "powershell",
+        }
+    }
+}
+
+/// Formats for command output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable table
+    Table,
+    /// JSON
+    Json,
+    /// JSON Lines, with one object written per line as soon as it is ready
+    Jsonl,
+    /// Tab-separated values
+    Tsv,
+    /// Comma-separated values, quoted where needed
+    Csv,
+}
+
+impl OutputFormat {
+    /// Check if the format is either kind of JSON
+    pub fn is_json(self) -> bool {
+        matches!(self, Self::Json | Self::Jsonl)
+    }
+}
+
+impl OutputArgs {
+    /// Get the output format to use
+    /// If none was explicitly given, decide based on `--json` and where output is going
+    pub fn format(&self, json: bool, terminal_output: bool) -> OutputFormat {
+        self.format.unwrap_or(if self.jsonl {
+            OutputFormat::Jsonl
+        } else if json {
+            OutputFormat::Json
+        } else if terminal_output && self.is_stdout() {
+            OutputFormat::Table
+        } else {
+            OutputFormat::Tsv
+        })
+    }
+
+    /// Check if output is going to stdout rather than a file
+    pub fn is_stdout(&self) -> bool {
+        match &self.output {
+            Some(path) => path.as_os_str() == "-",
+            None => true,
+        }
+    }
+}
+
 /// Generate candidates for mimes and file extensions to use
 #[mutants::skip] // TODO: figure out how to test with golden tests
 fn autocomplete_mimes(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
@@ -259,12 +943,36 @@ fn autocomplete_mimes(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
 fn autocomplete_desktop_files(
     current: &std::ffi::OsStr,
 ) -> Vec<CompletionCandidate> {
-    SystemApps::get_entries()
+    desktop_file_candidates(current, &[])
+}
+
+/// Generate candidates for desktop files that support any of the given mimes or extensions,
+/// or for every desktop file if none are given or none support them
+#[mutants::skip] // Cannot test directly, relies on system state
+fn desktop_file_candidates(
+    current: &std::ffi::OsStr,
+    mimes: &[String],
+) -> Vec<CompletionCandidate> {
+    let desktops = desktops_from_env();
+    let (supported, unsupported): (Vec<_>, Vec<_>) = SystemApps::get_entries()
         .expect("Could not get system desktop entries")
-        .filter(|(path, _)| {
-            path.to_string_lossy()
-                .starts_with(current.to_string_lossy().as_ref())
+        .filter(|(path, entry)| {
+            entry.is_application()
+                && entry.is_shown_in(&desktops)
+                && path
+                    .to_string_lossy()
+                    .starts_with(current.to_string_lossy().as_ref())
         })
+        .partition(|(_, entry)| mimes.iter().any(|mime| entry.supports(mime)));
+
+    let entries = if supported.is_empty() {
+        unsupported
+    } else {
+        supported
+    };
+
+    entries
+        .into_iter()
         .map(|(path, entry)| {
             let mut name = StyledStr::new();
             write!(name, "{}", entry.name)
@@ -273,3 +981,80 @@ fn autocomplete_desktop_files(
         })
         .collect()
 }
+
+/// Selector commands known to work with handlr, offered when their programs are installed
+const SELECTORS: &[&str] = &[
+    "rofi -dmenu -i -p 'Open With: '",
+    "fuzzel --dmenu",
+    "wofi --dmenu",
+    "tofi",
+    "bemenu",
+    "dmenu",
+    "fzf",
+];
+
+/// Generate candidates for selector commands whose programs can be found in `$PATH`
+#[mutants::skip] // Cannot test directly, relies on system state
+fn autocomplete_selectors(
+    current: &std::ffi::OsStr,
+) -> Vec<CompletionCandidate> {
+    let search_path = std::env::var_os("PATH").unwrap_or_default();
+    SELECTORS
+        .iter()
+        .filter(|selector| {
+            selector.starts_with(current.to_string_lossy().as_ref())
+        })
+        .filter(|selector| {
+            let program = selector.split(' ').next().unwrap_or_default();
+            std::env::split_paths(&search_path)
+                .any(|dir| dir.join(program).is_file())
+        })
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Generate candidates for the arguments of `handlr set` and `handlr add`, which can be either mimes or desktop files
+/// Once a mime has been given, only desktop files that support it are offered, if there are any
+#[mutants::skip] // Cannot test directly, relies on system state
+fn autocomplete_mimes_and_desktop_files(
+    current: &std::ffi::OsStr,
+) -> Vec<CompletionCandidate> {
+    // The mimes have not been parsed yet, so find them among the arguments being completed,
+    // leaving out the one being completed
+    let mut mimes = std::env::args()
+        .skip_while(|arg| arg != "set" && arg != "add")
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect::<Vec<_>>();
+    mimes.pop();
+
+    let mut candidates = autocomplete_mimes(current);
+    candidates.extend(desktop_file_candidates(current, &mimes));
+    candidates
+}
+
+/// Generate candidates for the handlers associated with the mime given to `handlr remove`
+#[mutants::skip] // Cannot test directly, relies on system state
+fn autocomplete_associated_handlers(
+    current: &std::ffi::OsStr,
+) -> Vec<CompletionCandidate> {
+    // The mime has not been parsed yet, so find it among the arguments being completed
+    let Some(mime) = std::env::args()
+        .skip_while(|arg| arg != "remove" && arg != "reorder")
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+    else {
+        return Vec::new();
+    };
+
+    MimeApps::describe_user_handlers(&mime)
+        .into_iter()
+        .filter(|(handler, _)| {
+            handler.starts_with(current.to_string_lossy().as_ref())
+        })
+        .map(|(handler, description)| {
+            CompletionCandidate::new(handler)
+                .help(Some(StyledStr::from(description)))
+        })
+        .collect()
+}
@@ -0,0 +1,266 @@
+use crate::{
+    common::{DesktopEntry, ExecMode},
+    error::{Error, Result},
+};
+use std::process::{Command, Stdio};
+use url::Url;
+
+/// A session bus that applications implementing `org.freedesktop.Application` can be reached on
+pub trait SessionBus {
+    /// Check whether something currently owns the given bus name
+    fn name_has_owner(&self, name: &str) -> Result<bool>;
+    /// Call a method of `org.freedesktop.Application` with the given arguments,
+    /// which are given in GVariant text format
+    fn call(&self, name: &str, method: &str, args: &[String]) -> Result<()>;
+}
+
+/// The user's session bus, reached through `gdbus`
+pub struct Gdbus;
+
+impl Gdbus {
+    /// Run `gdbus call` on the session bus and get its output
+    #[mutants::skip] // Cannot test directly, runs external command
+    fn call_raw(
+        dest: &str,
+        object_path: &str,
+        method: &str,
+        args: &[String],
+    ) -> Result<String> {
+        let output = Command::new("gdbus")
+            .args(["call", "--session", "--dest", dest])
+            .args(["--object-path", object_path, "--method", method])
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(Error::BadCmd(format!("gdbus call --dest {dest} {method}")))
+        }
+    }
+}
+
+impl SessionBus for Gdbus {
+    #[mutants::skip] // Cannot test directly, depends on system state
+    fn name_has_owner(&self, name: &str) -> Result<bool> {
+        Ok(Self::call_raw(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.NameHasOwner",
+            &[name.to_owned()],
+        )?
+        .contains("true"))
+    }
+
+    #[mutants::skip] // Cannot test directly, runs external command
+    fn call(&self, name: &str, method: &str, args: &[String]) -> Result<()> {
+        Self::call_raw(
+            name,
+            &object_path(name),
+            &format!("org.freedesktop.Application.{method}"),
+            args,
+        )
+        .map(|_| ())
+    }
+}
+
+/// Get the bus name of a D-Bus activatable desktop entry, i.e. its file name without `.desktop`
+fn bus_name(entry: &DesktopEntry) -> Option<String> {
+    entry
+        .file_name
+        .to_str()?
+        .strip_suffix(".desktop")
+        .filter(|name| name.contains('.'))
+        .map(str::to_owned)
+}
+
+/// Get the object path an application exports `org.freedesktop.Application` at, given its bus name
+fn object_path(name: &str) -> String {
+    format!("/{}", name.replace('.', "/").replace('-', "_"))
+}
+
+/// Get a GVariant array of URIs for the given paths and URLs
+/// Relative paths are resolved against the current directory
+fn uri_array(args: &[String]) -> Result<String> {
+    let cwd = std::env::current_dir()?;
+    let uris = args
+        .iter()
+        .map(|arg| match Url::parse(arg) {
+            // Anything with a one letter scheme is more likely a Windows-style path than a URL
+            Ok(url) if url.scheme().len() > 1 => url.to_string(),
+            _ => Url::from_file_path(cwd.join(arg))
+                .map_or_else(|_| arg.clone(), |url| url.to_string()),
+        })
+        .map(|uri| {
+            format!("'{}'", uri.replace('\\', "\\\\").replace('\'', "\\'"))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(format!("[{}]", uris.join(", ")))
+}
+
+/// Try to hand the given arguments to an already running instance of a D-Bus activatable entry
+/// Returns whether a running instance took them
+pub fn send_to_running<B: SessionBus>(
+    bus: &B,
+    entry: &DesktopEntry,
+    mode: ExecMode,
+    args: &[String],
+) -> Result<bool> {
+    let Some(name) = bus_name(entry).filter(|_| entry.dbus_activatable) else {
+        return Ok(false);
+    };
+
+    // Treat an unreachable bus the same as nothing running
+    if !bus.name_has_owner(&name).unwrap_or(false) {
+        return Ok(false);
+    }
+
+    // Only files and URLs can be handed over, not arbitrary launch arguments
+    let sent = match (mode, args.is_empty()) {
+        (_, true) => bus.call(&name, "Activate", &["{}".to_owned()]),
+        (ExecMode::Open, false) => {
+            bus.call(&name, "Open", &[uri_array(args)?, "{}".to_owned()])
+        }
+        (ExecMode::Launch, false) => return Ok(false),
+    };
+
+    // Start a new instance instead if the running one does not respond
+    Ok(sent.is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{cell::RefCell, convert::TryFrom};
+
+    /// Stand-in for a session bus with a single application on it
+    #[derive(Default)]
+    struct MockBus {
+        owned: Vec<&'static str>,
+        failing: bool,
+        calls: RefCell<Vec<(String, String, Vec<String>)>>,
+    }
+
+    impl SessionBus for MockBus {
+        fn name_has_owner(&self, name: &str) -> Result<bool> {
+            Ok(self.owned.contains(&name))
+        }
+
+        fn call(
+            &self,
+            name: &str,
+            method: &str,
+            args: &[String],
+        ) -> Result<()> {
+            self.calls.borrow_mut().push((
+                name.to_owned(),
+                method.to_owned(),
+                args.to_vec(),
+            ));
+            if self.failing {
+                Err(Error::BadCmd(method.to_owned()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    // Helper function to get the fixture for a single window, D-Bus activatable app
+    fn single_window_entry() -> Result<DesktopEntry> {
+        DesktopEntry::try_from(std::path::PathBuf::from(
+            "tests/org.example.Single.desktop",
+        ))
+    }
+
+    #[test]
+    fn single_window_keys() -> Result<()> {
+        let entry = single_window_entry()?;
+        assert!(entry.single_main_window);
+        assert!(entry.dbus_activatable);
+
+        let entry = DesktopEntry::try_from(std::path::PathBuf::from(
+            "tests/Helix.desktop",
+        ))?;
+        assert!(!entry.single_main_window);
+        assert!(!entry.dbus_activatable);
+
+        assert_eq!(object_path("org.example.my-app"), "/org/example/my_app");
+
+        Ok(())
+    }
+
+    #[test]
+    fn dbus_open() -> Result<()> {
+        let entry = single_window_entry()?;
+        let bus = MockBus {
+            owned: vec!["org.example.Single"],
+            ..Default::default()
+        };
+
+        let args =
+            ["/tmp/a b.txt".to_owned(), "https://example.com".to_owned()];
+        assert!(send_to_running(&bus, &entry, ExecMode::Open, &args)?);
+        assert!(send_to_running(&bus, &entry, ExecMode::Launch, &[])?);
+        // Launch arguments cannot be sent
+        assert!(!send_to_running(&bus, &entry, ExecMode::Launch, &args)?);
+
+        assert_eq!(
+            bus.calls.into_inner(),
+            vec![
+                (
+                    "org.example.Single".to_owned(),
+                    "Open".to_owned(),
+                    vec![
+                        "['file:///tmp/a%20b.txt', 'https://example.com/']"
+                            .to_owned(),
+                        "{}".to_owned()
+                    ]
+                ),
+                (
+                    "org.example.Single".to_owned(),
+                    "Activate".to_owned(),
+                    vec!["{}".to_owned()]
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dbus_not_running() -> Result<()> {
+        let entry = single_window_entry()?;
+        let args = ["/tmp/a.txt".to_owned()];
+
+        // Nothing owns the name
+        let bus = MockBus::default();
+        assert!(!send_to_running(&bus, &entry, ExecMode::Open, &args)?);
+        assert!(bus.calls.into_inner().is_empty());
+
+        // The running instance does not respond
+        let bus = MockBus {
+            owned: vec!["org.example.Single"],
+            failing: true,
+            ..Default::default()
+        };
+        assert!(!send_to_running(&bus, &entry, ExecMode::Open, &args)?);
+
+        // Entries that are not D-Bus activatable are never sent anything
+        let bus = MockBus {
+            owned: vec!["org.example.Single"],
+            ..Default::default()
+        };
+        let entry = DesktopEntry {
+            dbus_activatable: false,
+            ..entry
+        };
+        assert!(!send_to_running(&bus, &entry, ExecMode::Open, &args)?);
+        assert!(bus.calls.into_inner().is_empty());
+
+        Ok(())
+    }
+}
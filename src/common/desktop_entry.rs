@@ -1,37 +1,112 @@
+#[cfg(feature = "dbus")]
+use crate::common::dbus;
 use crate::{
-    config::Config,
+    common::{instance, path::expand_tilde, MimeOrExtension},
+    config::{Config, WorkingDir},
     error::{Error, Result},
+    utils::{self, Urgency},
 };
-use aho_corasick::AhoCorasick;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
 use freedesktop_desktop_entry::{
     get_languages_from_env, DesktopEntry as FreeDesktopEntry,
 };
 use itertools::Itertools;
 use mime::Mime;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     convert::TryFrom,
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    os::unix::{fs::PermissionsExt, process::CommandExt},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
     str::FromStr,
 };
+use wildmatch::WildMatch;
 
 /// Represents a desktop entry file for an application
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DesktopEntry {
     /// Name of the application
     pub name: String,
+    /// Generic name of the application, e.g. "Web Browser"
+    pub generic_name: Option<String>,
+    /// Name or path of the application's icon
+    pub icon: Option<String>,
     /// Command to execute
     pub exec: String,
+    /// Program that must be installed for the entry to be usable, from `TryExec`
+    pub try_exec: Option<String>,
     /// Name of the desktop entry file
     pub file_name: OsString,
+    /// Where the desktop entry file is, if the entry came from one
+    pub path: Option<PathBuf>,
+    /// Directory to run the program in, from `Path`
+    pub working_dir: Option<String>,
     /// Whether the program runs in a terminal window
     pub terminal: bool,
     /// The MIME type(s) supported by this application
     pub mime_type: Vec<Mime>,
     /// Categories in which the entry should be shown in a menu
     pub categories: Vec<String>,
+    /// Localized words describing the application, e.g. for searching
+    pub keywords: Vec<String>,
+    /// Extension keys starting with `X-`, which handlr does not interpret
+    pub extra: BTreeMap<String, String>,
+    /// What kind of entry this is
+    pub entry_type: EntryType,
+    /// Whether the application only ever has one main window, from `SingleMainWindow`
+    pub single_main_window: bool,
+    /// Whether the application can be started and sent files through D-Bus, from `DBusActivatable`
+    pub dbus_activatable: bool,
+    /// Arguments for the terminal emulator to use instead of `term_exec_args`,
+    /// which only entries made up for regex handlers have
+    pub term_args: Option<String>,
+    /// Other things the application can do, from `Actions` and the `[Desktop Action ...]` groups
+    pub actions: Vec<DesktopAction>,
+    /// Whether the entry should be left out of menus, from `NoDisplay`
+    pub no_display: bool,
+    /// Whether the entry has been deleted and should be treated as if it does not exist, from `Hidden`
+    pub hidden: bool,
+    /// Desktops the entry should only be shown in, from `OnlyShowIn`
+    pub only_show_in: Vec<String>,
+    /// Desktops the entry should not be shown in, from `NotShowIn`
+    pub not_show_in: Vec<String>,
+}
+
+/// An action from a `[Desktop Action <id>]` group, e.g. opening a new private window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DesktopAction {
+    /// Identifier of the action, as listed in `Actions`
+    pub id: String,
+    /// Localized name of the action
+    pub name: String,
+    /// Command to execute instead of the entry's `Exec`
+    pub exec: String,
+}
+
+/// The kinds of desktop entries, from their `Type` key
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryType {
+    /// An application that can be run
+    #[default]
+    Application,
+    /// A link to the given URL, e.g. a bookmark
+    Link(String),
+    /// A menu directory, which cannot be run or opened
+    Directory,
+}
+
+impl Display for EntryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Application => "Application",
+            Self::Link(_) => "Link",
+            Self::Directory => "Directory",
+        })
+    }
 }
 
 /// Modes for running a DesktopFile's `exec` command
@@ -52,38 +127,210 @@ impl DesktopEntry {
         mode: Mode,
         arguments: Vec<String>,
     ) -> Result<()> {
-        let supports_multiple =
-            self.exec.contains("%F") || self.exec.contains("%U");
-        if arguments.is_empty() {
-            self.exec_inner(config, vec![])?
-        } else if supports_multiple || mode == Mode::Launch {
-            self.exec_inner(config, arguments)?;
-        } else {
-            for arg in arguments {
-                self.exec_inner(config, vec![arg])?;
+        // Hand everything to an instance that is already running instead of starting another one
+        // Instances that are already running cannot be waited on for `--notify-done` or `--wait`
+        if self.single_main_window
+            && config.respects_single_window()
+            && !config.notifies_done()
+            && !config.waits()
+        {
+            #[cfg(feature = "dbus")]
+            if dbus::send_to_running(&dbus::Gdbus, self, mode, &arguments)? {
+                return utils::notify(
+                    &self.name,
+                    "already running — files sent to existing instance",
+                );
+            }
+
+            // Nothing can be sent without D-Bus, so only plain launches are skipped
+            if arguments.is_empty()
+                && config.scans_processes()
+                && instance::is_running(Path::new("/proc"), self)
+            {
+                return utils::notify(&self.name, "already running");
+            }
+        }
+
+        let in_terminal = self.terminal && config.terminal_output;
+        let mut notify = |title: &str, msg: &str, urgency| {
+            if config.notifies_done() {
+                utils::notify_with(title, msg, urgency)
+            } else {
+                Ok(())
             }
         };
 
-        Ok(())
+        let mut running = Vec::new();
+        let mut statuses = Vec::new();
+        for args in self.invocations_for(config, mode, arguments) {
+            let count = match (mode, args.len()) {
+                (Mode::Open, 1) => "1 path".to_owned(),
+                (Mode::Open, n) => format!("{n} paths"),
+                (Mode::Launch, 1) => "1 argument".to_owned(),
+                (Mode::Launch, n) => format!("{n} arguments"),
+            };
+            let mut cmd = self.command(config, mode, args)?;
+
+            if in_terminal {
+                // Programs in the terminal would fight over it, so they run one at a time in the foreground
+                statuses.push(wait_and_notify(
+                    cmd.spawn()?,
+                    &self.name,
+                    &count,
+                    &mut notify,
+                )?);
+            } else if config.notifies_done() || config.waits() {
+                // Waiting keeps handlr running until the handler is done, even if launched without a terminal
+                cmd.stdout(Stdio::null()).stderr(Stdio::null());
+                running.push((cmd.spawn()?, count));
+            } else {
+                // Keep GUI apps running after the terminal handlr was started from is closed
+                detach(&mut cmd);
+                cmd.spawn()?;
+            }
+        }
+
+        // Everything is started before anything is waited on, so one invocation failing does not stop the rest
+        for (child, count) in running {
+            statuses.push(wait_and_notify(
+                child,
+                &self.name,
+                &count,
+                &mut notify,
+            )?);
+        }
+
+        if config.notifies_done() || config.waits() {
+            worst_failure(&self.name, statuses)
+        } else {
+            Ok(())
+        }
     }
 
-    /// Internal helper function for `exec`
-    #[mutants::skip] // Cannot test directly, runs command
-    fn exec_inner(&self, config: &Config, args: Vec<String>) -> Result<()> {
-        let mut cmd = {
-            let (cmd, args) = self.get_cmd(config, args)?;
-            let mut cmd = Command::new(cmd);
-            cmd.args(args);
-            cmd
-        };
+    /// Split the arguments for the command in `exec` between the invocations it would be run with,
+    /// like `invocations` with the size of the environment and `max_args_size` from the config
+    pub fn invocations_for(
+        &self,
+        config: &Config,
+        mode: Mode,
+        arguments: Vec<String>,
+    ) -> Vec<Vec<String>> {
+        let base_size = env_size()
+            + split_exec(&self.exec)
+                .unwrap_or_default()
+                .iter()
+                .map(|arg| arg_size(arg))
+                .sum::<usize>();
+
+        self.invocations(mode, arguments, base_size, config.max_args_size())
+    }
 
-        if self.terminal && config.terminal_output {
-            cmd.spawn()?.wait()?;
+    /// Split the arguments for the command in `exec` between as many invocations as its field codes call for
+    /// With `%f` or `%u`, the program is run once per path, and with `%F`, `%U`, or no field codes at all,
+    /// every path is given at once, only splitting them up so that each invocation fits within `max_size`
+    /// alongside `base_size` bytes of other data
+    fn invocations(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+        base_size: usize,
+        max_size: usize,
+    ) -> Vec<Vec<String>> {
+        if arguments.is_empty() || mode == Mode::Launch {
+            vec![arguments]
+        } else if self.runs_once_per_path() {
+            arguments.into_iter().map(|arg| vec![arg]).collect()
         } else {
-            cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+            chunk_args(arguments, base_size, max_size)
         }
+    }
 
-        Ok(())
+    /// Split the command in `exec` into arguments, without replacing any field codes
+    fn exec_args(&self) -> Result<Vec<String>> {
+        split_exec(&self.exec).ok_or_else(|| {
+            Error::BadExec(
+                self.exec.clone(),
+                self.file_name.to_string_lossy().to_string(),
+            )
+        })
+    }
+
+    /// Check if the command in `exec` is run once for each path, i.e. it has `%f` or `%u` but not `%F` or `%U`
+    fn runs_once_per_path(&self) -> bool {
+        let codes = FIELD_CODES
+            .find_iter(&self.exec)
+            .map(|m| &self.exec[m.start()..m.end()])
+            .collect_vec();
+        codes.iter().any(|code| matches!(*code, "%f" | "%u"))
+            && !codes.iter().any(|code| matches!(*code, "%F" | "%U"))
+    }
+
+    /// Get the command to run, with its arguments, environment, working directory, and launch options
+    fn command(
+        &self,
+        config: &Config,
+        mode: Mode,
+        args: Vec<String>,
+    ) -> Result<Command> {
+        let working_dir =
+            self.working_dir(config.working_dir(), mode, &args)?;
+        // Relative paths would point somewhere else from another directory
+        let args = match working_dir {
+            Some(_) => args
+                .into_iter()
+                .map(|arg| absolute_arg(arg, mode))
+                .collect(),
+            None => args,
+        };
+        let (program, args) = self.get_cmd(config, args)?;
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+
+        if let Some(dir) = working_dir {
+            cmd.current_dir(dir);
+        }
+
+        // Let window manager rules and the like know which launches were tagged
+        if let Some(tag) = config.tag() {
+            cmd.env("HANDLR_TAG", tag);
+        }
+
+        config
+            .launch_options(&self.file_name.to_string_lossy())
+            .apply(&mut cmd);
+
+        Ok(cmd)
+    }
+
+    /// Get the directory to run the command in, if it should not be handlr's own
+    /// That is the entry's `Path` key if it has one, or with `cwd = "file-dir"`,
+    /// the directory of the first file being opened
+    fn working_dir(
+        &self,
+        cwd: WorkingDir,
+        mode: Mode,
+        args: &[String],
+    ) -> Result<Option<PathBuf>> {
+        if let Some(dir) = &self.working_dir {
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            let dir = expand_tilde(Path::new(dir), home.as_deref());
+            return match dir.is_dir() {
+                true => Ok(Some(dir)),
+                false => Err(Error::BadWorkingDir(self.name.clone(), dir)),
+            };
+        }
+
+        if cwd != WorkingDir::FileDir || mode != Mode::Open {
+            return Ok(None);
+        }
+
+        // Files in the current directory have no parent to change to
+        Ok(args
+            .iter()
+            .map(|arg| PathBuf::from(file_arg(arg)))
+            .find(|path| path.exists())
+            .and_then(|path| Some(path.parent()?.to_owned()))
+            .filter(|dir| !dir.as_os_str().is_empty()))
     }
 
     /// Get the `exec` command, formatted with given arguments
@@ -92,52 +339,72 @@ impl DesktopEntry {
         config: &Config,
         args: Vec<String>,
     ) -> Result<(String, Vec<String>)> {
-        let special =
-            AhoCorasick::new_auto_configured(&["%f", "%F", "%u", "%U"]);
+        let mut exec = self.exec_args()?;
 
-        let mut exec = shlex::split(&self.exec).ok_or_else(|| {
-            Error::BadExec(
-                self.exec.clone(),
-                self.file_name.to_string_lossy().to_string(),
-            )
-        })?;
+        let has_path_codes = FIELD_CODES.find_iter(&self.exec).any(|m| {
+            matches!(&self.exec[m.start()..m.end()], "%f" | "%F" | "%u" | "%U")
+        });
+        let location = self
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let icon = self.icon.as_deref().filter(|icon| !icon.is_empty());
+
+        // `%f` and `%F` only take files, so `file://` URLs are given as paths,
+        // while `%u` and `%U` take paths and URLs alike
+        let files = args.iter().map(|arg| file_arg(arg)).collect_vec();
+        let args_for = |code: &str| match code {
+            "%f" | "%F" => &files,
+            _ => &args,
+        };
 
-        // The desktop entry doesn't contain arguments - we make best effort and append them at
+        exec = exec
+            .into_iter()
+            .flat_map(|s| match s.as_str() {
+                "%f" | "%F" | "%u" | "%U" => args_for(&s).clone(),
+                "%c" => vec![self.name.clone()],
+                "%k" => vec![location.clone()],
+                // `%i` on its own is two arguments, or none without an icon
+                "%i" => icon
+                    .map(|icon| vec!["--icon".to_owned(), icon.to_owned()])
+                    .unwrap_or_default(),
+                // Deprecated codes are removed along with the argument if that is all there is to it
+                s if DEPRECATED_CODES.contains(&s) => Vec::new(),
+                s if FIELD_CODES.is_match(s) => vec![{
+                    let mut replaced =
+                        String::with_capacity(s.len() + args.len() * 2);
+                    FIELD_CODES.replace_all_with(
+                        s,
+                        &mut replaced,
+                        |_, code, dst| {
+                            match code {
+                                "%%" => dst.push('%'),
+                                "%c" => dst.push_str(&self.name),
+                                "%k" => dst.push_str(&location),
+                                "%i" => dst.push_str(icon.unwrap_or_default()),
+                                code if DEPRECATED_CODES.contains(&code) => {}
+                                code => dst.push_str(&args_for(code).join(" ")),
+                            }
+                            true
+                        },
+                    );
+                    replaced
+                }],
+                _ => vec![s],
+            })
+            .collect();
+
+        // The desktop entry doesn't say where paths go - we make best effort and append them at
         // the end
-        if special.is_match(&self.exec) {
-            exec = exec
-                .into_iter()
-                .flat_map(|s| match s.as_str() {
-                    "%f" | "%F" | "%u" | "%U" => args.clone(),
-                    s if special.is_match(s) => vec![{
-                        let mut replaced =
-                            String::with_capacity(s.len() + args.len() * 2);
-                        special.replace_all_with(
-                            s,
-                            &mut replaced,
-                            |_, _, dst| {
-                                dst.push_str(args.clone().join(" ").as_str());
-                                false
-                            },
-                        );
-                        replaced
-                    }],
-                    _ => vec![s],
-                })
-                .collect()
-        } else {
+        if !has_path_codes {
             exec.extend_from_slice(&args);
         }
 
         // If the entry expects a terminal (emulator), but this process is not running in one, we
         // launch a new one.
         if self.terminal && !config.terminal_output {
-            let term_cmd = config.terminal()?;
-            exec = shlex::split(&term_cmd)
-                .ok_or_else(|| Error::BadCmd(term_cmd))?
-                .into_iter()
-                .chain(exec)
-                .collect();
+            exec = wrap_in_terminal(config, self.term_args.as_deref(), exec)?;
         }
 
         Ok((exec.remove(0), exec))
@@ -145,16 +412,53 @@ impl DesktopEntry {
 
     /// Parse a desktop entry file, given a path
     fn parse_file(path: &Path) -> Option<DesktopEntry> {
-        // Assume the set locales will not change while handlr is running
-        static LOCALES: Lazy<Vec<String>> = Lazy::new(get_languages_from_env);
+        Self::parse_file_with(path, &LOCALES)
+    }
 
+    /// Parse a desktop entry file, given a path and the locales to pick localized values for
+    fn parse_file_with(
+        path: &Path,
+        locales: &[String],
+    ) -> Option<DesktopEntry> {
         let fd_entry =
-            FreeDesktopEntry::from_path(path.to_path_buf(), &LOCALES).ok()?;
+            FreeDesktopEntry::from_path(path.to_path_buf(), locales).ok()?;
+
+        let entry_type = match fd_entry.type_() {
+            // Be lenient with entries that leave out the required `Type` key
+            None | Some("Application") => EntryType::Application,
+            Some("Link") => {
+                EntryType::Link(fd_entry.desktop_entry("URL")?.to_owned())
+            }
+            Some("Directory") => EntryType::Directory,
+            // Unknown types are to be ignored according to the specification
+            Some(_) => return None,
+        };
+
+        let is_true = |key| fd_entry.desktop_entry(key) == Some("true");
+        let list = |key| {
+            fd_entry
+                .desktop_entry(key)
+                .unwrap_or_default()
+                .split(';')
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect_vec()
+        };
 
         let entry = DesktopEntry {
-            name: fd_entry.name(&LOCALES)?.into_owned(),
-            exec: fd_entry.exec()?.to_owned(),
+            name: fd_entry.name(locales)?.into_owned(),
+            generic_name: fd_entry
+                .generic_name(locales)
+                .map(|name| name.into_owned()),
+            icon: fd_entry.icon().map(str::to_owned),
+            exec: unescape_value(fd_entry.exec().unwrap_or_default()),
+            try_exec: fd_entry.desktop_entry("TryExec").map(str::to_owned),
             file_name: path.file_name()?.to_owned(),
+            path: Some(path.to_owned()),
+            working_dir: fd_entry
+                .desktop_entry("Path")
+                .filter(|dir| !dir.is_empty())
+                .map(str::to_owned),
             terminal: fd_entry.terminal(),
             mime_type: fd_entry
                 .mime_type()
@@ -168,15 +472,83 @@ impl DesktopEntry {
                 .iter()
                 .map(|&c| c.to_owned())
                 .collect_vec(),
+            keywords: fd_entry
+                .keywords(locales)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|k| !k.is_empty())
+                .map(|k| k.into_owned())
+                .collect_vec(),
+            extra: fd_entry
+                .groups
+                .get("Desktop Entry")
+                .into_iter()
+                .flatten()
+                .filter(|(key, _)| key.starts_with("X-"))
+                .map(|(key, (value, _))| (key.to_string(), value.to_string()))
+                .collect(),
+            entry_type,
+            single_main_window: is_true("SingleMainWindow"),
+            dbus_activatable: is_true("DBusActivatable"),
+            term_args: None,
+            // Actions without a command could only be run through D-Bus
+            actions: fd_entry
+                .actions()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|id| !id.is_empty())
+                .filter_map(|id| {
+                    Some(DesktopAction {
+                        id: id.to_owned(),
+                        name: fd_entry.action_name(id, locales)?.into_owned(),
+                        exec: unescape_value(
+                            fd_entry
+                                .action_exec(id)
+                                .filter(|exec| !exec.is_empty())?,
+                        ),
+                    })
+                })
+                .collect(),
+            no_display: is_true("NoDisplay"),
+            hidden: is_true("Hidden"),
+            only_show_in: list("OnlyShowIn"),
+            not_show_in: list("NotShowIn"),
         };
 
-        if !entry.name.is_empty() && !entry.exec.is_empty() {
+        // Only applications need something to run
+        if !entry.name.is_empty()
+            && (!entry.exec.is_empty() || !entry.is_application())
+        {
             Some(entry)
         } else {
             None
         }
     }
 
+    /// Get a copy of the entry that runs one of its actions instead of its own command
+    pub fn with_action(&self, id: &str) -> Result<DesktopEntry> {
+        let action = self
+            .actions
+            .iter()
+            .find(|action| action.id == id)
+            .ok_or_else(|| Error::UnknownAction {
+                handler: self.name.clone(),
+                action: id.to_owned(),
+                actions: self
+                    .actions
+                    .iter()
+                    .map(|action| action.id.clone())
+                    .collect(),
+            })?;
+
+        Ok(DesktopEntry {
+            exec: action.exec.clone(),
+            // Actions are started through D-Bus with `ActivateAction`, which handlr does not use
+            dbus_activatable: false,
+            ..self.clone()
+        })
+    }
+
     /// Make a fake DesktopEntry given only a value for exec and terminal.
     /// All other keys will have default values.
     pub fn fake_entry(exec: &str, terminal: bool) -> DesktopEntry {
@@ -187,10 +559,413 @@ impl DesktopEntry {
         }
     }
 
+    /// Fill in a template with information about the entry
+    /// `{name}`, `{file}`, and `{keywords}` are replaced with the entry's name, file name, and keywords,
+    /// and `{X-...}` is replaced with the value of that key, or nothing if it is not set
+    pub fn format(&self, template: &str) -> String {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+
+            match &rest[1..end] {
+                "name" => output.push_str(&self.name),
+                "file" => output.push_str(&self.file_name.to_string_lossy()),
+                "keywords" => output.push_str(&self.keywords.join(", ")),
+                key if key.starts_with("X-") => output
+                    .push_str(self.extra.get(key).map_or("", |v| v.as_str())),
+                // Leave anything else alone
+                _ => output.push_str(&rest[..=end]),
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+    /// Get the file name of the program run by the entry's `Exec` key, e.g. `vlc` for `/usr/bin/vlc %U`
+    pub fn program(&self) -> Option<OsString> {
+        let program = split_exec(&self.exec)?.into_iter().next()?;
+        Some(Path::new(&program).file_name()?.to_os_string())
+    }
+
+    /// Get the program from `TryExec` if it cannot be found, in which case the entry should not be used
+    /// Programs without a path are looked for in `search_path`, which is in the form of `$PATH`
+    pub fn missing_try_exec(&self, search_path: &OsStr) -> Option<&str> {
+        self.try_exec
+            .as_deref()
+            .filter(|program| !program_exists(program, search_path))
+    }
+
+    /// Get the program from `TryExec` or `Exec` that cannot be found, if any
+    /// Programs without a path are looked for in `search_path`, which is in the form of `$PATH`
+    /// Errors if `Exec` cannot be split into arguments
+    pub fn missing_program(
+        &self,
+        search_path: &OsStr,
+    ) -> Result<Option<String>> {
+        let exec = self.exec_args()?.into_iter().next();
+
+        Ok(self
+            .try_exec
+            .iter()
+            .cloned()
+            .chain(exec)
+            .find(|program| !program_exists(program, search_path)))
+    }
+
+    /// Check if the entry says it supports a mime or extension given on the command line,
+    /// either exactly or through a wildcard like `video/*` on either side
+    /// Anything that is not a valid mime or known extension is never supported
+    pub fn supports(&self, mime: &str) -> bool {
+        let Ok(mime) = MimeOrExtension::from_str(mime) else {
+            return false;
+        };
+        let mime = mime.essence_str();
+
+        self.mime_type.iter().any(|supported| {
+            let supported = supported.essence_str();
+            WildMatch::new(supported).matches(mime)
+                || WildMatch::new(mime).matches(supported)
+        })
+    }
+
+    /// Check if the entry should be shown on any of the given lowercased desktops, like from `$XDG_CURRENT_DESKTOP`,
+    /// i.e. it is not hidden with `NoDisplay` and `is_available_in` them
+    pub fn is_shown_in(&self, desktops: &[String]) -> bool {
+        !self.no_display && self.is_available_in(desktops)
+    }
+
+    /// Check if the entry can be used on any of the given lowercased desktops,
+    /// i.e. it is not deleted with `Hidden`, and `OnlyShowIn` and `NotShowIn` allow it
+    /// Unlike `is_shown_in`, `NoDisplay` does not matter
+    pub fn is_available_in(&self, desktops: &[String]) -> bool {
+        let any_desktop = |list: &[String]| {
+            list.iter()
+                .any(|desktop| desktops.contains(&desktop.to_lowercase()))
+        };
+
+        !self.hidden
+            && (self.only_show_in.is_empty() || any_desktop(&self.only_show_in))
+            && !any_desktop(&self.not_show_in)
+    }
+
+    /// Check if the given desktop entry is an application rather than a link or directory
+    pub fn is_application(&self) -> bool {
+        self.entry_type == EntryType::Application
+    }
+
+    /// Get an error for using an entry that is not an application as one, if it is not one
+    pub fn check_application(self) -> Result<Self> {
+        if self.is_application() {
+            Ok(self)
+        } else {
+            Err(Error::NotApplication(
+                self.file_name.to_string_lossy().into_owned(),
+                self.entry_type,
+            ))
+        }
+    }
+
+    /// Explain what handlr does with each field code in the entry's `Exec` key, in the order they appear
+    pub fn field_codes(&self) -> Vec<(String, &'static str)> {
+        let mut codes = Vec::new();
+        let mut chars = self.exec.chars();
+        let once_per_path = self.runs_once_per_path();
+
+        while chars.by_ref().any(|c| c == '%') {
+            let Some(code) = chars.next() else {
+                break;
+            };
+            let meaning = match code {
+                'f' | 'u' if once_per_path => "replaced with one path/URL, running the program once for each",
+                'f' | 'u' => "replaced with every path/URL at once, since %F or %U is also used",
+                'F' | 'U' => "replaced with every path/URL at once",
+                '%' => "replaced with a literal %",
+                'c' => "replaced with the application's name",
+                'k' => "replaced with the path of the desktop file",
+                'i' => "replaced with --icon and the application's icon, or removed if it has none",
+                'd' | 'D' | 'n' | 'N' | 'v' | 'm' => "deprecated and removed",
+                _ => "not a field code, passed as is",
+            };
+            codes.push((format!("%{code}"), meaning));
+        }
+
+        codes
+            .into_iter()
+            .unique_by(|(code, _)| code.clone())
+            .collect()
+    }
+
     /// Check if the given desktop entry represents a terminal emulator
     pub fn is_terminal_emulator(&self) -> bool {
         self.categories.contains(&"TerminalEmulator".to_string())
     }
+
+    /// Check if the given desktop entry represents a file manager
+    pub fn is_file_manager(&self) -> bool {
+        self.categories.contains(&"FileManager".to_string())
+    }
+}
+
+/// Wait for a handler's process to exit and notify that it is done, with critical urgency if it failed
+/// `count` describes what it was given, e.g. `3 paths`
+fn wait_and_notify<N>(
+    mut child: Child,
+    name: &str,
+    count: &str,
+    notify: &mut N,
+) -> Result<ExitStatus>
+where
+    N: FnMut(&str, &str, Urgency) -> Result<()>,
+{
+    let status = child.wait()?;
+    let urgency = if status.success() {
+        Urgency::Normal
+    } else {
+        Urgency::Critical
+    };
+    notify(&format!("Finished: {name} ({status})"), count, urgency)?;
+
+    Ok(status)
+}
+
+/// Fail with the worst exit code of a handler's processes if any of them failed, so that handlr fails with it
+fn worst_failure(
+    name: &str,
+    statuses: impl IntoIterator<Item = ExitStatus>,
+) -> Result<()> {
+    statuses
+        .into_iter()
+        .filter(|status| !status.success())
+        .map(|status| Error::HandlerFailed(name.to_owned(), status))
+        .max_by_key(Error::exit_code)
+        .map_or(Ok(()), Err)
+}
+
+/// Make a command start in its own session with no stdio,
+/// so that it is not tied to the terminal handlr is running in, like with `setsid`
+fn detach(cmd: &mut Command) {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // SAFETY: setsid only makes a syscall, which is safe to make between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Check if a program can be run, either as a path or by looking for it in `search_path`
+fn program_exists(program: &str, search_path: &OsStr) -> bool {
+    let is_executable = |path: &Path| {
+        path.metadata().is_ok_and(|metadata| {
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        })
+    };
+
+    if program.contains('/') {
+        is_executable(Path::new(program))
+    } else {
+        std::env::split_paths(search_path)
+            .any(|dir| is_executable(&dir.join(program)))
+    }
+}
+
+/// Estimate how much space an argument or environment variable takes up
+/// in the memory given to a new process, i.e. its bytes, a null terminator, and a pointer to it
+fn arg_size(arg: &str) -> usize {
+    arg.len() + 1 + std::mem::size_of::<usize>()
+}
+
+/// Estimate how much space the current environment takes up in the memory given to a new process
+fn env_size() -> usize {
+    std::env::vars_os()
+        .map(|(key, value)| {
+            // Account for the `=` between the key and value
+            arg_size(&key.to_string_lossy()) + value.len() + 1
+        })
+        .sum()
+}
+
+/// Turn a `file://` URL into the path it points to, for field codes that only take files
+/// Anything else, including URLs with other schemes, is left as it is
+fn file_arg(arg: &str) -> String {
+    url::Url::parse(arg)
+        .ok()
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+        .map_or_else(|| arg.to_owned(), |path| path.display().to_string())
+}
+
+/// Make a relative path absolute, so that it points to the same file from any working directory
+/// URLs are left as they are, and so are arguments to launched handlers that are not paths to anything
+fn absolute_arg(arg: String, mode: Mode) -> String {
+    let path = Path::new(&arg);
+    if path.is_absolute()
+        || url::Url::parse(&arg).is_ok()
+        || (mode == Mode::Launch && !path.exists())
+    {
+        return arg;
+    }
+
+    match std::path::absolute(path).map(|path| path.into_os_string()) {
+        Ok(absolute) => absolute.into_string().unwrap_or(arg),
+        Err(_) => arg,
+    }
+}
+
+/// Split arguments into consecutive chunks that each fit within `max_size`
+/// alongside `base_size` bytes of other data, preserving order
+/// Every chunk has at least one argument, even if it is too big on its own
+fn chunk_args(
+    args: Vec<String>,
+    base_size: usize,
+    max_size: usize,
+) -> Vec<Vec<String>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut size = base_size;
+
+    for arg in args {
+        let arg_size = arg_size(&arg);
+
+        if !chunk.is_empty() && size + arg_size > max_size {
+            chunks.push(std::mem::take(&mut chunk));
+            size = base_size;
+        }
+
+        size += arg_size;
+        chunk.push(arg);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+/// Assume the set locales will not change while handlr is running
+static LOCALES: Lazy<Vec<String>> = Lazy::new(get_languages_from_env);
+
+/// Matches the field codes that handlr expands in `Exec` keys
+/// `%%` is a literal `%`, so it comes first to keep e.g. `%%f` from being taken for `%f`
+static FIELD_CODES: Lazy<AhoCorasick> = Lazy::new(|| {
+    AhoCorasickBuilder::new()
+        .match_kind(MatchKind::LeftmostFirst)
+        .build([
+            "%%", "%f", "%F", "%u", "%U", "%c", "%k", "%i", "%d", "%D", "%n",
+            "%N", "%v", "%m",
+        ])
+});
+
+/// Field codes that the spec deprecates, which are removed from commands
+const DEPRECATED_CODES: [&str; 6] = ["%d", "%D", "%n", "%N", "%v", "%m"];
+
+/// Undo the escapes that the desktop entry spec allows in any string value,
+/// i.e. `\s`, `\n`, `\t`, `\r`, and `\\` for a space, newline, tab, carriage return, and backslash
+/// Any other backslash is kept, so that it is left for the quoting rules of `Exec` keys
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => unescaped.extend(['\\', c]),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+/// Split an `Exec` key into arguments with the desktop entry spec's quoting rules
+/// Arguments are separated by spaces and can be quoted with double quotes,
+/// inside of which `\"`, `` \` ``, `\$`, and `\\` are escapes for the second character
+/// Single quotes and backslashes outside of quotes work like in a shell, since regex handlers are often written that way,
+/// but unlike in a shell, `#` does not start a comment
+fn split_exec(exec: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    // Nothing between arguments, so that `""` is still an argument
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(arg.take()),
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '`' | '$' | '\\') => arg.push(c),
+                            c => arg.extend(['\\', c]),
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\\' => arg.get_or_insert_with(String::new).push(chars.next()?),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    args.extend(arg);
+    Some(args)
+}
+
+/// Get the locales used to pick localized values from desktop entries
+pub fn locales() -> &'static [String] {
+    &LOCALES
+}
+
+/// Prepend the terminal emulator command to the given command
+/// Shared by every kind of handler so that terminal programs are always wrapped the same way
+fn wrap_in_terminal(
+    config: &Config,
+    term_args: Option<&str>,
+    exec: Vec<String>,
+) -> Result<Vec<String>> {
+    let term_cmd = config.terminal(term_args)?;
+    Ok(shlex::split(&term_cmd)
+        .ok_or(Error::BadCmd(term_cmd))?
+        .into_iter()
+        .chain(exec)
+        .collect())
 }
 
 impl TryFrom<PathBuf> for DesktopEntry {
@@ -208,6 +983,456 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn field_codes() {
+        let codes = |exec: &str| {
+            DesktopEntry::fake_entry(exec, false)
+                .field_codes()
+                .into_iter()
+                .map(|(code, _)| code)
+                .collect_vec()
+        };
+
+        assert_eq!(codes("mpv %U"), ["%U"]);
+        assert_eq!(codes("app --icon %i %f %f"), ["%i", "%f"]);
+        assert_eq!(codes("printf 100%% %F"), ["%%", "%F"]);
+        assert_eq!(codes("app %"), Vec::<String>::new());
+        assert_eq!(codes("hx"), Vec::<String>::new());
+
+        // `%f` only runs the program once per path without `%F` or `%U`
+        let meaning = |exec: &str| {
+            DesktopEntry::fake_entry(exec, false).field_codes()[0].1
+        };
+        assert!(meaning("app %f").ends_with("once for each"));
+        assert!(meaning("app %u %U").starts_with("replaced with every"));
+    }
+
+    #[test]
+    fn shown_on_desktops() {
+        let entry = DesktopEntry::parse_file(Path::new(
+            "tests/org.kde.kdeconnect.handler.desktop",
+        ))
+        .expect("tests/org.kde.kdeconnect.handler.desktop should be valid");
+        assert!(entry.no_display);
+        assert_eq!(entry.only_show_in, ["KDE"]);
+        assert!(!entry.is_shown_in(&["kde".to_owned()]));
+        assert!(entry.is_available_in(&["kde".to_owned()]));
+        assert!(!entry.is_available_in(&["gnome".to_owned()]));
+
+        let desktops = |names: &[&str]| {
+            names.iter().map(|name| name.to_string()).collect_vec()
+        };
+        let entry = DesktopEntry {
+            no_display: false,
+            ..entry
+        };
+        assert!(entry.is_shown_in(&desktops(&["sway", "kde"])));
+        assert!(!entry.is_shown_in(&desktops(&["sway"])));
+        assert!(!entry.is_shown_in(&[]));
+
+        let entry = DesktopEntry {
+            only_show_in: Vec::new(),
+            not_show_in: vec!["GNOME".into()],
+            ..entry
+        };
+        assert!(entry.is_shown_in(&[]));
+        assert!(!entry.is_shown_in(&desktops(&["ubuntu", "gnome"])));
+        assert!(!DesktopEntry {
+            hidden: true,
+            ..entry
+        }
+        .is_shown_in(&[]));
+    }
+
+    #[test]
+    fn desktop_actions() -> Result<()> {
+        let entry = DesktopEntry::parse_file_with(
+            Path::new("tests/firefox.desktop"),
+            &["de".to_owned()],
+        )
+        .expect("tests/firefox.desktop should be valid");
+
+        // Actions without a command are left out
+        assert_eq!(
+            entry.actions,
+            [
+                DesktopAction {
+                    id: "new-window".into(),
+                    name: "New Window".into(),
+                    exec: "firefox --new-window %u".into(),
+                },
+                DesktopAction {
+                    id: "new-private-window".into(),
+                    name: "Neues privates Fenster".into(),
+                    exec: "firefox --private-window %u".into(),
+                },
+            ]
+        );
+
+        let args = vec!["https://example.com".to_owned()];
+        assert_eq!(
+            entry
+                .with_action("new-private-window")?
+                .get_cmd(&Config::default(), args)?,
+            (
+                "firefox".to_owned(),
+                vec![
+                    "--private-window".to_owned(),
+                    "https://example.com".to_owned()
+                ]
+            )
+        );
+
+        let error = entry.with_action("profile-manager").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Firefox has no action 'profile-manager', its actions are: new-window, new-private-window"
+        );
+        let entry = DesktopEntry {
+            name: "mpv".into(),
+            ..DesktopEntry::fake_entry("mpv %u", false)
+        };
+        assert_eq!(
+            entry.with_action("new-window").unwrap_err().to_string(),
+            "mpv has no action 'new-window', it has no actions"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn supported_mimes() -> Result<()> {
+        let entry = DesktopEntry {
+            mime_type: vec![Mime::from_str("video/*")?, "audio/ogg".parse()?],
+            ..Default::default()
+        };
+
+        assert!(entry.supports("video/mp4"));
+        assert!(entry.supports(".mkv"));
+        assert!(entry.supports("audio/ogg"));
+        assert!(entry.supports("audio/*"));
+        assert!(!entry.supports("audio/mpeg"));
+        assert!(!entry.supports("text/plain"));
+        assert!(!entry.supports("not a mime"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn detach_and_wait() -> Result<()> {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("1");
+        detach(&mut cmd);
+        let mut child = cmd.spawn()?;
+        // SAFETY: getsid has no memory safety requirements
+        let (own_session, child_session) =
+            unsafe { (libc::getsid(0), libc::getsid(child.id() as i32)) };
+        child.kill()?;
+        child.wait()?;
+        // The child leads a session of its own
+        assert_eq!(child_session, child.id() as i32);
+        assert_ne!(child_session, own_session);
+
+        let run = |script: &str| -> Result<()> {
+            let status =
+                Command::new("sh").args(["-c", script]).spawn()?.wait()?;
+            worst_failure("Foo", [status])
+        };
+        run("exit 0")?;
+        assert_eq!(run("exit 3").unwrap_err().exit_code(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn notify_when_done() -> Result<()> {
+        let mut notifications = Vec::new();
+        let mut notify = |title: &str, msg: &str, urgency| {
+            notifications.push((title.to_owned(), msg.to_owned(), urgency));
+            Ok(())
+        };
+
+        // Stand in for invocations that take a while, all started before any are waited on
+        let children = ["exit 3", "exit 0", "exit 5"]
+            .into_iter()
+            .map(|script| {
+                Command::new("sh")
+                    .args(["-c", &format!("sleep 0.1; {script}")])
+                    .spawn()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let statuses = children
+            .into_iter()
+            .map(|child| {
+                wait_and_notify(child, "Transcode", "2 paths", &mut notify)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The worst of them is what handlr fails with
+        let error = worst_failure("Transcode", statuses).unwrap_err();
+        assert_eq!(error.exit_code(), 5);
+        assert!(!error.should_notify());
+
+        let notification = |status: u8, urgency| {
+            (
+                format!("Finished: Transcode (exit status: {status})"),
+                "2 paths".to_owned(),
+                urgency,
+            )
+        };
+        assert_eq!(
+            notifications,
+            [
+                notification(3, Urgency::Critical),
+                notification(0, Urgency::Normal),
+                notification(5, Urgency::Critical),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn localized_keywords() {
+        let path = Path::new("tests/gimp.desktop");
+        let keywords = |locales: &[&str]| {
+            let locales = locales.iter().map(|l| l.to_string()).collect_vec();
+            DesktopEntry::parse_file_with(path, &locales)
+                .expect("Fixture should be a valid desktop entry")
+                .keywords
+        };
+
+        // Exact locales come first, then their languages, then the default
+        assert_eq!(keywords(&["de_AT"]), vec!["Foto", "Bildbearbeitung"]);
+        assert_eq!(keywords(&["de_DE"]), vec!["Foto", "Bild", "Editor"]);
+        assert_eq!(keywords(&["it", "de"]), vec!["Foto", "Bild", "Editor"]);
+        assert_eq!(keywords(&["ja"]), vec!["photo", "editor", "raster"]);
+        assert_eq!(keywords(&[]), vec!["photo", "editor", "raster"]);
+    }
+
+    #[test]
+    fn format_entry() -> Result<()> {
+        let entry =
+            DesktopEntry::parse_file_with(Path::new("tests/gimp.desktop"), &[])
+                .ok_or(Error::BadEntry("tests/gimp.desktop".into()))?;
+
+        assert_eq!(
+            entry.extra.keys().collect_vec(),
+            vec!["X-GNOME-UsesNotifications", "X-Workspace"]
+        );
+        assert_eq!(
+            entry.format("{name} ({file}) [{keywords}] {X-Workspace}{X-Missing} {other} {"),
+            "GNU Image Manipulation Program (gimp.desktop) [photo, editor, raster] 3 {other} {"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn working_dir() -> Result<()> {
+        let args = vec![
+            "https://example.com".to_owned(),
+            "tests/Helix.desktop".into(),
+        ];
+        let entry = DesktopEntry {
+            working_dir: Some("tests".into()),
+            ..DesktopEntry::fake_entry("hx %F", false)
+        };
+        let working_dir = |entry: &DesktopEntry, cwd, mode| {
+            entry.working_dir(cwd, mode, &args)
+        };
+
+        // `Path` is always used
+        assert_eq!(
+            working_dir(&entry, WorkingDir::Inherit, Mode::Launch)?,
+            Some(PathBuf::from("tests"))
+        );
+        assert!(matches!(
+            working_dir(
+                &DesktopEntry {
+                    working_dir: Some("tests/missing".into()),
+                    ..entry.clone()
+                },
+                WorkingDir::Inherit,
+                Mode::Open
+            ),
+            Err(Error::BadWorkingDir(_, dir)) if dir == Path::new("tests/missing")
+        ));
+
+        // Relative paths are made absolute to still point to the same files from there
+        let cmd =
+            entry.command(&Config::default(), Mode::Open, args.clone())?;
+        let absolute = std::env::current_dir()?.join("tests/Helix.desktop");
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("tests")));
+        assert_eq!(
+            cmd.get_args().collect_vec(),
+            [OsStr::new("https://example.com"), absolute.as_os_str()]
+        );
+        let cmd = entry.command(
+            &Config::default(),
+            Mode::Launch,
+            vec!["--new-window".into(), "tests/Helix.desktop".into()],
+        )?;
+        assert_eq!(
+            cmd.get_args().collect_vec(),
+            [OsStr::new("--new-window"), absolute.as_os_str()]
+        );
+
+        // Otherwise, the first file's directory is only used if asked for
+        let entry = DesktopEntry::fake_entry("hx %F", false);
+        assert_eq!(working_dir(&entry, WorkingDir::Inherit, Mode::Open)?, None);
+        assert_eq!(
+            working_dir(&entry, WorkingDir::FileDir, Mode::Open)?,
+            Some(PathBuf::from("tests"))
+        );
+        assert_eq!(
+            working_dir(&entry, WorkingDir::FileDir, Mode::Launch)?,
+            None
+        );
+        assert_eq!(
+            entry.working_dir(
+                WorkingDir::FileDir,
+                Mode::Open,
+                &["Cargo.toml".into()]
+            )?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tagged_command() -> Result<()> {
+        let mut config = Config::default();
+        let entry = DesktopEntry::fake_entry("mpv %U", false);
+
+        let cmd = entry.command(&config, Mode::Open, vec!["a.mp4".into()])?;
+        assert!(cmd.get_envs().all(|(key, _)| key != "HANDLR_TAG"));
+
+        config.set_tag(Some("video".into()))?;
+        let cmd = entry.command(&config, Mode::Open, vec!["a.mp4".into()])?;
+        assert_eq!(
+            cmd.get_envs().collect_vec(),
+            vec![(
+                std::ffi::OsStr::new("HANDLR_TAG"),
+                Some(std::ffi::OsStr::new("video"))
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_many_args() {
+        let args = (0..5000).map(|i| format!("{i}.txt")).collect_vec();
+        let (base_size, max_size) = (1000, 16 * 1024);
+
+        let chunks = chunk_args(args.clone(), base_size, max_size);
+
+        assert!(chunks.len() > 1);
+        for chunk in chunks.iter() {
+            assert!(
+                base_size + chunk.iter().map(|a| arg_size(a)).sum::<usize>()
+                    <= max_size
+            );
+        }
+        // Order should be preserved
+        assert_eq!(chunks.concat(), args);
+    }
+
+    #[test]
+    fn chunk_few_args() {
+        let args = vec!["a.txt".to_string(), "b.txt".to_string()];
+        assert_eq!(chunk_args(args.clone(), 0, 1024), vec![args]);
+        assert!(chunk_args(vec![], 0, 1024).is_empty());
+    }
+
+    #[test]
+    fn chunk_oversized_args() {
+        // Arguments that are too big on their own still get their own chunk
+        let args = vec!["a".repeat(100), "b".repeat(100)];
+        assert_eq!(
+            chunk_args(args.clone(), 50, 100),
+            vec![vec![args[0].clone()], vec![args[1].clone()]]
+        );
+    }
+
+    #[test]
+    fn field_code_invocations() {
+        let one = vec!["a.png".to_owned()];
+        let three = vec!["a.png".to_owned(), "b.png".into(), "c.png".into()];
+        let invocations = |exec: &str, mode: Mode, args: &[String]| {
+            DesktopEntry::fake_entry(exec, false).invocations(
+                mode,
+                args.to_vec(),
+                0,
+                1024,
+            )
+        };
+
+        for exec in ["app %f", "app --file=%f", "app %u"] {
+            assert_eq!(
+                invocations(exec, Mode::Open, &one),
+                std::slice::from_ref(&one)
+            );
+            assert_eq!(
+                invocations(exec, Mode::Open, &three),
+                three.iter().map(|arg| vec![arg.clone()]).collect_vec(),
+                "{exec} runs once per path"
+            );
+        }
+
+        // `%F`, `%U`, and no field codes at all take every path at once
+        for exec in ["app %F", "app %U", "app %f %F", "app"] {
+            assert_eq!(
+                invocations(exec, Mode::Open, &one),
+                std::slice::from_ref(&one)
+            );
+            assert_eq!(
+                invocations(exec, Mode::Open, &three),
+                std::slice::from_ref(&three),
+                "{exec} runs once for every path"
+            );
+        }
+
+        // Launching always passes the arguments as they are
+        assert_eq!(invocations("app %f", Mode::Launch, &three), [three]);
+        assert_eq!(
+            invocations("app %f", Mode::Open, &[]),
+            [Vec::<String>::new()]
+        );
+    }
+
+    #[test]
+    fn field_code_paths_and_urls() -> Result<()> {
+        let config = Config::default();
+        let args = vec![
+            "file:///tmp/a%20b.png".to_owned(),
+            "/tmp/c.png".into(),
+            "https://example.com/d.png".into(),
+        ];
+        let cmd = |exec: &str| -> Result<Vec<String>> {
+            Ok(DesktopEntry::fake_entry(exec, false)
+                .get_cmd(&config, args.clone())?
+                .1)
+        };
+
+        // `%f` and `%F` only take files, so `file://` URLs become paths
+        assert_eq!(
+            cmd("app %F")?,
+            ["/tmp/a b.png", "/tmp/c.png", "https://example.com/d.png"]
+        );
+        assert_eq!(
+            cmd("app --files=%f")?,
+            ["--files=/tmp/a b.png /tmp/c.png https://example.com/d.png"]
+        );
+        // `%u` and `%U` take URLs, and paths are valid as they are
+        assert_eq!(cmd("app %U")?, args);
+        assert_eq!(cmd("app")?, args);
+
+        Ok(())
+    }
+
     #[test]
     fn complex_exec() -> Result<()> {
         // Note that this entry also has no category key
@@ -233,6 +1458,167 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn tricky_execs() -> Result<()> {
+        let config = Config::default();
+        let entry = DesktopEntry {
+            name: "Foo App".into(),
+            icon: Some("foo".into()),
+            path: Some("/usr/share/applications/foo.desktop".into()),
+            ..Default::default()
+        };
+        // Each `Exec` is written as it would be in a desktop file
+        let cmd_with =
+            |entry: &DesktopEntry, exec: &str| -> Result<Vec<String>> {
+                let (program, args) = DesktopEntry {
+                    exec: unescape_value(exec),
+                    ..entry.clone()
+                }
+                .get_cmd(&config, vec!["a.txt".into(), "b.txt".into()])?;
+                Ok(std::iter::once(program).chain(args).collect())
+            };
+        let cmd = |exec: &str| cmd_with(&entry, exec);
+
+        let cases: &[(&str, &[&str])] = &[
+            (
+                "env FOO=1 app --name %c %u",
+                &["env", "FOO=1", "app", "--name", "Foo App", "a.txt", "b.txt"],
+            ),
+            (
+                "app --title=%c %U",
+                &["app", "--title=Foo App", "a.txt", "b.txt"],
+            ),
+            (
+                "app --desktop-file %k %F",
+                &[
+                    "app",
+                    "--desktop-file",
+                    "/usr/share/applications/foo.desktop",
+                    "a.txt",
+                    "b.txt",
+                ],
+            ),
+            ("printf 100%% %F", &["printf", "100%", "a.txt", "b.txt"]),
+            ("printf %%f", &["printf", "%f", "a.txt", "b.txt"]),
+            // Deprecated codes are removed
+            ("app %d %D %n %N %v %m %F", &["app", "a.txt", "b.txt"]),
+            ("app --dir=%d %f", &["app", "--dir=", "a.txt", "b.txt"]),
+            // Only `%f`, `%F`, `%u`, and `%U` say where paths go
+            (
+                "app --name %c",
+                &["app", "--name", "Foo App", "a.txt", "b.txt"],
+            ),
+            (
+                r#"app "some arg with spaces" %U"#,
+                &["app", "some arg with spaces", "a.txt", "b.txt"],
+            ),
+            (
+                r#"sh -c "echo \"\$HOME\" \\ \`date\`""#,
+                &["sh", "-c", r#"echo "$HOME" \ `date`"#, "a.txt", "b.txt"],
+            ),
+            (r#"app "a \q b""#, &["app", r"a \q b", "a.txt", "b.txt"]),
+            (r#"app "" %f"#, &["app", "", "a.txt", "b.txt"]),
+            // `#` does not start a comment
+            ("app #1 %f", &["app", "#1", "a.txt", "b.txt"]),
+            (
+                "app 'single quoted' %f",
+                &["app", "single quoted", "a.txt", "b.txt"],
+            ),
+            // Escapes in the value are undone before quoting is applied
+            (
+                r#"sh -c "echo \\"$HOME\\"""#,
+                &["sh", "-c", r#"echo "$HOME""#, "a.txt", "b.txt"],
+            ),
+            (
+                r#"app "back\\\\slash" %f"#,
+                &["app", r"back\slash", "a.txt", "b.txt"],
+            ),
+            (r#"app "a\sb" %f"#, &["app", "a b", "a.txt", "b.txt"]),
+            (r"app a\sb %f", &["app", "a", "b", "a.txt", "b.txt"]),
+            (r#"app "a\tb" %f"#, &["app", "a\tb", "a.txt", "b.txt"]),
+            ("app %i %f", &["app", "--icon", "foo", "a.txt", "b.txt"]),
+            ("app --icon=%i %f", &["app", "--icon=foo", "a.txt", "b.txt"]),
+        ];
+
+        for (exec, expected) in cases {
+            assert_eq!(cmd(exec)?, *expected, "{exec}");
+        }
+
+        assert!(matches!(
+            cmd(r#"app "unterminated"#),
+            Err(Error::BadExec(..))
+        ));
+
+        // `%i` is removed entirely when there is no icon
+        let no_icon = DesktopEntry {
+            icon: None,
+            ..entry.clone()
+        };
+        assert_eq!(cmd_with(&no_icon, "app %i %f")?, ["app", "a.txt", "b.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn literal_percent() -> Result<()> {
+        let config = Config::default();
+        let cmd = |exec: &str| {
+            DesktopEntry::fake_entry(exec, false)
+                .get_cmd(&config, vec!["a.txt".into(), "b.txt".into()])
+        };
+
+        // `%%` is not a field code, so the paths are still appended
+        assert_eq!(
+            cmd("printf 100%%")?,
+            (
+                "printf".into(),
+                vec!["100%".into(), "a.txt".into(), "b.txt".into()]
+            )
+        );
+        assert_eq!(
+            cmd("app --at=%%%F%%f %U")?,
+            (
+                "app".into(),
+                vec![
+                    "--at=%a.txt b.txt%f".into(),
+                    "a.txt".into(),
+                    "b.txt".into()
+                ]
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_types() -> Result<()> {
+        let helix =
+            DesktopEntry::try_from(PathBuf::from("tests/Helix.desktop"))?;
+        assert_eq!(helix.entry_type, EntryType::Application);
+
+        let link = DesktopEntry::try_from(PathBuf::from("tests/link.desktop"))?;
+        assert_eq!(
+            link.entry_type,
+            EntryType::Link("https://doc.rust-lang.org/".into())
+        );
+        assert!(link.exec.is_empty());
+
+        let directory =
+            DesktopEntry::try_from(PathBuf::from("tests/directory.desktop"))?;
+        assert_eq!(directory.entry_type, EntryType::Directory);
+
+        // Only applications can be used as handlers
+        assert!(helix.check_application().is_ok());
+        for entry in [link, directory] {
+            assert!(matches!(
+                entry.check_application(),
+                Err(Error::NotApplication(..))
+            ));
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn terminal_emulator() -> Result<()> {
         let entry = DesktopEntry::try_from(PathBuf::from(
@@ -279,10 +1665,8 @@ mod tests {
         config.terminal_output = false;
 
         config.add_handler(
-            &Mime::from_str("x-scheme-handler/terminal")?,
-            &DesktopHandler::assume_valid(
-                "tests/org.wezfurlong.wezterm.desktop".into(),
-            ),
+            &[Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
         )?;
 
         let entry =
@@ -0,0 +1,243 @@
+use super::path::expand_tilde;
+use crate::config::ConfigFile;
+use indexmap::IndexMap;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// Data directories that are looked in after `$XDG_DATA_DIRS`, from `extra_data_dirs` in the config file
+/// Desktop files are looked up far from where the config file is loaded, and also when it is not, e.g. while completing arguments,
+/// so they are read from the config file the first time they are needed
+/// Reading the config file depends on system state, so tests use the defaults
+static EXTRA_DATA_DIRS: Lazy<Vec<PathBuf>> = Lazy::new(|| {
+    if cfg!(test) {
+        default_extra_data_dirs()
+    } else {
+        ConfigFile::read_extra_data_dirs()
+    }
+});
+
+/// Data directories that desktop files are often exported to without being in `$XDG_DATA_DIRS`,
+/// i.e. flatpak's user and system exports and snap's
+pub fn default_extra_data_dirs() -> Vec<PathBuf> {
+    [
+        "~/.local/share/flatpak/exports/share",
+        "/var/lib/flatpak/exports/share",
+        "/var/lib/snapd/desktop",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect()
+}
+
+/// Get the data directories to look in after `$XDG_DATA_DIRS`, as set in the config file
+pub fn extra_data_dirs() -> &'static [PathBuf] {
+    &EXTRA_DATA_DIRS
+}
+
+/// Get the directories to look for desktop files in, most important first,
+/// i.e. `applications` in `$XDG_DATA_HOME`, each of `$XDG_DATA_DIRS`, and then the given extra data directories
+pub fn applications_dirs(
+    base_dirs: &xdg::BaseDirectories,
+    extra_data_dirs: &[PathBuf],
+) -> Vec<PathBuf> {
+    let xdg_dirs = xdg_applications_dirs(base_dirs);
+    let extra_dirs = extra_applications_dirs(base_dirs, extra_data_dirs);
+    xdg_dirs.into_iter().chain(extra_dirs).collect()
+}
+
+/// Get `applications` in each of the given extra data directories that is not also an XDG data directory
+#[mutants::skip] // Cannot test directly, depends on system state
+pub fn extra_applications_dirs(
+    base_dirs: &xdg::BaseDirectories,
+    extra_data_dirs: &[PathBuf],
+) -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    extra_dirs(
+        &xdg_applications_dirs(base_dirs),
+        extra_data_dirs,
+        home.as_deref(),
+    )
+}
+
+/// Get `applications` in `$XDG_DATA_HOME` followed by each of `$XDG_DATA_DIRS`
+fn xdg_applications_dirs(base_dirs: &xdg::BaseDirectories) -> Vec<PathBuf> {
+    std::iter::once(base_dirs.get_data_home())
+        .chain(base_dirs.get_data_dirs())
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// Get `applications` in each of the given data directories, with `~` replaced by `home`,
+/// leaving out any that are already in `xdg_dirs`
+fn extra_dirs(
+    xdg_dirs: &[PathBuf],
+    data_dirs: &[PathBuf],
+    home: Option<&Path>,
+) -> Vec<PathBuf> {
+    data_dirs
+        .iter()
+        .map(|dir| expand_tilde(dir, home).join("applications"))
+        .filter(|dir| !xdg_dirs.contains(dir))
+        .unique()
+        .collect()
+}
+
+/// Find every desktop file in the given applications directories along with its desktop file id,
+/// which is its path relative to the directory with each `/` replaced by `-`, e.g. `kde4-konsole.desktop`
+/// If more than one file has the same id, only the one in the earliest directory is used
+pub fn desktop_files(dirs: &[PathBuf]) -> Vec<(OsString, PathBuf)> {
+    let mut files = IndexMap::new();
+
+    dirs.iter().for_each(|dir| {
+        walk(dir, OsStr::new(""), &mut |id, path| {
+            files.entry(id).or_insert(path);
+        })
+    });
+
+    files.into_iter().collect()
+}
+
+/// Find the desktop file with a given desktop file id in the earliest of the given applications directories,
+/// which is always the same file that `desktop_files` gives for it
+pub fn find_desktop_file(dirs: &[PathBuf], id: &OsStr) -> Option<PathBuf> {
+    dirs.iter().find_map(|dir| find_in(dir, id))
+}
+
+/// Pass every desktop file in a directory and its subdirectories to `found` along with its id,
+/// which starts with `prefix`
+/// Files come before subdirectories, and subdirectories are gone through in order,
+/// so that ids more than one file could have always go to the same one
+fn walk<F: FnMut(OsString, PathBuf)>(
+    dir: &Path,
+    prefix: &OsStr,
+    found: &mut F,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    // Symlinks to directories are not followed, since they could make loops
+    let (subdirs, files): (Vec<_>, Vec<_>) = entries
+        .flatten()
+        .map(|entry| {
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            (entry.path(), is_dir)
+        })
+        .sorted()
+        .partition(|(_, is_dir)| *is_dir);
+
+    files
+        .into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| path.extension() == Some(OsStr::new("desktop")))
+        .for_each(|path| {
+            let id = join_id(prefix, path.file_name().unwrap_or_default());
+            found(id, path)
+        });
+
+    subdirs.into_iter().for_each(|(subdir, _)| {
+        let mut prefix =
+            join_id(prefix, subdir.file_name().unwrap_or_default());
+        prefix.push("-");
+        walk(&subdir, &prefix, found)
+    });
+}
+
+/// Find the desktop file with a given id in a directory or its subdirectories,
+/// looking in the same order as `walk`
+fn find_in(dir: &Path, id: &OsStr) -> Option<PathBuf> {
+    let path = dir.join(id);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    // Only ids that are valid UTF-8 can be split into directories
+    let id = id.to_str()?;
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| entry.path())
+        .sorted()
+        .find_map(|subdir| {
+            let rest = id
+                .strip_prefix(subdir.file_name()?.to_str()?)?
+                .strip_prefix('-')?;
+            find_in(&subdir, OsStr::new(rest))
+        })
+}
+
+/// Put the part of an id from a file or directory name after the rest of it
+fn join_id(prefix: &OsStr, name: &OsStr) -> OsString {
+    let mut id = prefix.to_owned();
+    id.push(name);
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, utils::TempDir};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn extra_data_dirs() {
+        let home = Path::new("/home/user");
+        let xdg_dirs = [
+            home.join(".local/share/applications"),
+            PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+        ];
+
+        // Directories that are already XDG data directories keep their place
+        assert_eq!(
+            extra_dirs(&xdg_dirs, &default_extra_data_dirs(), Some(home)),
+            [
+                home.join(".local/share/flatpak/exports/share/applications"),
+                PathBuf::from("/var/lib/snapd/desktop/applications"),
+            ]
+        );
+    }
+
+    #[test]
+    fn desktop_file_ids() -> Result<()> {
+        let root = TempDir::new("desktop-files")?;
+        let user = root.join("home/applications");
+        let system = root.join("usr/share/applications");
+        let file = |path: PathBuf| -> Result<PathBuf> {
+            std::fs::create_dir_all(path.parent().expect("has a parent"))?;
+            std::fs::write(&path, "[Desktop Entry]\n")?;
+            Ok(path)
+        };
+
+        let user_override = file(user.join("org.example.Foo.desktop"))?;
+        file(system.join("org.example.Foo.desktop"))?;
+        let konsole = file(system.join("kde4/konsole.desktop"))?;
+        let nested = file(system.join("vendor/sub/app.desktop"))?;
+        // Only desktop files count
+        file(system.join("mimeinfo.cache"))?;
+
+        let dirs = [user, system];
+        assert_eq!(
+            desktop_files(&dirs),
+            [
+                ("org.example.Foo.desktop".into(), user_override.clone()),
+                ("kde4-konsole.desktop".into(), konsole.clone()),
+                ("vendor-sub-app.desktop".into(), nested.clone()),
+            ]
+        );
+
+        let find = |id: &str| find_desktop_file(&dirs, OsStr::new(id));
+        // The user's copy shadows the system's
+        assert_eq!(find("org.example.Foo.desktop"), Some(user_override));
+        assert_eq!(find("kde4-konsole.desktop"), Some(konsole));
+        assert_eq!(find("vendor-sub-app.desktop"), Some(nested));
+        assert_eq!(find("konsole.desktop"), None);
+        assert_eq!(find("kde4-missing.desktop"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use crate::error::{Error, Result};
+use glob::{MatchOptions, Pattern};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr};
+
+/// Patterns that map file names to mimes, from shared-mime-info's `globs2` files
+/// Only used to tell how a file's mime was detected, detection itself is left to xdg-mime
+#[derive(Debug, Default, Clone)]
+pub struct Globs(Vec<Glob>);
+
+/// A single pattern from a `globs2` file
+#[derive(Debug, Clone)]
+struct Glob {
+    weight: u32,
+    mime: Mime,
+    pattern: String,
+    case_sensitive: bool,
+}
+
+impl Globs {
+    /// Load `mime/globs2` from `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn load() -> Result<Self> {
+        let mut globs = Self::default();
+
+        // Unreadable files are skipped like with the rest of shared-mime-info
+        xdg::BaseDirectories::new()?
+            .find_data_files("mime/globs2")
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .for_each(|contents| globs.add_globs2(&contents));
+
+        Ok(globs)
+    }
+
+    /// Add the patterns in the contents of a `globs2` file,
+    /// which has lines in the form `weight:mime:pattern[:flags]`
+    pub fn add_globs2(&mut self, contents: &str) {
+        self.0.extend(
+            contents
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .filter_map(|line| {
+                    let mut fields = line.split(':');
+                    Some(Glob {
+                        weight: fields.next()?.parse().ok()?,
+                        mime: Mime::from_str(fields.next()?).ok()?,
+                        pattern: fields
+                            .next()
+                            .filter(|p| !p.is_empty())?
+                            .to_owned(),
+                        case_sensitive: fields.next().is_some_and(|flags| {
+                            flags.split(',').any(|f| f == "cs")
+                        }),
+                    })
+                }),
+        );
+    }
+
+    /// Get the pattern and weight of the best glob for a given mime that matches a file name,
+    /// i.e. the one with the biggest weight, then the longest pattern, like shared-mime-info recommends
+    pub fn best_match(
+        &self,
+        file_name: &str,
+        mime: &Mime,
+    ) -> Option<(&str, u32)> {
+        self.0
+            .iter()
+            .filter(|glob| glob.mime == *mime && glob.matches(file_name))
+            // `max_by_key` keeps the last of equal elements, so go through them backwards to prefer the first
+            .rev()
+            .max_by_key(|glob| (glob.weight, glob.pattern.len()))
+            .map(|glob| (glob.pattern.as_str(), glob.weight))
+    }
+}
+
+impl Glob {
+    /// Check if the glob's pattern matches a given file name
+    fn matches(&self, file_name: &str) -> bool {
+        matches(&self.pattern, file_name, self.case_sensitive)
+    }
+}
+
+/// Patterns that map file names to mimes from the config file's `mime_overrides`
+/// These take precedence over every other way of detecting a file's mime
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MimeOverrides(BTreeMap<String, String>);
+
+impl MimeOverrides {
+    /// Check that every pattern and mime is valid, so that they can be relied on later
+    pub fn validate(&self) -> Result<()> {
+        self.0.iter().try_for_each(|(pattern, mime)| {
+            Pattern::new(pattern)?;
+            match Mime::from_str(mime) {
+                Ok(mime) if !mime.subtype().as_str().is_empty() => Ok(()),
+                _ => Err(Error::BadMimeOverride {
+                    pattern: pattern.clone(),
+                    mime: mime.clone(),
+                }),
+            }
+        })
+    }
+
+    /// Get the pattern and mime of the override that matches a file name, if any
+    /// If more than one matches, the longest pattern is used
+    /// Case is ignored, like with most shared-mime-info patterns
+    pub fn best_match(&self, file_name: &str) -> Option<(&str, Mime)> {
+        self.0
+            .iter()
+            .filter(|(pattern, _)| matches(pattern, file_name, false))
+            // `max_by_key` keeps the last of equal elements, so go through them backwards to prefer the first
+            .rev()
+            .max_by_key(|(pattern, _)| pattern.len())
+            .and_then(|(pattern, mime)| {
+                Some((pattern.as_str(), Mime::from_str(mime).ok()?))
+            })
+    }
+
+    /// Add an override, skipping validation
+    #[cfg(test)]
+    pub fn insert(&mut self, pattern: &str, mime: &str) {
+        self.0.insert(pattern.to_owned(), mime.to_owned());
+    }
+}
+
+/// Check if a glob pattern matches a given file name
+fn matches(pattern: &str, file_name: &str, case_sensitive: bool) -> bool {
+    Pattern::new(pattern).is_ok_and(|pattern| {
+        pattern.matches_with(
+            file_name,
+            MatchOptions {
+                case_sensitive,
+                require_literal_separator: false,
+                require_literal_leading_dot: false,
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn globs2() -> Result<()> {
+        let mut globs = Globs::default();
+        globs.add_globs2(&std::fs::read_to_string("tests/globs2")?);
+
+        let best = |name: &str, mime: &str| -> Result<Option<(String, u32)>> {
+            Ok(globs
+                .best_match(name, &Mime::from_str(mime)?)
+                .map(|(pattern, weight)| (pattern.to_owned(), weight)))
+        };
+
+        assert_eq!(best("p.html", "text/html")?, Some(("*.html".into(), 80)));
+        assert_eq!(
+            best("p.HTML", "application/xhtml+xml")?,
+            Some(("*.html".into(), 50))
+        );
+        // Patterns can cover more than one extension
+        assert_eq!(
+            best("a.tar.gz", "application/x-compressed-tar")?,
+            Some(("*.tar.gz".into(), 50))
+        );
+        // Case sensitive patterns only match with the same case
+        assert_eq!(
+            best("core", "application/x-core")?,
+            Some(("core".into(), 50))
+        );
+        assert_eq!(best("CORE", "application/x-core")?, None);
+        assert_eq!(
+            best("Makefile", "text/x-makefile")?,
+            Some(("makefile".into(), 50))
+        );
+        assert_eq!(
+            best("makefile.am", "text/x-makefile")?,
+            Some(("makefile.*".into(), 10))
+        );
+        assert_eq!(best("p.html", "image/png")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mime_overrides() -> Result<()> {
+        let mut overrides = MimeOverrides::default();
+        overrides.insert("*.mka", "audio/x-matroska");
+        overrides.insert("*.tar.*", "application/x-tar");
+        overrides.insert("*.tar.zst", "application/x-zstd-compressed-tar");
+        overrides.validate()?;
+
+        let best = |name: &str| {
+            overrides
+                .best_match(name)
+                .map(|(pattern, mime)| (pattern.to_owned(), mime.to_string()))
+        };
+
+        assert_eq!(
+            best("song.MKA"),
+            Some(("*.mka".into(), "audio/x-matroska".into()))
+        );
+        // The longest matching pattern wins
+        assert_eq!(
+            best("a.tar.zst"),
+            Some((
+                "*.tar.zst".into(),
+                "application/x-zstd-compressed-tar".into()
+            ))
+        );
+        assert_eq!(
+            best("a.tar.xz"),
+            Some(("*.tar.*".into(), "application/x-tar".into()))
+        );
+        assert_eq!(best("video.mkv"), None);
+
+        overrides.insert("*.chm", "chm");
+        assert!(matches!(
+            overrides.validate(),
+            Err(Error::BadMimeOverride { pattern, .. }) if pattern == "*.chm"
+        ));
+
+        let mut overrides = MimeOverrides::default();
+        overrides.insert("[*.chm", "application/vnd.ms-htmlhelp");
+        assert!(overrides.validate().is_err());
+
+        Ok(())
+    }
+}
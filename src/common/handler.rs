@@ -1,33 +1,81 @@
 use crate::{
-    common::{DesktopEntry, ExecMode, UserPath},
+    common::{
+        applications_dirs, extra_data_dirs, find_desktop_file, DesktopEntry,
+        EntryType, ExecMode, Row, UserPath,
+    },
     config::Config,
     error::{Error, Result},
 };
-use derive_more::Deref;
 use enum_dispatch::enum_dispatch;
+use itertools::Itertools;
+use mime::Mime;
+use once_cell::unsync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     convert::TryFrom,
     ffi::OsString,
     fmt::Display,
     hash::{Hash, Hasher},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
+use wildmatch::WildMatch;
 
 /// Represents a program or command that is used to open a file
 #[enum_dispatch(Handleable)]
 #[derive(Debug, PartialEq, Eq, Hash)]
+// Only a few handlers exist at a time, so boxing regex handlers would not save anything
+#[allow(clippy::large_enum_variant)]
 pub enum Handler {
     DesktopHandler,
     RegexHandler,
+    FallbackCommand,
+}
+
+impl Display for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DesktopHandler(handler) => handler.fmt(f),
+            Self::RegexHandler(handler) => handler.fmt(f),
+            Self::FallbackCommand(handler) => f.write_str(&handler.0),
+        }
+    }
+}
+
+impl Handler {
+    /// Get the desktop entry that is run for the handler
+    /// Only desktop handlers have actions, so `--action` is ignored for the others
+    pub fn get_entry_for(&self, config: &Config) -> Result<DesktopEntry> {
+        match self {
+            Self::DesktopHandler(handler) => handler.get_entry_for(config),
+            handler => handler.get_entry(),
+        }
+    }
+
+    /// Get how the handler is shown in place of running it,
+    /// along with the action it would run and the tag it would be given, if any
+    pub fn describe_for(&self, config: &Config) -> String {
+        let mut description = self.to_string();
+        if let (Self::DesktopHandler(_), Some(action)) = (self, config.action())
+        {
+            description.push_str(&format!(" --action={action}"));
+        }
+        if let Some(tag) = config.tag() {
+            description.push_str(&format!(" --tag={tag}"));
+        }
+        description
+    }
 }
 
 #[cfg(test)]
 impl Handler {
     /// Helper function for testing
     pub fn new(name: &str) -> Self {
-        Handler::DesktopHandler(DesktopHandler::assume_valid(name.into()))
+        Handler::DesktopHandler(
+            DesktopHandler::from_str(name)
+                .expect("Test handler names should be valid"),
+        )
     }
 }
 
@@ -57,70 +105,548 @@ impl Display for DesktopHandler {
 
 impl FromStr for DesktopHandler {
     type Err = Error;
+    /// Paths are allowed so that desktop files in subdirectories can be given,
+    /// but not characters that would stop the handler from being read back from mimeapps.list
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains([';', '\n', '\r']) {
+            return Err(Error::BadHandlerName(s.to_owned()));
+        }
+
         Ok(DesktopHandler(s.into()))
     }
 }
 
 impl Handleable for DesktopHandler {
+    /// Get the handler's desktop entry, which must be an application
     fn get_entry(&self) -> Result<DesktopEntry> {
-        DesktopEntry::try_from(Self::get_path(&self.0)?)
+        self.get_any_entry()?.check_application()
+    }
+
+    #[mutants::skip] // Cannot test directly, runs commands
+    fn open(&self, config: &Config, args: Vec<String>) -> Result<()> {
+        self.run(config, ExecMode::Open, args)
     }
 }
 
 impl DesktopHandler {
-    /// Create a DesktopHandler, skipping validity checks
-    pub fn assume_valid(name: OsString) -> Self {
-        Self(name)
+    /// Create a DesktopHandler from a desktop file name, skipping checks that it exists
+    /// Names that could not be written to mimeapps.list and read back as the same handler are rejected,
+    /// as are paths, which are never the names of desktop files
+    pub fn assume_valid(name: OsString) -> Result<Self> {
+        match name.to_str() {
+            Some(s) if !s.contains([';', '=', '\n', '\r', '/']) => {
+                Ok(Self(name))
+            }
+            _ => {
+                Err(Error::BadHandlerName(name.to_string_lossy().into_owned()))
+            }
+        }
     }
 
-    /// Get the path of a given desktop entry file
+    /// Get the path of the desktop entry file with a given desktop file id
+    /// The same file is found as when listing installed apps, so that a user's copy takes precedence over the system's
     pub fn get_path(name: &std::ffi::OsStr) -> Result<PathBuf> {
         if cfg!(test) {
             Ok(PathBuf::from(name))
         } else {
-            let mut path = PathBuf::from("applications");
-            path.push(name);
-            Ok(xdg::BaseDirectories::new()?
-                .find_data_file(path)
-                .ok_or_else(|| {
-                    Error::NotFound(name.to_string_lossy().into())
-                })?)
+            find_desktop_file(
+                &applications_dirs(
+                    &xdg::BaseDirectories::new()?,
+                    extra_data_dirs(),
+                ),
+                name,
+            )
+            .ok_or_else(|| Error::NotFound(name.to_string_lossy().into()))
+        }
+    }
+
+    /// Get the path of the handler's desktop entry file
+    /// If it cannot be found, the error lists every directory it was looked for in
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn locate(&self) -> Result<PathBuf> {
+        Self::get_path(&self.0).map_err(|e| match e {
+            Error::NotFound(name) => match xdg::BaseDirectories::new() {
+                Ok(base_dirs) => Error::NotFoundIn(
+                    name,
+                    applications_dirs(&base_dirs, extra_data_dirs())
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect(),
+                ),
+                Err(e) => e.into(),
+            },
+            e => e,
+        })
+    }
+
+    /// Check whether the handler's desktop entry file can be found
+    pub fn exists(&self) -> bool {
+        Self::get_path(&self.0).is_ok()
+    }
+
+    /// Check whether the handler can be used, i.e. its desktop entry file can be found
+    /// and, if `check_try_exec` is set, the program in its `TryExec` key is installed
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn check_usable(&self, check_try_exec: bool) -> Result<()> {
+        // Which programs are installed depends on system state,
+        // so tests only check `TryExec` through `check_usable_in` with their own search path
+        self.check_usable_in(
+            check_try_exec && !cfg!(test),
+            &std::env::var_os("PATH").unwrap_or_default(),
+        )
+    }
+
+    /// Check whether the handler can be used, looking for its `TryExec` program in `search_path`,
+    /// which is in the form of `$PATH`
+    pub fn check_usable_in(
+        &self,
+        check_try_exec: bool,
+        search_path: &std::ffi::OsStr,
+    ) -> Result<()> {
+        let path = Self::get_path(&self.0)?;
+
+        if !check_try_exec {
+            return Ok(());
+        }
+
+        // Entries that cannot be read fail later with a more useful error
+        match DesktopEntry::try_from(path).ok().and_then(|entry| {
+            entry.missing_try_exec(search_path).map(str::to_owned)
+        }) {
+            Some(program) => Err(Error::MissingTryExec {
+                handler: self.to_string(),
+                program,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the handler's desktop entry, even if it is not an application
+    pub fn get_any_entry(&self) -> Result<DesktopEntry> {
+        let entry = DesktopEntry::try_from(Self::get_path(&self.0)?)?;
+        // Entries in subdirectories are known by their desktop file id rather than their file name
+        Ok(DesktopEntry {
+            file_name: Path::new(&self.0)
+                .file_name()
+                .unwrap_or(&self.0)
+                .to_owned(),
+            ..entry
+        })
+    }
+
+    /// Get the handler's desktop entry, with the command of the action given with `--action` instead of its main one
+    pub fn get_entry_for(&self, config: &Config) -> Result<DesktopEntry> {
+        let entry = self.get_entry()?;
+        match config.action() {
+            Some(action) => entry.with_action(action),
+            None => Ok(entry),
         }
     }
 
     /// Launch a DesktopHandler's desktop entry
     #[mutants::skip] // Cannot test directly, runs command
     pub fn launch(&self, config: &Config, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(config, ExecMode::Launch, args)
+        self.run(config, ExecMode::Launch, args)
+    }
+
+    /// Run the handler's desktop entry in the given mode
+    /// If following links is enabled, a Link entry's URL is opened instead, ignoring `args`
+    #[mutants::skip] // Cannot test directly, runs commands
+    fn run(
+        &self,
+        config: &Config,
+        mode: ExecMode,
+        args: Vec<String>,
+    ) -> Result<()> {
+        match self.get_any_entry()?.entry_type {
+            EntryType::Link(url) if config.follows_links() => {
+                config.open_link(&url)
+            }
+            _ => self.get_entry_for(config)?.exec(config, mode, args),
+        }
     }
 }
 
 /// Represents a regex handler from the config
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RegexHandler {
+    /// Name used to refer to the handler, e.g. with `handlr launch --regex`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// Command to run, unless `handler` is given
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     exec: String,
+    /// Desktop file of a handler to use instead of `exec`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    handler: Option<String>,
     #[serde(default)]
     terminal: bool,
+    /// Arguments to give the terminal emulator instead of `term_exec_args` when `terminal` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    term_args: Option<String>,
+    /// Handlers with higher priorities are used over others that match the same path
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: i32,
+    /// Whether patterns ignore case
+    #[serde(default)]
+    case_insensitive: bool,
+    /// Whether patterns have to match the whole path/URL rather than any part of it
+    #[serde(default)]
+    anchored: bool,
+    /// Whether `$1` and `${name}` in `exec` are filled in from the capture groups of the matching pattern
+    /// Off by default, so that commands using `$` for the shell, like `$HOME`, keep working
+    #[serde(default)]
+    capture_groups: bool,
+    /// Patterns of paths to use the handler for, which can be left out if `mimes` are given
+    #[serde(default)]
     regexes: RegexSet,
+    /// Mimes, which can be wildcards like `video/*`, of paths to use the handler for
+    /// along with or instead of `regexes`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mimes: Vec<String>,
+    /// Whether a handler with both `regexes` and `mimes` needs either or both of them to match
+    #[serde(default)]
+    match_mode: MatchMode,
+    /// Patterns that stop the handler from being used even if `regexes` match
+    #[serde(default, skip_serializing_if = "RegexSet::is_empty")]
+    exclude_regexes: RegexSet,
 }
 
 impl RegexHandler {
-    /// Test if a given path matches the handler's regex
-    fn is_match(&self, path: &str) -> bool {
-        self.regexes.is_match(path)
+    /// Test if a given path matches the handler's regexes and/or mimes, and none of its excluded patterns
+    /// The path's mime is only asked for when it is needed, since detecting it can be slow
+    fn is_match<'m>(
+        &self,
+        path: &str,
+        mime: impl FnOnce() -> Option<&'m Mime>,
+    ) -> bool {
+        let matched = match (self.regexes.is_empty(), self.mimes.is_empty()) {
+            (_, true) => self.regexes.is_match(path),
+            (true, false) => self.matches_mime(mime()),
+            (false, false) => match self.match_mode {
+                MatchMode::Any => {
+                    self.regexes.is_match(path) || self.matches_mime(mime())
+                }
+                MatchMode::All => {
+                    self.regexes.is_match(path) && self.matches_mime(mime())
+                }
+            },
+        };
+
+        matched && !self.exclude_regexes.is_match(path)
+    }
+
+    /// Test if a mime matches any of the handler's mimes
+    fn matches_mime(&self, mime: Option<&Mime>) -> bool {
+        mime.is_some_and(|mime| {
+            self.mimes.iter().any(|pattern| {
+                WildMatch::new(pattern).matches(mime.essence_str())
+            })
+        })
+    }
+
+    /// Get the handler's name, or what it runs if it does not have one,
+    /// i.e. its desktop file or its whole command, so that handlers running the same program can be told apart
+    fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.handler.as_deref())
+            .unwrap_or(&self.exec)
+    }
+
+    /// Get the handler's priority over others that match the same path
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Get the flags to compile the handler's patterns with
+    fn flags(&self) -> PatternFlags {
+        PatternFlags {
+            case_insensitive: self.case_insensitive,
+            anchored: self.anchored,
+        }
+    }
+
+    /// Get a copy of the handler with placeholders in `exec` filled in from the given captures,
+    /// if it uses `capture_groups`
+    /// Without captures, every placeholder is left empty
+    fn with_captures(&self, captures: Option<&regex::Captures>) -> Self {
+        if !self.capture_groups {
+            return self.clone();
+        }
+
+        Self {
+            exec: expand_captures(&self.exec, captures),
+            ..self.clone()
+        }
+    }
+}
+
+/// Part of a regex handler's `exec`, split up to fill in capture groups
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+    /// Text that is used as is
+    Text(&'a str),
+    /// Placeholder for a capture group, given by index or name
+    Group(&'a str),
+}
+
+/// Split a regex handler's `exec` into text and placeholders for capture groups
+/// `$1` and `$name` refer to a group by index or name, taking as many letters, digits, and `_` as possible,
+/// `${1}` and `${name}` do the same but can be followed by any text, and `$$` is a literal `$`
+/// A `$` that starts none of these is used as is
+fn split_placeholders(exec: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = exec;
+
+    while let Some(start) = rest.find('$') {
+        pieces.push(Piece::Text(&rest[..start]));
+        let after = &rest[start + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            pieces.push(Piece::Text("$"));
+            rest = after;
+        } else if let Some((group, after)) = after
+            .strip_prefix('{')
+            .and_then(|braced| braced.split_once('}'))
+        {
+            pieces.push(Piece::Group(group));
+            rest = after;
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            pieces.push(match end {
+                0 => Piece::Text("$"),
+                _ => Piece::Group(&after[..end]),
+            });
+            rest = &after[end..];
+        }
+    }
+
+    pieces.push(Piece::Text(rest));
+    pieces
+}
+
+/// Fill in the placeholders for capture groups in a regex handler's `exec`
+/// Groups that did not take part in the match are left empty
+/// Captured text is quoted to keep it a single argument, and `%` is escaped so that it is not taken for a field code
+fn expand_captures(exec: &str, captures: Option<&regex::Captures>) -> String {
+    split_placeholders(exec)
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.to_owned(),
+            Piece::Group(group) => {
+                let value = captures
+                    .and_then(|captures| match group.parse::<usize>() {
+                        Ok(index) => captures.get(index),
+                        Err(_) => captures.name(group),
+                    })
+                    .map_or("", |m| m.as_str())
+                    .replace('%', "%%");
+                // Paths and URLs given on the command line cannot contain nul bytes, the only thing that cannot be quoted
+                shlex::try_quote(&value)
+                    .map(|quoted| quoted.into_owned())
+                    .unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+impl Display for RegexHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.display_name())
     }
 }
 
+/// Check if a number is zero, to leave default priorities out when serializing
+fn is_zero(n: &i32) -> bool {
+    *n == 0
+}
+
 impl Handleable for RegexHandler {
+    /// Get the desktop entry of the handler's desktop file if it has one,
+    /// otherwise make one up from its command
     fn get_entry(&self) -> Result<DesktopEntry> {
-        Ok(DesktopEntry::fake_entry(&self.exec, self.terminal))
+        if let Some(handler) = &self.handler {
+            return DesktopHandler::from_str(handler)?.get_entry();
+        }
+
+        Ok(DesktopEntry {
+            name: self.display_name().to_owned(),
+            term_args: self.term_args.clone(),
+            ..DesktopEntry::fake_entry(&self.exec, self.terminal)
+        })
+    }
+}
+
+/// Represents a command given to open paths that no handler could be found for
+/// It is run like a desktop entry's `Exec`, so without field codes, it is given every path at once, appended to the end
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FallbackCommand(String);
+
+impl FallbackCommand {
+    /// Create a FallbackCommand from a command
+    pub fn new(exec: String) -> Self {
+        Self(exec)
+    }
+}
+
+impl Handleable for FallbackCommand {
+    fn get_entry(&self) -> Result<DesktopEntry> {
+        Ok(DesktopEntry::fake_entry(&self.0, false))
+    }
+}
+
+/// Limits applied when compiling regex handlers' patterns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegexLimits {
+    /// Approximate maximum size in bytes of a handler's compiled patterns
+    pub size_limit: usize,
+    /// Maximum nesting depth of a single pattern
+    pub nest_limit: u32,
+}
+
+impl Default for RegexLimits {
+    fn default() -> Self {
+        // Same as the regex crate's own defaults, but stated explicitly so they can be configured
+        Self {
+            size_limit: 10 * (1 << 20),
+            nest_limit: 250,
+        }
     }
 }
 
+/// How a regex handler with both `regexes` and `mimes` decides whether a path matches
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+enum MatchMode {
+    /// Either the path or its mime has to match
+    #[default]
+    Any,
+    /// Both the path and its mime have to match
+    All,
+}
+
+/// Flags applied to every pattern of a regex handler when compiling them
+#[derive(Debug, Clone, Copy, Default)]
+struct PatternFlags {
+    case_insensitive: bool,
+    anchored: bool,
+}
+
 /// Helper struct needed because regex::RegexSet does not implement Hash
-#[derive(Deref, Debug, Clone, Deserialize)]
-struct RegexSet(#[serde(with = "serde_regex")] regex::RegexSet);
+/// Patterns are only compiled once the config is loaded, so that configured limits apply
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<String>", into = "Vec<String>")]
+struct RegexSet {
+    /// Patterns as written in the config
+    patterns: Vec<String>,
+    /// Compiled patterns, empty until `RegexSet::compile` is called
+    compiled: regex::RegexSet,
+    /// Each pattern compiled on its own to get capture groups from, empty until `RegexSet::compile` is called
+    each: Vec<regex::Regex>,
+}
+
+impl From<Vec<String>> for RegexSet {
+    fn from(patterns: Vec<String>) -> Self {
+        Self {
+            patterns,
+            compiled: regex::RegexSet::empty(),
+            each: Vec::new(),
+        }
+    }
+}
+
+impl From<RegexSet> for Vec<String> {
+    fn from(set: RegexSet) -> Self {
+        set.patterns
+    }
+}
+
+impl RegexSet {
+    /// Check if there are no patterns
+    fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Compile the patterns with the given limits and flags
+    /// On failure, returns the index of the offending pattern along with the error
+    fn compile(
+        &mut self,
+        limits: &RegexLimits,
+        flags: PatternFlags,
+    ) -> std::result::Result<(), (usize, regex::Error)> {
+        let build_one = |pattern: &str| {
+            regex::RegexBuilder::new(pattern)
+                .size_limit(limits.size_limit)
+                .nest_limit(limits.nest_limit)
+                .case_insensitive(flags.case_insensitive)
+                .build()
+        };
+        let build = |patterns: &[String]| {
+            regex::RegexSetBuilder::new(patterns)
+                .size_limit(limits.size_limit)
+                .nest_limit(limits.nest_limit)
+                .case_insensitive(flags.case_insensitive)
+                .build()
+        };
+
+        let patterns = if flags.anchored {
+            // Patterns have to be valid on their own, otherwise wrapping them could change what they mean,
+            // e.g. `a)|(b` would no longer be anchored
+            for (index, pattern) in self.patterns.iter().enumerate() {
+                build_one(pattern).map_err(|error| (index, error))?;
+            }
+            self.patterns
+                .iter()
+                .map(|pattern| format!("^(?:{pattern})$"))
+                .collect()
+        } else {
+            self.patterns.clone()
+        };
+
+        self.compiled = build(&patterns).map_err(|error| {
+            // Find the first pattern that makes compilation fail,
+            // which may just be the one that pushed the set over the size limit
+            let index = (1..=patterns.len())
+                .find(|&end| build(&patterns[..end]).is_err())
+                .map_or(0, |end| end - 1);
+            (index, error)
+        })?;
+
+        self.each = patterns
+            .iter()
+            .enumerate()
+            .map(|(index, pattern)| {
+                build_one(pattern).map_err(|error| (index, error))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(())
+    }
+
+    /// Test if any pattern matches the given string
+    fn is_match(&self, s: &str) -> bool {
+        self.compiled.is_match(s)
+    }
+
+    /// Get the capture groups of the first pattern that matches the given string
+    fn captures<'s>(&self, s: &'s str) -> Option<regex::Captures<'s>> {
+        let index = self.compiled.matches(s).into_iter().next()?;
+        self.each.get(index)?.captures(s)
+    }
+
+    /// Check if every pattern has a given capture group, by index or name
+    fn has_group(&self, group: &str) -> bool {
+        self.each.iter().all(|regex| match group.parse::<usize>() {
+            Ok(index) => index < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|name| name == group),
+        })
+    }
+}
 
 #[cfg(test)]
 impl RegexSet {
@@ -130,14 +656,22 @@ impl RegexSet {
         S: AsRef<str>,
         I: IntoIterator<Item = S>,
     {
-        Ok(RegexSet(regex::RegexSet::new(exprs)?))
+        let mut set = RegexSet::from(
+            exprs
+                .into_iter()
+                .map(|s| s.as_ref().to_owned())
+                .collect::<Vec<_>>(),
+        );
+        set.compile(&RegexLimits::default(), PatternFlags::default())
+            .map_err(|(_, e)| Error::BadRegex(e))?;
+        Ok(set)
     }
 }
 
 impl PartialEq for RegexSet {
     #[mutants::skip] // Trivial
     fn eq(&self, other: &Self) -> bool {
-        self.patterns() == other.patterns()
+        self.patterns == other.patterns
     }
 }
 
@@ -146,53 +680,374 @@ impl Eq for RegexSet {}
 impl Hash for RegexSet {
     #[mutants::skip] // Trivial
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.patterns().hash(state);
+        self.patterns.hash(state);
     }
 }
 
 /// A collection of all of the defined RegexHandlers
-#[derive(Debug, Clone, Default, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RegexApps(Vec<RegexHandler>);
 
 impl RegexApps {
-    /// Get a handler matching a given path
-    pub fn get_handler(&self, path: &UserPath) -> Result<RegexHandler> {
-        Ok(self
-            .0
+    /// Check if there are no regex handlers
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Compile every handler's patterns with the given limits
+    /// Handlers with excluded patterns must also have patterns to match
+    pub fn compile(&mut self, limits: &RegexLimits) -> Result<()> {
+        match self.compile_all(limits).into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Compile every handler's patterns with the given limits,
+    /// getting every problem rather than stopping at the first
+    /// Names are also checked, since they must be unique to be looked up
+    pub fn compile_all(&mut self, limits: &RegexLimits) -> Vec<Error> {
+        let mut errors = Vec::new();
+        let mut names = HashSet::new();
+
+        for (handler, app) in self.0.iter_mut().enumerate() {
+            let before = errors.len();
+
+            match (&app.handler, app.exec.is_empty()) {
+                (Some(_), false) | (None, true) => {
+                    errors.push(Error::ExecOrHandler(handler))
+                }
+                (Some(name), true) => {
+                    if let Err(e) = DesktopHandler::from_str(name) {
+                        errors.push(e);
+                    }
+                }
+                (None, false) => {}
+            }
+
+            if let Some(name) = &app.name {
+                if !names.insert(name.clone()) {
+                    errors.push(Error::DuplicateRegexHandlerName {
+                        handler,
+                        name: name.clone(),
+                    });
+                }
+            }
+
+            if app.regexes.is_empty() && app.mimes.is_empty() {
+                // A handler with nothing to match would silently never be used
+                errors.push(if app.exclude_regexes.is_empty() {
+                    Error::NothingToMatch(handler)
+                } else {
+                    Error::ExcludeWithoutRegexes(handler)
+                });
+                continue;
+            }
+
+            let flags = app.flags();
+            for (field, set) in [
+                ("regexes", &mut app.regexes),
+                ("exclude_regexes", &mut app.exclude_regexes),
+            ] {
+                if let Err((pattern, source)) = set.compile(limits, flags) {
+                    errors.push(Error::BadRegexHandler {
+                        handler,
+                        field,
+                        pattern,
+                        source,
+                    });
+                }
+            }
+
+            // Capture groups can only be checked once the patterns compile
+            if errors.len() > before || !app.capture_groups {
+                continue;
+            }
+
+            errors.extend(
+                split_placeholders(&app.exec)
+                    .into_iter()
+                    .filter_map(|piece| match piece {
+                        Piece::Group(group)
+                            if !app.regexes.has_group(group) =>
+                        {
+                            Some(Error::UnknownCaptureGroup {
+                                handler,
+                                group: group.to_owned(),
+                            })
+                        }
+                        _ => None,
+                    }),
+            );
+        }
+
+        errors
+    }
+
+    /// Get a handler matching a given path, with capture groups in its `exec` filled in
+    /// If more than one matches, the one with the highest priority is used, then the first one
+    /// Only the first `max_input_length` bytes of the path are considered when matching
+    /// `detect_mime` is only called if a handler needs the path's mime
+    pub fn get_handler<F: FnOnce() -> Option<Mime>>(
+        &self,
+        path: &UserPath,
+        max_input_length: usize,
+        detect_mime: F,
+    ) -> Result<RegexHandler> {
+        let path = path.to_string();
+        let input = truncate(&path, max_input_length);
+        let mime = Lazy::new(detect_mime);
+
+        let (_, app) = self
+            .best_match(input, &|| mime.as_ref())
+            .ok_or_else(|| Error::NotFound(path.clone()))?;
+
+        Ok(app.with_captures(app.regexes.captures(input).as_ref()))
+    }
+
+    /// Get the handler that would be used for a given input, along with its position
+    fn best_match<'m>(
+        &self,
+        input: &str,
+        mime: &dyn Fn() -> Option<&'m Mime>,
+    ) -> Option<(usize, &RegexHandler)> {
+        self.0
             .iter()
-            .find(|app| app.is_match(&path.to_string()))
-            .ok_or_else(|| Error::NotFound(path.to_string()))?
-            .clone())
+            .enumerate()
+            .filter(|(_, app)| app.is_match(input, mime))
+            // `max_by_key` keeps the last of equal elements, so go through them backwards to prefer the first
+            .rev()
+            .max_by_key(|(_, app)| app.priority)
+    }
+
+    /// Test a given path against every pattern and mime of every handler, in order
+    /// Only the first `max_input_length` bytes of the path are considered when matching
+    /// `detect_mime` is only called if a handler has mimes
+    pub fn test<F: FnOnce() -> Option<Mime>>(
+        &self,
+        path: &UserPath,
+        max_input_length: usize,
+        detect_mime: F,
+    ) -> Vec<PatternMatch> {
+        let path = path.to_string();
+        let input = truncate(&path, max_input_length);
+        let mime = Lazy::new(detect_mime);
+        let mime = || mime.as_ref();
+        let used = self.best_match(input, &mime).map(|(handler, _)| handler);
+
+        self.0
+            .iter()
+            .enumerate()
+            .flat_map(|(handler, app)| {
+                let row = |field, pattern: &str, matched| PatternMatch {
+                    handler,
+                    name: app.to_string(),
+                    priority: app.priority,
+                    field,
+                    pattern: pattern.to_owned(),
+                    matched,
+                    used: used == Some(handler),
+                };
+
+                let patterns = |field, set: &RegexSet| {
+                    set.patterns
+                        .iter()
+                        .zip(&set.each)
+                        .map(|(pattern, regex)| {
+                            row(field, pattern, regex.is_match(input))
+                        })
+                        .collect_vec()
+                };
+
+                let mut rows = patterns("regexes", &app.regexes);
+                rows.extend(app.mimes.iter().map(|pattern| {
+                    row(
+                        "mimes",
+                        pattern,
+                        mime().is_some_and(|mime| {
+                            WildMatch::new(pattern).matches(mime.essence_str())
+                        }),
+                    )
+                }));
+                rows.extend(patterns("exclude_regexes", &app.exclude_regexes));
+                rows
+            })
+            .collect()
+    }
+
+    /// Get the handler with a given name
+    /// Nothing is matched, so capture groups in its `exec` are left empty
+    pub fn get_named(&self, name: &str) -> Result<RegexHandler> {
+        self.0
+            .iter()
+            .find(|app| app.name.as_deref() == Some(name))
+            .map(|app| app.with_captures(None))
+            .ok_or_else(|| Error::UnknownRegexHandler(name.to_owned()))
+    }
+}
+
+/// How a single pattern of a regex handler fares against a path, to debug which handler is used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatternMatch {
+    /// Position of the handler in the config
+    pub handler: usize,
+    /// Name of the handler, or its command if it does not have one
+    pub name: String,
+    pub priority: i32,
+    /// Which of the handler's patterns it is, i.e. `regexes` or `exclude_regexes`
+    pub field: &'static str,
+    pub pattern: String,
+    pub matched: bool,
+    /// Whether the handler is the one that would be used for the path
+    pub used: bool,
+}
+
+impl Row for PatternMatch {
+    const HEADERS: &'static [&'static str] = &[
+        "handler", "name", "priority", "field", "pattern", "matched", "used",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.handler.to_string(),
+            self.name.clone(),
+            self.priority.to_string(),
+            self.field.to_owned(),
+            self.pattern.clone(),
+            self.matched.to_string(),
+            self.used.to_string(),
+        ]
     }
 }
 
+/// Truncate a string to at most `max_len` bytes without splitting a character
+fn truncate(s: &str, max_len: usize) -> &str {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::common::DesktopEntry;
+    use crate::{common::DesktopEntry, config::ConfigFile};
+    use proptest::prelude::*;
     use url::Url;
 
+    /// Make an uncompiled regex handler that runs `exec` for paths matching `regexes`,
+    /// with everything else left at its default
+    fn regex_handler(exec: &str, regexes: &[&str]) -> RegexHandler {
+        RegexHandler {
+            name: None,
+            exec: exec.to_owned(),
+            handler: None,
+            terminal: false,
+            term_args: None,
+            priority: 0,
+            case_insensitive: false,
+            anchored: false,
+            capture_groups: false,
+            regexes: patterns(regexes),
+            mimes: Vec::new(),
+            match_mode: MatchMode::Any,
+            exclude_regexes: RegexSet::default(),
+        }
+    }
+
+    /// Make an uncompiled set of patterns
+    fn patterns(patterns: &[&str]) -> RegexSet {
+        RegexSet::from(patterns.iter().map(|p| p.to_string()).collect_vec())
+    }
+
+    proptest! {
+        #[test]
+        fn desktop_handler_round_trip(name in "[^;\r\n]*") {
+            let handler = DesktopHandler::from_str(&name)?;
+            prop_assert_eq!(DesktopHandler::from_str(&handler.to_string())?, handler);
+        }
+
+        #[test]
+        fn assume_valid_names(name in "\\PC*") {
+            let valid = !name.contains([';', '=', '\n', '\r', '/']);
+            let handler = DesktopHandler::assume_valid(name.clone().into());
+            prop_assert_eq!(handler.is_ok(), valid);
+
+            // Anything assume_valid accepts is parsed back as the same handler
+            if let Ok(handler) = handler {
+                prop_assert_eq!(DesktopHandler::from_str(&handler.to_string())?, handler);
+            }
+        }
+    }
+
+    #[test]
+    fn bad_handler_names() {
+        for name in [
+            "a;b.desktop",
+            "a=b.desktop",
+            "a\nb.desktop",
+            "apps/a.desktop",
+        ] {
+            assert!(matches!(
+                DesktopHandler::assume_valid(name.into()),
+                Err(Error::BadHandlerName(n)) if n == name
+            ));
+        }
+
+        // mimeapps.list can only hold UTF-8
+        use std::os::unix::ffi::OsStringExt;
+        assert!(DesktopHandler::assume_valid(OsString::from_vec(vec![
+            b'a', 0xff
+        ]))
+        .is_err());
+
+        assert!(DesktopHandler::from_str("a;b.desktop").is_err());
+        // Desktop files can be given by path
+        assert!(DesktopHandler::from_str("apps/a.desktop").is_ok());
+    }
+
+    #[test]
+    fn reject_non_applications() -> Result<()> {
+        let link = DesktopHandler::from_str("tests/link.desktop")?;
+        let error = link.get_entry().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "link.desktop is a Link entry, not an application"
+        );
+        // The entry can still be read when needed
+        assert!(link.get_any_entry().is_ok());
+
+        let directory = DesktopHandler::from_str("tests/directory.desktop")?;
+        assert_eq!(
+            directory.get_entry().unwrap_err().to_string(),
+            "directory.desktop is a Directory entry, not an application"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn regex_handlers() -> Result<()> {
         let exec: &str = "freetube %u";
-        let regexes: &[String] =
-            &[String::from(r"(https://)?(www\.)?youtu(be\.com|\.be)/*")];
 
-        let regex_handler = RegexHandler {
-            exec: String::from(exec),
-            terminal: false,
-            regexes: RegexSet::new(regexes)?,
-        };
-
-        let regex_apps = RegexApps(vec![regex_handler.clone()]);
+        let mut regex_apps = RegexApps(vec![regex_handler(
+            exec,
+            &[r"(https://)?(www\.)?youtu(be\.com|\.be)/*"],
+        )]);
+        regex_apps.compile(&RegexLimits::default())?;
 
         assert_eq!(
             regex_apps
-                .get_handler(&UserPath::Url(Url::parse(
-                    "https://youtu.be/dQw4w9WgXcQ"
-                )?))?
+                .get_handler(
+                    &UserPath::Url(Url::parse("https://youtu.be/dQw4w9WgXcQ")?),
+                    usize::MAX,
+                    || None
+                )?
                 .get_entry()?,
             DesktopEntry {
+                // Unnamed handlers go by the command they run
+                name: exec.to_string(),
                 exec: exec.to_string(),
                 terminal: false,
                 ..Default::default()
@@ -200,11 +1055,612 @@ mod tests {
         );
 
         assert!(regex_apps
-            .get_handler(&UserPath::Url(Url::parse(
-                "https://en.wikipedia.org",
-            )?))
+            .get_handler(
+                &UserPath::Url(Url::parse("https://en.wikipedia.org",)?),
+                usize::MAX,
+                || None
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_compile_errors() -> Result<()> {
+        let handler =
+            |patterns: &[&str]| regex_handler("freetube %u", patterns);
+
+        let mut regex_apps = RegexApps(vec![
+            handler(&["youtube"]),
+            handler(&["youtu.be", "(unclosed"]),
+        ]);
+
+        let error = regex_apps.compile(&RegexLimits::default()).unwrap_err();
+        assert!(error.to_string().contains("handlers[1].regexes[1]"));
+
+        // Patterns that only fail because of configured limits
+        let mut regex_apps = RegexApps(vec![handler(&["a", r"\w{100}"])]);
+        let limits = RegexLimits {
+            size_limit: 1000,
+            ..Default::default()
+        };
+
+        let error = regex_apps.compile(&limits).unwrap_err();
+        assert!(error.to_string().contains("handlers[0].regexes[1]"));
+
+        let mut regex_apps = RegexApps(vec![handler(&["((((a))))"])]);
+        let limits = RegexLimits {
+            nest_limit: 2,
+            ..Default::default()
+        };
+
+        let error = regex_apps.compile(&limits).unwrap_err();
+        assert!(error.to_string().contains("handlers[0].regexes[0]"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_excludes() -> Result<()> {
+        let mut regex_apps: RegexApps = serde_json::from_str(
+            r#"[{
+                "exec": "firefox %u",
+                "regexes": ["reddit\\.com"],
+                "exclude_regexes": ["old\\.reddit\\.com"]
+            }]"#,
+        )?;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let get = |url: &str| -> Result<bool> {
+            Ok(regex_apps
+                .get_handler(
+                    &UserPath::Url(Url::parse(url)?),
+                    usize::MAX,
+                    || None,
+                )
+                .is_ok())
+        };
+
+        assert!(get("https://www.reddit.com/r/rust")?);
+        // Excluded patterns veto the handler even though `regexes` match
+        assert!(!get("https://old.reddit.com/r/rust")?);
+        assert!(!get("https://example.com")?);
+
+        // Both sets of patterns survive being written back out
+        let handler = &regex_apps.0[0];
+        assert_eq!(
+            serde_json::from_str::<RegexHandler>(&serde_json::to_string(
+                handler
+            )?)?,
+            *handler
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn named_regex_handlers() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            name = "youtube"
+            exec = "mpv %u"
+            regexes = ['youtu\.be']
+
+            [[handlers]]
+            exec = "firefox %u"
+            regexes = ['reddit\.com']
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let youtube = regex_apps.get_named("youtube")?;
+        assert_eq!(Handler::from(youtube.clone()).to_string(), "youtube");
+        assert_eq!(youtube.get_entry()?.name, "youtube");
+        assert!(matches!(
+            regex_apps.get_named("firefox %u"),
+            Err(Error::UnknownRegexHandler(name)) if name == "firefox %u"
+        ));
+
+        let reddit = regex_apps.get_handler(
+            &UserPath::Url(Url::parse("https://reddit.com")?),
+            usize::MAX,
+            || None,
+        )?;
+        // Unnamed handlers go by the command they run
+        assert_eq!(Handler::from(reddit).to_string(), "firefox %u");
+
+        regex_apps.0[1].name = Some("youtube".to_string());
+        assert!(matches!(
+            regex_apps.compile(&RegexLimits::default()),
+            Err(Error::DuplicateRegexHandlerName { handler: 1, name }) if name == "youtube"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_desktop_files() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            handler = "tests/Helix.desktop"
+            regexes = ['\.txt$']
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let handler = regex_apps.get_handler(
+            &UserPath::from_str("tests/empty.txt")?,
+            usize::MAX,
+            || None,
+        )?;
+        assert_eq!(handler.to_string(), "tests/Helix.desktop");
+
+        // The desktop file's entry is used as is
+        let entry = handler.get_entry()?;
+        assert_eq!(entry.name, "Helix");
+        assert_eq!(entry.exec, "hx %F");
+        assert!(entry.terminal);
+
+        // Handlers need exactly one of `exec` and `handler`
+        regex_apps.0[0].exec = "hx %F".to_string();
+        assert!(matches!(
+            regex_apps.compile(&RegexLimits::default()),
+            Err(Error::ExecOrHandler(0))
+        ));
+        regex_apps.0[0].exec.clear();
+        regex_apps.0[0].handler = None;
+        assert!(matches!(
+            regex_apps.compile(&RegexLimits::default()),
+            Err(Error::ExecOrHandler(0))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_mimes() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "transmission %f"
+            mimes = ['application/x-bittorrent']
+
+            [[handlers]]
+            exec = "vlc %f"
+            regexes = ['/downloads/']
+            mimes = ['video/*']
+            match_mode = "all"
+
+            [[handlers]]
+            exec = "mpv %u"
+            regexes = ['youtu\.be']
+            mimes = ['audio/*']
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let get = |path: &str, mime: &str| -> Result<Option<String>> {
+            let mime = Mime::from_str(mime)?;
+            Ok(regex_apps
+                .get_handler(&UserPath::from_str(path)?, usize::MAX, || {
+                    Some(mime)
+                })
+                .ok()
+                .map(|handler| handler.exec))
+        };
+
+        assert_eq!(
+            get("a.torrent", "application/x-bittorrent")?.as_deref(),
+            Some("transmission %f")
+        );
+        // Both the path and the mime have to match with `match_mode = "all"`
+        assert_eq!(
+            get("/home/me/downloads/a.mkv", "video/x-matroska")?.as_deref(),
+            Some("vlc %f")
+        );
+        assert_eq!(get("/home/me/a.mkv", "video/x-matroska")?, None);
+        assert_eq!(get("/home/me/downloads/a.txt", "text/plain")?, None);
+        // Either one is enough otherwise
+        assert_eq!(get("a.ogg", "audio/ogg")?.as_deref(), Some("mpv %u"));
+        assert_eq!(
+            get("https://youtu.be/a", "x-scheme-handler/https")?.as_deref(),
+            Some("mpv %u")
+        );
+
+        // Mimes are never detected if no handler needs them
+        let mut regex_apps = RegexApps(vec![RegexHandler {
+            mimes: Vec::new(),
+            ..regex_apps.0[2].clone()
+        }]);
+        regex_apps.compile(&RegexLimits::default())?;
+        assert!(regex_apps
+            .get_handler(&UserPath::from_str("a.ogg")?, usize::MAX, || {
+                unreachable!("the mime should not be needed")
+            })
             .is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn regex_handler_priority() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "firefox %u"
+            regexes = ['reddit\.com']
+
+            [[handlers]]
+            exec = "mpv %u"
+            regexes = ['v\.redd\.it', 'reddit\.com/.*/video']
+            priority = 10
+
+            [[handlers]]
+            exec = "mpv --no-video %u"
+            regexes = ['reddit\.com/.*/video']
+            priority = 10
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let get = |url: &str| -> Result<String> {
+            Ok(regex_apps
+                .get_handler(
+                    &UserPath::Url(Url::parse(url)?),
+                    usize::MAX,
+                    || None,
+                )?
+                .exec)
+        };
+
+        assert_eq!(get("https://reddit.com/r/rust")?, "firefox %u");
+        // Higher priorities win even if they come later,
+        // and handlers with the same priority are picked in order
+        assert_eq!(get("https://reddit.com/r/rust/video")?, "mpv %u");
+
+        // Default priorities are left out
+        assert!(!toml::to_string(&regex_apps.0[0])?.contains("priority"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_flags() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "freetube %u"
+            regexes = ['https://youtu\.be/[\w/]+']
+            exclude_regexes = ['https://youtu\.be/shorts']
+            anchored = true
+            case_insensitive = true
+
+            [[handlers]]
+            exec = "mpv %u"
+            regexes = ['\.mkv$']
+            case_insensitive = true
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let get = |url: &str| -> Result<Option<String>> {
+            Ok(regex_apps
+                .get_handler(
+                    &UserPath::Url(Url::parse(url)?),
+                    usize::MAX,
+                    || None,
+                )
+                .ok()
+                .map(|handler| handler.to_string()))
+        };
+
+        assert_eq!(
+            get("https://example.com/a.MKV")?.as_deref(),
+            Some("mpv %u")
+        );
+        assert_eq!(
+            get("HTTPS://YOUTU.BE/abc")?.as_deref(),
+            Some("freetube %u")
+        );
+        // Anchored patterns have to match the whole URL, including excluded ones
+        assert_eq!(get("https://youtu.be/abc?t=1")?, None);
+        assert_eq!(get("https://example.com/?u=https://youtu.be/abc")?, None);
+        assert_eq!(get("https://youtu.be/shorts")?, None);
+        assert_eq!(
+            get("https://youtu.be/shorts/abc")?.as_deref(),
+            Some("freetube %u")
+        );
+
+        // Anchoring cannot make invalid patterns valid
+        regex_apps.0[0].regexes =
+            RegexSet::from(vec!["youtube".to_string(), "a)|(b".to_string()]);
+        let error = regex_apps.compile(&RegexLimits::default()).unwrap_err();
+        assert!(error.to_string().contains("handlers[0].regexes[1]"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_captures() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "firefox https://old.reddit.com/r/$2 --www=${1}x"
+            regexes = ['https://(www\.)?reddit\.com/r/([^/]+)']
+            capture_groups = true
+
+            [[handlers]]
+            name = "notes"
+            exec = "hx ${dir}/$$name.md"
+            regexes = ['^(?P<dir>.*)/notes\.txt$']
+            capture_groups = true
+            "#,
+        )?
+        .handlers;
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let config = Config::default();
+        let cmd = |path: &str| -> Result<Vec<String>> {
+            let (program, mut args) = regex_apps
+                .get_handler(&UserPath::from_str(path)?, usize::MAX, || None)?
+                .get_entry()?
+                .get_cmd(&config, vec![])?;
+            args.insert(0, program);
+            Ok(args)
+        };
+
+        assert_eq!(
+            cmd("https://www.reddit.com/r/rust/comments")?,
+            ["firefox", "https://old.reddit.com/r/rust", "--www=www.x"]
+        );
+        // Groups that take no part in the match are left empty,
+        // and captured `%` is not taken for a field code
+        assert_eq!(
+            cmd("https://reddit.com/r/%F0%9F")?,
+            ["firefox", "https://old.reddit.com/r/%F0%9F", "--www=x"]
+        );
+        // Captured text stays one argument, and `$$` is a literal `$`
+        assert_eq!(
+            cmd("/tmp/my 'files'/notes.txt")?,
+            ["hx", "/tmp/my 'files'/$name.md"]
+        );
+
+        // Nothing is matched when launching a handler by name
+        assert_eq!(regex_apps.get_named("notes")?.exec, "hx ''/$name.md");
+
+        assert_eq!(expand_captures("a $ b $", None), "a $ b $");
+
+        for (exec, group) in [("mpv $3", "3"), ("mpv ${url}", "url")] {
+            regex_apps.0[0].exec = exec.to_string();
+            assert!(matches!(
+                regex_apps.compile(&RegexLimits::default()),
+                Err(Error::UnknownCaptureGroup { handler: 0, group: g }) if g == group
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_shell_variables() -> Result<()> {
+        let mut regex_apps: RegexApps = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "sh -c 'echo $HOME %u'"
+            regexes = ['example\.com']
+            "#,
+        )?
+        .handlers;
+
+        // Without `capture_groups`, `$` is left for the shell
+        regex_apps.compile(&RegexLimits::default())?;
+        assert_eq!(
+            regex_apps
+                .get_handler(
+                    &UserPath::from_str("https://example.com")?,
+                    usize::MAX,
+                    || None
+                )?
+                .exec,
+            "sh -c 'echo $HOME %u'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_exclude_errors() -> Result<()> {
+        let handler = |regexes: &[&str], excludes: &[&str]| RegexHandler {
+            exclude_regexes: patterns(excludes),
+            ..regex_handler("firefox %u", regexes)
+        };
+
+        let mut regex_apps = RegexApps(vec![
+            handler(&["reddit"], &[]),
+            handler(&[], &[r"old\.reddit\.com"]),
+        ]);
+        assert!(matches!(
+            regex_apps.compile(&RegexLimits::default()),
+            Err(Error::ExcludeWithoutRegexes(1))
+        ));
+
+        let mut regex_apps =
+            RegexApps(vec![handler(&["reddit"], &[]), handler(&[], &[])]);
+        assert!(matches!(
+            regex_apps.compile(&RegexLimits::default()),
+            Err(Error::NothingToMatch(1))
+        ));
+
+        let mut regex_apps =
+            RegexApps(vec![handler(&["reddit"], &["old", "(unclosed"])]);
+        let error = regex_apps.compile(&RegexLimits::default()).unwrap_err();
+        assert!(error.to_string().contains("handlers[0].exclude_regexes[1]"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_long_input() -> Result<()> {
+        let regex_set =
+            RegexSet::new([r"(https://)?(www\.)?youtu(be\.com|\.be)/*."])?;
+
+        // 100 KiB of input that almost, but not quite, matches
+        let input = "https://www.youtu".repeat(100 * 1024 / 17);
+
+        let start = std::time::Instant::now();
+        assert!(!regex_set.is_match(&input));
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_truncated_input() -> Result<()> {
+        let mut regex_apps =
+            RegexApps(vec![regex_handler("freetube %u", &["youtube"])]);
+        regex_apps.compile(&RegexLimits::default())?;
+
+        let path = UserPath::Url(Url::parse("https://example.com/youtube")?);
+
+        assert!(regex_apps.get_handler(&path, usize::MAX, || None).is_ok());
+        // The part of the URL that would match is cut off
+        assert!(regex_apps.get_handler(&path, 20, || None).is_err());
+
+        // Truncation should never split a character
+        assert_eq!(truncate("aé", 2), "a");
+
+        Ok(())
+    }
+
+    // Helper function to compare the final command of a terminal regex handler
+    // with an equivalent desktop entry
+    fn regex_handler_terminal_command(terminal_output: bool) -> Result<()> {
+        let mut config = Config::default();
+        config.terminal_output = terminal_output;
+
+        config.add_handler(
+            &[mime::Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+        )?;
+
+        let regex_handler = RegexHandler {
+            terminal: true,
+            ..regex_handler("hx %F", &[r".*\.txt"])
+        };
+
+        let desktop_entry =
+            DesktopEntry::try_from(PathBuf::from("tests/Helix.desktop"))?;
+
+        let args = vec!["test.txt".to_string()];
+
+        assert_eq!(
+            regex_handler.get_entry()?.get_cmd(&config, args.clone())?,
+            desktop_entry.get_cmd(&config, args)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_handler_terminal_command_piped() -> Result<()> {
+        regex_handler_terminal_command(false)
+    }
+
+    #[test]
+    fn regex_handler_terminal_command_in_terminal() -> Result<()> {
+        regex_handler_terminal_command(true)
+    }
+
+    #[test]
+    fn regex_handler_term_args() -> Result<()> {
+        let mut config = Config::default();
+        config.terminal_output = false;
+
+        config.add_handler(
+            &[mime::Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+        )?;
+
+        let mut regex_handler = RegexHandler {
+            terminal: true,
+            ..regex_handler(r#"sh -c 'echo "$0"; read' %f"#, &[r".*\.txt"])
+        };
+
+        let args = vec!["a b.txt".to_string()];
+        let argv = |handler: &RegexHandler| -> Result<Vec<String>> {
+            let (program, args) =
+                handler.get_entry()?.get_cmd(&config, args.clone())?;
+            Ok(std::iter::once(program).chain(args).collect())
+        };
+
+        // Quoted arguments in exec stay whole after the terminal's own
+        assert_eq!(
+            argv(&regex_handler)?,
+            [
+                "wezterm",
+                "start",
+                "--cwd",
+                ".",
+                "-e",
+                "sh",
+                "-c",
+                r#"echo "$0"; read"#,
+                "a b.txt"
+            ]
+        );
+
+        regex_handler.term_args = Some("--class 'handlr term' --".into());
+        assert_eq!(
+            argv(&regex_handler)?,
+            [
+                "wezterm",
+                "start",
+                "--cwd",
+                ".",
+                "--class",
+                "handlr term",
+                "--",
+                "sh",
+                "-c",
+                r#"echo "$0"; read"#,
+                "a b.txt"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn actions_only_apply_to_desktop_handlers() -> Result<()> {
+        let mut config = Config::default();
+        config.set_action(Some("new-window".into()));
+
+        let firefox = Handler::new("tests/firefox.desktop");
+        assert_eq!(
+            firefox.get_entry_for(&config)?.exec,
+            "firefox --new-window %u"
+        );
+        assert_eq!(
+            firefox.describe_for(&config),
+            "tests/firefox.desktop --action=new-window"
+        );
+
+        // Regex handlers and fallback commands have no actions to run
+        let regex = Handler::from(regex_handler("mpv %u", &[r"youtu\.be"]));
+        assert_eq!(regex.get_entry_for(&config)?.exec, "mpv %u");
+        assert_eq!(regex.describe_for(&config), regex.to_string());
+
+        let fallback = Handler::from(FallbackCommand::new("nvim".into()));
+        assert_eq!(fallback.get_entry_for(&config)?.exec, "nvim");
+        assert_eq!(fallback.describe_for(&config), "nvim");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,60 @@
+use crate::common::DesktopEntry;
+use std::path::Path;
+
+/// Check whether a process running the program from an entry's `Exec` key appears to be running
+/// by looking through the command lines of processes under `proc_dir`
+/// This is a best-effort guess, e.g. wrapper scripts and interpreters are not recognized
+pub fn is_running(proc_dir: &Path, entry: &DesktopEntry) -> bool {
+    let Some(program) = entry.program() else {
+        return false;
+    };
+
+    let Ok(processes) = std::fs::read_dir(proc_dir) else {
+        return false;
+    };
+
+    processes
+        .flatten()
+        // Only numbered directories are processes
+        .filter(|process| {
+            process.file_name().to_string_lossy().parse::<u32>().is_ok()
+        })
+        .filter_map(|process| {
+            std::fs::read(process.path().join("cmdline")).ok()
+        })
+        .any(|cmdline| {
+            let first = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+            Path::new(&*String::from_utf8_lossy(first)).file_name()
+                == Some(program.as_os_str())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, utils::TempDir};
+
+    #[test]
+    fn running_processes() -> Result<()> {
+        let proc_dir = TempDir::new("proc")?;
+        let process = |pid: &str, cmdline: &[u8]| -> Result<()> {
+            std::fs::create_dir_all(proc_dir.join(pid))?;
+            std::fs::write(proc_dir.join(pid).join("cmdline"), cmdline)?;
+            Ok(())
+        };
+
+        process("1", b"/sbin/init\0splash\0")?;
+        process("42", b"/usr/bin/single\0--new-window\0")?;
+        // Not a process
+        process("self", b"/usr/bin/other\0")?;
+
+        let entry = |exec: &str| DesktopEntry::fake_entry(exec, false);
+        assert!(is_running(&proc_dir, &entry("single %U")));
+        assert!(is_running(&proc_dir, &entry("/opt/bin/single")));
+        assert!(!is_running(&proc_dir, &entry("other")));
+        assert!(!is_running(&proc_dir, &entry("singles")));
+        assert!(!is_running(&proc_dir.join("missing"), &entry("single")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::Result;
+use mime::Mime;
+use std::{collections::HashMap, str::FromStr};
+
+/// Relationships between mimes from shared-mime-info's `subclasses` and `aliases` files
+/// e.g. `text/x-rust` is a subclass of `text/plain`, and `application/x-pdf` is an alias of `application/pdf`
+#[derive(Debug, Default, Clone)]
+pub struct MimeInfo {
+    /// Direct parents of each mime, in the order they were listed
+    parents: HashMap<Mime, Vec<Mime>>,
+    /// Canonical mime of each alias
+    aliases: HashMap<Mime, Mime>,
+}
+
+impl MimeInfo {
+    /// Load `mime/subclasses` and `mime/aliases` from `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn load() -> Result<Self> {
+        let mut info = Self::default();
+        let base_dirs = xdg::BaseDirectories::new()?;
+
+        // Unreadable files are skipped rather than making opening anything fail
+        let read = |file| {
+            base_dirs
+                .find_data_files(file)
+                .filter_map(|path| std::fs::read_to_string(path).ok())
+        };
+
+        read("mime/subclasses").for_each(|c| info.add_subclasses(&c));
+        read("mime/aliases").for_each(|c| info.add_aliases(&c));
+
+        Ok(info)
+    }
+
+    /// Add the subclasses in the contents of a `subclasses` file,
+    /// which has a mime and one of its parents on each line
+    pub fn add_subclasses(&mut self, contents: &str) {
+        for (mime, parent) in mime_pairs(contents) {
+            let parents = self.parents.entry(mime).or_default();
+            if !parents.contains(&parent) {
+                parents.push(parent);
+            }
+        }
+    }
+
+    /// Add the aliases in the contents of an `aliases` file,
+    /// which has an alias and its canonical mime on each line
+    /// Directories with higher precedence are loaded first, so aliases that are already known are kept
+    pub fn add_aliases(&mut self, contents: &str) {
+        for (alias, canonical) in mime_pairs(contents) {
+            self.aliases.entry(alias).or_insert(canonical);
+        }
+    }
+
+    /// Get the canonical mime for a given mime, which is the mime itself if it is not an alias
+    pub fn canonical(&self, mime: &Mime) -> Mime {
+        self.aliases.get(mime).unwrap_or(mime).clone()
+    }
+
+    /// Get every mime that a given mime is a subclass of, directly or not,
+    /// with closer parents first
+    pub fn ancestors(&self, mime: &Mime) -> Vec<Mime> {
+        let mut ancestors = Vec::<Mime>::new();
+        let mut next = 0;
+        let mut current = mime.clone();
+
+        loop {
+            for parent in self.parents_of(&current) {
+                if parent != *mime && !ancestors.contains(&parent) {
+                    ancestors.push(parent);
+                }
+            }
+
+            match ancestors.get(next) {
+                Some(ancestor) => current = ancestor.clone(),
+                None => break,
+            }
+            next += 1;
+        }
+
+        ancestors
+    }
+
+    /// Get the direct parents of a given mime
+    fn parents_of(&self, mime: &Mime) -> Vec<Mime> {
+        let mut parents = self.parents.get(mime).cloned().unwrap_or_default();
+
+        // Every text format is implicitly a subclass of text/plain
+        if mime.type_() == mime::TEXT
+            && *mime != mime::TEXT_PLAIN
+            && !parents.contains(&mime::TEXT_PLAIN)
+        {
+            parents.push(mime::TEXT_PLAIN);
+        }
+
+        parents
+    }
+}
+
+/// Get the pairs of mimes on each line of a shared-mime-info file
+fn mime_pairs(contents: &str) -> impl Iterator<Item = (Mime, Mime)> + '_ {
+    contents.lines().filter_map(|line| {
+        let (first, second) = line.split_once(' ')?;
+        Some((Mime::from_str(first).ok()?, Mime::from_str(second).ok()?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    // Helper function to load the bundled subclasses fixture
+    fn hierarchy() -> Result<MimeInfo> {
+        let mut hierarchy = MimeInfo::default();
+        hierarchy.add_subclasses(&std::fs::read_to_string("tests/subclasses")?);
+        Ok(hierarchy)
+    }
+
+    // Helper function to get the ancestors of a mime as strings
+    fn ancestors(hierarchy: &MimeInfo, mime: &str) -> Result<Vec<String>> {
+        Ok(hierarchy
+            .ancestors(&Mime::from_str(mime)?)
+            .iter()
+            .map(|m| m.to_string())
+            .collect())
+    }
+
+    #[test]
+    fn direct_parents() -> Result<()> {
+        let hierarchy = hierarchy()?;
+
+        assert_eq!(ancestors(&hierarchy, "text/x-rust")?, vec!["text/plain"]);
+        assert_eq!(
+            ancestors(&hierarchy, "application/x-zip-compressed-fb2")?,
+            vec!["application/zip"]
+        );
+        assert!(ancestors(&hierarchy, "image/png")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn indirect_parents() -> Result<()> {
+        let hierarchy = hierarchy()?;
+
+        assert_eq!(
+            ancestors(&hierarchy, "application/x-shellscript")?,
+            vec!["application/x-executable", "text/plain"]
+        );
+        assert_eq!(
+            ancestors(&hierarchy, "application/x-perl")?,
+            vec!["application/x-executable", "text/x-script", "text/plain"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn implicit_text_plain() -> Result<()> {
+        let hierarchy = MimeInfo::default();
+
+        assert_eq!(ancestors(&hierarchy, "text/x-lua")?, vec!["text/plain"]);
+        assert!(ancestors(&hierarchy, "text/plain")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_aliases() -> Result<()> {
+        let mut info = MimeInfo::default();
+        info.add_aliases(&std::fs::read_to_string("tests/aliases")?);
+        // Aliases from directories with lower precedence do not override
+        info.add_aliases("application/x-pdf application/x-other\n");
+
+        let canonical = |mime: &str| -> Result<String> {
+            Ok(info.canonical(&Mime::from_str(mime)?).to_string())
+        };
+
+        assert_eq!(canonical("application/x-pdf")?, "application/pdf");
+        assert_eq!(canonical("text/xml")?, "application/xml");
+        assert_eq!(canonical("audio/x-wav")?, "audio/vnd.wave");
+        // Canonical mimes are left alone
+        assert_eq!(canonical("application/pdf")?, "application/pdf");
+
+        Ok(())
+    }
+
+    #[test]
+    fn subclass_cycle() -> Result<()> {
+        let mut hierarchy = MimeInfo::default();
+        hierarchy.add_subclasses(
+            "application/x-a application/x-b\napplication/x-b application/x-a\n",
+        );
+
+        assert_eq!(
+            ancestors(&hierarchy, "application/x-a")?,
+            vec!["application/x-b"]
+        );
+
+        Ok(())
+    }
+}
@@ -1,22 +1,35 @@
-use crate::error::{Error, Result};
+use crate::{
+    common::{DesktopHandler, Globs, MimeOverrides},
+    error::{Error, Result},
+};
 use derive_more::Deref;
 use mime::Mime;
-use std::{convert::TryFrom, path::Path, str::FromStr};
+use serde::Serialize;
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    path::Path,
+    str::FromStr,
+};
 use url::Url;
+use xdg_mime::SharedMimeInfo;
 
 /// A mime derived from a path or URL
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MimeType(pub Mime);
 
 impl MimeType {
-    fn from_ext(ext: &str) -> Result<Mime> {
-        match &*xdg_mime::SharedMimeInfo::new()
-            .get_mime_types_from_file_name(ext)
-        {
+    /// Get the mime of a file extension or file name
+    pub fn from_ext(ext: &str) -> Result<Mime> {
+        let db = SharedMimeInfo::new();
+        match &*db.get_mime_types_from_file_name(ext) {
             [m] if m == &mime::APPLICATION_OCTET_STREAM => {
                 Err(Error::Ambiguous(ext.into()))
             }
-            [guess, ..] => Ok(guess.clone()),
+            [guess, ..] => {
+                Ok(db.unalias_mime_type(guess).unwrap_or_else(|| guess.clone()))
+            }
             [] => unreachable!(),
         }
     }
@@ -34,31 +47,198 @@ impl TryFrom<&Url> for MimeType {
 impl TryFrom<&Path> for MimeType {
     type Error = Error;
     fn try_from(path: &Path) -> Result<Self> {
-        let db = xdg_mime::SharedMimeInfo::new();
+        Self::from_path(path, true)
+    }
+}
 
-        let mut guess = db.guess_mime_type();
-        guess.file_name(&path.to_string_lossy());
+/// A mime detected from a file, along with how it was detected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub mime: Mime,
+    pub provenance: Provenance,
+}
 
-        let mime = if let Some(mime) =
-            mime_to_option(&db, guess.guess().mime_type().clone(), true)
-        {
-            mime
-        } else {
-            mime_to_option(
-                &db,
-                guess.path(path).guess().mime_type().clone(),
-                false,
-            )
-            .ok_or_else(|| Error::Ambiguous(path.to_owned()))?
+/// How a file's mime was detected
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Provenance {
+    /// What the file's extension was considered to be, if it has one
+    pub extension: Option<String>,
+    /// The shared-mime-info glob pattern that matched the file's name
+    pub matched_glob: Option<String>,
+    /// The weight of the pattern in `matched_glob`, higher weights take precedence
+    pub glob_weight: Option<u32>,
+    /// Whether the file's contents were used because its name was not enough
+    pub sniffed: bool,
+    /// The pattern from the config file's `mime_overrides` that matched the file's name,
+    /// in which case nothing else was used
+    #[serde(rename = "override")]
+    pub override_pattern: Option<String>,
+}
+
+impl MimeType {
+    /// Get the mime of the file at a given path
+    /// If its name is not enough to go on and `sniff` is true, the start of its contents is used too
+    pub fn from_path(path: &Path, sniff: bool) -> Result<Self> {
+        Ok(Self(
+            Self::detect(
+                path,
+                sniff,
+                &MimeOverrides::default(),
+                &Globs::default(),
+            )?
+            .mime,
+        ))
+    }
+
+    /// Get the mime of the file at a given path along with how it was detected
+    /// `overrides` that match the file's name are used before anything else
+    /// `globs` are only used to tell which pattern matched the file's name
+    pub fn detect(
+        path: &Path,
+        sniff: bool,
+        overrides: &MimeOverrides,
+        globs: &Globs,
+    ) -> Result<Detected> {
+        // Follows symlinks, and unlike guessing from the file name, is not thrown off by trailing slashes
+        if path.is_dir() {
+            return Ok(Detected {
+                mime: Mime::from_str("inode/directory")?,
+                provenance: Provenance::default(),
+            });
+        }
+
+        let db = SharedMimeInfo::new();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let (mime, provenance) = match overrides.best_match(&file_name) {
+            Some((pattern, mime)) => {
+                let provenance = Provenance {
+                    extension: extension(&file_name, Some(pattern)),
+                    override_pattern: Some(pattern.to_owned()),
+                    ..Default::default()
+                };
+                (mime, provenance)
+            }
+            None => Self::guess(&db, path, &file_name, sniff, globs)?,
         };
 
-        Ok(Self(mime))
+        Ok(Detected {
+            // Report aliases as their canonical mime
+            mime: db.unalias_mime_type(&mime).unwrap_or(mime),
+            provenance,
+        })
+    }
+
+    /// Guess the mime of the file at a given path from its name, then from its contents if `sniff` is true
+    fn guess(
+        db: &SharedMimeInfo,
+        path: &Path,
+        file_name: &str,
+        sniff: bool,
+        globs: &Globs,
+    ) -> Result<(Mime, Provenance)> {
+        let mut guess = db.guess_mime_type();
+        guess.file_name(&path.to_string_lossy());
+
+        Ok(
+            match mime_to_option(db, guess.guess().mime_type().clone(), true) {
+                Some(mime) => {
+                    let glob = globs.best_match(file_name, &mime);
+                    let provenance = Provenance {
+                        extension: extension(file_name, glob.map(|(p, _)| p)),
+                        matched_glob: glob
+                            .map(|(pattern, _)| pattern.to_owned()),
+                        glob_weight: glob.map(|(_, weight)| weight),
+                        ..Default::default()
+                    };
+                    (mime, provenance)
+                }
+                None if sniff => {
+                    let mime = sniff_mime(db, path)?
+                        .ok_or_else(|| Error::Ambiguous(path.to_owned()))?;
+                    let provenance = Provenance {
+                        extension: extension(file_name, None),
+                        sniffed: true,
+                        ..Default::default()
+                    };
+                    (mime, provenance)
+                }
+                None => return Err(Error::Ambiguous(path.to_owned())),
+            },
+        )
     }
 }
 
+/// Get what a file's extension is considered to be given the glob pattern that matched its name, if any
+/// Patterns like `*.tar.gz` cover everything after the first dot they include,
+/// otherwise it is whatever comes after the last dot
+fn extension(file_name: &str, pattern: Option<&str>) -> Option<String> {
+    let from_pattern = pattern
+        .and_then(|pattern| pattern.strip_prefix("*."))
+        .filter(|ext| !ext.contains(['*', '?', '[', '\\']))
+        .and_then(|ext| {
+            file_name.get(file_name.len().checked_sub(ext.len())?..)
+        });
+
+    from_pattern
+        .or_else(|| Path::new(file_name).extension()?.to_str())
+        .map(str::to_owned)
+}
+
+/// How much of a file is read when guessing its mime from its contents
+/// Comfortably more than the magic rules in shared-mime-info look at
+const SNIFF_LENGTH: u64 = 16 * 1024;
+
+/// Guess the mime of a file from the start of its contents
+/// Empty files are `application/x-zerosize`, and files that do not match any magic rule
+/// but look like text are `text/plain`
+fn sniff_mime(db: &SharedMimeInfo, path: &Path) -> Result<Option<Mime>> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        // Let nonexistent files be reported as ambiguous like before
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Fail on unreadable files rather than guessing from nothing
+    let data = read_start(path, SNIFF_LENGTH)?;
+
+    let mime = db
+        .guess_mime_type()
+        .metadata(metadata)
+        .data(&data)
+        .guess()
+        .mime_type()
+        .clone();
+
+    Ok(match mime_to_option(db, mime, false) {
+        None if looks_like_text(&data) => Some(mime::TEXT_PLAIN),
+        mime => mime,
+    })
+}
+
+/// Read at most the first `length` bytes of a file
+fn read_start(path: &Path, length: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.take(length).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Check if data looks like text, i.e. it is UTF-8 without any null bytes
+/// The data may have been cut off in the middle of a character
+fn looks_like_text(data: &[u8]) -> bool {
+    !data.is_empty()
+        && !data.contains(&0)
+        && std::str::from_utf8(data)
+            .map_or_else(|e| e.error_len().is_none(), |_| true)
+}
+
 /// Tests if a given mime is "acceptable" and returns None otherwise
 fn mime_to_option(
-    db: &xdg_mime::SharedMimeInfo,
+    db: &SharedMimeInfo,
     mime: Mime,
     discard_zerosize: bool,
 ) -> Option<Mime> {
@@ -75,28 +255,67 @@ fn mime_to_option(
 }
 
 /// Mime derived from user input: extension(.pdf) or type like image/jpg
+/// Along with the extension it came from, if any
 #[derive(Debug, Clone, Deref)]
-pub struct MimeOrExtension(pub Mime);
+pub struct MimeOrExtension(#[deref] pub Mime, pub Option<String>);
 
 impl FromStr for MimeOrExtension {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self> {
-        let mime = if s.starts_with('.') {
-            MimeType::from_ext(s)?
-        } else {
-            match Mime::from_str(s)? {
-                m if m.subtype() == "" => return Err(Error::InvalidMime(m)),
-                proper_mime => proper_mime,
+        if s.starts_with('.') {
+            let mime = MimeType::from_ext(s).map_err(|e| match e {
+                Error::Ambiguous(_) => Error::UnknownExtension(s.to_owned()),
+                e => e,
+            })?;
+            return Ok(Self(mime, Some(s.to_owned())));
+        }
+
+        match Mime::from_str(s)? {
+            m if m.subtype() == "" => Err(Error::InvalidMime(m)),
+            proper_mime => Ok(Self(proper_mime, None)),
+        }
+    }
+}
+
+impl MimeOrExtension {
+    /// Split the arguments of `handlr set` and `handlr add` into the mimes and the handler to use for them
+    /// If no handler is given some other way, it is the last argument if there is more than one,
+    /// otherwise it is left to be picked
+    pub fn with_handler(
+        args: &[String],
+        handler: Option<DesktopHandler>,
+    ) -> Result<(Vec<Self>, Option<DesktopHandler>)> {
+        let (mimes, handler) = match (args, handler) {
+            (mimes, Some(handler)) => (mimes, Some(handler)),
+            ([mimes @ .., handler], None) if !mimes.is_empty() => {
+                (mimes, Some(DesktopHandler::from_str(handler)?))
             }
+            (mimes, None) => (mimes, None),
         };
 
-        Ok(Self(mime))
+        Ok((
+            mimes
+                .iter()
+                .map(|mime| Self::from_str(mime))
+                .collect::<Result<_>>()?,
+            handler,
+        ))
+    }
+
+    /// Write which mime an extension was turned into, so that it is clear what is used in its place
+    /// Nothing is written for mimes given as they are
+    pub fn explain<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if let Some(extension) = &self.1 {
+            writeln!(writer, "note: using '{}' for '{extension}'", self.0)?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::TempDir;
 
     #[test]
     fn user_input() -> Result<()> {
@@ -108,6 +327,60 @@ mod tests {
 
         assert!("image//jpg".parse::<MimeOrExtension>().is_err());
         assert!("image".parse::<MimeOrExtension>().is_err());
+        assert!(matches!(
+            ".handlr-unknown".parse::<MimeOrExtension>(),
+            Err(Error::UnknownExtension(ext)) if ext == ".handlr-unknown"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_handler() -> Result<()> {
+        let args = |args: &[&str]| {
+            args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()
+        };
+
+        let (mimes, handler) =
+            MimeOrExtension::with_handler(&args(&["application/pdf"]), None)?;
+        assert_eq!(mimes.len(), 1);
+        assert_eq!(mimes[0].0, mime::APPLICATION_PDF);
+        assert!(handler.is_none());
+
+        let (mimes, handler) = MimeOrExtension::with_handler(
+            &args(&["image/png", ".jpg", "imv.desktop"]),
+            None,
+        )?;
+        assert_eq!(mimes.len(), 2);
+        assert_eq!(mimes[1].0, mime::IMAGE_JPEG);
+        assert_eq!(handler.map(|h| h.to_string()), Some("imv.desktop".into()));
+
+        assert!(MimeOrExtension::with_handler(
+            &args(&["image", "imv.desktop"]),
+            None
+        )
+        .is_err());
+
+        // Every argument is a mime when the handler is already known
+        let (mimes, handler) = MimeOrExtension::with_handler(
+            &args(&["video/mp4", ".mkv"]),
+            Some(DesktopHandler::assume_valid("vlc.desktop".into())?),
+        )?;
+        assert_eq!(mimes.len(), 2);
+        assert_eq!(handler.map(|h| h.to_string()), Some("vlc.desktop".into()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn explain_extension() -> Result<()> {
+        let mut buffer = Vec::new();
+        MimeOrExtension::from_str(".pdf")?.explain(&mut buffer)?;
+        MimeOrExtension::from_str("image/png")?.explain(&mut buffer)?;
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "note: using 'application/pdf' for '.pdf'\n"
+        );
 
         Ok(())
     }
@@ -118,6 +391,10 @@ mod tests {
             MimeType::try_from(Path::new("."))?.0.essence_str(),
             "inode/directory"
         );
+        assert_eq!(
+            MimeType::try_from(Path::new("./tests/"))?.0.essence_str(),
+            "inode/directory"
+        );
         assert_eq!(
             MimeType::try_from(Path::new("./tests/rust.vim"))?.0,
             "text/plain"
@@ -153,6 +430,195 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn sniff_contents() -> Result<()> {
+        let dir = TempDir::new("sniff")?;
+
+        let png = dir.join("download");
+        std::fs::write(
+            &png,
+            b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0",
+        )?;
+        assert_eq!(MimeType::from_path(&png, true)?.0, "image/png");
+        assert!(matches!(
+            MimeType::from_path(&png, false),
+            Err(Error::Ambiguous(_))
+        ));
+
+        // Text that matches no magic rule is still text
+        let notes = dir.join("notes");
+        std::fs::write(&notes, "groceries: eggs, milk, bread\n")?;
+        assert_eq!(MimeType::from_path(&notes, true)?.0, mime::TEXT_PLAIN);
+
+        // Only the start of large files is read
+        let large = dir.join("large");
+        std::fs::write(&large, vec![b'a'; 4 * SNIFF_LENGTH as usize])?;
+        assert_eq!(
+            read_start(&large, SNIFF_LENGTH)?.len(),
+            SNIFF_LENGTH as usize
+        );
+        assert_eq!(MimeType::from_path(&large, true)?.0, mime::TEXT_PLAIN);
+
+        // Unreadable files are an error rather than a guess
+        // Root can read anything, so this can only be checked as a regular user
+        let secret = dir.join("secret");
+        std::fs::write(&secret, "hunter2\n")?;
+        std::fs::set_permissions(
+            &secret,
+            std::os::unix::fs::PermissionsExt::from_mode(0o000),
+        )?;
+        if File::open(&secret).is_err() {
+            assert!(matches!(
+                MimeType::from_path(&secret, true),
+                Err(Error::Io(e)) if e.kind() == ErrorKind::PermissionDenied
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn weighted_glob_provenance() -> Result<()> {
+        let mut globs = Globs::default();
+        globs.add_globs2(&std::fs::read_to_string("tests/globs2")?);
+
+        // `*.html` is both text/html and application/xhtml+xml, but the former has a bigger weight
+        assert_eq!(
+            MimeType::detect(
+                Path::new("./tests/p.html"),
+                true,
+                &MimeOverrides::default(),
+                &globs
+            )?,
+            Detected {
+                mime: mime::TEXT_HTML,
+                provenance: Provenance {
+                    extension: Some("html".into()),
+                    matched_glob: Some("*.html".into()),
+                    glob_weight: Some(80),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Without any globs, only the extension is known
+        assert_eq!(
+            MimeType::detect(
+                Path::new("./tests/p.html"),
+                true,
+                &MimeOverrides::default(),
+                &Globs::default()
+            )?
+            .provenance,
+            Provenance {
+                extension: Some("html".into()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            extension("a.TAR.GZ", Some("*.tar.gz")),
+            Some("TAR.GZ".into())
+        );
+        assert_eq!(extension("a.tar.gz", None), Some("gz".into()));
+        assert_eq!(
+            extension("makefile.am", Some("makefile.*")),
+            Some("am".into())
+        );
+        assert_eq!(extension("Makefile", Some("makefile")), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sniffed_provenance() -> Result<()> {
+        let mut globs = Globs::default();
+        globs.add_globs2(&std::fs::read_to_string("tests/globs2")?);
+
+        let dir = TempDir::new("provenance")?;
+        let png = dir.join("download");
+        std::fs::write(
+            &png,
+            b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0",
+        )?;
+
+        assert_eq!(
+            MimeType::detect(&png, true, &MimeOverrides::default(), &globs)?,
+            Detected {
+                mime: mime::IMAGE_PNG,
+                provenance: Provenance {
+                    sniffed: true,
+                    ..Default::default()
+                },
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overridden_provenance() -> Result<()> {
+        let mut globs = Globs::default();
+        globs.add_globs2(&std::fs::read_to_string("tests/globs2")?);
+        let mut overrides = MimeOverrides::default();
+        overrides.insert("*.html", "application/xhtml+xml");
+        overrides.insert("cat", "text/x-script");
+
+        // Beats the file's extension
+        assert_eq!(
+            MimeType::detect(
+                Path::new("./tests/p.html"),
+                true,
+                &overrides,
+                &globs
+            )?,
+            Detected {
+                mime: Mime::from_str("application/xhtml+xml")?,
+                provenance: Provenance {
+                    extension: Some("html".into()),
+                    override_pattern: Some("*.html".into()),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Beats the file's contents, and is used even when they would not be
+        assert_eq!(
+            MimeType::detect(
+                Path::new("./tests/cat"),
+                false,
+                &overrides,
+                &globs
+            )?,
+            Detected {
+                mime: Mime::from_str("text/x-script")?,
+                provenance: Provenance {
+                    override_pattern: Some("cat".into()),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Only file names are matched
+        assert_eq!(
+            MimeType::detect(Path::new("./tests"), true, &overrides, &globs)?
+                .mime,
+            "inode/directory"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn text_detection() {
+        assert!(looks_like_text(b"plain old text"));
+        // Cut off in the middle of a character
+        assert!(looks_like_text(&"caf\u{e9}".as_bytes()[..4]));
+        assert!(!looks_like_text(b"\x89PNG\0\0"));
+        assert!(!looks_like_text(b"\xff\xfe text"));
+        assert!(!looks_like_text(b""));
+    }
+
     #[test]
     fn from_ext() -> Result<()> {
         assert_eq!(".mp3".parse::<MimeOrExtension>()?.0, "audio/mpeg");
@@ -1,15 +1,42 @@
 mod db;
+#[cfg(feature = "dbus")]
+mod dbus;
 mod desktop_entry;
+mod desktop_files;
+mod globs;
 mod handler;
+mod instance;
+mod mime_info;
 mod mime_types;
 mod path;
+mod scheme;
+mod selector;
 mod table;
+mod trace;
 
 pub use self::db::mime_types;
-pub use desktop_entry::{DesktopEntry, Mode as ExecMode};
+pub use desktop_entry::{
+    locales, DesktopAction, DesktopEntry, EntryType, Mode as ExecMode,
+};
+pub use desktop_files::{
+    applications_dirs, default_extra_data_dirs, desktop_files,
+    extra_applications_dirs, extra_data_dirs, find_desktop_file,
+};
+pub use globs::{Globs, MimeOverrides};
 pub use handler::{
-    DesktopHandler, Handleable, Handler, RegexApps, RegexHandler,
+    DesktopHandler, FallbackCommand, Handleable, Handler, PatternMatch,
+    RegexApps, RegexHandler, RegexLimits,
+};
+pub use mime_info::MimeInfo;
+pub use mime_types::{Detected, MimeOrExtension, MimeType, Provenance};
+pub use path::{dedup_paths, expand_globs, mime_table, UserPath};
+pub use scheme::{
+    applications_dir, remove_scheme_desktop_file, scheme_mime,
+    update_desktop_database, write_scheme_desktop_file,
+};
+pub use selector::{join as join_command, Selector};
+pub use table::{
+    render_table, render_table_without, write_row, write_rows, Row,
+    TableConfig, TableStyle,
 };
-pub use mime_types::{MimeOrExtension, MimeType};
-pub use path::{mime_table, UserPath};
-pub use table::render_table;
+pub use trace::{HandlerSource, Trace};
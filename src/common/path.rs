@@ -1,48 +1,228 @@
 use crate::{
-    common::{render_table, MimeType},
+    cli::OutputFormat,
+    common::{
+        write_rows, Detected, Globs, MimeOverrides, MimeType, Provenance, Row,
+        TableConfig,
+    },
     error::{Error, Result},
+    utils,
 };
+use itertools::Itertools;
 use mime::Mime;
 use serde::Serialize;
 use std::{
-    convert::{TryFrom, TryInto},
     fmt::{Display, Formatter},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
-use tabled::Tabled;
 use url::Url;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UserPath {
     Url(Url),
     File(PathBuf),
 }
 
 impl UserPath {
-    pub fn get_mime(&self) -> Result<Mime> {
-        Ok(match self {
-            Self::Url(url) => Ok(url.try_into()?),
-            Self::File(f) => MimeType::try_from(f.as_path()),
-        }?
-        .0)
+    /// Get the mime of the path/URL, only looking at a file's contents if `sniff` is true
+    /// Files whose names match one of `overrides` are given its mime instead
+    pub fn get_mime(
+        &self,
+        sniff: bool,
+        overrides: &MimeOverrides,
+    ) -> Result<Mime> {
+        Ok(self.detect_mime(sniff, overrides, &Globs::default())?.mime)
+    }
+
+    /// Get the mime of the path/URL along with how it was detected
+    /// URLs' mimes come from their schemes, so there is nothing to say about how they were detected
+    pub fn detect_mime(
+        &self,
+        sniff: bool,
+        overrides: &MimeOverrides,
+        globs: &Globs,
+    ) -> Result<Detected> {
+        match self {
+            Self::Url(url) => Ok(Detected {
+                mime: MimeType::try_from(url)?.0,
+                provenance: Provenance::default(),
+            }),
+            Self::File(f) => MimeType::detect(f, sniff, overrides, globs),
+        }
+    }
+
+    /// Get the mime of the file an http(s) URL points to from the extension at the end of its path, if there is one
+    pub fn url_file_mime(&self) -> Option<Mime> {
+        let Self::Url(url) = self else {
+            return None;
+        };
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let name = url.path_segments()?.next_back()?;
+        Path::new(name).extension()?;
+        MimeType::from_ext(name).ok()
+    }
+
+    /// Turn a file path that does not exist but looks like a URL without its scheme,
+    /// e.g. `example.com/page` or `localhost:8080`, into an https URL
+    pub fn assume_https(self) -> Self {
+        match self {
+            Self::File(ref path) if !path.exists() => path
+                .to_str()
+                .filter(|s| is_schemeless_url(s))
+                .and_then(|s| Url::parse(&format!("https://{s}")).ok())
+                .map_or(self, Self::Url),
+            path => path,
+        }
+    }
+
+    /// Expand a file path that does not exist but looks like a glob pattern into the files it matches
+    /// Paths that exist are never treated as patterns, even if they contain glob metacharacters
+    pub fn expand_glob(self) -> Result<Vec<Self>> {
+        match self {
+            Self::File(path) if !path.exists() && is_glob(&path) => {
+                let home = std::env::var_os("HOME").map(PathBuf::from);
+                let pattern = expand_tilde(&path, home.as_deref());
+
+                let matches = glob::glob(&pattern.to_string_lossy())?
+                    .map(|entry| entry.map(Self::File))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(glob::GlobError::into_error)?;
+
+                if matches.is_empty() {
+                    Err(Error::NoGlobMatches(path.to_string_lossy().into()))
+                } else {
+                    Ok(matches)
+                }
+            }
+            path => Ok(vec![path]),
+        }
+    }
+}
+
+/// Expand any paths that look like glob patterns because a shell did not expand them
+pub fn expand_globs(paths: Vec<UserPath>) -> Result<Vec<UserPath>> {
+    paths
+        .into_iter()
+        .map(UserPath::expand_glob)
+        .flatten_ok()
+        .collect()
+}
+
+/// Remove repeated paths and URLs, keeping the first of each
+/// Files are the same if they canonicalize to the same path, and URLs are the same if they are equal once parsed,
+/// e.g. when they only differ by a default port, but not when they only differ by a trailing slash
+/// Also returns how many were removed
+pub fn dedup_paths(paths: &[UserPath]) -> (Vec<UserPath>, usize) {
+    let unique = paths
+        .iter()
+        .unique_by(|path| match path {
+            UserPath::File(file) => UserPath::File(
+                std::fs::canonicalize(file).unwrap_or_else(|_| file.clone()),
+            ),
+            url => (*url).clone(),
+        })
+        .cloned()
+        .collect_vec();
+    let removed = paths.len() - unique.len();
+    (unique, removed)
+}
+
+/// Check if a path contains glob metacharacters
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Replace a leading `~` in a path with the given home directory, like a shell would
+pub fn expand_tilde(path: &Path, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+/// Parse a string as a URL, unless it only looks like one by accident
+/// Single letter schemes are Windows drive letters (e.g. `C:\Users` from wine),
+/// and hostnames followed by a port (e.g. `localhost:8080/page`) are hosts rather than schemes
+fn parse_url(s: &str) -> Option<Url> {
+    let url = Url::parse(s).ok()?;
+    let (scheme, rest) = s.split_once(':')?;
+
+    if scheme.len() == 1 || (is_hostname(scheme) && starts_with_port(rest)) {
+        None
+    } else {
+        Some(url)
     }
 }
 
+/// Check if a string is a hostname that could not be mistaken for a custom scheme,
+/// i.e. `localhost` or a name with more than one label
+fn is_hostname(s: &str) -> bool {
+    let labels = s.split('.').collect_vec();
+    (labels.len() > 1 || s.eq_ignore_ascii_case("localhost"))
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Check if a string starts with a port number that is followed by nothing or the rest of a URL
+fn starts_with_port(s: &str) -> bool {
+    let (port, _) = s.split_once(['/', '?', '#']).unwrap_or((s, ""));
+    port.parse::<u16>().is_ok() && port.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Generic top-level domains that are also extensions of known file formats, e.g. `.org` for Org mode
+const GENERIC_TLDS: &[&str] = &["com", "org", "net", "io", "dev", "app"];
+
+/// Check if a name on its own like `notes.txt` is more likely a file than a domain,
+/// because its last label is the extension of a known file format rather than a common top-level domain
+#[mutants::skip] // Depends on the system's shared mime info database
+fn is_file_name(name: &str) -> bool {
+    name.rsplit('.').next().is_some_and(|ext| {
+        !GENERIC_TLDS.iter().any(|tld| tld.eq_ignore_ascii_case(ext))
+    }) && MimeType::from_ext(name).is_ok()
+}
+
+/// Check if a string looks like a URL without its scheme, i.e. a hostname like `example.com`
+/// with a top-level domain made of letters, or `localhost`, optionally followed by a port and a path
+/// Hostnames on their own that look like file names, e.g. `README.md`, do not count
+fn is_schemeless_url(s: &str) -> bool {
+    let (authority, _) = s.split_once(['/', '?', '#']).unwrap_or((s, ""));
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    };
+
+    let has_tld = host.rsplit('.').next().is_some_and(|tld| {
+        tld.len() > 1 && tld.chars().all(|c| c.is_ascii_alphabetic())
+    });
+
+    is_hostname(host)
+        && (has_tld || host.eq_ignore_ascii_case("localhost"))
+        && port.is_none_or(starts_with_port)
+        && (port.is_some() || authority.len() < s.len() || !is_file_name(host))
+}
+
 impl FromStr for UserPath {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let normalized = match url::Url::parse(s) {
-            Ok(url) if url.scheme() == "file" => {
+        let normalized = match parse_url(s) {
+            Some(url) if url.scheme() == "file" => {
                 let path = url
                     .to_file_path()
                     .map_err(|_| Error::BadPath(url.path().to_owned()))?;
 
                 Self::File(path)
             }
-            Ok(url) => Self::Url(url),
-            _ => Self::File(PathBuf::from(s)),
+            Some(url) => Self::Url(url),
+            None => Self::File(PathBuf::from(s)),
         };
 
         Ok(normalized)
@@ -52,6 +232,13 @@ impl FromStr for UserPath {
 impl Display for UserPath {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
+            // Relative paths like `a:b` would be read back as URLs, so make it clear they are files
+            Self::File(f)
+                if f.is_relative()
+                    && parse_url(&f.to_string_lossy()).is_some() =>
+            {
+                write!(fmt, "./{}", f.to_string_lossy())
+            }
             Self::File(f) => fmt.write_str(&f.to_string_lossy()),
             Self::Url(u) => fmt.write_str(u.as_ref()),
         }
@@ -59,48 +246,174 @@ impl Display for UserPath {
 }
 
 /// Internal helper struct for turning a UserPath into tabular data
-#[derive(Tabled, Serialize)]
+#[derive(Serialize)]
 struct UserPathTable {
     path: String,
     mime: String,
+    /// Only included in JSON output, and only when asked for
+    #[serde(flatten)]
+    provenance: Option<Provenance>,
 }
 
 impl UserPathTable {
-    fn new(path: &UserPath) -> Result<Self> {
+    fn new(
+        path: &UserPath,
+        sniff: bool,
+        overrides: &MimeOverrides,
+        globs: Option<&Globs>,
+    ) -> Result<Self> {
+        let detected = path.detect_mime(
+            sniff,
+            overrides,
+            globs.unwrap_or(&Globs::default()),
+        )?;
         Ok(Self {
             path: path.to_string(),
-            mime: path.get_mime()?.essence_str().to_owned(),
+            mime: detected.mime.essence_str().to_owned(),
+            provenance: globs.map(|_| detected.provenance),
         })
     }
 }
 
+impl Row for UserPathTable {
+    const HEADERS: &'static [&'static str] = &["path", "mime"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.path.clone(), self.mime.clone()]
+    }
+}
+
 /// Render a table of mime types from a list of paths
 /// and write it to the given writer
+/// Files' contents are only used to get their mimes if `sniff` is true,
+/// and files whose names match one of `overrides` are given its mime instead
+/// If `globs` are given, JSON output also says how each file's mime was detected using them
 pub fn mime_table<W: Write>(
     writer: &mut W,
     paths: &[UserPath],
-    output_json: bool,
-    terminal_output: bool,
+    format: OutputFormat,
+    sniff: bool,
+    overrides: &MimeOverrides,
+    globs: Option<&Globs>,
+    table: &TableConfig,
 ) -> Result<()> {
-    let rows = paths
+    let mut rows = paths
         .iter()
-        .map(UserPathTable::new)
-        .collect::<Result<Vec<UserPathTable>>>()?;
-
-    let table = if output_json {
-        serde_json::to_string(&rows)?
-    } else {
-        render_table(&rows, terminal_output)
-    };
+        .map(|path| UserPathTable::new(path, sniff, overrides, globs));
 
-    writeln!(writer, "{table}")?;
+    if format == OutputFormat::Jsonl {
+        // Write each row as soon as its mime is known
+        return rows.try_for_each(|row| utils::write_json_line(writer, &row?));
+    }
 
-    Ok(())
+    write_rows(
+        writer,
+        &rows.collect::<Result<Vec<UserPathTable>>>()?,
+        format,
+        table,
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::TempDir;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn user_path_round_trip(s in "\\PC*") {
+            if let Ok(path) = UserPath::from_str(&s) {
+                let parsed = UserPath::from_str(&path.to_string())?;
+                prop_assert_eq!(parsed.to_string(), path.to_string());
+                // Files must not turn into URLs or the other way around
+                prop_assert_eq!(
+                    matches!(parsed, UserPath::File(_)),
+                    matches!(path, UserPath::File(_))
+                );
+            }
+        }
+
+        #[test]
+        fn file_round_trip(s in "([a-z]+:)?[^\0]+") {
+            let path = UserPath::File(PathBuf::from(&s));
+            let parsed = UserPath::from_str(&path.to_string())?;
+            prop_assert!(
+                parsed == path
+                    || parsed == UserPath::File(Path::new(".").join(&s)),
+                "{} was parsed as {:?}",
+                path,
+                parsed
+            );
+        }
+    }
+
+    #[test]
+    fn tricky_paths() -> Result<()> {
+        // (input, parsed, with `assume_https`), where `None` means a file with the input as its path
+        for (input, url, https) in [
+            // Wine paths
+            (r"C:\Users\me\file.txt", None, None),
+            ("c:/windows/notepad.exe", None, None),
+            // Hosts with ports
+            (
+                "localhost:8080/page",
+                None,
+                Some("https://localhost:8080/page"),
+            ),
+            ("localhost:8080", None, Some("https://localhost:8080/")),
+            (
+                "example.com:443?q=1",
+                None,
+                Some("https://example.com/?q=1"),
+            ),
+            // Bare domains
+            (
+                "chrome.google.com",
+                None,
+                Some("https://chrome.google.com/"),
+            ),
+            ("docs.rs/url", None, Some("https://docs.rs/url")),
+            ("localhost", None, Some("https://localhost/")),
+            ("example.org", None, Some("https://example.org/")),
+            // Not domains
+            ("archive.tar.7z", None, None),
+            ("-bad-.com", None, None),
+            ("notes", None, None),
+            ("tests/p.html", None, None),
+            // File names that do not exist
+            ("notes.txt", None, None),
+            ("README.md", None, None),
+            ("report.pdf", None, None),
+            // Real URLs, including custom schemes
+            ("https://example.com", Some("https://example.com/"), None),
+            ("mailto:me@example.com", Some("mailto:me@example.com"), None),
+            ("tel:911", Some("tel:911"), None),
+            (
+                "magnet:?xt=urn:btih:abc",
+                Some("magnet:?xt=urn:btih:abc"),
+                None,
+            ),
+            ("steam://run/440", Some("steam://run/440"), None),
+            ("org.example.app:path", Some("org.example.app:path"), None),
+            ("localhost:page", Some("localhost:page"), None),
+        ] {
+            let parsed = UserPath::from_str(input)?;
+            let expected = match url {
+                Some(url) => UserPath::Url(Url::parse(url)?),
+                None => UserPath::File(PathBuf::from(input)),
+            };
+            assert_eq!(parsed, expected, "{input}");
+
+            let expected = match https {
+                Some(url) => UserPath::Url(Url::parse(url)?),
+                None => expected,
+            };
+            assert_eq!(parsed.assume_https(), expected, "{input}");
+        }
+
+        Ok(())
+    }
 
     // Helper function to create a vector of UserPaths for testing `mime_table`
     fn paths() -> Result<Vec<UserPath>> {
@@ -124,9 +437,18 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "tables")]
     fn mime_table_terminal() -> Result<()> {
         let mut buffer = Vec::new();
-        mime_table(&mut buffer, &paths()?, false, true)?;
+        mime_table(
+            &mut buffer,
+            &paths()?,
+            OutputFormat::Table,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
@@ -134,24 +456,329 @@ mod tests {
     #[test]
     fn test_mime_table_piped() -> Result<()> {
         let mut buffer = Vec::new();
-        mime_table(&mut buffer, &paths()?, false, false)?;
+        mime_table(
+            &mut buffer,
+            &paths()?,
+            OutputFormat::Tsv,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mime_table_csv() -> Result<()> {
+        let mut buffer = Vec::new();
+        mime_table(
+            &mut buffer,
+            &paths()?,
+            OutputFormat::Csv,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
     #[test]
     fn test_mime_table_json() -> Result<()> {
-        //NOTE: both calls should have the same result
-        // JSON output and terminal output
         let mut buffer = Vec::new();
-        mime_table(&mut buffer, &paths()?, true, true)?;
+        mime_table(
+            &mut buffer,
+            &paths()?,
+            OutputFormat::Json,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
         goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mime_table_provenance() -> Result<()> {
+        let mut globs = Globs::default();
+        globs.add_globs2(&std::fs::read_to_string("tests/globs2")?);
 
-        // JSON output and no terminal output
         let mut buffer = Vec::new();
-        mime_table(&mut buffer, &paths()?, true, false)?;
+        mime_table(
+            &mut buffer,
+            &paths()?,
+            OutputFormat::Json,
+            true,
+            &MimeOverrides::default(),
+            Some(&globs),
+            &TableConfig::default(),
+        )?;
         goldie::assert!(String::from_utf8(buffer)?);
 
+        // Tables never include provenance
+        let mut table = Vec::new();
+        mime_table(
+            &mut table,
+            &paths()?,
+            OutputFormat::Tsv,
+            true,
+            &MimeOverrides::default(),
+            Some(&globs),
+            &TableConfig::default(),
+        )?;
+        let mut plain = Vec::new();
+        mime_table(
+            &mut plain,
+            &paths()?,
+            OutputFormat::Tsv,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
+        assert_eq!(table, plain);
+
+        Ok(())
+    }
+
+    // Writer that records everything written to it at each flush
+    #[derive(Default)]
+    struct FlushRecorder {
+        buffer: Vec<u8>,
+        flushes: Vec<String>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes
+                .push(String::from_utf8_lossy(&self.buffer).into_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mime_table_jsonl() -> Result<()> {
+        let paths = paths()?;
+        let mut recorder = FlushRecorder::default();
+        mime_table(
+            &mut recorder,
+            &paths,
+            OutputFormat::Jsonl,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
+
+        let output = String::from_utf8(recorder.buffer)?;
+
+        // Each line should match the corresponding element of the JSON array
+        let mut json = Vec::new();
+        mime_table(
+            &mut json,
+            &paths,
+            OutputFormat::Json,
+            true,
+            &MimeOverrides::default(),
+            None,
+            &TableConfig::default(),
+        )?;
+        let json: Vec<serde_json::Value> = serde_json::from_slice(&json)?;
+        let lines = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(lines, json);
+
+        // Output should have been flushed after every line rather than all at once
+        assert_eq!(recorder.flushes.len(), paths.len());
+        for (i, flushed) in recorder.flushes.iter().enumerate() {
+            assert_eq!(flushed.lines().count(), i + 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn directory_mime() -> Result<()> {
+        let dir = TempDir::new("directory")?;
+        let link = dir.join("tests");
+        std::os::unix::fs::symlink(std::fs::canonicalize("tests")?, &link)?;
+
+        for path in [
+            "tests/".to_owned(),
+            format!("file://{}/", std::fs::canonicalize("tests")?.display()),
+            link.to_string_lossy().into_owned(),
+            format!("{}/", link.display()),
+        ] {
+            assert_eq!(
+                UserPath::from_str(&path)?
+                    .get_mime(true, &MimeOverrides::default())?
+                    .essence_str(),
+                "inode/directory",
+                "{path}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn url_file_mimes() -> Result<()> {
+        for (url, mime) in [
+            ("https://example.com/paper.pdf", Some("application/pdf")),
+            ("http://example.com/a/b/song.mp3?t=10#x", Some("audio/mpeg")),
+            ("https://example.com/papers/", None),
+            ("https://example.com/paper", None),
+            ("https://example.com", None),
+            ("ftp://example.com/paper.pdf", None),
+        ] {
+            assert_eq!(
+                UserPath::from_str(url)?
+                    .url_file_mime()
+                    .map(|m| m.to_string()),
+                mime.map(str::to_owned),
+                "{url}"
+            );
+        }
+
+        assert_eq!(UserPath::from_str("tests/p.html")?.url_file_mime(), None);
+
         Ok(())
     }
+
+    // Helper function to get a unique directory with the given files in it
+    fn glob_dir(name: &str, files: &[&str]) -> Result<TempDir> {
+        let dir = TempDir::new(&format!("glob-{name}"))?;
+        for file in files {
+            std::fs::write(dir.join(file), "")?;
+        }
+        Ok(dir)
+    }
+
+    #[test]
+    fn glob_matches() -> Result<()> {
+        let dir = glob_dir("matches", &["b.png", "a.png", "c.txt"])?;
+
+        let paths = expand_globs(vec![
+            UserPath::File(dir.join("*.png")),
+            UserPath::from_str("https://duckduckgo.com")?,
+            UserPath::File(dir.join("c.tx?")),
+        ])?;
+
+        assert_eq!(
+            paths.iter().map(|p| p.to_string()).collect_vec(),
+            [
+                dir.join("a.png").to_string_lossy().into_owned(),
+                dir.join("b.png").to_string_lossy().into_owned(),
+                "https://duckduckgo.com/".to_owned(),
+                dir.join("c.txt").to_string_lossy().into_owned(),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_no_matches() -> Result<()> {
+        let dir = glob_dir("no-matches", &["a.png"])?;
+
+        assert!(matches!(
+            expand_globs(vec![UserPath::File(dir.join("*.gif"))]),
+            Err(Error::NoGlobMatches(pattern)) if pattern.ends_with("*.gif")
+        ));
+
+        // Paths without metacharacters are left for opening to fail on
+        let missing = dir.join("missing.gif");
+        assert_eq!(
+            expand_globs(vec![UserPath::File(missing.clone())])?
+                .iter()
+                .map(|p| p.to_string())
+                .collect_vec(),
+            [missing.to_string_lossy()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_literal_star() -> Result<()> {
+        let dir = glob_dir("literal", &["*.png", "a.png"])?;
+
+        // A file that exists is opened as is, even though it looks like a pattern
+        let star = dir.join("*.png");
+        assert_eq!(
+            expand_globs(vec![UserPath::File(star.clone())])?
+                .iter()
+                .map(|p| p.to_string())
+                .collect_vec(),
+            [star.to_string_lossy()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_paths() -> Result<()> {
+        let paths = [
+            "tests/p.html",
+            "https://example.com/a",
+            "./tests/p.html",
+            "tests/../tests/p.html",
+            "https://example.com:443/a",
+            "https://example.com/a/",
+            "http://example.com:80",
+            "http://example.com/",
+            "tests/rust.vim",
+        ]
+        .iter()
+        .map(|path| UserPath::from_str(path))
+        .collect::<Result<Vec<_>>>()?;
+
+        let (unique, removed) = dedup_paths(&paths);
+        assert_eq!(
+            unique.iter().map(|path| path.to_string()).collect_vec(),
+            [
+                "tests/p.html",
+                "https://example.com/a",
+                // Trailing slashes are not normalized away
+                "https://example.com/a/",
+                "http://example.com/",
+                "tests/rust.vim",
+            ]
+        );
+        assert_eq!(removed, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tilde_expansion() {
+        let home = Path::new("/home/user");
+
+        assert_eq!(
+            expand_tilde(Path::new("~/Pictures/*.png"), Some(home)),
+            Path::new("/home/user/Pictures/*.png")
+        );
+        assert_eq!(
+            expand_tilde(Path::new("~"), Some(home)),
+            Path::new("/home/user")
+        );
+        // Only a leading `~` on its own is the home directory
+        assert_eq!(
+            expand_tilde(Path::new("~user/*.png"), Some(home)),
+            Path::new("~user/*.png")
+        );
+        assert_eq!(
+            expand_tilde(Path::new("~/*.png"), None),
+            Path::new("~/*.png")
+        );
+    }
 }
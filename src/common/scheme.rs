@@ -0,0 +1,297 @@
+use crate::{
+    common::DesktopHandler,
+    error::{Error, Result},
+    utils,
+};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+/// Key added to desktop files generated by handlr so that they are never mistaken for anything else
+const GENERATED_KEY: &str = "X-Handlr-Generated=true";
+
+/// Check that a URL scheme is valid according to RFC 3986
+/// i.e. it starts with a letter followed by letters, digits, `+`, `-`, or `.`
+pub fn validate_scheme(scheme: &str) -> Result<()> {
+    let mut chars = scheme.chars();
+
+    match chars.next() {
+        Some(first)
+            if first.is_ascii_alphabetic()
+                && chars.all(|c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')
+                }) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::BadScheme(scheme.to_owned())),
+    }
+}
+
+/// Get the mime used for handlers of a given URL scheme
+pub fn scheme_mime(scheme: &str) -> Result<mime::Mime> {
+    validate_scheme(scheme)?;
+    Ok(mime::Mime::from_str(&format!(
+        "x-scheme-handler/{}",
+        scheme.to_ascii_lowercase()
+    ))?)
+}
+
+/// Get the user's applications directory, i.e. `$XDG_DATA_HOME/applications`
+#[mutants::skip] // Cannot test directly, depends on system state
+pub fn applications_dir() -> Result<PathBuf> {
+    Ok(xdg::BaseDirectories::new()?
+        .get_data_home()
+        .join("applications"))
+}
+
+/// Get the name of the desktop file generated for a given URL scheme
+fn generated_file_name(scheme: &str) -> String {
+    format!("handlr-scheme-{}.desktop", scheme.to_ascii_lowercase())
+}
+
+/// Escape a command for use as the value of an `Exec` key
+/// Backslashes are escaped so that they reach the command as written,
+/// as is any `%` that does not start `%u`, `%U`, `%f`, or `%F`
+/// Returns the escaped command and whether it has any of those field codes
+fn escape_exec(command: &str) -> (String, bool) {
+    let mut escaped = String::with_capacity(command.len());
+    let mut has_field_code = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '%' => match chars.peek() {
+                Some(&code @ ('u' | 'U' | 'f' | 'F')) => {
+                    chars.next();
+                    has_field_code = true;
+                    escaped.push('%');
+                    escaped.push(code);
+                }
+                _ => escaped.push_str("%%"),
+            },
+            c => escaped.push(c),
+        }
+    }
+
+    (escaped, has_field_code)
+}
+
+/// Get the contents of a desktop file that opens URLs with a given scheme using a given command
+fn desktop_file_contents(scheme: &str, command: &str) -> String {
+    let (exec, has_field_code) = escape_exec(command);
+    // Pass the URL at the end if the command does not say where it goes
+    let exec = if has_field_code {
+        exec
+    } else {
+        format!("{exec} %u")
+    };
+    let scheme = scheme.to_ascii_lowercase();
+
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name={scheme} URL handler
+Exec={exec}
+NoDisplay=true
+MimeType=x-scheme-handler/{scheme};
+{GENERATED_KEY}
+"
+    )
+}
+
+/// Write a desktop file to `applications_dir` that opens URLs with a given scheme using a given command
+/// Nothing is written if an identical file already exists
+pub fn write_scheme_desktop_file(
+    applications_dir: &Path,
+    scheme: &str,
+    command: &str,
+) -> Result<DesktopHandler> {
+    validate_scheme(scheme)?;
+
+    if !matches!(shlex::split(command), Some(words) if !words.is_empty()) {
+        return Err(Error::BadCmd(command.to_owned()));
+    }
+    // A line break would end the `Exec` key early and start a new one
+    if command.contains(['\n', '\r']) {
+        return Err(Error::MultilineCmd(command.to_owned()));
+    }
+
+    let file_name = generated_file_name(scheme);
+    let path = applications_dir.join(&file_name);
+    let contents = desktop_file_contents(scheme, command);
+
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(&contents) {
+        // Never overwrite a file that handlr did not create
+        if path.exists() && !is_generated(&path) {
+            return Err(Error::NotGenerated(path));
+        }
+
+        std::fs::create_dir_all(applications_dir)?;
+        utils::write_atomic(&path, contents.as_bytes())?;
+    }
+
+    DesktopHandler::from_str(&file_name)
+}
+
+/// Remove the desktop file generated for a given scheme from `applications_dir`, if there is one
+/// Returns whether a file was removed
+pub fn remove_scheme_desktop_file(
+    applications_dir: &Path,
+    scheme: &str,
+) -> Result<bool> {
+    validate_scheme(scheme)?;
+    let path = applications_dir.join(generated_file_name(scheme));
+
+    if path.exists() && is_generated(&path) {
+        std::fs::remove_file(path)?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Check whether a desktop file is one that handlr generated
+fn is_generated(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .is_ok_and(|contents| contents.lines().any(|l| l == GENERATED_KEY))
+}
+
+/// Run `update-desktop-database` on `applications_dir` so that other programs see changes to it
+#[mutants::skip] // Cannot test directly, runs external command
+pub fn update_desktop_database(applications_dir: &Path) -> Result<()> {
+    let status = Command::new("update-desktop-database")
+        .arg(applications_dir)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::BadCmd(format!(
+            "update-desktop-database {}",
+            applications_dir.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::DesktopEntry, config::Config, utils::TempDir};
+    use pretty_assertions::assert_eq;
+    use std::convert::TryFrom;
+
+    // Helper function to get a unique applications directory, which does not exist yet, inside a temporary directory
+    fn temp_applications_dir(name: &str) -> Result<(TempDir, PathBuf)> {
+        let root = TempDir::new(&format!("scheme-{name}"))?;
+        let dir = root.join("applications");
+        Ok((root, dir))
+    }
+
+    #[test]
+    fn scheme_syntax() {
+        for scheme in ["myapp", "web+myapp", "com.example.app", "a1-b2"] {
+            assert!(validate_scheme(scheme).is_ok(), "{scheme}");
+        }
+
+        for scheme in ["", "1app", "my app", "myapp:", "my/app", "-app"] {
+            assert!(
+                matches!(validate_scheme(scheme), Err(Error::BadScheme(_))),
+                "{scheme}"
+            );
+        }
+    }
+
+    #[test]
+    fn generate_desktop_file() -> Result<()> {
+        let (_root, dir) = temp_applications_dir("generate")?;
+
+        let handler = write_scheme_desktop_file(&dir, "MyApp", "myapp --open")?;
+        assert_eq!(handler.to_string(), "handlr-scheme-myapp.desktop");
+
+        let entry = DesktopEntry::try_from(dir.join(handler.to_string()))?;
+        assert_eq!(entry.exec, "myapp --open %u");
+        assert_eq!(
+            entry.mime_type,
+            vec![mime::Mime::from_str("x-scheme-handler/myapp")?]
+        );
+
+        // Field codes already in the command are left alone
+        write_scheme_desktop_file(&dir, "myapp", "myapp --url=%u --new")?;
+        let entry = DesktopEntry::try_from(dir.join(handler.to_string()))?;
+        assert_eq!(entry.exec, "myapp --url=%u --new");
+
+        // Anything else that means something in desktop files reaches the command as written
+        write_scheme_desktop_file(
+            &dir,
+            "myapp",
+            r#"sh -c "printf '100%% %s\\n' \"$1\"" sh"#,
+        )?;
+        let entry = DesktopEntry::try_from(dir.join(handler.to_string()))?;
+        assert_eq!(
+            entry.get_cmd(&Config::default(), vec!["myapp://a".to_owned()])?,
+            (
+                "sh".to_owned(),
+                vec![
+                    "-c".to_owned(),
+                    r#"printf '100%% %s\n' "$1""#.to_owned(),
+                    "sh".to_owned(),
+                    "myapp://a".to_owned()
+                ]
+            )
+        );
+
+        for command in ["myapp\nExec=other", "myapp\r"] {
+            assert!(matches!(
+                write_scheme_desktop_file(&dir, "myapp", command),
+                Err(Error::MultilineCmd(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reregister_is_idempotent() -> Result<()> {
+        let (_root, dir) = temp_applications_dir("idempotent")?;
+
+        let handler = write_scheme_desktop_file(&dir, "myapp", "myapp")?;
+        let path = dir.join(handler.to_string());
+        let modified = std::fs::metadata(&path)?.modified()?;
+
+        assert_eq!(write_scheme_desktop_file(&dir, "myapp", "myapp")?, handler);
+        // An identical file should not be rewritten
+        assert_eq!(std::fs::metadata(&path)?.modified()?, modified);
+
+        assert!(remove_scheme_desktop_file(&dir, "myapp")?);
+        assert!(!path.exists());
+        // Nothing left to remove
+        assert!(!remove_scheme_desktop_file(&dir, "myapp")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn never_touch_other_files() -> Result<()> {
+        let (_root, dir) = temp_applications_dir("others")?;
+        std::fs::create_dir_all(&dir)?;
+
+        let path = dir.join(generated_file_name("myapp"));
+        std::fs::write(
+            &path,
+            "[Desktop Entry]\nName=Mine\nExec=mine %u\nType=Application\n",
+        )?;
+
+        assert!(matches!(
+            write_scheme_desktop_file(&dir, "myapp", "myapp"),
+            Err(Error::NotGenerated(_))
+        ));
+        assert!(!remove_scheme_desktop_file(&dir, "myapp")?);
+        assert!(path.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use crate::error::{Error, Result};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The selector command from the config file
+/// Either a single string that is split into shell words, or a list of arguments that are used as they are
+/// `{prompt}` and `{mime}` in any argument are replaced each time the selector is run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Selector {
+    Command(String),
+    Args(Vec<String>),
+}
+
+impl Selector {
+    /// Check that every placeholder is one that can be filled in,
+    /// so that mistakes are reported when the config file is loaded
+    /// Only the list form is checked, since single strings were written before placeholders existed
+    /// and may use braces for anything
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::Command(_) => Ok(()),
+            Self::Args(args) => args
+                .iter()
+                .try_for_each(|arg| fill(arg, "", "", true).map(|_| ())),
+        }
+    }
+
+    /// Get the program and arguments to run the selector with, filling in its placeholders
+    pub fn args(&self, prompt: &str, mime: &Mime) -> Result<Vec<String>> {
+        let args = match self {
+            Self::Command(command) => shlex::split(command)
+                .ok_or_else(|| Error::BadCmd(command.clone()))?,
+            Self::Args(args) => args.clone(),
+        };
+
+        if args.is_empty() {
+            return Err(Error::BadCmd(self.to_string()));
+        }
+
+        // Placeholders are filled in after splitting, so that prompts with spaces or quotes stay whole
+        let strict = matches!(self, Self::Args(_));
+        args.iter()
+            .map(|arg| fill(arg, prompt, mime.essence_str(), strict))
+            .collect()
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(command) => f.write_str(command),
+            Self::Args(args) => f.write_str(&join(args)),
+        }
+    }
+}
+
+impl From<String> for Selector {
+    fn from(command: String) -> Self {
+        Self::Command(command)
+    }
+}
+
+/// Join arguments into a single command, quoting them where needed
+pub fn join(args: &[String]) -> String {
+    shlex::try_join(args.iter().map(String::as_str))
+        .unwrap_or_else(|_| args.join(" "))
+}
+
+/// Replace `{prompt}` and `{mime}` in an argument
+/// If `strict`, anything else in braces that looks like a placeholder, e.g. `{promt}`, is an error,
+/// but other uses of braces, like fzf's `{}` and `{1}`, are always left alone
+fn fill(arg: &str, prompt: &str, mime: &str, strict: bool) -> Result<String> {
+    let mut filled = String::with_capacity(arg.len());
+    let mut rest = arg;
+
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let Some(name) = rest.find('}').map(|end| &rest[..end]) else {
+            filled.push('{');
+            continue;
+        };
+
+        match name {
+            "prompt" => filled.push_str(prompt),
+            "mime" => filled.push_str(mime),
+            name if strict
+                && name.len() > 1
+                && name.chars().all(|c| c.is_ascii_lowercase() || c == '_') =>
+            {
+                return Err(Error::BadSelectorPlaceholder(name.to_owned()))
+            }
+            _ => {
+                filled.push('{');
+                continue;
+            }
+        }
+
+        rest = &rest[name.len() + 1..];
+    }
+
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    #[test]
+    fn selector_args() -> Result<()> {
+        let mime = Mime::from_str("video/mp4")?;
+
+        let selector = toml::from_str::<ConfigFile>(
+            r#"selector = "rofi -dmenu -p 'Open {mime} with'""#,
+        )?
+        .selector;
+        assert_eq!(
+            selector.args("Open With", &mime)?,
+            ["rofi", "-dmenu", "-p", "Open video/mp4 with"]
+        );
+
+        // Arguments are used as they are, so quotes in them need no escaping
+        let selector = toml::from_str::<ConfigFile>(
+            r#"selector = ["fuzzel", "--dmenu", '--prompt={prompt} "{mime}": ']"#,
+        )?
+        .selector;
+        assert_eq!(
+            selector.args("Open With", &mime)?,
+            ["fuzzel", "--dmenu", r#"--prompt=Open With "video/mp4": "#]
+        );
+        assert_eq!(
+            selector.to_string(),
+            r#"fuzzel --dmenu '--prompt={prompt} "{mime}": '"#
+        );
+
+        // Other uses of braces are left alone
+        let selector =
+            Selector::from("fzf --preview 'echo {} {1} {q}' {".to_owned());
+        assert_eq!(
+            selector.args("", &mime)?,
+            ["fzf", "--preview", "echo {} {1} {q}", "{"]
+        );
+
+        assert!(matches!(
+            Selector::from("".to_owned()).args("", &mime),
+            Err(Error::BadCmd(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_placeholders() -> Result<()> {
+        let args = |args: &[&str]| {
+            Selector::Args(args.iter().map(|&arg| arg.to_owned()).collect())
+        };
+
+        assert!(args(&["rofi", "-p", "{prompt}", "{mime}"])
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            args(&["rofi", "-p", "{promt}"]).validate(),
+            Err(Error::BadSelectorPlaceholder(name)) if name == "promt"
+        ));
+        assert!(matches!(
+            args(&["rofi", "-p", "{mime_type}"]).args("", &mime::TEXT_PLAIN),
+            Err(Error::BadSelectorPlaceholder(name)) if name == "mime_type"
+        ));
+
+        // Single strings may have been written before placeholders existed, so their braces are left alone
+        let selector = Selector::from("rofi -p {promt} {mime}".to_owned());
+        assert!(selector.validate().is_ok());
+        assert_eq!(
+            selector.args("", &mime::TEXT_PLAIN)?,
+            ["rofi", "-p", "{promt}", "text/plain"]
+        );
+
+        // An empty selector is only a problem if it is used
+        assert!(Selector::from("".to_owned()).validate().is_ok());
+
+        Ok(())
+    }
+}
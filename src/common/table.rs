@@ -1,39 +1,246 @@
+use crate::{cli::OutputFormat, error::Result, utils};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, io::Write};
+#[cfg(feature = "tables")]
 use tabled::{
-    settings::{themes::Colorization, Alignment, Color, Padding, Style},
-    Table, Tabled,
+    builder::Builder,
+    settings::{
+        peaker::PriorityMax, themes::Colorization, Color, Style, Width,
+    },
 };
 
-/// Render a table from a vector of instances of Tabled structs
-pub fn render_table<T: Tabled>(rows: &Vec<T>, terminal_output: bool) -> String {
-    let mut table = Table::new(rows);
+/// A row of tabular output
+pub trait Row {
+    /// Names of the columns, which are also the headers of the table
+    const HEADERS: &'static [&'static str];
 
-    if terminal_output {
-        // If output is going to a terminal, print as a table
-        table
-            .with(Style::sharp())
-            .with(Colorization::rows([Color::FG_WHITE, Color::BG_BLACK]))
+    /// Contents of each column, in the same order as `HEADERS`
+    fn fields(&self) -> Vec<String>;
+}
+
+/// How tables are drawn, from the `[table]` section of the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableConfig {
+    /// Style of the table's borders
+    pub style: TableStyle,
+    /// Maximum width of the table in characters, wrapping the widest columns to fit
+    pub max_width: Option<usize>,
+    /// Whether to leave out colors, set from `NO_COLOR` rather than the config file
+    #[serde(skip)]
+    pub no_color: bool,
+}
+
+/// Styles that tables can be drawn in
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TableStyle {
+    #[default]
+    Sharp,
+    Rounded,
+    Modern,
+    Ascii,
+    Markdown,
+    /// No borders at all
+    Blank,
+}
+
+/// Write rows in the given format, i.e. as a JSON array, as JSON lines, or as a table
+pub fn write_rows<W: Write, T: Row + Serialize>(
+    writer: &mut W,
+    rows: &[T],
+    format: OutputFormat,
+    config: &TableConfig,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            writeln!(writer, "{}", serde_json::to_string(rows)?)?
+        }
+        OutputFormat::Jsonl => rows
+            .iter()
+            .try_for_each(|row| utils::write_json_line(writer, row))?,
+        _ => writeln!(writer, "{}", render_table(rows, format, config))?,
+    }
+
+    Ok(())
+}
+
+/// Write a single row in the given format, where JSON is an object rather than an array
+pub fn write_row<W: Write, T: Row + Serialize>(
+    writer: &mut W,
+    row: T,
+    format: OutputFormat,
+    config: &TableConfig,
+) -> Result<()> {
+    if format.is_json() {
+        utils::write_json_line(writer, &row)
     } else {
-        // If output is being piped, print as tab-delimited text
-        table
-            .with(Style::empty().vertical('\t'))
-            .with(Alignment::left())
-            .with(Padding::zero())
+        write_rows(writer, &[row], format, config)
+    }
+}
+
+/// Render a table from a slice of rows
+pub fn render_table<T: Row>(
+    rows: &[T],
+    format: OutputFormat,
+    config: &TableConfig,
+) -> String {
+    render_table_without(rows, format, config, &[])
+}
+
+/// Render a table from a slice of rows, leaving out the columns with the given names
+/// JSON is written by callers, so it is treated like tab-separated values here
+pub fn render_table_without<T: Row>(
+    rows: &[T],
+    format: OutputFormat,
+    config: &TableConfig,
+    hidden: &[&str],
+) -> String {
+    let records = records(rows, hidden);
+    match format {
+        OutputFormat::Table => render_pretty(records, config),
+        OutputFormat::Csv => render_delimited(&records, ",", csv_field),
+        _ => render_delimited(&records, "\t", tsv_field),
+    }
+}
+
+/// Get the headers followed by the fields of each row, leaving out the columns with the given names
+fn records<T: Row>(rows: &[T], hidden: &[&str]) -> Vec<Vec<String>> {
+    let shown = T::HEADERS
+        .iter()
+        .map(|header| !hidden.contains(header))
+        .collect_vec();
+
+    std::iter::once(T::HEADERS.iter().map(|h| h.to_string()).collect_vec())
+        .chain(rows.iter().map(Row::fields))
+        .map(|fields| {
+            fields
+                .into_iter()
+                .zip(&shown)
+                .filter(|(_, &shown)| shown)
+                .map(|(field, _)| field)
+                .collect_vec()
+        })
+        .collect_vec()
+}
+
+/// Draw records as a table with borders, as configured
+#[cfg(feature = "tables")]
+fn render_pretty(records: Vec<Vec<String>>, config: &TableConfig) -> String {
+    let mut table = Builder::from(records).build();
+
+    match config.style {
+        TableStyle::Sharp => table.with(Style::sharp()),
+        TableStyle::Rounded => table.with(Style::rounded()),
+        TableStyle::Modern => table.with(Style::modern()),
+        TableStyle::Ascii => table.with(Style::ascii()),
+        TableStyle::Markdown => table.with(Style::markdown()),
+        TableStyle::Blank => table.with(Style::blank()),
+    };
+
+    if let Some(max_width) = config.max_width {
+        table.with(
+            Width::wrap(max_width)
+                .keep_words()
+                .priority::<PriorityMax>(),
+        );
+    }
+
+    if !config.no_color {
+        table.with(Colorization::rows([Color::FG_WHITE, Color::BG_BLACK]));
+    }
+
+    table.to_string()
+}
+
+/// Write records as columns of plain text aligned with spaces,
+/// since drawing tables needs the `tables` feature
+/// Fields with more than one line take up as many lines as needed
+#[cfg(not(feature = "tables"))]
+fn render_pretty(records: Vec<Vec<String>>, _config: &TableConfig) -> String {
+    let widths = records.iter().fold(Vec::new(), |mut widths, fields| {
+        widths.resize(widths.len().max(fields.len()), 0);
+        for (width, field) in widths.iter_mut().zip(fields) {
+            let longest = field.lines().map(|l| l.chars().count()).max();
+            *width = (*width).max(longest.unwrap_or_default());
+        }
+        widths
+    });
+    let widths = &widths;
+
+    records
+        .iter()
+        .flat_map(|fields| {
+            let height =
+                fields.iter().map(|f| f.lines().count()).max().unwrap_or(1);
+            (0..height.max(1)).map(move |line| {
+                fields
+                    .iter()
+                    .zip(widths)
+                    .map(|(field, &width)| {
+                        let text = field.lines().nth(line).unwrap_or_default();
+                        format!("{text:width$}")
+                    })
+                    .join("  ")
+                    .trim_end()
+                    .to_owned()
+            })
+        })
+        .join("\n")
+}
+
+/// Render records as lines of fields separated by `delimiter`
+/// Fields are written exactly as given, without any padding, after going through `escape`
+fn render_delimited(
+    records: &[Vec<String>],
+    delimiter: &str,
+    escape: fn(&str) -> Cow<'_, str>,
+) -> String {
+    records
+        .iter()
+        .map(|fields| fields.iter().map(|field| escape(field)).join(delimiter))
+        .join("\n")
+}
+
+/// Make a field safe to use in tab-separated values, which have no way to escape tabs or newlines
+fn tsv_field(field: &str) -> Cow<'_, str> {
+    if field.contains(['\t', '\n', '\r']) {
+        field.replace(['\t', '\n', '\r'], " ").into()
+    } else {
+        field.into()
+    }
+}
+
+/// Quote a field for use in comma-separated values if needed, as described in RFC 4180
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
     }
-    .to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::error::Result;
-    use itertools::Itertools;
 
-    #[derive(Tabled)]
     struct TestRow<'a> {
         col1: &'a str,
         col2: &'a str,
     }
 
+    impl Row for TestRow<'_> {
+        const HEADERS: &'static [&'static str] = &["col1", "col2"];
+
+        fn fields(&self) -> Vec<String> {
+            vec![self.col1.to_owned(), self.col2.to_owned()]
+        }
+    }
+
     // Arbitrary sample text
     const LOREM_IPSUM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
 
@@ -51,14 +258,120 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "tables")]
     fn terminal_output() -> Result<()> {
-        goldie::assert!(render_table(&rows(LOREM_IPSUM), true));
+        goldie::assert!(render_table(
+            &rows(LOREM_IPSUM),
+            OutputFormat::Table,
+            &TableConfig::default()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tables")]
+    fn styled_output() -> Result<()> {
+        let rows = rows("Lorem ipsum dolor sit");
+        let output =
+            [TableStyle::Rounded, TableStyle::Markdown, TableStyle::Blank]
+                .into_iter()
+                .map(|style| {
+                    let config = TableConfig {
+                        style,
+                        no_color: true,
+                        ..Default::default()
+                    };
+                    render_table(&rows, OutputFormat::Table, &config)
+                })
+                .join("\n");
+        goldie::assert!(output);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tables")]
+    fn narrow_output() -> Result<()> {
+        let config = TableConfig {
+            style: TableStyle::Ascii,
+            max_width: Some(30),
+            no_color: true,
+        };
+        let output =
+            render_table(&rows(LOREM_IPSUM), OutputFormat::Table, &config);
+        assert!(output.lines().all(|line| line.chars().count() <= 30));
+        goldie::assert!(output);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(feature = "tables"))]
+    fn plain_output() -> Result<()> {
+        let rows = [
+            TestRow {
+                col1: "Lorem",
+                col2: "ipsum,\ndolor",
+            },
+            TestRow {
+                col1: "sit amet",
+                col2: "elit",
+            },
+        ];
+        assert_eq!(
+            render_table_without(
+                &rows,
+                OutputFormat::Table,
+                &TableConfig::default(),
+                &[]
+            ),
+            "col1      col2\nLorem     ipsum,\n          dolor\nsit amet  elit"
+        );
         Ok(())
     }
 
     #[test]
     fn piped_output() -> Result<()> {
-        goldie::assert!(render_table(&rows(LOREM_IPSUM), false));
+        goldie::assert!(render_table(
+            &rows(LOREM_IPSUM),
+            OutputFormat::Tsv,
+            &TableConfig::default()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_columns() -> Result<()> {
+        assert_eq!(
+            render_table_without(
+                &rows("a b c d"),
+                OutputFormat::Tsv,
+                &TableConfig::default(),
+                &["col2"]
+            ),
+            "col1\na\nc"
+        );
+        assert_eq!(
+            render_table_without(
+                &rows("a b c d"),
+                OutputFormat::Csv,
+                &TableConfig::default(),
+                &["col1"]
+            ),
+            "col2\nb\nd"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_fields() -> Result<()> {
+        let rows = rows("a,b say\t\"hi\"");
+        assert_eq!(
+            render_table(&rows, OutputFormat::Csv, &TableConfig::default()),
+            "col1,col2\n\"a,b\",\"say\t\"\"hi\"\"\""
+        );
+        assert_eq!(
+            render_table(&rows, OutputFormat::Tsv, &TableConfig::default()),
+            "col1\tcol2\na,b\tsay \"hi\""
+        );
         Ok(())
     }
 }
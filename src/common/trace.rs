@@ -0,0 +1,54 @@
+use serde::Serialize;
+use std::fmt::Display;
+
+/// Where a handler that could be used for a mime comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandlerSource {
+    /// An exact match in mimeapps.list's default apps
+    Default,
+    /// A wildcard match in mimeapps.list's default apps
+    Wildcard,
+    /// mimeapps.list's added associations
+    AddedAssociation,
+    /// Installed desktop entries that support the mime
+    System,
+    /// An installed file manager, for directories with nothing else to open them
+    FileManager,
+}
+
+impl Display for HandlerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::Wildcard => "wildcard",
+            Self::AddedAssociation => "added association",
+            Self::System => "system",
+            Self::FileManager => "file manager",
+        })
+    }
+}
+
+/// Steps taken to find the handler for a mime, which are only recorded when tracing is enabled
+#[derive(Debug, Default)]
+pub struct Trace(Option<Vec<String>>);
+
+impl Trace {
+    /// Create a trace that records steps
+    pub fn enabled() -> Self {
+        Self(Some(Vec::new()))
+    }
+
+    /// Record a step if tracing is enabled
+    /// Takes a function so that nothing is formatted otherwise
+    pub fn step<F: FnOnce() -> String>(&mut self, step: F) {
+        if let Some(steps) = &mut self.0 {
+            steps.push(step());
+        }
+    }
+
+    /// Get the recorded steps, in the order they were taken
+    pub fn steps(&self) -> &[String] {
+        self.0.as_deref().unwrap_or_default()
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    apps::DesktopList,
+    common::{DesktopHandler, Handleable, RegexApps, RegexLimits},
+    error::{Error, Result},
+};
+use indexmap::IndexMap;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+/// Default handlers and regex handlers in the form read by `handlr import` and written by `handlr export`,
+/// so that they can be managed declaratively, e.g. alongside dotfiles
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Associations {
+    /// Handlers of each mime, in order of preference
+    pub default_apps: BTreeMap<String, Vec<String>>,
+    /// Regex handlers, in the same form as in handlr.toml
+    #[serde(skip_serializing_if = "RegexApps::is_empty")]
+    pub handlers: RegexApps,
+}
+
+impl FromStr for Associations {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+impl Associations {
+    /// Read associations from a given file, or from stdin if it is `-`
+    #[mutants::skip] // Cannot test directly, may read stdin
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = if path == Path::new("-") {
+            std::io::read_to_string(std::io::stdin())?
+        } else {
+            std::fs::read_to_string(path)?
+        };
+        contents.parse()
+    }
+
+    /// Write the associations in the form they are read in
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Check every mime, handler, and regex handler, and get each mime's handlers in the form used by mimeapps.list
+    /// Every problem is reported at once rather than stopping at the first
+    pub fn resolve(
+        &self,
+        limits: &RegexLimits,
+    ) -> Result<IndexMap<Mime, DesktopList>> {
+        let mut problems = Vec::new();
+        let mut default_apps = IndexMap::new();
+
+        for (mime, handlers) in &self.default_apps {
+            let mime = match Mime::from_str(mime) {
+                Ok(mime) => mime,
+                Err(e) => {
+                    problems.push((mime.clone(), e.into()));
+                    continue;
+                }
+            };
+
+            if handlers.is_empty() {
+                problems.push((
+                    mime.to_string(),
+                    Error::NotFound(mime.to_string()),
+                ));
+                continue;
+            }
+
+            let mut list = DesktopList::default();
+            for handler in handlers {
+                match DesktopHandler::from_str(handler)
+                    .and_then(|handler| handler.get_entry().map(|_| handler))
+                {
+                    Ok(handler) => list.insert_at(handler, None),
+                    Err(e) => problems.push((format!("{mime}: {handler}"), e)),
+                }
+            }
+            default_apps.insert(mime, list);
+        }
+
+        if let Err(e) = self.handlers.clone().compile(limits) {
+            problems.push(("handlers".to_owned(), e));
+        }
+
+        if problems.is_empty() {
+            Ok(default_apps)
+        } else {
+            Err(Error::BadImport(problems))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn associations_round_trip(
+            default_apps in prop::collection::btree_map(
+                "\\PC*",
+                prop::collection::vec("\\PC*", 0..4),
+                0..8,
+            )
+        ) {
+            let associations = Associations {
+                default_apps,
+                ..Default::default()
+            };
+            prop_assert_eq!(
+                Associations::from_str(&associations.to_toml()?)?,
+                associations
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_associations() -> Result<()> {
+        let associations: Associations = r#"
+            [default_apps]
+            "text/plain" = ["tests/Helix.desktop", "tests/Helix.desktop"]
+            "text/html" = ["tests/link.desktop", "tests/missing.desktop"]
+            "text" = ["tests/Helix.desktop"]
+            "image/png" = []
+
+            [[handlers]]
+            exec = "freetube %u"
+            regexes = ["(youtube.com"]
+        "#
+        .parse()?;
+
+        let Err(Error::BadImport(problems)) =
+            associations.resolve(&RegexLimits::default())
+        else {
+            panic!("Every problem should be reported");
+        };
+        assert_eq!(
+            problems
+                .iter()
+                .map(|(what, _)| what.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "image/png",
+                "text",
+                "text/html: tests/link.desktop",
+                "text/html: tests/missing.desktop",
+                "handlers"
+            ]
+        );
+
+        let associations: Associations = r#"
+            [default_apps]
+            "text/plain" = ["tests/Helix.desktop", "tests/Helix.desktop"]
+        "#
+        .parse()?;
+        let default_apps = associations.resolve(&RegexLimits::default())?;
+        // Repeated handlers are only kept once
+        assert_eq!(
+            default_apps[&mime::TEXT_PLAIN].to_string(),
+            "tests/Helix.desktop;"
+        );
+
+        // Anything unexpected is most likely a mistake
+        assert!("[added_associations]".parse::<Associations>().is_err());
+
+        Ok(())
+    }
+}
@@ -1,9 +1,19 @@
 use crate::{
     cli::SelectorArgs,
-    common::{RegexApps, RegexHandler, UserPath},
+    common::{
+        default_extra_data_dirs, MimeOverrides, PatternMatch, RegexApps,
+        RegexHandler, RegexLimits, Selector, TableConfig, UserPath,
+    },
+    config::LaunchOptions,
     error::Result,
 };
+use mime::Mime;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use wildmatch::WildMatch;
 
 /// The config file
 #[derive(Debug, Serialize, Deserialize)]
@@ -11,28 +21,157 @@ use serde::{Deserialize, Serialize};
 pub struct ConfigFile {
     /// Whether to enable the selector when multiple handlers are set
     pub enable_selector: bool,
-    /// The selector command to run
-    pub selector: String,
+    /// The selector command to run, either as a string or a list of arguments
+    /// `{prompt}` and `{mime}` are replaced with what is being chosen and the mime it is for
+    pub selector: Selector,
+    /// How handlers are shown in the selector
+    /// `{name}`, `{file}`, `{keywords}`, and `{X-...}` are replaced with the handler's desktop entry's values
+    pub selector_format: String,
+    /// Whether to give the selector each handler's desktop file id before what is shown for it,
+    /// separated by a tab, so that handlers with the same name can be told apart
+    pub selector_detailed: bool,
     /// Extra arguments to pass to terminal application
     pub term_exec_args: Option<String>,
     /// Whether to expand wildcards when saving mimeapps.list
     pub expand_wildcards: bool,
+    /// Approximate maximum size in bytes of each regex handler's compiled patterns
+    pub regex_size_limit: usize,
+    /// Maximum nesting depth of regex handler patterns
+    pub regex_nest_limit: u32,
+    /// Maximum length of paths/URLs considered when matching regex handlers
+    /// Anything longer is truncated for the purposes of matching
+    pub regex_max_input_length: usize,
+    /// Whether to keep a copy of mimeapps.list from before the last change as mimeapps.list.bak
+    pub backup_mimeapps: bool,
+    /// Whether to warn about potential problems with mimeapps.list after changing it
+    pub lint: bool,
+    /// Maximum estimated size in bytes of the arguments and environment given to a handler at once
+    /// When opening many files with a handler that accepts multiple files,
+    /// they are split into multiple invocations so that this is not exceeded
+    pub max_args_size: usize,
+    /// Mimes that require confirmation before being opened, wildcards are supported
+    pub confirm_mimes: Vec<String>,
+    /// Mimes to always use the selector for when they have multiple handlers, even if `enable_selector` is false
+    /// Wildcards are supported
+    pub always_ask: Vec<String>,
+    /// Whether to make the handler chosen with the selector the default for the mime
+    /// Only mimes that are set exactly, not through wildcards, are changed
+    pub selector_persist: bool,
+    /// Mimes to never use the selector for, even if `enable_selector` is true or they are in `always_ask`
+    /// Wildcards are supported
+    pub never_ask: Vec<String>,
+    /// Whether to open http(s) URLs whose paths end in a file extension with the handler for that file's mime,
+    /// falling back to the URL scheme's handler if there is none
+    pub url_mime_from_extension: bool,
+    /// Whether regex handlers are tried before mime associations,
+    /// otherwise they are only used for paths that no mime association handles
+    pub regex_handlers_take_precedence: bool,
+    /// Whether to open paths that do not exist but look like URLs without a scheme,
+    /// e.g. `example.com` or `localhost:8080/page`, as https URLs
+    pub assume_http: bool,
+    /// Whether to guess the mimes of files from their contents when their names are not enough
+    pub sniff_content: bool,
+    /// Desktop file name of the file manager to open directories with
+    /// when no handler is set for `inode/directory`
+    pub default_file_manager: Option<String>,
+    /// Whether to send files to an already running instance of handlers that say they only have one main window,
+    /// rather than starting another one, if the handler can be reached through D-Bus
+    pub respect_single_window: bool,
+    /// Whether to also look for running processes of single window handlers that cannot be reached through D-Bus,
+    /// skipping launches without files if one is found
+    /// Only used with `respect_single_window`
+    pub scan_processes: bool,
+    /// Whether to skip handlers whose `TryExec` program cannot be found, like the desktop entry spec says to
+    pub check_try_exec: bool,
+    /// Whether to open a mime with apps hidden with `NoDisplay` when they are the only installed apps that can,
+    /// rather than failing with an error that names them
+    pub use_hidden_fallback: bool,
+    /// Where to start handlers whose desktop entries do not set `Path`
+    pub cwd: WorkingDir,
+    /// Data directories to look for desktop files in after `$XDG_DATA_DIRS`, e.g. flatpak's exports
+    pub extra_data_dirs: Vec<PathBuf>,
+    /// Mimes that handlr manages itself, which wildcard associations never apply to
+    /// Their handlers can only be changed by setting them exactly
+    pub protected_mimes: Vec<String>,
+    // NOTE: Everything from here on is a table, which TOML requires to come after every plain value,
+    // otherwise the default config file cannot be written
+    /// Options for launching handlers, keyed by desktop file name
+    /// Options under `default` apply to every handler unless overridden
+    pub launch: BTreeMap<String, LaunchOptions>,
+    /// Arguments to add to terminal emulators' commands when launching with `--tag`, keyed by desktop file name
+    /// `{tag}` is replaced with the tag, e.g. `--class handlr-{tag}`
+    pub tag_args: BTreeMap<String, String>,
+    /// Mimes to give files whose names match glob patterns, e.g. `"*.mka" = "audio/x-matroska"`,
+    /// used before anything else when detecting files' mimes
+    /// If more than one pattern matches, the longest one is used
+    pub mime_overrides: MimeOverrides,
+    /// How tables are drawn
+    pub table: TableConfig,
     /// Regex handlers
     // NOTE: Serializing is only necessary for generating a default config file
     #[serde(skip_serializing)]
     pub handlers: RegexApps,
+    /// Whether the selector was enabled or disabled from the command line,
+    /// which takes precedence over everything else
+    #[serde(skip)]
+    pub selector_override: Option<bool>,
+}
+
+/// Where to start handlers whose desktop entries do not set `Path`
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkingDir {
+    /// The directory handlr was run from
+    #[default]
+    Inherit,
+    /// The directory of the first file being opened
+    FileDir,
 }
 
 impl Default for ConfigFile {
     fn default() -> Self {
         ConfigFile {
             enable_selector: false,
-            selector: "rofi -dmenu -i -p 'Open With: '".into(),
+            selector: Selector::from(
+                "rofi -dmenu -i -p 'Open With: '".to_owned(),
+            ),
+            selector_format: "{name}".into(),
+            selector_detailed: false,
             // Required for many xterm-compatible terminal emulators
             // Unfortunately, messes up emulators that don't accept it
             term_exec_args: Some("-e".into()),
             expand_wildcards: false,
+            regex_size_limit: RegexLimits::default().size_limit,
+            regex_nest_limit: RegexLimits::default().nest_limit,
+            regex_max_input_length: 8192,
+            backup_mimeapps: false,
+            lint: true,
+            // Well under the typical limit of 2 MiB on Linux
+            max_args_size: 128 * 1024,
+            confirm_mimes: Vec::new(),
+            always_ask: Vec::new(),
+            never_ask: Vec::new(),
+            selector_persist: false,
+            launch: BTreeMap::new(),
+            tag_args: BTreeMap::new(),
+            url_mime_from_extension: false,
+            regex_handlers_take_precedence: true,
+            assume_http: false,
+            sniff_content: true,
+            mime_overrides: MimeOverrides::default(),
+            default_file_manager: None,
+            respect_single_window: false,
+            scan_processes: false,
+            check_try_exec: true,
+            use_hidden_fallback: false,
+            cwd: WorkingDir::default(),
+            extra_data_dirs: default_extra_data_dirs(),
+            protected_mimes: vec!["x-scheme-handler/terminal".into()],
+            table: TableConfig::default(),
             handlers: Default::default(),
+            selector_override: None,
         }
     }
 }
@@ -40,24 +179,176 @@ impl Default for ConfigFile {
 impl ConfigFile {
     /// Get the handler associated with a given mime from the config file's regex handlers
     pub fn get_regex_handler(&self, path: &UserPath) -> Result<RegexHandler> {
-        self.handlers.get_handler(path)
+        self.handlers
+            .get_handler(path, self.regex_max_input_length, || {
+                self.detect_mime(path)
+            })
+    }
+
+    /// Test a path against every pattern of every regex handler
+    pub fn test_regex_handlers(&self, path: &UserPath) -> Vec<PatternMatch> {
+        self.handlers
+            .test(path, self.regex_max_input_length, || self.detect_mime(path))
+    }
+
+    /// Get the mime of a path for regex handlers to match, if it can be figured out
+    fn detect_mime(&self, path: &UserPath) -> Option<Mime> {
+        path.get_mime(self.sniff_content, &self.mime_overrides).ok()
+    }
+
+    /// Get the regex handler with a given name
+    pub fn get_named_regex_handler(&self, name: &str) -> Result<RegexHandler> {
+        self.handlers.get_named(name)
+    }
+
+    /// Check if opening a file with the given mime requires confirmation
+    pub fn needs_confirmation(&self, mime: &Mime) -> bool {
+        matches_any(&self.confirm_mimes, mime)
+    }
+
+    /// Check if the selector should be used to choose between the handlers of a given mime
+    pub fn should_select(&self, mime: &Mime) -> bool {
+        self.selector_override.unwrap_or_else(|| {
+            !matches_any(&self.never_ask, mime)
+                && (self.enable_selector || matches_any(&self.always_ask, mime))
+        })
+    }
+
+    /// Check if a given mime is protected from wildcard associations
+    pub fn is_protected(&self, mime: &Mime) -> bool {
+        self.protected_mimes
+            .iter()
+            .any(|protected| protected.eq_ignore_ascii_case(mime.essence_str()))
     }
 
     /// Load ~/.config/handlr/handlr.toml
     #[mutants::skip] // Cannot test directly, depends on system state
     pub fn load() -> Result<Self> {
-        Ok(confy::load("handlr")?)
+        let mut config = Self::load_from(Self::path()?)?;
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// Get the path of ~/.config/handlr/handlr.toml, whether it exists or not
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn path() -> Result<PathBuf> {
+        Ok(Self::path_in(
+            &xdg::BaseDirectories::new()?.get_config_home(),
+        ))
+    }
+
+    /// Get the path of handlr.toml in a given config directory like ~/.config, whether it exists or not
+    pub fn path_in(config_home: &Path) -> PathBuf {
+        config_home.join("handlr").join("handlr.toml")
+    }
+
+    /// Apply the settings that come from the environment rather than the file
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn apply_env(&mut self) {
+        // See https://no-color.org
+        self.table.no_color =
+            std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    }
+
+    /// Read `extra_data_dirs` from the config file without creating or loading the rest of it,
+    /// for looking up desktop files where the config is not at hand, e.g. while completing arguments
+    /// The defaults are used if the file does not exist or cannot be read
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn read_extra_data_dirs() -> Vec<PathBuf> {
+        // Other keys are ignored, so that problems with them are only reported when the config is loaded
+        #[derive(Deserialize)]
+        struct DataDirs {
+            #[serde(default = "default_extra_data_dirs")]
+            extra_data_dirs: Vec<PathBuf>,
+        }
+
+        Self::path()
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str::<DataDirs>(&contents).ok())
+            .map_or_else(default_extra_data_dirs, |dirs| dirs.extra_data_dirs)
+    }
+
+    /// Load a config file from a given path, using the defaults if it does not exist
+    /// Nothing is created, so that commands that only read the config never change anything
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Self::prepare(if path.exists() {
+            confy::load_path(path)?
+        } else {
+            Self::default()
+        })
+    }
+
+    /// Compile and validate the parts of a freshly read config file that need it
+    fn prepare(mut config: Self) -> Result<Self> {
+        config.handlers.compile(&config.regex_limits())?;
+        config.mime_overrides.validate()?;
+        config.selector.validate()?;
+        config
+            .launch
+            .iter()
+            .try_for_each(|(handler, options)| options.validate(handler))?;
+        Ok(config)
+    }
+
+    /// Get the options to launch a given handler with
+    pub fn launch_options(&self, handler: &str) -> LaunchOptions {
+        let get = |key: &str| self.launch.get(key).copied().unwrap_or_default();
+        get(handler).or(get("default"))
+    }
+
+    /// Get the configured limits for compiling regex handlers
+    pub fn regex_limits(&self) -> RegexLimits {
+        RegexLimits {
+            size_limit: self.regex_size_limit,
+            nest_limit: self.regex_nest_limit,
+        }
     }
 
     /// Override the set selector
     /// Currently assumes the config file will never be saved to
     pub fn override_selector(&mut self, selector_args: SelectorArgs) {
         if let Some(selector) = selector_args.selector {
-            self.selector = selector;
+            self.selector = Selector::from(selector);
         }
 
         self.enable_selector = (self.enable_selector
             || selector_args.enable_selector)
             && !selector_args.disable_selector;
+
+        if selector_args.enable_selector || selector_args.disable_selector {
+            self.selector_override = Some(!selector_args.disable_selector);
+        }
+    }
+}
+
+/// Check if a mime matches any of the given patterns, which can be wildcards
+fn matches_any(patterns: &[String], mime: &Mime) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| WildMatch::new(pattern).matches(mime.essence_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_file() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("handlr-config-{}.toml", std::process::id()));
+
+        // Written when there is no config file yet
+        confy::store_path(&path, ConfigFile::default())?;
+        let config = ConfigFile::load_from(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            serde_json::to_value(config?)?,
+            serde_json::to_value(ConfigFile::default())?
+        );
+
+        Ok(())
     }
 }
@@ -0,0 +1,175 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Options applied to the processes spawned by a handler
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchOptions {
+    /// Niceness to run the handler with, from -20 (highest priority) to 19 (lowest priority)
+    pub nice: Option<i32>,
+    /// I/O scheduling class to run the handler with
+    /// Only supported on Linux and ignored elsewhere
+    pub ionice_class: Option<IoniceClass>,
+}
+
+/// I/O scheduling classes, as used by `ionice`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+impl LaunchOptions {
+    /// Fill in any options that are not set with the ones from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            nice: self.nice.or(fallback.nice),
+            ionice_class: self.ionice_class.or(fallback.ionice_class),
+        }
+    }
+
+    /// Check that the options are within the ranges the system accepts
+    pub fn validate(&self, handler: &str) -> Result<()> {
+        match self.nice {
+            Some(nice) if !(-20..=19).contains(&nice) => {
+                Err(Error::BadLaunchOptions {
+                    handler: handler.to_owned(),
+                    reason: format!(
+                        "nice must be between -20 and 19, got {nice}"
+                    ),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply the options to a command so that they take effect in the spawned process
+    pub fn apply(self, cmd: &mut Command) {
+        if self == Self::default() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: `set_priorities` only makes syscalls, which are safe to make between fork and exec
+            unsafe {
+                cmd.pre_exec(move || self.set_priorities());
+            }
+        }
+
+        #[cfg(not(unix))]
+        let _ = cmd;
+    }
+
+    /// Set the priorities of the current process
+    #[cfg(unix)]
+    fn set_priorities(&self) -> std::io::Result<()> {
+        if let Some(nice) = self.nice {
+            // SAFETY: setpriority has no memory safety requirements
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(class) = self.ionice_class {
+            // Values from linux/ioprio.h
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+            // Default priority level within a class
+            const IOPRIO_LEVEL: libc::c_int = 4;
+
+            let class = match class {
+                IoniceClass::Realtime => 1,
+                IoniceClass::BestEffort => 2,
+                IoniceClass::Idle => 3,
+            };
+
+            // SAFETY: ioprio_set has no memory safety requirements
+            if unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    class << IOPRIO_CLASS_SHIFT | IOPRIO_LEVEL,
+                )
+            } == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fallback_options() {
+        let handler = LaunchOptions {
+            nice: Some(5),
+            ionice_class: None,
+        };
+        let default = LaunchOptions {
+            nice: Some(10),
+            ionice_class: Some(IoniceClass::Idle),
+        };
+
+        assert_eq!(
+            handler.or(default),
+            LaunchOptions {
+                nice: Some(5),
+                ionice_class: Some(IoniceClass::Idle),
+            }
+        );
+    }
+
+    #[test]
+    fn nice_out_of_range() {
+        for nice in [-21, 20] {
+            let options = LaunchOptions {
+                nice: Some(nice),
+                ..Default::default()
+            };
+            assert!(matches!(
+                options.validate("mpv.desktop"),
+                Err(Error::BadLaunchOptions { .. })
+            ));
+        }
+
+        for nice in [-20, 0, 19] {
+            let options = LaunchOptions {
+                nice: Some(nice),
+                ..Default::default()
+            };
+            assert!(options.validate("mpv.desktop").is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn nice_is_applied() -> Result<()> {
+        // Lowering priority as far as possible never requires extra privileges
+        let options = LaunchOptions {
+            nice: Some(19),
+            ..Default::default()
+        };
+
+        let mut cmd = Command::new("nice");
+        options.apply(&mut cmd);
+
+        assert_eq!(String::from_utf8(cmd.output()?.stdout)?.trim(), "19");
+
+        Ok(())
+    }
+}
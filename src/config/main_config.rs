@@ -1,17 +1,33 @@
+use indexmap::IndexMap;
+use itertools::Itertools;
 use mime::Mime;
+use once_cell::unsync::OnceCell;
 use serde::Serialize;
 use std::{
-    collections::{BTreeMap, HashMap, VecDeque},
+    collections::{HashMap, VecDeque},
+    fmt::Display,
     io::{IsTerminal, Write},
     str::FromStr,
 };
-use tabled::Tabled;
 
 use crate::{
-    apps::{DesktopList, MimeApps, SystemApps},
-    cli::SelectorArgs,
-    common::{render_table, DesktopHandler, Handleable, Handler, UserPath},
-    config::config_file::ConfigFile,
+    apps::{
+        desktops_from_env, lint_mime_apps, CleanReport, DesktopList, MimeApps,
+        Reorder, SystemApps,
+    },
+    cli::{Cmd, OutputFormat, SelectorArgs},
+    common::{
+        applications_dir, dedup_paths, mime_types, remove_scheme_desktop_file,
+        render_table, render_table_without, scheme_mime,
+        update_desktop_database, write_row, write_rows,
+        write_scheme_desktop_file, DesktopEntry, DesktopHandler, ExecMode,
+        FallbackCommand, Handleable, Handler, HandlerSource, MimeInfo,
+        MimeOrExtension, MimeOverrides, RegexHandler, Row, TableConfig,
+        TableStyle, Trace, UserPath,
+    },
+    config::{
+        config_file::ConfigFile, Associations, LaunchOptions, WorkingDir,
+    },
     error::{Error, Result},
     utils,
 };
@@ -23,84 +39,506 @@ pub struct Config {
     /// User-configured associations
     mime_apps: MimeApps,
     /// Available applications on the system
-    system_apps: SystemApps,
+    /// Only populated once something actually needs them, since doing so is relatively expensive
+    system_apps: OnceCell<SystemApps>,
+    /// Hierarchy of mime subclasses
+    /// Only loaded once something actually needs it
+    mime_info: OnceCell<MimeInfo>,
     /// Handlr-specific config file
     config: ConfigFile,
     /// Whether or not stdout is a terminal
     pub terminal_output: bool,
+    /// Whether changes should be written to the desktop-specific mimeapps.list
+    write_desktop: bool,
+    /// Tag given to launched handlers so that they can be told apart
+    tag: Option<String>,
+    /// Action from handlers' desktop files to run instead of their main command
+    action: Option<String>,
+    /// Whether to list and fall back to installed apps that should not be shown on the current desktop
+    include_hidden: bool,
+    /// Whether to open the URLs of Link desktop entries used as handlers
+    follow_links: bool,
+    /// Whether to open the same paths as many times as they are given
+    keep_duplicates: bool,
+    /// Whether to wait for handlers to exit and notify when they do
+    notify_done: bool,
+    /// Whether to wait for handlers to exit instead of detaching from them
+    wait: bool,
+    /// Whether to print what would be run instead of running anything
+    dry_run: bool,
+    /// Handler to open every path with instead of the ones set for them
+    handler_override: Option<DesktopHandler>,
+    /// Command to open paths with when no handler can be found for them
+    no_handler_fallback: Option<FallbackCommand>,
 }
 
 impl Config {
-    /// Create a new instance of AppsConfig
-    pub fn new() -> Result<Self> {
-        let config = ConfigFile::load();
-        let terminal_output = std::io::stdout().is_terminal();
-
-        // Issue a notification if handlr is not being run in a terminal
-        // Config's errors are not able to be handled by `main`'s similar error handling
-        if let Err(ref e) = config {
-            if !terminal_output {
-                utils::notify("handlr error", &e.to_string())?
-            }
-        }
+    /// Load the config file and mimeapps.list for running a given command
+    /// Every command gets its config from here, and a config file that cannot be loaded is always an error
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn load(cmd: &Cmd) -> Result<Self> {
+        Ok(Self::from_parts(
+            ConfigFile::load()?,
+            MimeApps::read()?,
+            std::io::stdout().is_terminal(),
+            cmd,
+        ))
+    }
+
+    /// Get the config for running a given command without reading the config file or mimeapps.list,
+    /// for the commands that do not depend on them
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn unloaded(cmd: &Cmd) -> Self {
+        Self::from_parts(
+            ConfigFile::default(),
+            MimeApps::default(),
+            std::io::stdout().is_terminal(),
+            cmd,
+        )
+    }
 
-        Ok(Self {
-            // Ensure fields individually default rather than making the whole thing fail if one is missing
-            mime_apps: MimeApps::read()?,
-            system_apps: SystemApps::populate()?,
-            config: config?,
+    /// Put together the config for running a given command,
+    /// with the config file's settings overridden by the command's arguments
+    fn from_parts(
+        config: ConfigFile,
+        mime_apps: MimeApps,
+        terminal_output: bool,
+        cmd: &Cmd,
+    ) -> Self {
+        let mut config = Self {
+            mime_apps,
+            system_apps: OnceCell::new(),
+            mime_info: OnceCell::new(),
+            config,
             terminal_output,
-        })
+            write_desktop: false,
+            tag: None,
+            action: None,
+            include_hidden: false,
+            follow_links: false,
+            keep_duplicates: false,
+            notify_done: false,
+            wait: false,
+            dry_run: false,
+            handler_override: None,
+            no_handler_fallback: None,
+        };
+
+        if let Some(selector_args) = cmd.selector_args() {
+            config.override_selector(selector_args.clone());
+        }
+
+        // Selections are only remembered by commands that actually use them
+        config.config.selector_persist = cmd
+            .remember()
+            .is_some_and(|remember| remember || config.config.selector_persist);
+
+        config
     }
 
     /// Get the handler associated with a given mime
     pub fn get_handler(&self, mime: &Mime) -> Result<DesktopHandler> {
-        match self.mime_apps.get_handler_from_user(mime, &self.config) {
-            Err(e) if matches!(e, Error::Cancelled) => Err(e),
-            h => h.or_else(|_| self.get_handler_from_added_associations(mime)),
+        // Lists of handlers are never empty
+        Ok(self.get_handlers(mime)?.remove(0))
+    }
+
+    /// Get the handlers associated with a given mime, in the order they should be tried
+    /// Aliases are resolved to their canonical mime first, though associations for the alias itself are still used.
+    /// If there are none, fall back to the handlers of the mimes it is a subclass of
+    pub fn get_handlers(&self, mime: &Mime) -> Result<Vec<DesktopHandler>> {
+        Ok(self
+            .get_handlers_traced(mime, None, &mut Trace::default())?
+            .0)
+    }
+
+    /// Same as `get_handlers`, but also gets where the handlers came from and records the steps taken to find them
+    /// `path` is what is being opened, if anything, for the selector to be told about
+    fn get_handlers_traced(
+        &self,
+        mime: &Mime,
+        path: Option<&str>,
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)> {
+        let mime_info = self.mime_info()?;
+        let canonical = mime_info.canonical(mime);
+
+        if canonical != *mime {
+            trace.step(|| format!("'{mime}' is an alias of '{canonical}'"));
+        }
+
+        let candidates = std::iter::once(canonical.clone())
+            .chain((canonical != *mime).then(|| mime.clone()))
+            .chain(mime_info.ancestors(&canonical))
+            .collect_vec();
+
+        for candidate in &candidates {
+            trace.step(|| format!("trying '{candidate}'"));
+            match self.get_handlers_without_parents(candidate, path, trace) {
+                Err(Error::NotFound(_)) => continue,
+                handlers => return handlers,
+            }
         }
+
+        if canonical.essence_str() == "inode/directory" {
+            trace.step(|| "falling back to a file manager".to_owned());
+            return Ok((
+                vec![self.file_manager()?],
+                HandlerSource::FileManager,
+            ));
+        }
+
+        self.get_hidden_handlers(mime, &candidates, trace)
     }
 
-    /// Get the handler associated with a given mime from mimeapps.list's added associations
-    /// If there is none, default to the system apps
-    fn get_handler_from_added_associations(
+    /// Get the installed apps hidden with `NoDisplay` for the first of the candidates for a given mime that has any,
+    /// once nothing else can open it
+    /// Unless `use_hidden_fallback` is set, they are only named in the error
+    fn get_hidden_handlers(
         &self,
         mime: &Mime,
-    ) -> Result<DesktopHandler> {
-        self.mime_apps
-            .added_associations
-            .get(mime)
-            .map_or_else(
-                || self.system_apps.get_handler(mime),
-                |h| h.front().cloned(),
+        candidates: &[Mime],
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)> {
+        for candidate in candidates {
+            let handlers = self
+                .system_apps()?
+                .get_hidden_handlers(candidate)
+                .into_iter()
+                .flat_map(|list| list.iter().cloned().collect_vec())
+                .filter(|h| !self.mime_apps.is_removed(candidate, h))
+                .filter(|h| h.exists())
+                .collect_vec();
+
+            if handlers.is_empty() {
+                continue;
+            }
+
+            trace.step(|| {
+                format!(
+                    "hidden apps for '{candidate}': {}",
+                    handlers.iter().join(", ")
+                )
+            });
+
+            if !self.config.use_hidden_fallback {
+                return Err(Error::OnlyHidden(
+                    mime.to_string(),
+                    handlers.iter().map(|h| h.to_string()).collect(),
+                ));
+            }
+
+            eprintln!(
+                "note: only hidden applications can open '{mime}', using {}",
+                handlers[0]
+            );
+            return Ok((handlers, HandlerSource::System));
+        }
+
+        Err(Error::NotFound(mime.to_string()))
+    }
+
+    /// Get a file manager to open directories with when none is set for inode/directory
+    /// The one in the config file is preferred over any installed file manager
+    fn file_manager(&self) -> Result<DesktopHandler> {
+        if let Some(handler) = self
+            .config
+            .default_file_manager
+            .as_deref()
+            .map(DesktopHandler::from_str)
+            .transpose()?
+            .filter(|h| h.exists())
+        {
+            return Ok(handler);
+        }
+
+        self.system_apps()?
+            .file_manager()
+            .ok_or(Error::NoFileManager)
+    }
+
+    /// Get every handler that could be used for a given mime in priority order,
+    /// along with the mime it is associated with and where the association comes from
+    /// Unlike `get_handlers`, which only uses the first source with any handlers,
+    /// handlers from every source, and from every parent of the mime, are included
+    pub fn get_all_handlers(
+        &self,
+        mime: &Mime,
+    ) -> Result<Vec<(DesktopHandler, Mime, HandlerSource)>> {
+        let mime_info = self.mime_info()?;
+        let canonical = mime_info.canonical(mime);
+
+        let candidates = std::iter::once(canonical.clone())
+            .chain((canonical != *mime).then(|| mime.clone()))
+            .chain(mime_info.ancestors(&canonical));
+
+        let mut handlers = Vec::new();
+
+        for candidate in candidates {
+            // Same as what `get_handlers_from_added_associations` skips
+            let kept = |h: &DesktopHandler| {
+                !self.mime_apps.is_removed(&candidate, h) && h.exists()
+            };
+
+            let defaults = self
+                .mime_apps
+                .get_default_candidates(&candidate, &self.config)
+                .into_iter()
+                .filter(|(h, _)| h.exists())
+                .map(|(h, wildcard)| {
+                    let source = if wildcard {
+                        HandlerSource::Wildcard
+                    } else {
+                        HandlerSource::Default
+                    };
+                    (h, source)
+                })
+                .collect_vec();
+
+            let added = self
+                .mime_apps
+                .get_added_handlers(&candidate)
+                .into_iter()
+                .flat_map(|list| list.iter())
+                .filter(|h| kept(h))
+                .map(|h| (h.clone(), HandlerSource::AddedAssociation))
+                .collect_vec();
+
+            let system = self
+                .system_apps()?
+                .get_handlers(&candidate)
+                .into_iter()
+                .flat_map(|list| list.iter().cloned().collect_vec())
+                .filter(|h| kept(h))
+                .map(|h| (h, HandlerSource::System))
+                .collect_vec();
+
+            handlers.extend(
+                defaults
+                    .into_iter()
+                    .chain(added)
+                    .chain(system)
+                    .map(|(h, source)| (h, candidate.clone(), source)),
+            );
+        }
+
+        if handlers.is_empty() && canonical.essence_str() == "inode/directory" {
+            handlers.push((
+                self.file_manager()?,
+                canonical,
+                HandlerSource::FileManager,
+            ));
+        }
+
+        if handlers.is_empty() {
+            return Err(Error::NotFound(mime.to_string()));
+        }
+
+        Ok(handlers
+            .into_iter()
+            .unique_by(|(handler, _, _)| handler.clone())
+            .collect())
+    }
+
+    /// Get the mime to write to mimeapps.list for a given mime
+    /// Aliases are replaced with their canonical mime unless `raw` is true,
+    /// and either way, a message explaining what happened is written to `writer`
+    pub fn unalias<W: Write>(
+        &self,
+        writer: &mut W,
+        mime: &Mime,
+        raw: bool,
+    ) -> Result<Mime> {
+        let canonical = self.mime_info()?.canonical(mime);
+
+        if canonical == *mime {
+            Ok(canonical)
+        } else if raw {
+            writeln!(
+                writer,
+                "warning: '{mime}' is an alias of '{canonical}', which other programs may expect instead"
+            )?;
+            Ok(mime.clone())
+        } else {
+            writeln!(
+                writer,
+                "note: using '{canonical}' instead of its alias '{mime}', pass --raw to keep the alias"
+            )?;
+            Ok(canonical)
+        }
+    }
+
+    /// Get the handlers associated with exactly a given mime, in the order they should be tried
+    fn get_handlers_without_parents(
+        &self,
+        mime: &Mime,
+        path: Option<&str>,
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)> {
+        match self.mime_apps.get_handlers_from_user(
+            mime,
+            path,
+            &self.config,
+            trace,
+        ) {
+            Err(e)
+                if matches!(
+                    e,
+                    Error::Cancelled | Error::SelectorNotFound(_)
+                ) =>
+            {
+                Err(e)
+            }
+            h => h.or_else(|_| {
+                self.get_handlers_from_added_associations_traced(mime, trace)
+            }),
+        }
+    }
+
+    /// Get the handlers associated with a given mime from mimeapps.list's added associations
+    /// If there are none, default to the system apps
+    /// Either way, handlers in mimeapps.list's removed associations are ignored
+    fn get_handlers_from_added_associations(
+        &self,
+        mime: &Mime,
+    ) -> Result<Vec<DesktopHandler>> {
+        Ok(self
+            .get_handlers_from_added_associations_traced(
+                mime,
+                &mut Trace::default(),
+            )?
+            .0)
+    }
+
+    /// Same as `get_handlers_from_added_associations`, but also gets where the handlers came from
+    /// and records the steps taken to find them
+    fn get_handlers_from_added_associations_traced(
+        &self,
+        mime: &Mime,
+        trace: &mut Trace,
+    ) -> Result<(Vec<DesktopHandler>, HandlerSource)> {
+        // Skip handlers listed in removed associations or whose desktop files cannot be found
+        let kept = |handlers: &DesktopList| {
+            Some(
+                handlers
+                    .iter()
+                    .filter(|h| !self.mime_apps.is_removed(mime, h))
+                    .filter(|h| h.exists())
+                    .cloned()
+                    .collect_vec(),
             )
+            .filter(|handlers| !handlers.is_empty())
+        };
+
+        let added = self.mime_apps.get_added_handlers(mime).and_then(kept);
+        trace.step(|| match &added {
+            Some(handlers) => format!(
+                "added associations for '{mime}': {}",
+                handlers.iter().join(", ")
+            ),
+            None => format!("no added associations for '{mime}'"),
+        });
+
+        if let Some(handlers) = added {
+            return Ok((handlers, HandlerSource::AddedAssociation));
+        }
+
+        let system = self.system_apps()?.get_handlers(mime);
+        let system = system.as_ref().and_then(kept);
+        trace.step(|| match &system {
+            Some(handlers) => format!(
+                "installed apps for '{mime}': {}",
+                handlers.iter().join(", ")
+            ),
+            None => format!("no installed apps for '{mime}'"),
+        });
+
+        system
+            .map(|handlers| (handlers, HandlerSource::System))
             .ok_or_else(|| Error::NotFound(mime.to_string()))
     }
 
+    /// Get the available applications on the system, populating them if they have not been yet
+    fn system_apps(&self) -> Result<&SystemApps> {
+        self.system_apps.get_or_try_init(|| {
+            // Populating depends on system state, so tests start with no system apps
+            if cfg!(test) {
+                Ok(SystemApps::default())
+            } else {
+                SystemApps::populate(
+                    self.config.check_try_exec,
+                    self.include_hidden,
+                )
+            }
+        })
+    }
+
+    /// Get the hierarchy of mime subclasses, loading it if it has not been yet
+    fn mime_info(&self) -> Result<&MimeInfo> {
+        self.mime_info.get_or_try_init(|| {
+            // Loading depends on system state, so tests start with an empty hierarchy
+            if cfg!(test) {
+                Ok(MimeInfo::default())
+            } else {
+                MimeInfo::load()
+            }
+        })
+    }
+
     /// Given a mime and arguments, launch the associated handler with the arguments
     #[mutants::skip] // Cannot test directly, runs external command
     pub fn launch_handler(&self, mime: &Mime, args: Vec<String>) -> Result<()> {
-        self.get_handler(mime)?
-            .launch(self, args.into_iter().map(|a| a.to_string()).collect())
+        let handlers = self.get_handlers(mime)?;
+
+        if self.dry_run {
+            let mut stdout = std::io::stdout().lock();
+            let handler = Handler::from(handlers[0].clone());
+            return write_plan(&mut stdout, &handler.describe_for(self), &args);
+        }
+
+        try_handlers(&handlers, |handler| handler.launch(self, args.clone()))
+    }
+
+    /// Launch the regex handler with a given name with arguments, regardless of what they are
+    #[mutants::skip] // Cannot test directly, runs external command
+    pub fn launch_regex_handler(
+        &self,
+        name: &str,
+        args: Vec<String>,
+    ) -> Result<()> {
+        let handler = Handler::from(self.config.get_named_regex_handler(name)?);
+
+        if self.dry_run {
+            let mut stdout = std::io::stdout().lock();
+            return write_plan(&mut stdout, &handler.describe_for(self), &args);
+        }
+
+        handler.get_entry()?.exec(self, ExecMode::Launch, args)
     }
 
     /// Get the handler associated with a given mime
+    /// The steps taken to find it are recorded in `trace`
     pub fn show_handler<W: Write>(
         &self,
         writer: &mut W,
         mime: &Mime,
-        output_json: bool,
+        format: OutputFormat,
+        trace: &mut Trace,
     ) -> Result<()> {
-        let handler = self.get_handler(mime)?;
+        // Lists of handlers are never empty
+        let (handlers, source) = self.get_handlers_traced(mime, None, trace)?;
+        let handler = &handlers[0];
+        trace.step(|| format!("using {handler} from {source}"));
 
-        let output = if output_json {
+        let output = if format.is_json() {
             let entry = handler.get_entry()?;
             let cmd = entry.get_cmd(self, vec![])?;
 
             (serde_json::json!( {
                 "handler": handler.to_string(),
                 "name": entry.name,
+                "keywords": entry.keywords,
                 "cmd": cmd.0 + " " + &cmd.1.join(" "),
+                "source": source,
             }))
             .to_string()
         } else {
@@ -110,58 +548,363 @@ impl Config {
         Ok(())
     }
 
-    /// Set a default application association, overwriting any existing association for the same mimetype
-    /// and writes it to mimeapps.list
+    /// Print every handler that could be used for a given mime in priority order, along with where it comes from
+    pub fn show_all_handlers<W: Write>(
+        &self,
+        writer: &mut W,
+        mime: &Mime,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let rows = self
+            .get_all_handlers(mime)?
+            .into_iter()
+            .map(|(handler, mime, source)| CandidateEntry {
+                name: handler.get_entry().map(|e| e.name).unwrap_or_default(),
+                handler: handler.to_string(),
+                mime: mime.to_string(),
+                source,
+            })
+            .collect_vec();
+
+        write_rows(writer, &rows, format, &self.config.table)?;
+
+        Ok(())
+    }
+
+    /// Set a default application association for each of the given mimes, overwriting any existing associations for them
+    /// and writes them to mimeapps.list all at once
     pub fn set_handler(
         &mut self,
-        mime: &Mime,
+        mimes: &[Mime],
         handler: &DesktopHandler,
     ) -> Result<()> {
-        self.mime_apps.set_handler(
-            mime,
-            handler,
-            self.config.expand_wildcards,
-        )?;
-        self.mime_apps.save()
+        let expand_wildcards = self.config.expand_wildcards;
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        mimes.iter().try_for_each(|mime| {
+            mime_apps.set_handler(mime, handler, expand_wildcards)
+        })?;
+        mime_apps.save(backup)
+    }
+
+    /// Get the mimes and the handler to use for them from the arguments of `handlr set` or `handlr add`,
+    /// writing which mimes extensions and aliases were turned into
+    /// The handler is the one that runs `from_command` if given, or the last argument if there is more than one,
+    /// and is otherwise picked from the installed applications that support the only mime if `select` is true
+    #[mutants::skip] // Cannot test directly, depends on system state and may require user input
+    pub fn mimes_and_handler<W: Write>(
+        &self,
+        writer: &mut W,
+        args: &[String],
+        from_command: Option<&str>,
+        raw: bool,
+        select: bool,
+    ) -> Result<(Vec<Mime>, DesktopHandler)> {
+        let handler = from_command
+            .map(SystemApps::handler_for_command)
+            .transpose()?;
+        let (mimes, handler) = MimeOrExtension::with_handler(args, handler)?;
+
+        // Every mime is checked, and the handler picked if need be, before anything is changed
+        let mimes = mimes
+            .iter()
+            .map(|mime| {
+                mime.explain(writer)?;
+                self.unalias(writer, mime, raw)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let handler = match handler {
+            Some(handler) => handler,
+            // Only a single mime can be given without a handler
+            None if select => self.pick_handler(&mimes[0])?,
+            None => return Err(Error::NoHandlerGiven(mimes[0].to_string())),
+        };
+
+        Ok((mimes, handler))
+    }
+
+    /// Ask the user to pick a handler for a given mime from the installed applications that support it
+    #[mutants::skip] // Cannot test directly, requires user input
+    pub fn pick_handler(&self, mime: &Mime) -> Result<DesktopHandler> {
+        self.pick_handler_with(mime, |options| {
+            utils::choose(
+                &format!("Handler for {mime}?"),
+                &options,
+                mime,
+                &self.config.selector,
+            )
+        })
+    }
+
+    /// Pick a handler for a given mime from the installed applications that support it,
+    /// choosing between them with `choose`, which is given each one's name and desktop file name
+    /// Makes testing easier
+    fn pick_handler_with<C>(
+        &self,
+        mime: &Mime,
+        choose: C,
+    ) -> Result<DesktopHandler>
+    where
+        C: FnOnce(Vec<String>) -> Result<usize>,
+    {
+        let mut candidates = self.system_apps()?.get_candidates(mime);
+
+        if candidates.is_empty() {
+            return Err(Error::NotFound(mime.to_string()));
+        }
+
+        let options = candidates
+            .iter()
+            .map(|handler| match handler.get_entry() {
+                Ok(entry) => format!("{} ({handler})", entry.name),
+                Err(_) => handler.to_string(),
+            })
+            .collect_vec();
+
+        let choice = choose(options)?;
+        if choice >= candidates.len() {
+            return Err(Error::Cancelled);
+        }
+
+        Ok(candidates.swap_remove(choice))
     }
 
-    /// Add a handler to an existing default application association
-    /// and writes it to mimeapps.list
+    /// Add a handler to the end of the existing default application associations of each of the given mimes
+    /// and writes them to mimeapps.list all at once
     pub fn add_handler(
         &mut self,
-        mime: &Mime,
+        mimes: &[Mime],
         handler: &DesktopHandler,
     ) -> Result<()> {
-        self.mime_apps.add_handler(
-            mime,
-            handler,
-            self.config.expand_wildcards,
-        )?;
-        self.mime_apps.save()
+        self.change_handlers(mimes, |mime_apps, mime, expand_wildcards| {
+            mime_apps.add_handler(mime, handler, expand_wildcards)
+        })
+    }
+
+    /// Add a handler to the start of the existing default application associations of each of the given mimes,
+    /// making it the preferred one, and writes them to mimeapps.list all at once
+    pub fn add_handler_front(
+        &mut self,
+        mimes: &[Mime],
+        handler: &DesktopHandler,
+    ) -> Result<()> {
+        self.change_handlers(mimes, |mime_apps, mime, expand_wildcards| {
+            mime_apps.add_handler_front(mime, handler, expand_wildcards)
+        })
+    }
+
+    /// Add a handler at the given index of the existing default application associations of each of the given mimes
+    /// and writes them to mimeapps.list all at once
+    pub fn insert_handler(
+        &mut self,
+        mimes: &[Mime],
+        handler: &DesktopHandler,
+        index: usize,
+    ) -> Result<()> {
+        self.change_handlers(mimes, |mime_apps, mime, expand_wildcards| {
+            mime_apps.insert_handler(mime, handler, expand_wildcards, index)
+        })
+    }
+
+    /// Make the same change to the associations of each of the given mimes in the mimeapps.list being written to,
+    /// then save it once
+    fn change_handlers(
+        &mut self,
+        mimes: &[Mime],
+        change: impl Fn(&mut MimeApps, &Mime, bool) -> Result<()>,
+    ) -> Result<()> {
+        let expand_wildcards = self.config.expand_wildcards;
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        mimes
+            .iter()
+            .try_for_each(|mime| change(mime_apps, mime, expand_wildcards))?;
+        mime_apps.save(backup)
     }
 
     /// Open the given paths with their respective handlers
+    /// Only the first of the same files or URLs is opened unless duplicates are kept
     #[mutants::skip] // Cannot test directly, runs external commands
-    pub fn open_paths(&self, paths: &[UserPath]) -> Result<()> {
-        for (handler, paths) in
-            self.assign_files_to_handlers(paths)?.into_iter()
+    pub fn open_paths(
+        &self,
+        paths: &[UserPath],
+        skip_confirmation: bool,
+    ) -> Result<()> {
+        let paths = paths
+            .iter()
+            .cloned()
+            .map(|path| {
+                if self.config.assume_http {
+                    path.assume_https()
+                } else {
+                    path
+                }
+            })
+            .collect_vec();
+
+        let paths = if self.keep_duplicates {
+            paths
+        } else {
+            let (unique, removed) = dedup_paths(&paths);
+            match removed {
+                0 => {}
+                1 => eprintln!("note: skipped 1 duplicate path"),
+                n => eprintln!("note: skipped {n} duplicate paths"),
+            }
+            unique
+        };
+
+        if self.dry_run {
+            return self.print_plan(&mut std::io::stdout().lock(), &paths);
+        }
+
+        let confirm = |message: &str, mime: &Mime| {
+            if skip_confirmation {
+                Ok(true)
+            } else {
+                utils::confirm(message, mime, &self.config.selector)
+            }
+        };
+
+        for (handlers, paths) in
+            self.assign_files_to_handlers(&paths, confirm)?.into_iter()
         {
-            handler.open(self, paths)?;
+            try_handlers(&handlers, |handler| {
+                handler.open(self, paths.clone())
+            })?;
         }
 
         Ok(())
     }
 
+    /// Open the URL of a Link desktop entry with its handlers
+    /// Those handlers are never followed in turn, so links cannot lead to each other forever
+    #[mutants::skip] // Cannot test directly, runs external commands
+    pub fn open_link(&self, url: &str) -> Result<()> {
+        let path = UserPath::from_str(url)?;
+
+        try_handlers(&self.get_handlers_from_path(&path)?, |handler| {
+            handler.get_entry()?.exec(
+                self,
+                ExecMode::Open,
+                vec![path.to_string()],
+            )
+        })
+    }
+
+    /// Print each time a handler would be run to open the given paths, followed by the paths it would be given,
+    /// as tab-separated lines in the order the handlers are first needed
+    /// Paths are assigned to handlers like when opening them, and split between as many runs as the handler's field codes call for
+    /// Paths without a handler are reported on stderr, and make this fail once everything else is printed
+    // Handlers are only hashed by their regex patterns, which never change
+    #[allow(clippy::mutable_key_type)]
+    fn print_plan<W: Write>(
+        &self,
+        writer: &mut W,
+        paths: &[UserPath],
+    ) -> Result<()> {
+        let mut unresolved = 0;
+        // Nothing is opened, so there is nothing to confirm
+        let groups = self.assign_files_to_handlers_with(
+            paths,
+            |_, _| Ok(true),
+            |path, e| {
+                eprintln!("error: {path}: {e}");
+                unresolved += 1;
+                Ok(())
+            },
+        )?;
+
+        for (handlers, paths) in groups {
+            // Lists of handlers are never empty, and only the first would be given the paths unless it fails
+            let handler = &handlers[0];
+            let entry = match handler.get_entry_for(self) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("error: {handler}: {e}");
+                    unresolved += paths.len();
+                    continue;
+                }
+            };
+
+            let description = handler.describe_for(self);
+            for args in entry.invocations_for(self, ExecMode::Open, paths) {
+                write_plan(writer, &description, &args)?;
+            }
+        }
+
+        match unresolved {
+            0 => Ok(()),
+            n => Err(Error::Unresolved(n)),
+        }
+    }
+
     /// Helper function to assign files to their respective handlers
-    fn assign_files_to_handlers(
+    /// Files are grouped by the list of handlers to try opening them with, in order,
+    /// in the order those lists are first needed
+    /// Files with mimes in `confirm_mimes` are only assigned if `confirm` returns true,
+    /// otherwise the whole operation is cancelled
+    /// Files without any handler are assigned to the fallback command if there is one
+    // Handlers are only hashed by their regex patterns, which never change
+    #[allow(clippy::mutable_key_type)]
+    fn assign_files_to_handlers<F: FnMut(&str, &Mime) -> Result<bool>>(
+        &self,
+        paths: &[UserPath],
+        confirm: F,
+    ) -> Result<IndexMap<Vec<Handler>, Vec<String>>> {
+        self.assign_files_to_handlers_with(paths, confirm, |_, e| Err(e))
+    }
+
+    /// Assign files to their respective handlers like `assign_files_to_handlers`,
+    /// passing the files that no handler can be found for to `unresolved` along with why,
+    /// which can skip them by returning `Ok`
+    // Handlers are only hashed by their regex patterns, which never change
+    #[allow(clippy::mutable_key_type)]
+    fn assign_files_to_handlers_with<F, U>(
         &self,
         paths: &[UserPath],
-    ) -> Result<HashMap<Handler, Vec<String>>> {
-        let mut handlers: HashMap<Handler, Vec<String>> = HashMap::new();
+        mut confirm: F,
+        mut unresolved: U,
+    ) -> Result<IndexMap<Vec<Handler>, Vec<String>>>
+    where
+        F: FnMut(&str, &Mime) -> Result<bool>,
+        U: FnMut(&UserPath, Error) -> Result<()>,
+    {
+        let mut handlers: IndexMap<Vec<Handler>, Vec<String>> = IndexMap::new();
 
         for path in paths.iter() {
+            let path_handlers = match (
+                self.get_handlers_from_path(path),
+                &self.no_handler_fallback,
+            ) {
+                (Err(Error::NotFound(_)), Some(fallback)) => {
+                    vec![fallback.clone().into()]
+                }
+                (Ok(handlers), _) => handlers,
+                (Err(e), _) => {
+                    unresolved(path, e)?;
+                    continue;
+                }
+            };
+
+            let mime = path
+                .get_mime(
+                    self.config.sniff_content,
+                    &self.config.mime_overrides,
+                )
+                .ok()
+                .filter(|mime| self.config.needs_confirmation(mime));
+            if let Some(mime) = mime {
+                if !confirm(
+                    &format!("Open '{path}' with {}?", path_handlers[0]),
+                    &mime,
+                )? {
+                    return Err(Error::Cancelled);
+                }
+            }
+
             handlers
-                .entry(self.get_handler_from_path(path)?)
+                .entry(path_handlers)
                 .or_default()
                 .push(path.to_string())
         }
@@ -169,51 +912,198 @@ impl Config {
         Ok(handlers)
     }
 
-    /// Get the handler associated with a given path
-    fn get_handler_from_path(&self, path: &UserPath) -> Result<Handler> {
-        Ok(if let Ok(handler) = self.config.get_regex_handler(path) {
-            handler.into()
-        } else {
-            self.get_handler(&path.get_mime()?)?.into()
-        })
+    /// Get the handlers associated with a given path, in the order they should be tried
+    fn get_handlers_from_path(&self, path: &UserPath) -> Result<Vec<Handler>> {
+        self.get_handlers_from_path_traced(path, &mut Trace::default())
+    }
+
+    /// Get the handlers associated with a given path, in the order they should be tried
+    /// The steps taken to find them are recorded in `trace`
+    fn get_handlers_from_path_traced(
+        &self,
+        path: &UserPath,
+        trace: &mut Trace,
+    ) -> Result<Vec<Handler>> {
+        if let Some(handler) = &self.handler_override {
+            trace.step(|| {
+                format!("using {handler}, which was given explicitly")
+            });
+            return Ok(vec![handler.clone().into()]);
+        }
+
+        if self.config.regex_handlers_take_precedence {
+            if let Ok(handler) = self.get_regex_handler_traced(path, trace) {
+                trace.step(|| {
+                    format!("using {handler}, since regex handlers take precedence over mime associations")
+                });
+                return Ok(vec![handler.into()]);
+            }
+
+            return self.get_mime_handlers_traced(path, trace);
+        }
+
+        trace.step(|| {
+            "trying mime associations first, since `regex_handlers_take_precedence` is false".to_owned()
+        });
+
+        match self.get_mime_handlers_traced(path, trace) {
+            Ok(handlers) => Ok(handlers),
+            // Regex handlers only catch what mime associations do not
+            Err(e) => match self.get_regex_handler_traced(path, trace) {
+                Ok(handler) => {
+                    trace.step(|| {
+                        format!("using {handler}, since no mime association could be used")
+                    });
+                    Ok(vec![handler.into()])
+                }
+                Err(_) => Err(e),
+            },
+        }
+    }
+
+    /// Get the regex handler that matches a given path, recording the result in `trace`
+    fn get_regex_handler_traced(
+        &self,
+        path: &UserPath,
+        trace: &mut Trace,
+    ) -> Result<RegexHandler> {
+        let handler = self.config.get_regex_handler(path);
+        trace.step(|| match &handler {
+            Ok(handler) => format!(
+                "'{path}' matches regex handler {handler} with priority {}",
+                handler.priority()
+            ),
+            Err(_) => format!("no regex handler matches '{path}'"),
+        });
+        handler
+    }
+
+    /// Get the handlers associated with a given path's mime, in the order they should be tried
+    /// The steps taken to find them are recorded in `trace`
+    fn get_mime_handlers_traced(
+        &self,
+        path: &UserPath,
+        trace: &mut Trace,
+    ) -> Result<Vec<Handler>> {
+        let mime = path
+            .get_mime(self.config.sniff_content, &self.config.mime_overrides)?;
+        trace.step(|| format!("'{path}' is '{mime}'"));
+        // The selector is told what is being opened if it has to choose a handler
+        let opened = path.to_string();
+        let opened = Some(opened.as_str());
+
+        let (handlers, source) = match path
+            .url_file_mime()
+            .filter(|_| self.config.url_mime_from_extension)
+        {
+            // Fall back to the URL scheme's handler if nothing handles the file
+            Some(file_mime) => {
+                trace.step(|| format!("the URL's file is '{file_mime}'"));
+                match self.get_handlers_traced(&file_mime, opened, trace) {
+                    Err(Error::NotFound(_)) => {
+                        self.get_handlers_traced(&mime, opened, trace)?
+                    }
+                    handlers => handlers?,
+                }
+            }
+            None => self.get_handlers_traced(&mime, opened, trace)?,
+        };
+
+        // Lists of handlers are never empty
+        trace.step(|| format!("using {} from {source}", handlers[0]));
+
+        Ok(handlers.into_iter().map(Handler::from).collect())
     }
 
     /// Get the command for the x-scheme-handler/terminal handler if one is set.
     /// Otherwise, finds a terminal emulator program and uses it.
+    /// `term_args` replaces the configured `term_exec_args` if given.
     // TODO: test falling back to system
-    pub fn terminal(&self) -> Result<String> {
+    pub fn terminal(&self, term_args: Option<&str>) -> Result<String> {
         // Get the terminal handler if there is one set
-        self.get_handler(&Mime::from_str("x-scheme-handler/terminal")?)
+        let entry = match self
+            .get_handler(&Mime::from_str("x-scheme-handler/terminal")?)
             .ok()
             .and_then(|h| h.get_entry().ok())
+        {
+            Some(entry) => Some(entry),
             // Otherwise, get a terminal emulator program
-            .or_else(|| self.system_apps.terminal_emulator())
+            None => self.system_apps()?.terminal_emulator(),
+        };
+
+        entry
             .map(|e| {
                 let mut exec = e.exec.to_owned();
 
-                if let Some(opts) = &self.config.term_exec_args {
+                // Arguments that make the terminal's window identifiable by its tag
+                if let Some(args) =
+                    self.tag_args(&e.file_name.to_string_lossy())
+                {
+                    exec.push(' ');
+                    exec.push_str(&args)
+                }
+
+                if let Some(opts) =
+                    term_args.or(self.config.term_exec_args.as_deref())
+                {
                     exec.push(' ');
                     exec.push_str(opts)
                 }
 
                 exec
             })
-            .ok_or_else(|| Error::NoTerminal)
+            .ok_or(Error::NoTerminal)
     }
 
-    /// Print the set associations and system-level associations in a table
+    /// Print the handlers associated with a given mime or extension in mimeapps.list as `handler\tdescription` lines
+    /// Used by completions, so nothing is printed if the mime is invalid or has no associations
+    pub fn print_handlers_of<W: Write>(
+        &self,
+        writer: &mut W,
+        mime: &str,
+    ) -> Result<()> {
+        for (handler, description) in self.mime_apps.describe_handlers(mime) {
+            writeln!(writer, "{handler}\t{description}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Print the set associations and system-level associations in a table
+    /// If a pattern is given, only mimes that match it are included, see `matches_pattern`
+    /// If `names` is true, the names of handlers are shown alongside them
     pub fn print<W: Write>(
         &self,
         writer: &mut W,
         detailed: bool,
-        output_json: bool,
+        pattern: Option<&str>,
+        names: bool,
+        format: OutputFormat,
     ) -> Result<()> {
+        let output_json = format == OutputFormat::Json;
+        let pretty = format == OutputFormat::Table;
+        let hidden: &[&str] = if names { &[] } else { &["names"] };
+
+        // System apps are only shown in detailed output, so avoid populating them otherwise
+        let no_system_apps = SystemApps::default();
+        let system_apps = if detailed {
+            self.system_apps()?
+        } else {
+            &no_system_apps
+        };
+
         let mimeapps_table = MimeAppsTable::new(
             &self.mime_apps,
-            &self.system_apps,
-            self.terminal_output,
+            system_apps,
+            pattern,
+            names,
+            pretty,
         );
 
+        if format == OutputFormat::Jsonl {
+            return mimeapps_table.write_json_lines(writer, detailed);
+        }
+
         if detailed {
             if output_json {
                 writeln!(writer, "{}", serde_json::to_string(&mimeapps_table)?)?
@@ -222,29 +1112,67 @@ impl Config {
                 writeln!(
                     writer,
                     "{}",
-                    render_table(
+                    render_table_without(
                         &mimeapps_table.default_apps,
-                        self.terminal_output
+                        format,
+                        &self.config.table,
+                        hidden
                     )
                 )?;
-                if !self.mime_apps.added_associations.is_empty() {
+                if !mimeapps_table.added_associations.is_empty() {
                     writeln!(writer, "Added associations")?;
                     writeln!(
                         writer,
                         "{}",
-                        render_table(
+                        render_table_without(
                             &mimeapps_table.added_associations,
-                            self.terminal_output
+                            format,
+                            &self.config.table,
+                            hidden
                         )
                     )?;
                 }
+                for layer in mimeapps_table.layers.iter() {
+                    if !layer.default_apps.is_empty() {
+                        writeln!(writer, "Default Apps from {}", layer.source)?;
+                        writeln!(
+                            writer,
+                            "{}",
+                            render_table_without(
+                                &layer.default_apps,
+                                format,
+                                &self.config.table,
+                                hidden
+                            )
+                        )?;
+                    }
+                    if !layer.added_associations.is_empty() {
+                        writeln!(
+                            writer,
+                            "Added associations from {}",
+                            layer.source
+                        )?;
+                        writeln!(
+                            writer,
+                            "{}",
+                            render_table_without(
+                                &layer.added_associations,
+                                format,
+                                &self.config.table,
+                                hidden
+                            )
+                        )?;
+                    }
+                }
                 writeln!(writer, "System Apps")?;
                 writeln!(
                     writer,
                     "{}",
-                    render_table(
+                    render_table_without(
                         &mimeapps_table.system_apps,
-                        self.terminal_output
+                        format,
+                        &self.config.table,
+                        hidden
                     )
                 )?
             }
@@ -258,9 +1186,11 @@ impl Config {
             writeln!(
                 writer,
                 "{}",
-                render_table(
+                render_table_without(
                     &mimeapps_table.default_apps,
-                    self.terminal_output
+                    format,
+                    &self.config.table,
+                    hidden
                 )
             )?
         }
@@ -268,671 +1198,4010 @@ impl Config {
         Ok(())
     }
 
-    /// Entirely remove a given mime's default application association
-    pub fn unset_handler(&mut self, mime: &Mime) -> Result<()> {
-        if self.mime_apps.unset_handler(mime).is_some() {
-            self.mime_apps.save()?
-        }
-
-        Ok(())
+    /// Print installed apps that handle mimes but are not associated with any in mimeapps.list
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn print_orphans<W: Write>(
+        &self,
+        writer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        self.print_orphans_from(writer, self.listed_entries()?, format)
     }
 
-    /// Remove a given handler from a given mime's default file associaion
-    pub fn remove_handler(
-        &mut self,
-        mime: &Mime,
-        handler: &DesktopHandler,
+    /// Print which of the given desktop entries are orphans
+    fn print_orphans_from<W: Write, I: IntoIterator<Item = DesktopEntry>>(
+        &self,
+        writer: &mut W,
+        entries: I,
+        format: OutputFormat,
     ) -> Result<()> {
-        if self.mime_apps.remove_handler(mime, handler).is_some() {
-            self.mime_apps.save()?
-        }
+        let pretty = format == OutputFormat::Table;
+        let index = self.mime_apps.handler_index();
+
+        let orphans = entries
+            .into_iter()
+            .filter(|entry| !entry.mime_type.is_empty())
+            .filter_map(|entry| {
+                let handler =
+                    DesktopHandler::assume_valid(entry.file_name).ok()?;
+
+                if index.contains_key(&handler) {
+                    return None;
+                }
 
-        Ok(())
-    }
+                // Mimes that this app would not be used for as things currently are
+                let handled_by_others = entry
+                    .mime_type
+                    .iter()
+                    .filter(|mime| {
+                        self.resolve_handler(mime)
+                            .is_some_and(|other| other != handler)
+                    })
+                    .map(|mime| mime.to_string())
+                    .collect();
+
+                Some(OrphanEntry {
+                    handler: handler.to_string(),
+                    name: entry.name,
+                    handled_by_others,
+                    keywords: entry.keywords,
+                    pretty,
+                })
+            })
+            .sorted_by(|a, b| a.handler.cmp(&b.handler))
+            .collect_vec();
 
-    /// Override the set selector
-    /// Currently assumes the config file will never be saved to other than to create an existing one
-    pub fn override_selector(&mut self, selector_args: SelectorArgs) {
-        self.config.override_selector(selector_args);
-    }
-}
+        write_rows(writer, &orphans, format, &self.config.table)?;
 
-/// Internal helper struct for turning MimeApps into tabular data
-#[derive(PartialEq, Eq, PartialOrd, Ord, Tabled, Serialize)]
-struct MimeAppsEntry {
-    mime: String,
-    #[tabled(display_with("Self::display_handlers", self))]
-    handlers: Vec<String>,
-    #[tabled(skip)]
-    #[serde(skip_serializing)]
-    // This field should not appear in any output
-    // It is only used for determining how to render output
-    separator: String,
-}
+        Ok(())
+    }
 
-impl MimeAppsEntry {
-    /// Create a new `MimeAppsEntry`
-    fn new(
-        mime: &Mime,
-        handlers: &VecDeque<DesktopHandler>,
-        separator: &str,
-    ) -> Self {
-        Self {
-            mime: mime.to_string(),
-            handlers: handlers
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>(),
-            separator: separator.to_string(),
-        }
+    /// Print every installed app along with the details from its desktop entry
+    #[mutants::skip] // Cannot test directly, depends on system state
+    pub fn print_apps<W: Write>(
+        &self,
+        writer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        self.print_apps_from(writer, self.listed_entries()?, format)
     }
 
-    /// Display list of handlers as a string
-    fn display_handlers(&self) -> String {
-        self.handlers.join(&self.separator)
+    /// Get the installed desktop entries to list,
+    /// leaving out the ones that should not be shown on the current desktop unless `--include-hidden` is used
+    #[mutants::skip] // Cannot test directly, depends on system state
+    fn listed_entries(&self) -> Result<impl Iterator<Item = DesktopEntry>> {
+        let desktops = desktops_from_env();
+        let include_hidden = self.include_hidden;
+        Ok(SystemApps::get_entries()?.map(|(_, entry)| entry).filter(
+            move |entry| include_hidden || entry.is_shown_in(&desktops),
+        ))
     }
-}
 
-/// Internal helper struct for turning MimeApps into tabular data
-#[derive(Serialize)]
-struct MimeAppsTable {
-    added_associations: Vec<MimeAppsEntry>,
-    default_apps: Vec<MimeAppsEntry>,
-    system_apps: Vec<MimeAppsEntry>,
-}
+    /// Print the given desktop entries that are applications
+    fn print_apps_from<W: Write, I: IntoIterator<Item = DesktopEntry>>(
+        &self,
+        writer: &mut W,
+        entries: I,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let pretty = format == OutputFormat::Table;
+
+        let apps = entries
+            .into_iter()
+            .filter(DesktopEntry::is_application)
+            .map(|entry| AppEntry {
+                // Desktop file names are not always UTF-8, but they should never stop the rest from being listed
+                handler: entry.file_name.to_string_lossy().into_owned(),
+                name: entry.name,
+                generic_name: entry.generic_name,
+                icon: entry.icon,
+                terminal: entry.terminal,
+                mime_type: entry
+                    .mime_type
+                    .iter()
+                    .map(|mime| mime.to_string())
+                    .collect(),
+                pretty,
+            })
+            .sorted_by(|a, b| a.handler.cmp(&b.handler))
+            .collect_vec();
 
-impl MimeAppsTable {
-    /// Create a new `MimeAppsTable`
-    fn new(
-        mimeapps: &MimeApps,
-        system_apps: &SystemApps,
-        terminal_output: bool,
-    ) -> Self {
-        // If output is a terminal, optimize for readability
-        // Otherwise, if piped, optimize for parseability
-        let separator = if terminal_output { ",\n" } else { ", " };
+        write_rows(writer, &apps, format, &self.config.table)?;
 
-        let to_entries =
-            |map: &BTreeMap<Mime, DesktopList>| -> Vec<MimeAppsEntry> {
-                let mut rows = map
-                    .iter()
-                    .map(|(mime, handlers)| {
-                        MimeAppsEntry::new(mime, handlers, separator)
-                    })
-                    .collect::<Vec<_>>();
-                rows.sort_unstable();
-                rows
-            };
-        Self {
-            added_associations: to_entries(&mimeapps.added_associations),
-            default_apps: to_entries(&mimeapps.default_apps),
-            system_apps: to_entries(&system_apps.associations),
-        }
+        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::assert_eq;
 
-    #[test]
-    fn wildcard_mimes() -> Result<()> {
-        let mut config = Config::default();
-        config.add_handler(
-            &Mime::from_str("video/*")?,
-            &DesktopHandler::assume_valid("mpv.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("video/webm")?,
-            &DesktopHandler::assume_valid("brave.desktop".into()),
-        )?;
+    /// Print every mime associated with a given handler, where each association comes from,
+    /// and whether the mime currently resolves to the handler
+    /// Wildcards are listed as they are unless `expand` is true
+    pub fn print_mimes_of<W: Write>(
+        &self,
+        writer: &mut W,
+        handler: &DesktopHandler,
+        expand: bool,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let system = self
+            .system_apps()?
+            .associations
+            .iter()
+            .filter(|(_, list)| list.contains(handler))
+            .map(|(mime, _)| {
+                (mime.clone(), HandlerSource::System, handler.to_string())
+            });
+
+        let rows = self
+            .mime_apps
+            .associations_of(handler)
+            .into_iter()
+            .chain(system)
+            .flat_map(|(mime, source, file)| {
+                let mimes = if expand && source == HandlerSource::Wildcard {
+                    let wildcard = wildmatch::WildMatch::new(mime.as_ref());
+                    mime_types()
+                        .iter()
+                        .filter(|m| wildcard.matches(m))
+                        .filter_map(|m| Mime::from_str(m).ok())
+                        .collect_vec()
+                } else {
+                    vec![mime]
+                };
+
+                mimes
+                    .into_iter()
+                    .map(move |mime| (mime, source, file.clone()))
+            })
+            .map(|(mime, source, file)| HandlerMimeEntry {
+                current: self.resolve_handler(&mime).as_ref() == Some(handler),
+                mime: mime.to_string(),
+                source,
+                file,
+            })
+            .collect_vec();
 
-        assert_eq!(
-            config
-                .get_handler(&Mime::from_str("video/mp4")?)?
-                .to_string(),
-            "mpv.desktop"
-        );
-        assert_eq!(
-            config
-                .get_handler(&Mime::from_str("video/asdf")?)?
-                .to_string(),
-            "mpv.desktop"
-        );
-        assert_eq!(
-            config
-                .get_handler(&Mime::from_str("video/webm")?)?
-                .to_string(),
-            "brave.desktop"
-        );
+        write_rows(writer, &rows, format, &self.config.table)?;
 
         Ok(())
     }
 
-    #[test]
-    fn complex_wildcard_mimes() -> Result<()> {
-        let mut config = Config::default();
-        config.add_handler(
-            &Mime::from_str("application/vnd.oasis.opendocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("application/vnd.openxmlformats-officedocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
-        )?;
+    /// Print how a given path fares against every pattern of every regex handler, and which handler would be used
+    pub fn print_regex_matches<W: Write>(
+        &self,
+        writer: &mut W,
+        path: &UserPath,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let path = if self.config.assume_http {
+            path.clone().assume_https()
+        } else {
+            path.clone()
+        };
 
-        assert_eq!(
-            config
-                .get_handler(&Mime::from_str(
-                    "application/vnd.oasis.opendocument.text"
-                )?,)?
-                .to_string(),
-            "startcenter.desktop"
-        );
-        assert_eq!(
-            config
-                .get_handler(
-                    &Mime::from_str("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")?,
-                )?
-                .to_string(),
-            "startcenter.desktop"
-        );
+        let matches = self.config.test_regex_handlers(&path);
+
+        write_rows(writer, &matches, format, &self.config.table)?;
 
         Ok(())
     }
 
-    // Helper command to test the tables of handlers
-    // Renders a table with a bunch of arbitrary handlers to a writer
-    // TODO: test printing with non-empty system apps too
-    fn print_handlers_test<W: Write>(
-        buffer: &mut W,
-        detailed: bool,
-        output_json: bool,
-        terminal_output: bool,
+    /// Print the handler that would open a given path and the command it would be run with, without running it
+    /// The steps taken to find the handler are recorded in `trace`
+    pub fn print_command<W: Write>(
+        &self,
+        writer: &mut W,
+        path: &UserPath,
+        format: OutputFormat,
+        trace: &mut Trace,
     ) -> Result<()> {
-        let mut config = Config::default();
+        let path = if self.config.assume_http {
+            path.clone().assume_https()
+        } else {
+            path.clone()
+        };
 
-        // Add arbitrary video handlers
-        config.add_handler(
-            &Mime::from_str("video/mp4")?,
-            &DesktopHandler::assume_valid("mpv.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("video/asdf")?,
-            &DesktopHandler::assume_valid("mpv.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("video/webm")?,
-            &DesktopHandler::assume_valid("brave.desktop".into()),
-        )?;
+        // Lists of handlers are never empty
+        let handler = &self.get_handlers_from_path_traced(&path, trace)?[0];
+        let (program, args) = handler
+            .get_entry_for(self)?
+            .get_cmd(self, vec![path.to_string()])?;
 
-        // Add arbitrary text handlers
-        config.add_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("helix.desktop".into()),
-        )?;
-        config.add_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
-        )?;
-        config.add_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("kakoune.desktop".into()),
-        )?;
+        let command = CommandEntry {
+            handler: handler.describe_for(self),
+            program,
+            args,
+        };
 
-        // Add arbitrary document handlers
-        config.add_handler(
-            &Mime::from_str("application/vnd.oasis.opendocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("application/vnd.openxmlformats-officedocument.*")?,
-            &DesktopHandler::assume_valid("startcenter.desktop".into()),
-        )?;
+        write_row(writer, command, format, &self.config.table)?;
 
-        // Add arbirtary terminal emulator as an added association
-        config
-            .mime_apps
-            .added_associations
-            .entry(Mime::from_str("x-scheme-handler/terminal")?)
-            .or_default()
-            .push_back(DesktopHandler::assume_valid(
-                "org.wezfurlong.wezterm.desktop".into(),
-            ));
+        Ok(())
+    }
 
-        // Set terminal output
-        config.terminal_output = terminal_output;
+    /// Print what handlr knows about a handler's desktop entry,
+    /// including the mimes in mimeapps.list that currently open with it
+    pub fn print_info<W: Write>(
+        &self,
+        writer: &mut W,
+        handler: &DesktopHandler,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let path = handler.locate()?;
+        let entry = DesktopEntry::try_from(path.clone())?;
+
+        let info = HandlerInfo {
+            handler: handler.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            name: entry.name.clone(),
+            exec: entry.exec.clone(),
+            field_codes: entry
+                .field_codes()
+                .into_iter()
+                .map(|(code, meaning)| format!("{code}: {meaning}"))
+                .collect(),
+            terminal: entry.terminal,
+            terminal_emulator: entry.is_terminal_emulator(),
+            mime_type: entry.mime_type.iter().map(Mime::to_string).collect(),
+            actions: entry
+                .actions
+                .iter()
+                .map(|action| format!("{}: {}", action.id, action.name))
+                .collect(),
+            default_for: self
+                .mime_apps
+                .associations_of(handler)
+                .into_iter()
+                .map(|(mime, _, _)| mime)
+                .filter(|mime| {
+                    self.resolve_handler(mime).as_ref() == Some(handler)
+                })
+                .map(|mime| mime.to_string())
+                .unique()
+                .collect(),
+        };
 
-        config.print(buffer, detailed, output_json)?;
+        // The table has a row per field rather than one for the whole entry
+        if format.is_json() {
+            utils::write_json_line(writer, &info)?
+        } else {
+            writeln!(
+                writer,
+                "{}",
+                render_table(&info.rows(format), format, &self.config.table)
+            )?
+        }
 
         Ok(())
     }
 
-    #[test]
-    fn print_handlers_default() -> Result<()> {
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, false, false, true)?;
-        goldie::assert!(String::from_utf8(buffer)?);
-        Ok(())
+    /// Get the handler a given mime would be opened with, without running the selector
+    fn resolve_handler(&self, mime: &Mime) -> Option<DesktopHandler> {
+        match self
+            .mime_apps
+            .get_default_handlers(mime, &self.config)
+            .and_then(|handlers| handlers.front())
+        {
+            Some(handler) => Some(handler.clone()),
+            None => self
+                .get_handlers_from_added_associations(mime)
+                .ok()?
+                .into_iter()
+                .next(),
+        }
     }
 
-    #[test]
-    fn print_handlers_piped() -> Result<()> {
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, false, false, false)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+    /// Entirely remove a given mime's default application association
+    pub fn unset_handler(&mut self, mime: &Mime) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        if mime_apps.unset_handler(mime).is_some() {
+            mime_apps.save(backup)?
+        }
+
         Ok(())
     }
 
-    #[test]
-    fn print_handlers_detailed() -> Result<()> {
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, true, false, true)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+    /// Remove a given handler from a given mime's default file associaion
+    pub fn remove_handler(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+    ) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        if mime_apps.remove_handler(mime, handler).is_some() {
+            mime_apps.save(backup)?
+        }
+
         Ok(())
     }
 
-    #[test]
-    fn print_handlers_detailed_piped() -> Result<()> {
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, true, false, false)?;
+    /// Write the default handlers in the mimeapps.list being written to, along with the regex handlers,
+    /// in the form read by `import`
+    pub fn export<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        let handlers = self.config.handlers.clone();
+        let default_apps = self
+            .target_mime_apps()?
+            .default_apps
+            .iter()
+            .map(|(mime, handlers)| {
+                (
+                    mime.to_string(),
+                    handlers.iter().map(|h| h.to_string()).collect(),
+                )
+            })
+            .collect();
+
+        let associations = Associations {
+            default_apps,
+            handlers,
+        };
+        write!(writer, "{}", associations.to_toml()?)?;
+
+        Ok(())
+    }
+
+    /// Set the default handlers of mimes from associations in the form written by `export`,
+    /// saving mimeapps.list once at the end
+    /// Mimes that are not in them keep their handlers unless `replace` is true, in which case they are removed
+    /// Nothing is changed unless every mime and handler is valid, and nothing is written if nothing would change
+    pub fn import<W: Write>(
+        &mut self,
+        writer: &mut W,
+        associations: &Associations,
+        replace: bool,
+    ) -> Result<()> {
+        let default_apps = associations.resolve(&self.config.regex_limits())?;
+
+        if !associations.handlers.is_empty()
+            && associations.handlers != self.config.handlers
+        {
+            writeln!(
+                writer,
+                "note: regex handlers are not imported, since handlr never writes to handlr.toml; copy `handlers` into ~/.config/handlr/handlr.toml to use them"
+            )?;
+        }
+
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        let before = mime_apps.default_apps.clone();
+
+        if replace {
+            mime_apps
+                .default_apps
+                .retain(|mime, _| default_apps.contains_key(mime));
+        }
+        mime_apps.default_apps.extend(default_apps);
+
+        if mime_apps.default_apps != before {
+            mime_apps.save(backup)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write how the default handlers of mimes differ from associations in the form written by `export`,
+    /// one mime per line or as JSON, without changing anything
+    /// Handlers' order matters, and mimes that are not in the associations are only compared if `replace` is true
+    /// Errors if anything differs, after writing the differences
+    pub fn check_status<W: Write>(
+        &mut self,
+        writer: &mut W,
+        associations: &Associations,
+        replace: bool,
+        output_json: bool,
+    ) -> Result<()> {
+        let default_apps = associations.resolve(&self.config.regex_limits())?;
+        let drift = self.target_mime_apps()?.drift(&default_apps, replace);
+
+        if output_json {
+            writeln!(writer, "{}", serde_json::to_string(&drift)?)?;
+        } else {
+            drift.iter().try_for_each(|d| writeln!(writer, "{d}"))?;
+        }
+
+        if drift.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Drift(drift.len()))
+        }
+    }
+
+    /// Move a given handler within a given mime's default application association
+    /// and write the handlers in their new order, one per line
+    pub fn reorder_handler<W: Write>(
+        &mut self,
+        writer: &mut W,
+        mime: &Mime,
+        handler: &DesktopHandler,
+        to: Reorder,
+    ) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        let handlers = mime_apps.reorder_handler(mime, handler, to)?.clone();
+        mime_apps.save(backup)?;
+
+        handlers
+            .iter()
+            .try_for_each(|handler| writeln!(writer, "{handler}"))?;
+
+        Ok(())
+    }
+
+    /// Hide a given handler from a given mime's added and system associations
+    /// by writing it to mimeapps.list's removed associations
+    pub fn remove_association(
+        &mut self,
+        mime: &Mime,
+        handler: &DesktopHandler,
+    ) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        if mime_apps.remove_association(mime, handler).is_some() {
+            mime_apps.save(backup)?
+        }
+
+        Ok(())
+    }
+
+    /// Remove every handler whose desktop file cannot be found from a given mime's default file association
+    /// and report what was removed and what remains
+    pub fn prune_mime<W: Write>(
+        &mut self,
+        writer: &mut W,
+        mime: &Mime,
+        dry_run: bool,
+        output_json: bool,
+    ) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let mime_apps = self.target_mime_apps()?;
+        let report = if dry_run {
+            mime_apps.clone().prune_mime(mime)
+        } else {
+            mime_apps.prune_mime(mime)
+        };
+
+        if output_json {
+            writeln!(writer, "{}", serde_json::to_string(&report)?)?;
+        } else if report.removed.is_empty() {
+            writeln!(writer, "No missing handlers for {}", report.mime)?;
+        } else {
+            let verb = if dry_run { "Would remove" } else { "Removed" };
+            writeln!(
+                writer,
+                "{verb} from {}: {}",
+                report.mime,
+                report.removed.join(", ")
+            )?;
+            if report.remaining.is_empty() {
+                writeln!(writer, "No handlers remain for {}", report.mime)?;
+            } else {
+                writeln!(writer, "Remaining: {}", report.remaining.join(", "))?;
+            }
+        }
+
+        if !dry_run && !report.removed.is_empty() {
+            mime_apps.save(backup)?
+        }
+
+        Ok(())
+    }
+
+    /// Remove every handler whose desktop file cannot be found from every association
+    /// and print a summary of what was removed
+    pub fn clean<W: Write>(
+        &mut self,
+        writer: &mut W,
+        dry_run: bool,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let backup = self.config.backup_mimeapps;
+        let table = self.config.table.clone();
+        let mime_apps = self.target_mime_apps()?;
+        let reports = if dry_run {
+            mime_apps.clone().clean()
+        } else {
+            mime_apps.clean()
+        };
+
+        if format.is_json() {
+            writeln!(writer, "{}", serde_json::to_string(&reports)?)?;
+        } else if reports.is_empty() {
+            writeln!(writer, "No missing handlers found")?;
+        } else {
+            let pretty = format == OutputFormat::Table;
+            let rows = reports
+                .iter()
+                .map(|report| CleanEntry::new(report, pretty))
+                .collect_vec();
+            writeln!(
+                writer,
+                "{}:",
+                if dry_run { "Would remove" } else { "Removed" }
+            )?;
+            writeln!(writer, "{}", render_table(&rows, format, &table))?;
+        }
+
+        if !dry_run && !reports.is_empty() {
+            mime_apps.save(backup)?
+        }
+
+        Ok(())
+    }
+
+    /// Set the default handler for a URL scheme
+    /// If given a command rather than a desktop file, a desktop file is generated for it
+    #[mutants::skip] // Cannot test directly, alters system state
+    pub fn register_scheme(
+        &mut self,
+        scheme: &str,
+        target: &str,
+        update_database: bool,
+    ) -> Result<()> {
+        self.register_scheme_in(&applications_dir()?, scheme, target)?;
+
+        if update_database {
+            update_desktop_database(&applications_dir()?)?
+        }
+
+        Ok(())
+    }
+
+    /// Set the default handler for a URL scheme, generating desktop files in `applications_dir`
+    /// Makes testing easier
+    fn register_scheme_in(
+        &mut self,
+        applications_dir: &std::path::Path,
+        scheme: &str,
+        target: &str,
+    ) -> Result<DesktopHandler> {
+        let mime = scheme_mime(scheme)?;
+
+        let handler = if target.ends_with(".desktop") {
+            let handler = DesktopHandler::from_str(target)?;
+            if !handler.exists() {
+                return Err(Error::NotFound(target.to_owned()));
+            }
+            // A desktop file generated for an earlier registration is no longer needed
+            remove_scheme_desktop_file(applications_dir, scheme)?;
+            handler
+        } else {
+            write_scheme_desktop_file(applications_dir, scheme, target)?
+        };
+
+        self.set_handler(&[mime], &handler)?;
+
+        Ok(handler)
+    }
+
+    /// Undo `register_scheme`, removing the scheme's default handler and any desktop file generated for it
+    #[mutants::skip] // Cannot test directly, alters system state
+    pub fn unregister_scheme(
+        &mut self,
+        scheme: &str,
+        update_database: bool,
+    ) -> Result<()> {
+        let removed_file =
+            self.unregister_scheme_in(&applications_dir()?, scheme)?;
+
+        if update_database && removed_file {
+            update_desktop_database(&applications_dir()?)?
+        }
+
+        Ok(())
+    }
+
+    /// Undo `register_scheme_in`
+    /// Returns whether a generated desktop file was removed
+    fn unregister_scheme_in(
+        &mut self,
+        applications_dir: &std::path::Path,
+        scheme: &str,
+    ) -> Result<bool> {
+        self.unset_handler(&scheme_mime(scheme)?)?;
+        remove_scheme_desktop_file(applications_dir, scheme)
+    }
+
+    /// Write changes to the desktop-specific mimeapps.list
+    /// for the first desktop in `$XDG_CURRENT_DESKTOP` rather than the generic one
+    pub fn write_to_desktop(&mut self, desktop: bool) {
+        self.write_desktop = desktop;
+    }
+
+    /// Get the mimeapps.list that changes should be written to
+    fn target_mime_apps(&mut self) -> Result<&mut MimeApps> {
+        if self.write_desktop {
+            self.mime_apps.desktop_mut()
+        } else {
+            Ok(&mut self.mime_apps)
+        }
+    }
+
+    /// Warn about mimes with more than one entry in the same section of a mimeapps.list file,
+    /// whose handlers were merged when it was read
+    pub fn print_repeated_keys<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for (source, section, mime) in self.mime_apps.repeated_keys() {
+            writeln!(
+                writer,
+                "warning: '{mime}' appears more than once in [{section}] of {source}, so its handlers were merged"
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Print potential problems with the mimeapps.list that changes are written to
+    /// Does nothing if linting is disabled in the config
+    pub fn print_lints<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if !self.config.lint {
+            return Ok(());
+        }
+
+        let mime_apps = if self.write_desktop {
+            self.mime_apps.desktop()?
+        } else {
+            &self.mime_apps
+        };
+
+        let system_default =
+            |mime: &Mime| match self.mime_apps.get_system_default_handler(mime)
+            {
+                Some(handler) => Some(handler.clone()),
+                None => self
+                    .system_apps()
+                    .ok()?
+                    .get_handlers(mime)?
+                    .front()
+                    .cloned(),
+            };
+
+        for lint in lint_mime_apps(mime_apps, system_default) {
+            let level = if lint.is_informational() {
+                "note"
+            } else {
+                "warning"
+            };
+            writeln!(writer, "{level}: {lint}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether to guess the mimes of files from their contents when their names are not enough
+    pub fn sniff_content(&self) -> bool {
+        self.config.sniff_content
+    }
+
+    /// Get the mimes to give files whose names match glob patterns
+    pub fn mime_overrides(&self) -> &MimeOverrides {
+        &self.config.mime_overrides
+    }
+
+    /// Check whether to send files to running instances of single window handlers
+    pub fn respects_single_window(&self) -> bool {
+        self.config.respect_single_window
+    }
+
+    /// Check whether to look for running processes of single window handlers
+    pub fn scans_processes(&self) -> bool {
+        self.config.scan_processes
+    }
+
+    /// Get where to start handlers whose desktop entries do not set `Path`
+    pub fn working_dir(&self) -> WorkingDir {
+        self.config.cwd
+    }
+
+    /// Get the maximum estimated size of the arguments and environment given to a handler at once
+    pub fn max_args_size(&self) -> usize {
+        self.config.max_args_size
+    }
+
+    /// Get the options to launch a given handler with
+    pub fn launch_options(&self, handler: &str) -> LaunchOptions {
+        self.config.launch_options(handler)
+    }
+
+    /// Tag launched handlers with the given tag, which must only contain
+    /// letters, digits, '.', '_', and '-' so that it is safe to use in commands
+    pub fn set_tag(&mut self, tag: Option<String>) -> Result<()> {
+        if let Some(tag) = &tag {
+            if tag.is_empty()
+                || !tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+            {
+                return Err(Error::BadTag(tag.clone()));
+            }
+        }
+
+        self.tag = tag;
+        Ok(())
+    }
+
+    /// Run the given action from handlers' desktop files instead of their main command
+    pub fn set_action(&mut self, action: Option<String>) {
+        self.action = action;
+    }
+
+    /// List and fall back to installed apps even if they have `NoDisplay` or `Hidden` set,
+    /// or `OnlyShowIn` and `NotShowIn` leave out the current desktop
+    pub fn set_include_hidden(&mut self, include_hidden: bool) {
+        self.include_hidden = include_hidden;
+    }
+
+    /// Get the action to run instead of handlers' main command, if any
+    pub fn action(&self) -> Option<&str> {
+        self.action.as_deref()
+    }
+
+    /// Open every path with the given handler instead of the ones set for them
+    /// The handler must be an installed application
+    pub fn override_handler(
+        &mut self,
+        handler: Option<DesktopHandler>,
+    ) -> Result<()> {
+        if let Some(handler) = &handler {
+            handler.get_entry()?;
+        }
+
+        self.handler_override = handler;
+        Ok(())
+    }
+
+    /// Open paths that no handler can be found for with the given command instead of failing
+    pub fn fallback_command(&mut self, command: Option<String>) {
+        self.no_handler_fallback = command.map(FallbackCommand::new);
+    }
+
+    /// Get how tables are drawn
+    pub fn table_config(&self) -> &TableConfig {
+        &self.config.table
+    }
+
+    /// Draw tables without borders if `plain` is true, regardless of the config file
+    pub fn plain_tables(&mut self, plain: bool) {
+        if plain {
+            self.config.table.style = TableStyle::Blank;
+        }
+    }
+
+    /// Set whether to open the URLs of Link desktop entries used as handlers
+    pub fn follow_links(&mut self, follow: bool) {
+        self.follow_links = follow;
+    }
+
+    /// Set whether to open the same paths as many times as they are given
+    pub fn keep_duplicates(&mut self, keep: bool) {
+        self.keep_duplicates = keep;
+    }
+
+    /// Set whether to wait for handlers to exit and notify when they do
+    pub fn notify_done(&mut self, notify: bool) {
+        self.notify_done = notify;
+    }
+
+    /// Check whether to wait for handlers to exit and notify when they do
+    pub fn notifies_done(&self) -> bool {
+        self.notify_done
+    }
+
+    /// Set whether to wait for handlers to exit instead of detaching from them
+    pub fn wait(&mut self, wait: bool) {
+        self.wait = wait;
+    }
+
+    /// Check whether to wait for handlers to exit instead of detaching from them
+    pub fn waits(&self) -> bool {
+        self.wait
+    }
+
+    /// Set whether to print what would be run instead of running anything
+    pub fn dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Check whether to open the URLs of Link desktop entries used as handlers
+    pub fn follows_links(&self) -> bool {
+        self.follow_links
+    }
+
+    /// Get the tag launched handlers are given, if any
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Get the arguments that make a terminal emulator's window identifiable by the tag, if any
+    fn tag_args(&self, terminal: &str) -> Option<String> {
+        let tag = self.tag()?;
+        Some(self.config.tag_args.get(terminal)?.replace("{tag}", tag))
+    }
+
+    /// Override the set selector
+    /// Currently assumes the config file will never be saved to other than to create an existing one
+    pub fn override_selector(&mut self, selector_args: SelectorArgs) {
+        self.config.override_selector(selector_args);
+    }
+}
+
+/// Write a handler followed by the arguments it would be run with, separated by tabs
+fn write_plan<W: Write>(
+    writer: &mut W,
+    handler: &str,
+    args: &[String],
+) -> Result<()> {
+    writeln!(
+        writer,
+        "{}",
+        std::iter::once(handler.to_owned())
+            .chain(args.iter().cloned())
+            .join("\t")
+    )?;
+    Ok(())
+}
+
+/// Run `run` with each handler in turn until it succeeds for one of them
+/// If every handler fails, the returned error lists why each of them failed
+fn try_handlers<H: Display>(
+    handlers: &[H],
+    mut run: impl FnMut(&H) -> Result<()>,
+) -> Result<()> {
+    let mut failures = Vec::new();
+
+    for handler in handlers {
+        match run(handler) {
+            Ok(()) => return Ok(()),
+            // Cancelling is deliberate, so do not try anything else
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(e) => failures.push((handler.to_string(), e)),
+        }
+    }
+
+    // Keep the original error when there was nothing to fall back to
+    if failures.len() == 1 {
+        Err(failures.remove(0).1)
+    } else {
+        Err(Error::AllHandlersFailed(failures))
+    }
+}
+
+/// Internal helper struct for turning MimeApps into tabular data
+#[derive(PartialEq, Eq, Serialize)]
+struct MimeAppsEntry {
+    mime: String,
+    handlers: Vec<String>,
+    /// Names of the handlers, in the same order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    names: Vec<String>,
+    wildcard: bool,
+    /// Handlers whose desktop files cannot be found
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing: Vec<String>,
+    #[serde(skip_serializing)]
+    // This field should not appear in any output
+    // It is only used for determining how to render output
+    pretty: bool,
+}
+
+impl MimeAppsEntry {
+    /// Create a new `MimeAppsEntry`
+    /// Handlers are only checked for whether they exist if `check_missing` is true,
+    /// and their names are only looked up if `names` is given
+    fn new(
+        mime: &Mime,
+        handlers: &VecDeque<DesktopHandler>,
+        names: Option<&mut HandlerNames>,
+        pretty: bool,
+        check_missing: bool,
+    ) -> Self {
+        Self {
+            mime: mime.to_string(),
+            handlers: handlers
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>(),
+            names: names.map_or_else(Vec::new, |names| {
+                handlers.iter().map(|h| names.get(h)).collect()
+            }),
+            wildcard: mime.as_ref().contains('*'),
+            missing: handlers
+                .iter()
+                .filter(|h| check_missing && !h.exists())
+                .map(|h| h.to_string())
+                .collect(),
+            pretty,
+        }
+    }
+
+    /// Display mime as a string, marking wildcards in pretty output
+    fn display_mime(&self) -> String {
+        if self.wildcard && self.pretty {
+            format!("{} (wildcard)", self.mime)
+        } else {
+            self.mime.clone()
+        }
+    }
+
+    /// Display list of handlers as a string
+    fn display_handlers(&self) -> String {
+        self.handlers
+            .iter()
+            .map(|handler| {
+                if self.pretty && self.missing.contains(handler) {
+                    format!("{handler} (missing)")
+                } else {
+                    handler.clone()
+                }
+            })
+            .join(self.separator())
+    }
+
+    /// Display list of handler names as a string, like the handlers themselves
+    fn display_names(&self) -> String {
+        self.names.join(self.separator())
+    }
+
+    /// Separator between handlers or names in the same cell
+    /// If output is a pretty table, optimize for readability
+    /// Otherwise, separate them like in mimeapps.list for parseability
+    fn separator(&self) -> &'static str {
+        if self.pretty {
+            ",\n"
+        } else {
+            ";"
+        }
+    }
+
+    /// Key used to sort entries
+    /// Wildcards are sorted after all exact mimes with the same top-level type
+    fn sort_key(&self) -> (&str, bool, &str) {
+        let top_level = self.mime.split('/').next().unwrap_or_default();
+        (top_level, self.wildcard, &self.mime)
+    }
+}
+
+impl Row for MimeAppsEntry {
+    const HEADERS: &'static [&'static str] = &["mime", "handlers", "names"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.display_mime(),
+            self.display_handlers(),
+            self.display_names(),
+        ]
+    }
+}
+
+/// Names of handlers from their desktop entries, so that each is only read once
+/// no matter how many mimes a handler is associated with
+#[derive(Default)]
+struct HandlerNames(HashMap<String, String>);
+
+impl HandlerNames {
+    /// Get the name of a handler, falling back to its desktop file id if its entry cannot be read
+    fn get(&mut self, handler: &DesktopHandler) -> String {
+        self.0
+            .entry(handler.to_string())
+            .or_insert_with(|| {
+                handler
+                    .get_entry()
+                    .ok()
+                    .map(|entry| entry.name)
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| handler.to_string())
+            })
+            .clone()
+    }
+}
+
+/// Internal helper struct for turning the handlers that could be used for a mime into tabular data
+#[derive(Serialize)]
+struct CandidateEntry {
+    handler: String,
+    name: String,
+    /// The mime the handler is associated with, which may be a parent of the one asked about
+    mime: String,
+    source: HandlerSource,
+}
+
+impl Row for CandidateEntry {
+    const HEADERS: &'static [&'static str] =
+        &["handler", "name", "mime", "source"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.handler.clone(),
+            self.name.clone(),
+            self.mime.clone(),
+            self.source.to_string(),
+        ]
+    }
+}
+
+/// Internal helper struct for turning the mimes associated with a handler into tabular data
+#[derive(Serialize)]
+struct HandlerMimeEntry {
+    mime: String,
+    source: HandlerSource,
+    /// The mimeapps.list file the association is in, or the handler's desktop file for system associations
+    file: String,
+    /// Whether the mime currently resolves to the handler
+    current: bool,
+}
+
+impl Row for HandlerMimeEntry {
+    const HEADERS: &'static [&'static str] =
+        &["mime", "source", "file", "current"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.mime.clone(),
+            self.source.to_string(),
+            self.file.clone(),
+            self.current.to_string(),
+        ]
+    }
+}
+
+/// Internal helper struct for turning installed apps that are not associated with any mime
+/// into tabular data
+#[derive(Serialize)]
+struct OrphanEntry {
+    handler: String,
+    name: String,
+    /// Mimes the app supports that are opened with other handlers
+    handled_by_others: Vec<String>,
+    /// Words describing the app, for searching
+    keywords: Vec<String>,
+    #[serde(skip_serializing)]
+    // This field should not appear in any output
+    // It is only used for determining how to render output
+    pretty: bool,
+}
+
+impl OrphanEntry {
+    /// Display list of mimes as a string
+    fn display_mimes(&self) -> String {
+        // If output is a pretty table, optimize for readability
+        // Otherwise, optimize for parseability
+        let separator = if self.pretty { ",\n" } else { ", " };
+        self.handled_by_others.join(separator)
+    }
+}
+
+impl Row for OrphanEntry {
+    const HEADERS: &'static [&'static str] =
+        &["handler", "name", "handled_by_others"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.handler.clone(),
+            self.name.clone(),
+            self.display_mimes(),
+        ]
+    }
+}
+
+/// Internal helper struct for turning installed apps into tabular data
+#[derive(Serialize)]
+struct AppEntry {
+    handler: String,
+    name: String,
+    generic_name: Option<String>,
+    icon: Option<String>,
+    terminal: bool,
+    /// Mimes the app supports
+    mime_type: Vec<String>,
+    #[serde(skip_serializing)]
+    // This field should not appear in any output
+    // It is only used for determining how to render output
+    pretty: bool,
+}
+
+impl Row for AppEntry {
+    const HEADERS: &'static [&'static str] = &[
+        "handler",
+        "name",
+        "generic_name",
+        "icon",
+        "terminal",
+        "mime_type",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        // If output is a pretty table, optimize for readability
+        // Otherwise, optimize for parseability
+        let separator = if self.pretty { ",\n" } else { ", " };
+        vec![
+            self.handler.clone(),
+            self.name.clone(),
+            self.generic_name.clone().unwrap_or_default(),
+            self.icon.clone().unwrap_or_default(),
+            self.terminal.to_string(),
+            self.mime_type.join(separator),
+        ]
+    }
+}
+
+/// Internal helper struct for turning the command a path would be opened with into tabular data
+#[derive(Serialize)]
+struct CommandEntry {
+    handler: String,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Row for CommandEntry {
+    const HEADERS: &'static [&'static str] = &["handler", "command"];
+
+    fn fields(&self) -> Vec<String> {
+        let words = std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(String::as_str)
+            .collect_vec();
+        // Only nul bytes cannot be quoted, and those cannot be in arguments anyway
+        let command = shlex::try_join(words.iter().copied())
+            .unwrap_or_else(|_| words.join(" "));
+        vec![self.handler.clone(), command]
+    }
+}
+
+/// Internal helper struct for turning what is known about a handler into tabular data
+#[derive(Serialize)]
+struct HandlerInfo {
+    handler: String,
+    /// Path of the handler's desktop file
+    path: String,
+    name: String,
+    /// The `Exec` key as written in the desktop file
+    exec: String,
+    /// What handlr does with each field code in `exec`
+    field_codes: Vec<String>,
+    terminal: bool,
+    /// Whether handlr considers the handler a terminal emulator
+    terminal_emulator: bool,
+    /// Mimes the desktop file says the handler supports
+    mime_type: Vec<String>,
+    /// Actions from the desktop file that can be run with `--action`, as `id: name`
+    actions: Vec<String>,
+    /// Mimes in mimeapps.list that currently open with the handler
+    default_for: Vec<String>,
+}
+
+impl HandlerInfo {
+    /// Turn into one row per field, since there is only one handler
+    fn rows(&self, format: OutputFormat) -> Vec<InfoRow> {
+        // If output is a pretty table, optimize for readability
+        // Otherwise, optimize for parseability
+        let separator = if format == OutputFormat::Table {
+            "\n"
+        } else {
+            ", "
+        };
+
+        [
+            ("handler", self.handler.clone()),
+            ("path", self.path.clone()),
+            ("name", self.name.clone()),
+            ("exec", self.exec.clone()),
+            ("field_codes", self.field_codes.join(separator)),
+            ("terminal", self.terminal.to_string()),
+            ("terminal_emulator", self.terminal_emulator.to_string()),
+            ("mime_type", self.mime_type.join(separator)),
+            ("actions", self.actions.join(separator)),
+            ("default_for", self.default_for.join(separator)),
+        ]
+        .into_iter()
+        .map(|(field, value)| InfoRow { field, value })
+        .collect()
+    }
+}
+
+/// Internal helper struct for a single field of `HandlerInfo` in tabular data
+struct InfoRow {
+    field: &'static str,
+    value: String,
+}
+
+impl Row for InfoRow {
+    const HEADERS: &'static [&'static str] = &["field", "value"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.field.to_owned(), self.value.clone()]
+    }
+}
+
+/// Internal helper struct for turning handlers removed by `handlr clean` into tabular data
+struct CleanEntry {
+    section: &'static str,
+    mime: String,
+    removed: String,
+    remaining: String,
+}
+
+impl CleanEntry {
+    /// Create a new `CleanEntry`
+    fn new(report: &CleanReport, pretty: bool) -> Self {
+        // If output is a pretty table, optimize for readability
+        // Otherwise, optimize for parseability
+        let separator = if pretty { ",\n" } else { ", " };
+        Self {
+            section: report.section,
+            mime: report.report.mime.clone(),
+            removed: report.report.removed.join(separator),
+            remaining: report.report.remaining.join(separator),
+        }
+    }
+}
+
+impl Row for CleanEntry {
+    const HEADERS: &'static [&'static str] =
+        &["section", "mime", "removed", "remaining"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.section.to_owned(),
+            self.mime.clone(),
+            self.removed.clone(),
+            self.remaining.clone(),
+        ]
+    }
+}
+
+/// Internal helper struct for turning MimeApps into tabular data
+#[derive(Serialize)]
+struct MimeAppsTable {
+    added_associations: Vec<MimeAppsEntry>,
+    default_apps: Vec<MimeAppsEntry>,
+    system_apps: Vec<MimeAppsEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<MimeAppsLayer>,
+}
+
+impl MimeAppsTable {
+    /// Create a new `MimeAppsTable`
+    /// Only mimes that match `pattern` are included, if there is one
+    /// Handler names are included if `names` is true
+    fn new(
+        mimeapps: &MimeApps,
+        system_apps: &SystemApps,
+        pattern: Option<&str>,
+        names: bool,
+        pretty: bool,
+    ) -> Self {
+        let mut cache = HandlerNames::default();
+        let mut names = names.then_some(&mut cache);
+
+        Self {
+            added_associations: to_entries(
+                &mimeapps.added_associations,
+                pattern,
+                names.as_deref_mut(),
+                pretty,
+                true,
+            ),
+            default_apps: to_entries(
+                &mimeapps.default_apps,
+                pattern,
+                names.as_deref_mut(),
+                pretty,
+                true,
+            ),
+            system_apps: to_entries(
+                &system_apps.associations,
+                pattern,
+                names.as_deref_mut(),
+                pretty,
+                false,
+            ),
+            layers: mimeapps
+                .other_layers()
+                .map(|layer| {
+                    MimeAppsLayer::new(
+                        layer,
+                        pattern,
+                        names.as_deref_mut(),
+                        pretty,
+                    )
+                })
+                // Leave out files with nothing left in them after filtering
+                .filter(|layer| {
+                    !(layer.default_apps.is_empty()
+                        && layer.added_associations.is_empty())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl MimeAppsTable {
+    /// Write every entry as its own line of JSON
+    /// When detailed, each entry also includes the section it belongs to
+    /// and the file it came from if it is not the user's mimeapps.list
+    fn write_json_lines<W: Write>(
+        &self,
+        writer: &mut W,
+        detailed: bool,
+    ) -> Result<()> {
+        if !detailed {
+            return self
+                .default_apps
+                .iter()
+                .try_for_each(|entry| utils::write_json_line(writer, entry));
+        }
+
+        let sections = [
+            ("default_apps", None, &self.default_apps),
+            ("added_associations", None, &self.added_associations),
+        ]
+        .into_iter()
+        .chain(self.layers.iter().flat_map(|layer| {
+            [
+                (
+                    "default_apps",
+                    Some(layer.source.as_str()),
+                    &layer.default_apps,
+                ),
+                (
+                    "added_associations",
+                    Some(layer.source.as_str()),
+                    &layer.added_associations,
+                ),
+            ]
+        }))
+        .chain(std::iter::once((
+            "system_apps",
+            None,
+            &self.system_apps,
+        )));
+
+        for (section, source, entries) in sections {
+            for entry in entries {
+                utils::write_json_line(
+                    writer,
+                    &MimeAppsLine {
+                        section,
+                        source,
+                        entry,
+                    },
+                )?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Internal helper struct for writing a `MimeAppsEntry` as a line of JSON
+/// along with where it came from
+#[derive(Serialize)]
+struct MimeAppsLine<'a> {
+    section: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(flatten)]
+    entry: &'a MimeAppsEntry,
+}
+
+/// Internal helper struct for turning associations from mimeapps.list files
+/// other than the user's into tabular data
+#[derive(Serialize)]
+struct MimeAppsLayer {
+    source: String,
+    added_associations: Vec<MimeAppsEntry>,
+    default_apps: Vec<MimeAppsEntry>,
+}
+
+impl MimeAppsLayer {
+    /// Create a new `MimeAppsLayer`
+    fn new(
+        mimeapps: &MimeApps,
+        pattern: Option<&str>,
+        mut names: Option<&mut HandlerNames>,
+        pretty: bool,
+    ) -> Self {
+        Self {
+            source: mimeapps.source(),
+            added_associations: to_entries(
+                &mimeapps.added_associations,
+                pattern,
+                names.as_deref_mut(),
+                pretty,
+                true,
+            ),
+            default_apps: to_entries(
+                &mimeapps.default_apps,
+                pattern,
+                names,
+                pretty,
+                true,
+            ),
+        }
+    }
+}
+
+/// Turn a map of associations into sorted table rows, keeping only mimes that match `pattern` if there is one
+/// System apps always exist, so checking whether their handlers do can be skipped
+fn to_entries<'a>(
+    map: impl IntoIterator<Item = (&'a Mime, &'a DesktopList)>,
+    pattern: Option<&str>,
+    mut names: Option<&mut HandlerNames>,
+    pretty: bool,
+    check_missing: bool,
+) -> Vec<MimeAppsEntry> {
+    let mut rows = map
+        .into_iter()
+        .filter(|(mime, _)| pattern.is_none_or(|p| matches_pattern(p, mime)))
+        .map(|(mime, handlers)| {
+            MimeAppsEntry::new(
+                mime,
+                handlers,
+                names.as_deref_mut(),
+                pretty,
+                check_missing,
+            )
+        })
+        .collect::<Vec<_>>();
+    rows.sort_unstable_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    rows
+}
+
+/// Check if a mime matches a pattern given to `handlr list`
+/// Patterns with `*` or `?` are globs that must match the whole mime, e.g. `video/*`,
+/// and anything else only needs to be part of it, e.g. `text`
+/// Either way, case is ignored
+fn matches_pattern(pattern: &str, mime: &Mime) -> bool {
+    let pattern = pattern.to_lowercase();
+    let mime = mime.as_ref().to_lowercase();
+
+    if pattern.contains(['*', '?']) {
+        wildmatch::WildMatch::new(&pattern).matches(&mime)
+    } else {
+        mime.contains(&pattern)
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Helper function for testing
+    fn system_apps_mut(&mut self) -> &mut SystemApps {
+        self.system_apps.get_or_init(SystemApps::default);
+        self.system_apps
+            .get_mut()
+            .expect("System apps should have just been initialized")
+    }
+
+    /// Helper function for testing
+    fn mime_info_mut(&mut self) -> &mut MimeInfo {
+        self.mime_info.get_or_init(MimeInfo::default);
+        self.mime_info
+            .get_mut()
+            .expect("Mime hierarchy should have just been initialized")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apps::PruneReport, common::RegexLimits, utils::TempDir};
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reading_creates_nothing() -> Result<()> {
+        let config_home = TempDir::new("read-only-config")?;
+        let data_dir = TempDir::new("read-only-data")?;
+
+        let run = |args: &[&str]| -> Result<()> {
+            let cmd = Cmd::try_parse_from(
+                std::iter::once("handlr").chain(args.iter().copied()),
+            )
+            .expect("Test commands should be valid");
+            // Same as `Config::load`, but with the directories given rather than taken from the environment
+            let config = Config::from_parts(
+                ConfigFile::load_from(ConfigFile::path_in(&config_home))?,
+                MimeApps::read_in(
+                    &config_home,
+                    &[data_dir.join("applications")],
+                    &["sway".to_owned()],
+                )?,
+                false,
+                &cmd,
+            );
+            // Nothing is associated with anything, so only reading matters, not what is found
+            let _ = match cmd {
+                Cmd::Get { mime, .. } => config.show_handler(
+                    &mut Vec::new(),
+                    &mime,
+                    OutputFormat::Tsv,
+                    &mut Trace::default(),
+                ),
+                Cmd::List { .. } => config.print(
+                    &mut Vec::new(),
+                    false,
+                    None,
+                    true,
+                    OutputFormat::Tsv,
+                ),
+                _ => Ok(()),
+            };
+            Ok(())
+        };
+        run(&["get", "text/plain"])?;
+        run(&["list"])?;
+        run(&["mime", "tests/empty.txt"])?;
+
+        // Neither mimeapps.list nor handlr.toml is created
+        assert_eq!(std::fs::read_dir(&config_home)?.count(), 0);
+        assert_eq!(std::fs::read_dir(&data_dir)?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn system_apps_populated_lazily() -> Result<()> {
+        let mut config = Config::default();
+        let handler = DesktopHandler::assume_valid("swayimg.desktop".into())?;
+
+        config.set_handler(&[mime::IMAGE_PNG], &handler)?;
+        config.add_handler(&[mime::IMAGE_PNG], &handler)?;
+        assert_eq!(config.get_handler(&mime::IMAGE_PNG)?, handler);
+        config.print(&mut Vec::new(), false, None, true, OutputFormat::Tsv)?;
+        config.unset_handler(&mime::IMAGE_PNG)?;
+
+        // None of the above should need system apps
+        assert!(config.system_apps.get().is_none());
+
+        // Falling back to system apps populates them
+        assert!(config.get_handler(&mime::IMAGE_JPEG).is_err());
+        assert!(config.system_apps.get().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_mimes() -> Result<()> {
+        let mut config = Config::default();
+        config.add_handler(
+            &[Mime::from_str("video/*")?],
+            &DesktopHandler::assume_valid("mpv.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("video/webm")?],
+            &DesktopHandler::assume_valid("brave.desktop".into())?,
+        )?;
+
+        assert_eq!(
+            config
+                .get_handler(&Mime::from_str("video/mp4")?)?
+                .to_string(),
+            "mpv.desktop"
+        );
+        assert_eq!(
+            config
+                .get_handler(&Mime::from_str("video/asdf")?)?
+                .to_string(),
+            "mpv.desktop"
+        );
+        assert_eq!(
+            config
+                .get_handler(&Mime::from_str("video/webm")?)?
+                .to_string(),
+            "brave.desktop"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn complex_wildcard_mimes() -> Result<()> {
+        let mut config = Config::default();
+        config.add_handler(
+            &[Mime::from_str("application/vnd.oasis.opendocument.*")?],
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str(
+                "application/vnd.openxmlformats-officedocument.*",
+            )?],
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
+        )?;
+
+        assert_eq!(
+            config
+                .get_handler(&Mime::from_str(
+                    "application/vnd.oasis.opendocument.text"
+                )?,)?
+                .to_string(),
+            "startcenter.desktop"
+        );
+        assert_eq!(
+            config
+                .get_handler(
+                    &Mime::from_str("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")?,
+                )?
+                .to_string(),
+            "startcenter.desktop"
+        );
+
+        Ok(())
+    }
+
+    // Helper command to test the tables of handlers
+    // Renders a table with a bunch of arbitrary handlers to a writer
+    // TODO: test printing with non-empty system apps too
+    fn print_handlers_test<W: Write>(
+        buffer: &mut W,
+        detailed: bool,
+        pattern: Option<&str>,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut config = Config::default();
+
+        // Add arbitrary video handlers
+        config.add_handler(
+            &[Mime::from_str("video/mp4")?],
+            &DesktopHandler::assume_valid("mpv.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("video/asdf")?],
+            &DesktopHandler::assume_valid("mpv.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("video/webm")?],
+            &DesktopHandler::assume_valid("brave.desktop".into())?,
+        )?;
+
+        // Add arbitrary text handlers
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("kakoune.desktop".into())?,
+        )?;
+
+        // Add arbitrary document handlers
+        config.add_handler(
+            &[Mime::from_str("application/vnd.oasis.opendocument.*")?],
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str(
+                "application/vnd.openxmlformats-officedocument.*",
+            )?],
+            &DesktopHandler::assume_valid("startcenter.desktop".into())?,
+        )?;
+
+        // Add arbirtary terminal emulator as an added association
+        config
+            .mime_apps
+            .added_associations
+            .entry(Mime::from_str("x-scheme-handler/terminal")?)
+            .or_default()
+            .push_back(DesktopHandler::assume_valid(
+                "org.wezfurlong.wezterm.desktop".into(),
+            )?);
+
+        config.print(buffer, detailed, pattern, true, format)?;
+
+        Ok(())
+    }
+
+    // Helper command to test printing associations from other mimeapps.list files
+    fn print_layered_handlers_test<W: Write>(
+        buffer: &mut W,
+        pattern: Option<&str>,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut config = Config::default();
+
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
+        )?;
+
+        let system = MimeApps::builder()
+            .default("text/plain", ["org.gnome.TextEditor.desktop"])
+            .default("text/html", ["firefox.desktop"])
+            .added("image/png", ["org.gnome.Loupe.desktop"])
+            .build()?;
+
+        config
+            .mime_apps
+            .push_system_layer("/usr/share/applications/mimeapps.list", system);
+
+        config.print(buffer, true, pattern, true, format)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn print_layered_handlers() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_layered_handlers_test(&mut buffer, None, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_filtered_layered_handlers() -> Result<()> {
+        // Only the system file has anything left, and the empty user sections are left out
+        let mut buffer = Vec::new();
+        print_layered_handlers_test(
+            &mut buffer,
+            Some("IMAGE/*"),
+            OutputFormat::Tsv,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn pattern_matching() -> Result<()> {
+        let video = Mime::from_str("video/mp4")?;
+        assert!(matches_pattern("video/*", &video));
+        assert!(matches_pattern("vid", &video));
+        assert!(matches_pattern("MP4", &video));
+        assert!(matches_pattern("*/mp?", &video));
+        // Globs have to match the whole mime
+        assert!(!matches_pattern("vid*", &mime::TEXT_PLAIN));
+        assert!(!matches_pattern("mp4*", &video));
+        assert!(!matches_pattern("audio", &video));
+        Ok(())
+    }
+
+    #[test]
+    fn print_layered_handlers_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_layered_handlers_test(&mut buffer, None, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_layered_handlers_jsonl() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_layered_handlers_test(&mut buffer, None, OutputFormat::Jsonl)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tables")]
+    fn print_handlers_default() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, false, None, OutputFormat::Table)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_piped() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, false, None, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tables")]
+    fn print_handlers_detailed() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, true, None, OutputFormat::Table)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_detailed_piped() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, true, None, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_csv() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, true, None, OutputFormat::Csv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, false, None, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_detailed_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(&mut buffer, true, None, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_filtered() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(
+            &mut buffer,
+            false,
+            Some("video/*"),
+            OutputFormat::Tsv,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_handlers_detailed_filtered_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_handlers_test(
+            &mut buffer,
+            true,
+            Some("text"),
+            OutputFormat::Json,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    // Helper command to test how wildcards are sorted and marked in tables of handlers
+    fn print_wildcard_handlers_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut config = Config::default();
+
+        for (mime, handler) in [
+            ("video/webm", "brave.desktop"),
+            ("video/*", "mpv.desktop"),
+            ("video/mp4", "mpv.desktop"),
+            ("text/*", "helix.desktop"),
+            ("text/plain", "nvim.desktop"),
+            (
+                "application/vnd.oasis.opendocument.*",
+                "startcenter.desktop",
+            ),
+            ("application/pdf", "mupdf.desktop"),
+            ("application/x-yaml", "helix.desktop"),
+        ] {
+            config.add_handler(
+                &[Mime::from_str(mime)?],
+                &DesktopHandler::assume_valid(handler.into())?,
+            )?;
+        }
+
+        config.print(buffer, false, None, true, format)?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "tables")]
+    fn print_wildcard_handlers() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_wildcard_handlers_test(&mut buffer, OutputFormat::Table)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_wildcard_handlers_piped() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_wildcard_handlers_test(&mut buffer, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_wildcard_handlers_jsonl() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_wildcard_handlers_test(&mut buffer, OutputFormat::Jsonl)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn print_wildcard_handlers_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_wildcard_handlers_test(&mut buffer, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn subclass_fallback() -> Result<()> {
+        let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let zip = Mime::from_str("application/zip")?;
+
+        config
+            .mime_info_mut()
+            .add_subclasses(&std::fs::read_to_string("tests/subclasses")?);
+        config.add_handler(&[mime::TEXT_PLAIN], &helix)?;
+
+        // Direct and indirect parents are both used
+        for mime in ["text/x-rust", "application/x-shellscript"] {
+            assert_eq!(config.get_handler(&Mime::from_str(mime)?)?, helix);
+        }
+
+        // Closer parents take priority
+        let script = DesktopHandler::from_str("tests/cmus.desktop")?;
+        config.add_handler(
+            &[Mime::from_str("application/x-executable")?],
+            &script,
+        )?;
+        assert_eq!(
+            config
+                .get_handler(&Mime::from_str("application/x-shellscript")?)?,
+            script
+        );
+
+        // Errors still name the original mime
+        assert!(matches!(
+            config.get_handler(&Mime::from_str("application/x-zip-compressed-fb2")?),
+            Err(Error::NotFound(mime)) if mime == "application/x-zip-compressed-fb2"
+        ));
+        assert!(config.get_handler(&zip).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn directory_fallback() -> Result<()> {
+        let mut config = Config::default();
+        let directory = Mime::from_str("inode/directory")?;
+        let thunar = DesktopHandler::from_str("tests/thunar.desktop")?;
+
+        assert!(matches!(
+            config.get_handler(&directory),
+            Err(Error::NoFileManager)
+        ));
+
+        // Installed file managers are found by their category
+        config
+            .system_apps_mut()
+            .add_unassociated(DesktopHandler::from_str("tests/cmus.desktop")?);
+        config.system_apps_mut().add_unassociated(thunar.clone());
+        assert_eq!(config.get_handler(&directory)?, thunar);
+
+        // The configured file manager takes priority
+        config.config.default_file_manager = Some("tests/Helix.desktop".into());
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        assert_eq!(config.get_handler(&directory)?, helix);
+
+        // As does anything set in mimeapps.list
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+        config.add_handler(std::slice::from_ref(&directory), &cmus)?;
+        assert_eq!(config.get_handler(&directory)?, cmus);
+
+        // Other mimes are unaffected
+        assert!(matches!(
+            config.get_handler(&mime::TEXT_PLAIN),
+            Err(Error::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    // Helper function to set up handlers from every source for `show_all_handlers`
+    fn all_handlers_config() -> Result<Config> {
+        let mut config = Config::default();
+        let rust = Mime::from_str("text/x-rust")?;
+        let handler = |name: &str| DesktopHandler::from_str(name);
+
+        config
+            .mime_info_mut()
+            .add_subclasses(&std::fs::read_to_string("tests/subclasses")?);
+        config.add_handler(
+            std::slice::from_ref(&rust),
+            &handler("tests/Helix.desktop")?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("text/*")?],
+            &handler("tests/cmus.desktop")?,
+        )?;
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &handler("tests/Helix.desktop")?,
+        )?;
+        config.mime_apps.added_associations.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("tests/gimp.desktop;")?,
+        );
+        config.system_apps_mut().associations.insert(
+            rust,
+            DesktopList::from_str(
+                "tests/org.wezfurlong.wezterm.desktop;tests/Helix.desktop;",
+            )?,
+        );
+
+        Ok(config)
+    }
+
+    #[test]
+    fn all_handlers() -> Result<()> {
+        let config = all_handlers_config()?;
+
+        // The first handler is the one that would actually be used
+        let rust = Mime::from_str("text/x-rust")?;
+        let all = config.get_all_handlers(&rust)?;
+        assert_eq!(all[0].0, config.get_handler(&rust)?);
+
+        assert!(matches!(
+            config.get_all_handlers(&Mime::from_str("image/png")?),
+            Err(Error::NotFound(_))
+        ));
+
+        let mut buffer = Vec::new();
+        config.show_all_handlers(&mut buffer, &rust, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_handler() -> Result<()> {
+        let mut config = all_handlers_config()?;
+        config.mime_apps.added_associations.insert(
+            Mime::from_str("image/png")?,
+            DesktopList::from_str("tests/gimp.desktop;")?,
+        );
+
+        let mut output = String::new();
+
+        for mime in [
+            "text/x-rust",
+            "text/html",
+            "application/x-shellscript",
+            "image/png",
+            "video/mp4",
+        ] {
+            let mut trace = Trace::enabled();
+            let mut buffer = Vec::new();
+            let result = config.show_handler(
+                &mut buffer,
+                &Mime::from_str(mime)?,
+                OutputFormat::Tsv,
+                &mut trace,
+            );
+
+            output += &format!("$ handlr get {mime} --trace\n");
+            output += &String::from_utf8(buffer)?;
+            trace
+                .steps()
+                .iter()
+                .for_each(|step| output += &format!("trace: {step}\n"));
+            if let Err(e) = result {
+                output += &format!("error: {e}\n");
+            }
+        }
+
+        goldie::assert!(output);
+
+        Ok(())
+    }
+
+    #[test]
+    fn all_handlers_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        all_handlers_config()?.show_all_handlers(
+            &mut buffer,
+            &Mime::from_str("text/x-rust")?,
+            OutputFormat::Json,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    // Helper function to set up associations with cmus from every source
+    fn mimes_of_config() -> Result<Config> {
+        let mut config = Config::default();
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+
+        config.add_handler(&[Mime::from_str("video/mp4")?], &cmus)?;
+        config.add_handler(&[Mime::from_str("video/x-matroska")?], &cmus)?;
+        config.add_handler(
+            &[Mime::from_str("video/x-matroska")?],
+            &DesktopHandler::from_str("tests/Helix.desktop")?,
+        )?;
+        config.add_handler(&[Mime::from_str("video/*")?], &cmus)?;
+        // Opened with something else
+        config.mime_apps.default_apps.insert(
+            Mime::from_str("audio/flac")?,
+            DesktopList::from_str("tests/Helix.desktop;tests/cmus.desktop;")?,
+        );
+        config.mime_apps.added_associations.insert(
+            Mime::from_str("audio/ogg")?,
+            DesktopList::from_str("tests/cmus.desktop;")?,
+        );
+        config.system_apps_mut().associations.insert(
+            Mime::from_str("audio/mpeg")?,
+            DesktopList::from_str("tests/cmus.desktop;")?,
+        );
+        config.system_apps_mut().associations.insert(
+            Mime::from_str("image/png")?,
+            DesktopList::from_str("tests/gimp.desktop;")?,
+        );
+
+        Ok(config)
+    }
+
+    #[test]
+    fn mimes_of() -> Result<()> {
+        let mut buffer = Vec::new();
+        mimes_of_config()?.print_mimes_of(
+            &mut buffer,
+            &DesktopHandler::from_str("tests/cmus.desktop")?,
+            false,
+            OutputFormat::Tsv,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn mimes_of_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        mimes_of_config()?.print_mimes_of(
+            &mut buffer,
+            &DesktopHandler::from_str("tests/cmus.desktop")?,
+            false,
+            OutputFormat::Json,
+        )?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn mimes_of_expanded() -> Result<()> {
+        let config = mimes_of_config()?;
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+
+        let mut buffer = Vec::new();
+        config.print_mimes_of(&mut buffer, &cmus, true, OutputFormat::Json)?;
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&buffer)?;
+
+        // Wildcards are replaced with every mime they match
+        assert!(rows.iter().all(|row| row["mime"] != "video/*"));
+        assert!(rows.iter().any(|row| {
+            row["mime"] == "video/webm"
+                && row["source"] == "wildcard"
+                && row["current"] == true
+        }));
+        // Including ones with their own associations
+        assert!(rows.iter().any(|row| {
+            row["mime"] == "video/x-matroska" && row["source"] == "wildcard"
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn handlers_of() -> Result<()> {
+        let mut config = Config::default();
+        let mp4 = Mime::from_str("video/mp4")?;
+        config.add_handler(
+            std::slice::from_ref(&mp4),
+            &DesktopHandler::from_str("tests/cmus.desktop")?,
+        )?;
+        config.add_handler(
+            std::slice::from_ref(&mp4),
+            &DesktopHandler::from_str("tests/Helix.desktop")?,
+        )?;
+        config.mime_apps.added_associations.insert(
+            mp4.clone(),
+            DesktopList::from_str(
+                "tests/gimp.desktop;tests/Helix.desktop;missing.desktop;",
+            )?,
+        );
+
+        let mut buffer = Vec::new();
+        config.print_handlers_of(&mut buffer, "video/mp4")?;
+        goldie::assert!(String::from_utf8(buffer)?);
+
+        // Extensions work too, and anything unknown prints nothing
+        let mut buffer = Vec::new();
+        config.print_handlers_of(&mut buffer, ".mp4")?;
+        assert!(!buffer.is_empty());
+        for mime in ["video/webm", "not a mime", ""] {
+            let mut buffer = Vec::new();
+            config.print_handlers_of(&mut buffer, mime)?;
+            assert!(buffer.is_empty(), "{mime}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn url_mime_from_extension() -> Result<()> {
+        let mut config = Config::default();
+        let browser = DesktopHandler::from_str("tests/cmus.desktop")?;
+        let reader = DesktopHandler::from_str("tests/Helix.desktop")?;
+        config.add_handler(
+            &[Mime::from_str("x-scheme-handler/https")?],
+            &browser,
+        )?;
+        config.add_handler(&[mime::APPLICATION_PDF], &reader)?;
+
+        let handlers = |config: &Config, url: &str| -> Result<Vec<Handler>> {
+            config.get_handlers_from_path(&UserPath::from_str(url)?)
+        };
+        let paper = "https://example.com/paper.pdf";
+
+        // Off by default
+        assert_eq!(handlers(&config, paper)?, [browser.clone().into()]);
+
+        config.config.url_mime_from_extension = true;
+        assert_eq!(handlers(&config, paper)?, [reader.into()]);
+        // Without an extension or a handler for the file, the scheme's handler is used
+        for url in ["https://example.com/paper", "https://example.com/song.mp3"]
+        {
+            assert_eq!(handlers(&config, url)?, [browser.clone().into()]);
+        }
+
+        // Regex handlers still come first
+        config.config.handlers = serde_json::from_str(
+            r#"[{"exec": "zathura %u", "regexes": ["\\.pdf$"]}]"#,
+        )?;
+        config.config.handlers.compile(&RegexLimits::default())?;
+        assert!(matches!(
+            handlers(&config, paper)?.as_slice(),
+            [Handler::RegexHandler(_)]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_aliases() -> Result<()> {
+        let mut config = Config::default();
+        let pdf = Mime::from_str("application/pdf")?;
+        let alias = Mime::from_str("application/x-pdf")?;
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+
+        config
+            .mime_info_mut()
+            .add_aliases(&std::fs::read_to_string("tests/aliases")?);
+        config.add_handler(std::slice::from_ref(&pdf), &helix)?;
+
+        // Aliases are looked up as their canonical mime
+        assert_eq!(config.get_handler(&alias)?, helix);
+
+        // Aliases are replaced when writing unless asked not to
+        let mut buffer = Vec::new();
+        assert_eq!(config.unalias(&mut buffer, &alias, false)?, pdf);
+        assert_eq!(config.unalias(&mut buffer, &alias, true)?, alias);
+        assert_eq!(config.unalias(&mut buffer, &pdf, false)?, pdf);
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "note: using 'application/pdf' instead of its alias 'application/x-pdf', pass --raw to keep the alias\n\
+             warning: 'application/x-pdf' is an alias of 'application/pdf', which other programs may expect instead\n"
+        );
+
+        // Associations for the alias itself still work
+        let mut config = Config::default();
+        config
+            .mime_info_mut()
+            .add_aliases(&std::fs::read_to_string("tests/aliases")?);
+        config.add_handler(std::slice::from_ref(&alias), &helix)?;
+        assert_eq!(config.get_handler(&alias)?, helix);
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_command_set() -> Result<()> {
+        let mut config = Config::default();
+
+        config.add_handler(
+            &[Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+        )?;
+
+        assert_eq!(config.terminal(None)?, "wezterm start --cwd . -e");
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_ignores_wildcards() -> Result<()> {
+        let mut config = Config::default();
+        let terminal = Mime::from_str("x-scheme-handler/terminal")?;
+
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let wezterm =
+            DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?;
+        config.add_handler(&[Mime::from_str("x-scheme-handler/*")?], &helix)?;
+        config.system_apps_mut().add_unassociated(wezterm.clone());
+
+        // The wildcard is skipped in favor of the fallback, which is the only terminal emulator installed
+        assert_eq!(config.resolve_handler(&terminal), None);
+        assert!(!matches!(config.get_handler(&terminal), Ok(h) if h == helix));
+        assert_eq!(config.terminal(None)?, "wezterm start --cwd . -e");
+
+        // Setting the terminal exactly uses it
+        config.add_handler(std::slice::from_ref(&terminal), &wezterm)?;
+        assert_eq!(config.resolve_handler(&terminal), Some(wezterm.clone()));
+        assert_eq!(config.get_handler(&terminal)?, wezterm);
+        assert_eq!(config.terminal(None)?, "wezterm start --cwd . -e");
+
+        // Other mimes still use the wildcard
+        assert_eq!(
+            config.resolve_handler(&Mime::from_str("x-scheme-handler/https")?),
+            Some(helix)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_fallback() -> Result<()> {
+        let mut config = Config::default();
+        let pdf = Mime::from_str("application/pdf")?;
+        let previewer =
+            DesktopHandler::from_str("org.gnome.Evince-previewer.desktop")?;
+
+        *config.system_apps_mut() = SystemApps::from_available_entries(
+            [DesktopEntry::try_from(std::path::PathBuf::from(
+                "tests/org.gnome.Evince-previewer.desktop",
+            ))?],
+            &[],
+            false,
+        );
+
+        // The only app that can open pdfs is hidden, so it is named in the error rather than used
+        assert!(matches!(
+            config.get_handler(&pdf),
+            Err(Error::OnlyHidden(mime, handlers))
+                if mime == "application/pdf"
+                    && handlers == ["org.gnome.Evince-previewer.desktop"]
+        ));
+
+        config.config.use_hidden_fallback = true;
+        assert_eq!(config.get_handler(&pdf)?, previewer);
+
+        // Visible apps are still preferred
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        config.add_handler(std::slice::from_ref(&pdf), &helix)?;
+        assert_eq!(config.get_handler(&pdf)?, helix);
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_command_fallback() -> Result<()> {
+        let mut config = Config::default();
+
+        config
+            .system_apps_mut()
+            .add_unassociated(DesktopHandler::from_str(
+                "tests/org.wezfurlong.wezterm.desktop",
+            )?);
+
+        assert_eq!(config.terminal(None)?, "wezterm start --cwd . -e");
+
+        Ok(())
+    }
+
+    #[test]
+    fn terminal_command_tagged() -> Result<()> {
+        let mut config = Config::default();
+
+        config.add_handler(
+            &[Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+        )?;
+        config.config.tag_args.insert(
+            "org.wezfurlong.wezterm.desktop".into(),
+            "--class handlr-{tag}".into(),
+        );
+
+        // Nothing is added without a tag
+        assert_eq!(config.terminal(None)?, "wezterm start --cwd . -e");
+
+        config.set_tag(Some("notes".into()))?;
+        assert_eq!(
+            config.terminal(None)?,
+            "wezterm start --cwd . --class handlr-notes -e"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_tag() {
+        let mut config = Config::default();
+
+        for tag in ["", "my tag", "it's", "\"quoted\"", "a;b", "$(id)"] {
+            assert!(
+                matches!(
+                    config.set_tag(Some(tag.into())),
+                    Err(Error::BadTag(_))
+                ),
+                "{tag}"
+            );
+        }
+        assert_eq!(config.tag(), None);
+
+        assert!(config.set_tag(Some("work-2.video_1".into())).is_ok());
+        assert_eq!(config.tag(), Some("work-2.video_1"));
+    }
+
+    fn test_show_handler<W: Write>(
+        writer: &mut W,
+        format: OutputFormat,
+        terminal_output: bool,
+    ) -> Result<()> {
+        let mut config = Config {
+            terminal_output,
+            ..Default::default()
+        };
+
+        // Use actual desktop file because command may be needed
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::from_str("tests/Helix.desktop")?,
+        )?;
+
+        // May be needed if terminal command is needed
+        config.add_handler(
+            &[Mime::from_str("x-scheme-handler/terminal")?],
+            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+        )?;
+
+        config.show_handler(
+            writer,
+            &mime::TEXT_PLAIN,
+            format,
+            &mut Trace::default(),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    // NOTE: result will begin with tests/, which is normal ONLY for tests
+    fn show_handler() -> Result<()> {
+        let mut buffer = Vec::new();
+        test_show_handler(&mut buffer, OutputFormat::Tsv, false)?;
+        println!("{}", String::from_utf8(buffer.clone())?);
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn show_handler_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        test_show_handler(&mut buffer, OutputFormat::Json, false)?;
+        println!("{}", String::from_utf8(buffer.clone())?);
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    #[test]
+    // NOTE: result will begin with tests/, which is normal ONLY for tests
+    fn show_handler_terminal() -> Result<()> {
+        let mut buffer = Vec::new();
+        test_show_handler(&mut buffer, OutputFormat::Table, true)?;
+        println!("{}", String::from_utf8(buffer.clone())?);
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
+    #[test]
+    fn show_handler_json_terminal() -> Result<()> {
+        let mut buffer = Vec::new();
+        test_show_handler(&mut buffer, OutputFormat::Json, true)?;
+        println!("{}", String::from_utf8(buffer.clone())?);
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
+
+    fn test_add_handlers(config: &mut Config) -> Result<()> {
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
+        )?;
+
+        // Should return first added handler
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
+            "Helix.desktop"
+        );
+
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
+
+        // Should still return first added handler
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
+            "Helix.desktop"
+        );
+
+        Ok(())
+    }
+
+    fn test_remove_handlers(config: &mut Config) -> Result<()> {
+        config.remove_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
+        )?;
+
+        // With first added handler removed, second handler replaces it
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
+            "nvim.desktop"
+        );
+
+        config.remove_handler(
+            &mime::TEXT_PLAIN,
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
+
+        // Both handlers removed, should not be any left
+        assert!(config.get_handler(&mime::TEXT_PLAIN).is_err());
+
+        Ok(())
+    }
+
+    fn test_set_handlers(config: &mut Config) -> Result<()> {
+        config.set_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("Helix.desktop".into())?,
+        )?;
+
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
+            "Helix.desktop"
+        );
+
+        config.set_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
+
+        // Should return second set handler because it should replace the first one
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
+            "nvim.desktop"
+        );
+
+        Ok(())
+    }
+
+    fn test_unset_handlers(config: &mut Config) -> Result<()> {
+        config.unset_handler(&mime::TEXT_PLAIN)?;
+
+        // Handler completely unset, should not be any left
+        assert!(config.get_handler(&mime::TEXT_PLAIN).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_and_remove_handlers() -> Result<()> {
+        let mut config = Config::default();
+
+        test_add_handlers(&mut config)?;
+        test_remove_handlers(&mut config)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_and_unset_handlers() -> Result<()> {
+        let mut config = Config::default();
+
+        test_set_handlers(&mut config)?;
+        test_unset_handlers(&mut config)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn import_and_export() -> Result<()> {
+        let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+        config.set_handler(&[mime::TEXT_PLAIN], &helix)?;
+        config.add_handler(&[mime::TEXT_PLAIN], &cmus)?;
+        config.set_handler(&[mime::TEXT_HTML], &helix)?;
+
+        let export = |config: &mut Config| -> Result<String> {
+            let mut buffer = Vec::new();
+            config.export(&mut buffer)?;
+            Ok(String::from_utf8(buffer)?)
+        };
+        let exported = export(&mut config)?;
+        assert_eq!(
+            exported,
+            "[default_apps]\n\"text/html\" = [\"tests/Helix.desktop\"]\n\"text/plain\" = [\"tests/Helix.desktop\", \"tests/cmus.desktop\"]\n"
+        );
+
+        // Importing what was exported changes nothing
+        let before = config.mime_apps.default_apps.clone();
+        let mut notes = Vec::new();
+        config.import(&mut notes, &exported.parse()?, true)?;
+        assert_eq!(config.mime_apps.default_apps, before);
+        assert_eq!(export(&mut config)?, exported);
+        assert!(notes.is_empty());
+
+        let associations: Associations = r#"
+            [default_apps]
+            "text/plain" = ["tests/cmus.desktop", "tests/Helix.desktop"]
+            "audio/mpeg" = ["tests/cmus.desktop"]
+
+            [[handlers]]
+            exec = "freetube %u"
+            regexes = ["youtu\\.be"]
+        "#
+        .parse()?;
+
+        config.import(&mut notes, &associations, false)?;
+        assert_eq!(
+            export(&mut config)?,
+            "[default_apps]\n\"audio/mpeg\" = [\"tests/cmus.desktop\"]\n\"text/html\" = [\"tests/Helix.desktop\"]\n\"text/plain\" = [\"tests/cmus.desktop\", \"tests/Helix.desktop\"]\n"
+        );
+        assert!(String::from_utf8(notes)?.contains("not imported"));
+
+        config.import(&mut Vec::new(), &associations, true)?;
+        assert_eq!(
+            export(&mut config)?,
+            "[default_apps]\n\"audio/mpeg\" = [\"tests/cmus.desktop\"]\n\"text/plain\" = [\"tests/cmus.desktop\", \"tests/Helix.desktop\"]\n"
+        );
+
+        // Nothing is changed if anything is wrong
+        let associations: Associations = r#"
+            [default_apps]
+            "text/plain" = ["tests/Helix.desktop"]
+            "video/mp4" = ["tests/missing.desktop"]
+        "#
+        .parse()?;
+        let before = config.mime_apps.default_apps.clone();
+        assert!(matches!(
+            config.import(&mut Vec::new(), &associations, true),
+            Err(Error::BadImport(problems)) if problems.len() == 1
+        ));
+        assert_eq!(config.mime_apps.default_apps, before);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_status() -> Result<()> {
+        let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+        config.set_handler(&[mime::TEXT_PLAIN], &helix)?;
+        config.add_handler(&[mime::TEXT_PLAIN], &cmus)?;
+        config.set_handler(&[mime::TEXT_HTML], &helix)?;
+        config.set_handler(&[mime::IMAGE_PNG], &helix)?;
+
+        let associations: Associations = r#"
+            [default_apps]
+            "text/plain" = ["tests/cmus.desktop", "tests/Helix.desktop"]
+            "text/html" = ["tests/Helix.desktop"]
+            "image/png" = ["tests/cmus.desktop"]
+            "audio/mpeg" = ["tests/cmus.desktop"]
+        "#
+        .parse()?;
+
+        let check = |config: &mut Config,
+                     replace: bool,
+                     json: bool|
+         -> (String, Result<()>) {
+            let mut buffer = Vec::new();
+            let result =
+                config.check_status(&mut buffer, &associations, replace, json);
+            (String::from_utf8_lossy(&buffer).into_owned(), result)
+        };
+
+        let (output, result) = check(&mut config, false, false);
+        assert_eq!(
+            output,
+            "+ audio/mpeg: tests/cmus.desktop;
+~ image/png: tests/Helix.desktop; -> tests/cmus.desktop;
+~ text/plain: tests/Helix.desktop;tests/cmus.desktop; -> tests/cmus.desktop;tests/Helix.desktop; (reordered)
+"
+        );
+        assert!(matches!(result, Err(Error::Drift(3))));
+        assert_eq!(result.unwrap_err().exit_code(), 1);
+
+        let (output, _) = check(&mut config, false, true);
+        assert!(output.starts_with(
+            r#"[{"mime":"audio/mpeg","kind":"added","current":[],"desired":["tests/cmus.desktop"]},"#
+        ));
+
+        // Importing brings everything in line
+        config.import(&mut Vec::new(), &associations, false)?;
+        let (output, result) = check(&mut config, false, false);
+        assert_eq!(output, "");
+        assert!(result.is_ok());
+
+        // Mimes that are not in the file only matter when replacing
+        config.set_handler(&[mime::TEXT_CSS], &helix)?;
+        assert!(check(&mut config, false, true).1.is_ok());
+        let (output, result) = check(&mut config, true, false);
+        assert_eq!(output, "- text/css: tests/Helix.desktop;\n");
+        assert!(matches!(result, Err(Error::Drift(1))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reorder_handlers() -> Result<()> {
+        let mut config = Config::default();
+        let handler = |name: &str| {
+            DesktopHandler::assume_valid(name.into())
+                .expect("Test handler names should be valid")
+        };
+        let mime = mime::TEXT_PLAIN;
+
+        ["helix.desktop", "nvim.desktop", "kate.desktop"]
+            .into_iter()
+            .try_for_each(|name| {
+                config.add_handler(std::slice::from_ref(&mime), &handler(name))
+            })?;
+
+        let mut reorder = |name: &str, to: Reorder| -> Result<String> {
+            let mut buffer = Vec::new();
+            config.reorder_handler(&mut buffer, &mime, &handler(name), to)?;
+            Ok(String::from_utf8(buffer)?)
+        };
+
+        assert_eq!(
+            reorder("kate.desktop", Reorder::To(0))?,
+            "kate.desktop\nhelix.desktop\nnvim.desktop\n"
+        );
+        assert_eq!(
+            reorder("nvim.desktop", Reorder::Up)?,
+            "kate.desktop\nnvim.desktop\nhelix.desktop\n"
+        );
+        // Handlers cannot be moved past either end
+        assert_eq!(
+            reorder("kate.desktop", Reorder::Up)?,
+            "kate.desktop\nnvim.desktop\nhelix.desktop\n"
+        );
+        assert_eq!(
+            reorder("helix.desktop", Reorder::Down)?,
+            "kate.desktop\nnvim.desktop\nhelix.desktop\n"
+        );
+        assert_eq!(
+            reorder("kate.desktop", Reorder::To(9))?,
+            "nvim.desktop\nhelix.desktop\nkate.desktop\n"
+        );
+
+        assert!(matches!(
+            reorder("vim.desktop", Reorder::Up),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            config.reorder_handler(
+                &mut Vec::new(),
+                &mime::TEXT_HTML,
+                &handler("helix.desktop"),
+                Reorder::Up
+            ),
+            Err(Error::NotFound(target)) if target == "text/html"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_and_unset_handlers() -> Result<()> {
+        let mut config = Config::default();
+
+        test_add_handlers(&mut config)?;
+        test_unset_handlers(&mut config)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_and_remove_handlers() -> Result<()> {
+        let mut config = Config::default();
+
+        test_set_handlers(&mut config)?;
+        test_remove_handlers(&mut config)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn override_selector() -> Result<()> {
+        let mut config = Config::default();
+
+        // Ensure defaults are as expected just in case
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, false);
+
+        config.override_selector(SelectorArgs {
+            selector: Some("fzf".to_string()),
+            enable_selector: true,
+            disable_selector: false,
+        });
+
+        assert_eq!(config.config.selector.to_string(), "fzf");
+        assert_eq!(config.config.enable_selector, true);
+
+        config.override_selector(SelectorArgs {
+            selector: Some("fuzzel --dmenu --prompt='Open With: '".to_string()),
+            enable_selector: false,
+            disable_selector: true,
+        });
+
+        assert_eq!(
+            config.config.selector.to_string(),
+            "fuzzel --dmenu --prompt='Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, false);
+
+        Ok(())
+    }
 
     #[test]
-    fn print_handlers_json() -> Result<()> {
-        // NOTE: both calls should have the same result
-        // JSON output and terminal output
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, false, true, true)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+    fn dont_override_selector() -> Result<()> {
+        // NOTE: `enable_selector` and `disable_selector` should not both be true in practice anyways
 
-        // JSON output and piped
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, false, true, false)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+        let mut config = Config::default();
+
+        // Ensure defaults are as expected just in case
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, false);
+
+        config.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: false,
+            disable_selector: false,
+        });
+
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, false);
+
+        config.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: false,
+            disable_selector: true,
+        });
+
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, false);
+
+        // Now repeat with `enable_selector` set to true
+        config.config.enable_selector = true;
+
+        config.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: true,
+            disable_selector: false,
+        });
+
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, true);
+
+        config.override_selector(SelectorArgs {
+            selector: None,
+            enable_selector: false,
+            disable_selector: false,
+        });
+
+        assert_eq!(
+            config.config.selector.to_string(),
+            "rofi -dmenu -i -p 'Open With: '"
+        );
+        assert_eq!(config.config.enable_selector, true);
 
         Ok(())
     }
 
     #[test]
-    fn print_handlers_detailed_json() -> Result<()> {
-        // NOTE: both calls should have the same result
-        // JSON output and terminal output
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, true, true, false)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+    fn set_handler_for_many_mimes() -> Result<()> {
+        let Cmd::Set { args, .. } = Cmd::try_parse_from([
+            "handlr",
+            "set",
+            "image/png",
+            ".jpg",
+            "image/webp",
+            "imv.desktop",
+        ])
+        .expect("Arguments should be valid") else {
+            unreachable!()
+        };
+        let (mimes, handler) = MimeOrExtension::with_handler(&args, None)?;
+        let handler = handler.expect("The last argument should be the handler");
+        let mimes = mimes.into_iter().map(|mime| mime.0).collect_vec();
+        assert_eq!(mimes.len(), 3);
+        assert_eq!(handler.to_string(), "imv.desktop");
 
-        // JSON output and piped
-        let mut buffer = Vec::new();
-        print_handlers_test(&mut buffer, true, true, false)?;
-        goldie::assert!(String::from_utf8(buffer)?);
+        let mut config = Config::default();
+        let swayimg = DesktopHandler::assume_valid("swayimg.desktop".into())?;
+        config.set_handler(&mimes, &handler)?;
+        config.add_handler(&mimes[1..], &swayimg)?;
+
+        assert_eq!(config.get_handlers(&mimes[0])?, vec![handler.clone()]);
+        for mime in &mimes[1..] {
+            assert_eq!(
+                config.get_handlers(mime)?,
+                vec![handler.clone(), swayimg.clone()]
+            );
+        }
+
+        // At least a mime is required
+        assert!(Cmd::try_parse_from(["handlr", "set"]).is_err());
 
         Ok(())
     }
 
     #[test]
-    fn terminal_command_set() -> Result<()> {
+    fn pick_handler() -> Result<()> {
+        let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let cmus = DesktopHandler::from_str("tests/cmus.desktop")?;
+        config.system_apps_mut().associations.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("tests/Helix.desktop;missing.desktop")?,
+        );
+        config.system_apps_mut().associations.insert(
+            Mime::from_str("text/*")?,
+            DesktopList::from_str("tests/cmus.desktop")?,
+        );
+
+        let mut shown = Vec::new();
+        let picked =
+            config.pick_handler_with(&mime::TEXT_PLAIN, |options| {
+                shown = options;
+                Ok(2)
+            })?;
+        assert_eq!(picked, cmus);
+        assert_eq!(
+            shown,
+            vec![
+                "Helix (tests/Helix.desktop)",
+                "missing.desktop",
+                "cmus-remote (tests/cmus.desktop)"
+            ]
+        );
+        assert_eq!(
+            config.pick_handler_with(&mime::TEXT_PLAIN, |_| Ok(0))?,
+            helix
+        );
+
+        // Nothing is picked when cancelled, or when there is nothing to pick from
+        assert!(matches!(
+            config.pick_handler_with(&mime::TEXT_PLAIN, |_| Err(
+                Error::Cancelled
+            )),
+            Err(Error::Cancelled)
+        ));
+        assert!(matches!(
+            config.pick_handler_with(&mime::IMAGE_PNG, |_| Ok(0)),
+            Err(Error::NotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn same_settings_for_every_command() -> Result<()> {
+        // Helper function to get the config a command would run with, given its arguments
+        let config_for = |args: &[&str]| -> Result<Config> {
+            let cmd = Cmd::try_parse_from(
+                std::iter::once("handlr").chain(args.iter().copied()),
+            )
+            .expect("Arguments should be valid");
+            let mut config = Config::from_parts(
+                ConfigFile::load_from("tests/handlr.toml")?,
+                MimeApps::default(),
+                false,
+                &cmd,
+            );
+            config.system_apps_mut().add_unassociated(
+                DesktopHandler::from_str(
+                    "tests/org.wezfurlong.wezterm.desktop",
+                )?,
+            );
+            Ok(config)
+        };
+        // Helper function to get every setting that affects choosing and running handlers
+        let settings =
+            |config: &Config| -> Result<(serde_json::Value, String)> {
+                Ok((
+                    serde_json::to_value(&config.config)?,
+                    config.terminal(None)?,
+                ))
+            };
+
+        let get = config_for(&["get", "--selector", "fzf", "text/plain"])?;
+        let open = config_for(&["open", "--selector", "fzf", "a.txt"])?;
+        let launch =
+            config_for(&["launch", "--selector", "fzf", "text/plain"])?;
+
+        assert_eq!(settings(&get)?, settings(&open)?);
+        assert_eq!(settings(&get)?, settings(&launch)?);
+        assert_eq!(get.config.selector.to_string(), "fzf");
+        assert_eq!(get.config.enable_selector, true);
+        assert_eq!(get.terminal(None)?, "wezterm start --cwd . --");
+
+        let get = config_for(&["get", "-d", "text/plain"])?;
+        let open = config_for(&["open", "-d", "a.txt"])?;
+        let launch = config_for(&["launch", "-d", "text/plain"])?;
+
+        assert_eq!(settings(&get)?, settings(&open)?);
+        assert_eq!(settings(&get)?, settings(&launch)?);
+        assert_eq!(get.config.selector.to_string(), "fuzzel --dmenu");
+        assert_eq!(get.config.enable_selector, false);
+
+        // Commands without selector arguments keep the config file's settings
+        let list = config_for(&["list"])?;
+        assert_eq!(list.config.selector.to_string(), "fuzzel --dmenu");
+        assert_eq!(list.config.enable_selector, true);
+
+        // Selections are only remembered by commands that use them, and never in dry runs
+        assert!(
+            config_for(&["open", "--remember", "a.txt"])?
+                .config
+                .selector_persist
+        );
+        assert!(
+            config_for(&["get", "--remember", "text/plain"])?
+                .config
+                .selector_persist
+        );
+        assert!(!config_for(&["get", "text/plain"])?.config.selector_persist);
+        assert!(
+            !config_for(&["launch", "--remember", "--dry-run", "text/plain"])?
+                .config
+                .selector_persist
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_associations_are_skipped() -> Result<()> {
+        let mut config = Config::default();
+        let handler = |name: &str| {
+            DesktopHandler::assume_valid(name.into())
+                .expect("Test handler names should be valid")
+        };
+
+        config.system_apps_mut().associations.insert(
+            mime::TEXT_PLAIN,
+            DesktopList::from_str("wine-extension-txt.desktop;gedit.desktop;")?,
+        );
+        config.mime_apps.added_associations.insert(
+            mime::TEXT_HTML,
+            DesktopList::from_str(
+                "wine-extension-html.desktop;firefox.desktop;",
+            )?,
+        );
+
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?,
+            handler("wine-extension-txt.desktop")
+        );
+        assert_eq!(
+            config.get_handler(&mime::TEXT_HTML)?,
+            handler("wine-extension-html.desktop")
+        );
+
+        config.remove_association(
+            &mime::TEXT_PLAIN,
+            &handler("wine-extension-txt.desktop"),
+        )?;
+        config.remove_association(
+            &mime::TEXT_HTML,
+            &handler("wine-extension-html.desktop"),
+        )?;
+
+        assert_eq!(
+            config.get_handler(&mime::TEXT_PLAIN)?,
+            handler("gedit.desktop")
+        );
+        assert_eq!(
+            config.get_handler(&mime::TEXT_HTML)?,
+            handler("firefox.desktop")
+        );
+
+        // Removing every handler means there are none left
+        config
+            .remove_association(&mime::TEXT_PLAIN, &handler("gedit.desktop"))?;
+        assert!(config.get_handler(&mime::TEXT_PLAIN).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn confirm_risky_mimes() -> Result<()> {
+        let mut config = Config::default();
+        config.config.confirm_mimes = vec![
+            "application/x-shellscript".into(),
+            "application/x-*exec*".into(),
+        ];
+        config.add_handler(
+            &[Mime::from_str("application/x-shellscript")?],
+            &DesktopHandler::assume_valid("bash.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("image/png")?],
+            &DesktopHandler::assume_valid("swayimg.desktop".into())?,
+        )?;
+
+        let paths = [UserPath::from_str("a.png")?, UserPath::from_str("a.sh")?];
+
+        // Declining cancels everything
+        let mut prompts = Vec::new();
+        let declined = config.assign_files_to_handlers(&paths, |message, _| {
+            prompts.push(message.to_owned());
+            Ok(false)
+        });
+        assert!(matches!(declined, Err(Error::Cancelled)));
+        // Only the risky file should be prompted for, along with the handler it would be opened with
+        assert_eq!(prompts, vec!["Open 'a.sh' with bash.desktop?"]);
+
+        // Accepting opens everything as usual
+        let accepted =
+            config.assign_files_to_handlers(&paths, |_, _| Ok(true))?;
+        assert_eq!(
+            accepted.get(&vec![Handler::new("bash.desktop")]),
+            Some(&vec!["a.sh".to_owned()])
+        );
+        assert_eq!(
+            accepted.get(&vec![Handler::new("swayimg.desktop")]),
+            Some(&vec!["a.png".to_owned()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn override_handler() -> Result<()> {
         let mut config = Config::default();
+        config.add_handler(
+            &[Mime::from_str("image/png")?],
+            &DesktopHandler::assume_valid("swayimg.desktop".into())?,
+        )?;
+        config.config.handlers = serde_json::from_str(
+            r#"[{"exec": "freetube %u", "regexes": ["youtu\\.be"]}]"#,
+        )?;
+        config.config.handlers.compile(&RegexLimits::default())?;
+
+        config.override_handler(Some(DesktopHandler::from_str(
+            "tests/gimp.desktop",
+        )?))?;
+
+        // Every path goes to the override, even ones with regex handlers or no handler at all
+        let paths = ["a.png", "b.xcf", "https://youtu.be/dQw4w9WgXcQ"]
+            .iter()
+            .map(|p| UserPath::from_str(p))
+            .collect::<Result<Vec<_>>>()?;
+        let assigned =
+            config.assign_files_to_handlers(&paths, |_, _| Ok(true))?;
+        assert_eq!(
+            assigned,
+            IndexMap::from([(
+                vec![Handler::new("tests/gimp.desktop")],
+                paths.iter().map(|p| p.to_string()).collect_vec()
+            )])
+        );
+
+        // Handlers that cannot be used are rejected up front
+        for handler in ["tests/missing.desktop", "tests/link.desktop"] {
+            assert!(config
+                .override_handler(Some(DesktopHandler::from_str(handler)?))
+                .is_err());
+        }
+
+        Ok(())
+    }
 
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn fallback_command() -> Result<()> {
+        let mut config = Config::default();
         config.add_handler(
-            &Mime::from_str("x-scheme-handler/terminal")?,
-            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
+            &[Mime::from_str("image/png")?],
+            &DesktopHandler::assume_valid("swayimg.desktop".into())?,
         )?;
+        let paths = ["a.png", "unknown-a://x", "unknown-b://y"]
+            .iter()
+            .map(|p| UserPath::from_str(p))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Without a fallback, paths without handlers fail as usual
+        assert!(matches!(
+            config.assign_files_to_handlers(&paths, |_, _| Ok(true)),
+            Err(Error::NotFound(_))
+        ));
+
+        config.fallback_command(Some("nvim".into()));
+        let fallback = FallbackCommand::new("nvim".into());
+        let assigned =
+            config.assign_files_to_handlers(&paths, |_, _| Ok(true))?;
+
+        // Paths with handlers ignore the fallback
+        assert_eq!(
+            assigned,
+            IndexMap::from([
+                (
+                    vec![Handler::new("swayimg.desktop")],
+                    vec!["a.png".to_owned()]
+                ),
+                (
+                    vec![fallback.clone().into()],
+                    vec![
+                        "unknown-a://x".to_owned(),
+                        "unknown-b://y".to_owned()
+                    ]
+                ),
+            ])
+        );
 
-        assert_eq!(config.terminal()?, "wezterm start --cwd . -e");
+        // Without field codes, the fallback is given every path at once, appended to the end
+        assert_eq!(
+            fallback.get_entry()?.get_cmd(
+                &config,
+                vec!["unknown-a://x".into(), "unknown-b://y".into()]
+            )?,
+            (
+                "nvim".to_owned(),
+                vec!["unknown-a://x".to_owned(), "unknown-b://y".to_owned()]
+            )
+        );
 
         Ok(())
     }
 
     #[test]
-    fn terminal_command_fallback() -> Result<()> {
+    #[allow(clippy::mutable_key_type)]
+    fn properly_assign_files_to_handlers() -> Result<()> {
+        let mut config = Config::default();
+        config.add_handler(
+            &[Mime::from_str("image/png")?],
+            &DesktopHandler::assume_valid("swayimg.desktop".into())?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("application/pdf")?],
+            &DesktopHandler::assume_valid("mupdf.desktop".into())?,
+        )?;
+
+        let mut expected_handlers = IndexMap::new();
+        expected_handlers.insert(
+            vec![Handler::new("swayimg.desktop")],
+            vec!["a.png".to_owned()],
+        );
+        expected_handlers.insert(
+            vec![Handler::new("mupdf.desktop")],
+            vec!["a.pdf".to_owned()],
+        );
+
+        assert_eq!(
+            config.assign_files_to_handlers(
+                &[UserPath::from_str("a.png")?, UserPath::from_str("a.pdf")?],
+                |_, _| Ok(true)
+            )?,
+            expected_handlers
+        );
+
+        assert_eq!(
+            config.assign_files_to_handlers(
+                &[UserPath::from_str("a.pdf")?, UserPath::from_str("a.png")?],
+                |_, _| Ok(true)
+            )?,
+            expected_handlers
+        );
+
+        let mut expected_handlers = IndexMap::new();
+        expected_handlers.insert(
+            vec![Handler::new("swayimg.desktop")],
+            vec!["a.png".to_owned(), "b.png".to_owned()],
+        );
+        expected_handlers.insert(
+            vec![Handler::new("mupdf.desktop")],
+            vec!["a.pdf".to_owned()],
+        );
+
+        assert_eq!(
+            config.assign_files_to_handlers(
+                &[
+                    UserPath::from_str("a.png")?,
+                    UserPath::from_str("b.png")?,
+                    UserPath::from_str("a.pdf")?
+                ],
+                |_, _| Ok(true)
+            )?,
+            expected_handlers
+        );
+
+        assert_eq!(
+            config.assign_files_to_handlers(
+                &[
+                    UserPath::from_str("a.pdf")?,
+                    UserPath::from_str("a.png")?,
+                    UserPath::from_str("b.png")?
+                ],
+                |_, _| Ok(true)
+            )?,
+            expected_handlers
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fall_back_to_next_handler() -> Result<()> {
+        let mut config = Config::default();
+        for handler in
+            ["missing.desktop", "also-missing.desktop", "mpv.desktop"]
+        {
+            config.add_handler(
+                &[Mime::from_str("video/mp4")?],
+                &DesktopHandler::assume_valid(handler.into())?,
+            )?;
+        }
+
+        let handlers = config.get_handlers(&Mime::from_str("video/mp4")?)?;
+
+        // Handlers are tried in order until one succeeds
+        let mut attempted = Vec::new();
+        try_handlers(&handlers, |handler| {
+            attempted.push(handler.to_string());
+            if handler.to_string().contains("missing") {
+                Err(Error::NotFound(handler.to_string()))
+            } else {
+                Ok(())
+            }
+        })?;
+        assert_eq!(
+            attempted,
+            vec!["missing.desktop", "also-missing.desktop", "mpv.desktop"]
+        );
+
+        // If every handler fails, each failure is listed
+        let error = try_handlers(&handlers, |handler| {
+            Err(Error::NotFound(handler.to_string()))
+        })
+        .unwrap_err();
+        assert!(
+            matches!(error, Error::AllHandlersFailed(ref f) if f.len() == 3)
+        );
+        let message = error.to_string();
+        for handler in handlers.iter() {
+            assert!(message
+                .contains(&format!("\n  {handler}: no handlers found for")));
+        }
+
+        // A single handler keeps its original error
+        let error = try_handlers(&handlers[..1], |_| Err(Error::NoTerminal))
+            .unwrap_err();
+        assert!(matches!(error, Error::NoTerminal));
+
+        // Cancelling stops immediately
+        let mut attempts = 0;
+        let error = try_handlers(&handlers, |_| {
+            attempts += 1;
+            Err(Error::Cancelled)
+        })
+        .unwrap_err();
+        assert!(matches!(error, Error::Cancelled));
+        assert_eq!(attempts, 1);
+
+        Ok(())
+    }
+
+    // Helper command to test printing installed apps that are not associated with any mime
+    fn print_orphans_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
         let mut config = Config::default();
 
+        // Helix handles text/plain, but another editor is set for it
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
+        // cmus is referenced by an added association, so it is not an orphan
         config
-            .system_apps
-            .add_unassociated(DesktopHandler::from_str(
-                "tests/org.wezfurlong.wezterm.desktop",
-            )?);
+            .mime_apps
+            .added_associations
+            .entry(Mime::from_str("audio/flac")?)
+            .or_default()
+            .push_back(DesktopHandler::assume_valid("cmus.desktop".into())?);
+
+        let entries = [
+            "tests/Helix.desktop",
+            "tests/cmus.desktop",
+            // Terminal emulators do not handle any mimes
+            "tests/org.wezfurlong.wezterm.desktop",
+        ]
+        .into_iter()
+        .map(|path| DesktopEntry::try_from(std::path::PathBuf::from(path)))
+        .collect::<Result<Vec<_>>>()?;
+
+        config.print_orphans_from(buffer, entries, format)
+    }
 
-        assert_eq!(config.terminal()?, "wezterm start --cwd . -e");
+    #[test]
+    #[cfg(feature = "tables")]
+    fn print_orphans() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_orphans_test(&mut buffer, OutputFormat::Table)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
 
+    #[test]
+    fn print_orphans_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_orphans_test(&mut buffer, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
-    fn test_show_handler<W: Write>(
-        writer: &mut W,
-        output_json: bool,
-        terminal_output: bool,
+    // Helper command to test printing the command a path would be opened with
+    fn print_command_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
     ) -> Result<()> {
         let mut config = Config {
-            terminal_output,
+            terminal_output: true,
             ..Default::default()
         };
-
-        // Use actual desktop file because command may be needed
         config.add_handler(
-            &mime::TEXT_PLAIN,
+            &[mime::TEXT_PLAIN],
             &DesktopHandler::from_str("tests/Helix.desktop")?,
         )?;
-
-        // May be needed if terminal command is needed
-        config.add_handler(
-            &Mime::from_str("x-scheme-handler/terminal")?,
-            &DesktopHandler::from_str("tests/org.wezfurlong.wezterm.desktop")?,
-        )?;
-
-        config.show_handler(writer, &mime::TEXT_PLAIN, output_json)?;
+        config.config.handlers = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "mpv --no-terminal %u"
+            regexes = ['youtu\.be']
+            "#,
+        )?
+        .handlers;
+        config.config.handlers.compile(&RegexLimits::default())?;
+
+        for path in ["tests/empty.txt", "https://youtu.be/it's here"] {
+            config.print_command(
+                buffer,
+                &UserPath::from_str(path)?,
+                format,
+                &mut Trace::default(),
+            )?;
+        }
 
         Ok(())
     }
 
     #[test]
-    // NOTE: result will begin with tests/, which is normal ONLY for tests
-    fn show_handler() -> Result<()> {
+    fn print_command() -> Result<()> {
         let mut buffer = Vec::new();
-        test_show_handler(&mut buffer, false, false)?;
-        println!("{}", String::from_utf8(buffer.clone())?);
+        print_command_test(&mut buffer, OutputFormat::Tsv)?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
     #[test]
-    fn show_handler_json() -> Result<()> {
+    fn print_command_json() -> Result<()> {
         let mut buffer = Vec::new();
-        test_show_handler(&mut buffer, true, false)?;
-        println!("{}", String::from_utf8(buffer.clone())?);
+        print_command_test(&mut buffer, OutputFormat::Json)?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
+    // Helper function to test printing how a path fares against regex handlers
+    fn print_regex_matches_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut config = Config::default();
+        config.config.handlers = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "firefox %u"
+            regexes = ['youtube\.com', 'youtu\.be']
+
+            [[handlers]]
+            name = "shorts"
+            exec = "mpv %u"
+            regexes = ['youtu\.be/', 'youtube\.com/shorts']
+            exclude_regexes = ['/live/']
+            priority = 1
+
+            [[handlers]]
+            name = "reddit"
+            exec = "firefox %u"
+            regexes = ['reddit\.com']
+            mimes = ['x-scheme-handler/http*']
+            "#,
+        )?
+        .handlers;
+        config.config.handlers.compile(&RegexLimits::default())?;
+
+        config.print_regex_matches(
+            buffer,
+            &UserPath::from_str("https://youtu.be/abc")?,
+            format,
+        )
+    }
+
     #[test]
-    // NOTE: result will begin with tests/, which is normal ONLY for tests
-    fn show_handler_terminal() -> Result<()> {
+    fn print_regex_matches() -> Result<()> {
         let mut buffer = Vec::new();
-        test_show_handler(&mut buffer, false, true)?;
-        println!("{}", String::from_utf8(buffer.clone())?);
+        print_regex_matches_test(&mut buffer, OutputFormat::Tsv)?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
+
     #[test]
-    fn show_handler_json_terminal() -> Result<()> {
+    fn print_regex_matches_json() -> Result<()> {
         let mut buffer = Vec::new();
-        test_show_handler(&mut buffer, true, true)?;
-        println!("{}", String::from_utf8(buffer.clone())?);
+        print_regex_matches_test(&mut buffer, OutputFormat::Json)?;
         goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
-    fn test_add_handlers(config: &mut Config) -> Result<()> {
-        config.add_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
-        )?;
-
-        // Should return first added handler
-        assert_eq!(
-            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
-            "Helix.desktop"
-        );
-
+    #[test]
+    fn regex_precedence() -> Result<()> {
+        let mut config = Config::default();
         config.add_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
         )?;
+        config.config.handlers = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            name = "text"
+            exec = "kate %f"
+            regexes = ['(\.txt|/cat)$']
+            "#,
+        )?
+        .handlers;
+        config.config.handlers.compile(&RegexLimits::default())?;
+
+        let mut output = String::new();
+        for precedence in [true, false] {
+            config.config.regex_handlers_take_precedence = precedence;
+            for path in ["tests/empty.txt", "tests/cat"] {
+                let mut trace = Trace::enabled();
+                let handler = config
+                    .get_handlers_from_path_traced(
+                        &UserPath::from_str(path)?,
+                        &mut trace,
+                    )?
+                    .remove(0);
+                output.push_str(&format!(
+                    "{path} ({precedence}): {handler}\n{}\n\n",
+                    trace.steps().join("\n")
+                ));
+            }
+        }
 
-        // Should still return first added handler
-        assert_eq!(
-            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
-            "Helix.desktop"
-        );
+        goldie::assert!(output);
 
         Ok(())
     }
 
-    fn test_remove_handlers(config: &mut Config) -> Result<()> {
-        config.remove_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
+    #[test]
+    fn dry_run() -> Result<()> {
+        let mut config = Config::default();
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::from_str("tests/Helix.desktop")?,
+        )?;
+        config.add_handler(
+            &[Mime::from_str("text/html")?],
+            &DesktopHandler::from_str("tests/firefox.desktop")?,
         )?;
+        config.config.handlers = toml::from_str::<ConfigFile>(
+            r#"
+            [[handlers]]
+            exec = "mpv %u"
+            regexes = ['youtu\.be']
+            "#,
+        )?
+        .handlers;
+        config.config.handlers.compile(&RegexLimits::default())?;
+
+        let paths = [
+            "tests/empty.txt",
+            "https://youtu.be/1",
+            "tests/p.html",
+            "tests/rust.vim",
+            "tests/cat",
+            "https://youtu.be/2",
+            "tests/no_html_tags.html",
+        ]
+        .into_iter()
+        .map(UserPath::from_str)
+        .collect::<Result<Vec<_>>>()?;
+
+        let plan = |config: &Config| {
+            let mut buffer = Vec::new();
+            let result = config.print_plan(&mut buffer, &paths);
+            (String::from_utf8(buffer), result)
+        };
 
-        // With first added handler removed, second handler replaces it
+        // Vim files are plain text, but nothing handles shell scripts
+        // Handlers with `%u` are run once per path, while `%F` takes every path at once
+        let (output, result) = plan(&config);
         assert_eq!(
-            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
-            "nvim.desktop"
+            output?,
+            "tests/Helix.desktop\ttests/empty.txt\ttests/rust.vim
+mpv %u\thttps://youtu.be/1
+mpv %u\thttps://youtu.be/2
+tests/firefox.desktop\ttests/p.html
+tests/firefox.desktop\ttests/no_html_tags.html
+"
         );
+        assert!(matches!(result, Err(Error::Unresolved(1))));
 
-        config.remove_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
-        )?;
-
-        // Both handlers removed, should not be any left
-        assert!(config.get_handler(&mime::TEXT_PLAIN).is_err());
-
-        Ok(())
-    }
-
-    fn test_set_handlers(config: &mut Config) -> Result<()> {
-        config.set_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("Helix.desktop".into()),
-        )?;
-
+        config.fallback_command(Some("xdg-open".into()));
+        config.set_tag(Some("work".into()))?;
+        let (output, result) = plan(&config);
         assert_eq!(
-            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
-            "Helix.desktop"
+            output?,
+            "tests/Helix.desktop --tag=work\ttests/empty.txt\ttests/rust.vim
+mpv %u --tag=work\thttps://youtu.be/1
+mpv %u --tag=work\thttps://youtu.be/2
+tests/firefox.desktop --tag=work\ttests/p.html
+tests/firefox.desktop --tag=work\ttests/no_html_tags.html
+xdg-open --tag=work\ttests/cat
+"
         );
+        result?;
 
-        config.set_handler(
-            &mime::TEXT_PLAIN,
-            &DesktopHandler::assume_valid("nvim.desktop".into()),
-        )?;
-
-        // Should return second set handler because it should replace the first one
+        // Actions only apply to desktop handlers, and Helix has none to run
+        config.set_action(Some("new-window".into()));
+        let (output, result) = plan(&config);
         assert_eq!(
-            config.get_handler(&mime::TEXT_PLAIN)?.to_string(),
-            "nvim.desktop"
+            output?,
+            "mpv %u --tag=work\thttps://youtu.be/1
+mpv %u --tag=work\thttps://youtu.be/2
+tests/firefox.desktop --action=new-window --tag=work\ttests/p.html
+tests/firefox.desktop --action=new-window --tag=work\ttests/no_html_tags.html
+xdg-open --tag=work\ttests/cat
+"
         );
+        assert!(matches!(result, Err(Error::Unresolved(2))));
 
         Ok(())
     }
 
-    fn test_unset_handlers(config: &mut Config) -> Result<()> {
-        config.unset_handler(&mime::TEXT_PLAIN)?;
+    // Helper command to test printing what is known about a handler
+    fn print_info_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
 
-        // Handler completely unset, should not be any left
-        assert!(config.get_handler(&mime::TEXT_PLAIN).is_err());
+        config.add_handler(&[mime::TEXT_PLAIN], &helix)?;
+        config.add_handler(&[Mime::from_str("text/x-c")?], &helix)?;
+        // Overridden by a default, so it does not currently open with Helix
+        config
+            .mime_apps
+            .added_associations
+            .entry(Mime::from_str("text/markdown")?)
+            .or_default()
+            .push_back(helix.clone());
+        config.add_handler(
+            &[Mime::from_str("text/markdown")?],
+            &DesktopHandler::assume_valid("nvim.desktop".into())?,
+        )?;
 
-        Ok(())
+        config.print_info(buffer, &helix, format)
     }
 
     #[test]
-    fn add_and_remove_handlers() -> Result<()> {
-        let mut config = Config::default();
-
-        test_add_handlers(&mut config)?;
-        test_remove_handlers(&mut config)?;
-
+    fn print_info() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_info_test(&mut buffer, OutputFormat::Tsv)?;
+        goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
     #[test]
-    fn set_and_unset_handlers() -> Result<()> {
-        let mut config = Config::default();
+    fn print_info_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_info_test(&mut buffer, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
+        Ok(())
+    }
 
-        test_set_handlers(&mut config)?;
-        test_unset_handlers(&mut config)?;
+    // Helper command to test printing installed apps
+    fn print_apps_test<W: Write>(
+        buffer: &mut W,
+        format: OutputFormat,
+    ) -> Result<()> {
+        let entries = [
+            "tests/Helix.desktop",
+            "tests/cmus.desktop",
+            "tests/org.wezfurlong.wezterm.desktop",
+            // Links are not apps
+            "tests/link.desktop",
+        ]
+        .into_iter()
+        .map(|path| DesktopEntry::try_from(std::path::PathBuf::from(path)))
+        .collect::<Result<Vec<_>>>()?;
+
+        Config::default().print_apps_from(buffer, entries, format)
+    }
 
+    #[test]
+    #[cfg(feature = "tables")]
+    fn print_apps() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_apps_test(&mut buffer, OutputFormat::Table)?;
+        goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
     #[test]
-    fn add_and_unset_handlers() -> Result<()> {
-        let mut config = Config::default();
-
-        test_add_handlers(&mut config)?;
-        test_unset_handlers(&mut config)?;
-
+    fn print_apps_json() -> Result<()> {
+        let mut buffer = Vec::new();
+        print_apps_test(&mut buffer, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
         Ok(())
     }
 
     #[test]
-    fn set_and_remove_handlers() -> Result<()> {
+    fn handler_index() -> Result<()> {
         let mut config = Config::default();
+        let helix = DesktopHandler::assume_valid("helix.desktop".into())?;
 
-        test_set_handlers(&mut config)?;
-        test_remove_handlers(&mut config)?;
+        config.add_handler(&[mime::TEXT_PLAIN], &helix)?;
+        config.add_handler(&[Mime::from_str("text/*")?], &helix)?;
+        config
+            .mime_apps
+            .added_associations
+            .entry(mime::TEXT_HTML)
+            .or_default()
+            .push_back(helix.clone());
+
+        let index = config.mime_apps.handler_index();
+        assert_eq!(
+            index[&helix].iter().map(|m| m.to_string()).collect_vec(),
+            vec!["text/*", "text/html", "text/plain"]
+        );
+        assert_eq!(index.len(), 1);
 
         Ok(())
     }
 
     #[test]
-    fn override_selector() -> Result<()> {
+    fn print_lints() -> Result<()> {
         let mut config = Config::default();
+        let mpv = DesktopHandler::assume_valid("mpv.desktop".into())?;
 
-        // Ensure defaults are as expected just in case
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, false);
+        config.add_handler(&[Mime::from_str("video/*")?], &mpv)?;
+        config.add_handler(&[Mime::from_str("video/**")?], &mpv)?;
+        config.add_handler(&[Mime::from_str("video/mp4")?], &mpv)?;
 
-        config.override_selector(SelectorArgs {
-            selector: Some("fzf".to_string()),
-            enable_selector: true,
-            disable_selector: false,
-        });
+        let mut buffer = Vec::new();
+        config.print_lints(&mut buffer)?;
+        assert_eq!(
+            String::from_utf8(buffer)?,
+            "warning: 'video/*' never takes effect because 'video/**' matches the same mimes with the same handlers\n"
+        );
 
-        assert_eq!(config.config.selector, "fzf");
-        assert_eq!(config.config.enable_selector, true);
+        // System-wide mimeapps.list files count as the system default
+        let mut system = MimeApps::default();
+        system.add_handler(&Mime::from_str("video/mp4")?, &mpv, false)?;
+        config
+            .mime_apps
+            .push_system_layer("/etc/xdg/mimeapps.list", system);
 
-        config.override_selector(SelectorArgs {
-            selector: Some("fuzzel --dmenu --prompt='Open With: '".to_string()),
-            enable_selector: false,
-            disable_selector: true,
-        });
+        let mut buffer = Vec::new();
+        config.print_lints(&mut buffer)?;
+        assert!(String::from_utf8(buffer)?.ends_with(
+            "note: 'video/mp4' is set to 'mpv.desktop', which is already the system default\n"
+        ));
 
-        assert_eq!(
-            config.config.selector,
-            "fuzzel --dmenu --prompt='Open With: '"
-        );
-        assert_eq!(config.config.enable_selector, false);
+        // Linting can be turned off
+        config.config.lint = false;
+        let mut buffer = Vec::new();
+        config.print_lints(&mut buffer)?;
+        assert!(buffer.is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn dont_override_selector() -> Result<()> {
-        // NOTE: `enable_selector` and `disable_selector` should not both be true in practice anyways
-
+    fn print_handler_names() -> Result<()> {
         let mut config = Config::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
 
-        // Ensure defaults are as expected just in case
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, false);
+        config.add_handler(&[mime::TEXT_PLAIN], &helix)?;
+        config.add_handler(
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("uninstalled.desktop".into())?,
+        )?;
+        config.add_handler(&[Mime::from_str("text/markdown")?], &helix)?;
 
-        config.override_selector(SelectorArgs {
-            selector: None,
-            enable_selector: false,
-            disable_selector: false,
-        });
+        let mut buffer = Vec::new();
+        config.print(&mut buffer, false, None, true, OutputFormat::Tsv)?;
+        config.print(&mut buffer, false, None, true, OutputFormat::Json)?;
+        // The old table without names
+        config.print(&mut buffer, false, None, false, OutputFormat::Tsv)?;
+        config.print(&mut buffer, false, None, false, OutputFormat::Json)?;
+        goldie::assert!(String::from_utf8(buffer)?);
 
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, false);
+        Ok(())
+    }
 
-        config.override_selector(SelectorArgs {
-            selector: None,
-            enable_selector: false,
-            disable_selector: true,
-        });
+    #[test]
+    fn handler_names_are_cached() -> Result<()> {
+        let mut names = HandlerNames::default();
+        let helix = DesktopHandler::from_str("tests/Helix.desktop")?;
+        let handlers = DesktopList::from_str("tests/Helix.desktop;")?;
+
+        for mime in ["text/plain", "text/markdown", "text/x-rust"] {
+            MimeAppsEntry::new(
+                &Mime::from_str(mime)?,
+                &handlers,
+                Some(&mut names),
+                false,
+                false,
+            );
+        }
 
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, false);
+        assert_eq!(names.0.len(), 1);
+        assert_eq!(names.get(&helix), "Helix");
 
-        // Now repeat with `enable_selector` set to true
-        config.config.enable_selector = true;
+        Ok(())
+    }
 
-        config.override_selector(SelectorArgs {
-            selector: None,
-            enable_selector: true,
-            disable_selector: false,
-        });
+    #[test]
+    fn mark_missing_handlers() -> Result<()> {
+        let handlers =
+            DesktopList::from_str("zathura.desktop;evince.desktop;")?;
+        let mut entry = MimeAppsEntry::new(
+            &Mime::from_str("application/pdf")?,
+            &handlers,
+            None,
+            true,
+            false,
+        );
+        entry.missing = vec!["zathura.desktop".to_string()];
 
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, true);
+        assert_eq!(
+            entry.display_handlers(),
+            "zathura.desktop (missing),\nevince.desktop"
+        );
 
-        config.override_selector(SelectorArgs {
-            selector: None,
-            enable_selector: false,
-            disable_selector: false,
-        });
+        // Markers would get in the way of parsing
+        entry.pretty = false;
+        assert_eq!(entry.display_handlers(), "zathura.desktop;evince.desktop");
 
-        assert_eq!(config.config.selector, "rofi -dmenu -i -p 'Open With: '");
-        assert_eq!(config.config.enable_selector, true);
+        assert_eq!(
+            serde_json::to_string(&entry)?,
+            r#"{"mime":"application/pdf","handlers":["zathura.desktop","evince.desktop"],"wildcard":false,"missing":["zathura.desktop"]}"#
+        );
 
         Ok(())
     }
 
-    #[test]
-    fn properly_assign_files_to_handlers() -> Result<()> {
+    // Helper command to test summarizing what `handlr clean` removes
+    fn clean_test(format: OutputFormat, dry_run: bool) -> Result<String> {
         let mut config = Config::default();
+
+        // Handlers cannot be missing in tests, so this is never actually cleaned
         config.add_handler(
-            &Mime::from_str("image/png")?,
-            &DesktopHandler::assume_valid("swayimg.desktop".into()),
-        )?;
-        config.add_handler(
-            &Mime::from_str("application/pdf")?,
-            &DesktopHandler::assume_valid("mupdf.desktop".into()),
+            &[mime::TEXT_PLAIN],
+            &DesktopHandler::assume_valid("helix.desktop".into())?,
         )?;
 
-        let mut expected_handlers = HashMap::new();
-        expected_handlers
-            .insert(Handler::new("swayimg.desktop"), vec!["a.png".to_owned()]);
-        expected_handlers
-            .insert(Handler::new("mupdf.desktop"), vec!["a.pdf".to_owned()]);
+        let mut buffer = Vec::new();
+        config.clean(&mut buffer, dry_run, format)?;
 
+        // Nothing should be removed since every handler is treated as valid in tests
         assert_eq!(
-            config.assign_files_to_handlers(&[
-                UserPath::from_str("a.png")?,
-                UserPath::from_str("a.pdf")?
-            ])?,
-            expected_handlers
+            config.mime_apps.default_apps[&mime::TEXT_PLAIN].to_string(),
+            "helix.desktop;"
         );
 
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    #[test]
+    fn clean_nothing_missing() -> Result<()> {
         assert_eq!(
-            config.assign_files_to_handlers(&[
-                UserPath::from_str("a.pdf")?,
-                UserPath::from_str("a.png")?
-            ])?,
-            expected_handlers
+            clean_test(OutputFormat::Table, false)?,
+            "No missing handlers found\n"
         );
+        assert_eq!(clean_test(OutputFormat::Json, true)?, "[]\n");
+        Ok(())
+    }
 
-        let mut expected_handlers = HashMap::new();
-        expected_handlers.insert(
-            Handler::new("swayimg.desktop"),
-            vec!["a.png".to_owned(), "b.png".to_owned()],
-        );
-        expected_handlers
-            .insert(Handler::new("mupdf.desktop"), vec!["a.pdf".to_owned()]);
+    #[test]
+    fn clean_entry_rows() -> Result<()> {
+        let report = CleanReport {
+            section: "default_apps",
+            report: PruneReport {
+                mime: "text/plain".into(),
+                removed: vec!["a.desktop".into(), "b.desktop".into()],
+                remaining: vec!["helix.desktop".into()],
+            },
+        };
+
+        goldie::assert!(render_table(
+            &[CleanEntry::new(&report, false)],
+            OutputFormat::Tsv,
+            &TableConfig::default()
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn register_and_unregister_scheme() -> Result<()> {
+        let mut config = Config::default();
+        let root = TempDir::new("register-scheme")?;
+        let dir = root.join("applications");
+        let mime = Mime::from_str("x-scheme-handler/myapp")?;
+
+        // Registering a command generates a desktop file for it
+        let handler =
+            config.register_scheme_in(&dir, "myapp", "myapp --open")?;
+        assert!(dir.join(handler.to_string()).exists());
+        assert_eq!(config.get_handler(&mime)?, handler);
 
+        // Registering again changes nothing
         assert_eq!(
-            config.assign_files_to_handlers(&[
-                UserPath::from_str("a.png")?,
-                UserPath::from_str("b.png")?,
-                UserPath::from_str("a.pdf")?
-            ])?,
-            expected_handlers
+            config.register_scheme_in(&dir, "myapp", "myapp --open")?,
+            handler
         );
-
         assert_eq!(
-            config.assign_files_to_handlers(&[
-                UserPath::from_str("a.pdf")?,
-                UserPath::from_str("a.png")?,
-                UserPath::from_str("b.png")?
-            ])?,
-            expected_handlers
+            config.mime_apps.default_apps[&mime].to_string(),
+            format!("{handler};")
         );
 
+        // Unregistering removes everything that was created
+        assert!(config.unregister_scheme_in(&dir, "myapp")?);
+        assert!(!dir.join(handler.to_string()).exists());
+        assert!(config.get_handler(&mime).is_err());
+
+        // Registering an existing desktop file does not generate anything
+        config.register_scheme_in(&dir, "myapp", "myapp.desktop")?;
+        assert_eq!(config.get_handler(&mime)?.to_string(), "myapp.desktop");
+        assert!(!config.unregister_scheme_in(&dir, "myapp")?);
+
+        assert!(matches!(
+            config.register_scheme_in(&dir, "not a scheme", "myapp"),
+            Err(Error::BadScheme(_))
+        ));
+
         Ok(())
     }
 }
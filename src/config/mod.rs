@@ -1,5 +1,9 @@
+mod associations;
 mod config_file;
+mod launch;
 mod main_config;
 
-pub use config_file::ConfigFile;
+pub use associations::Associations;
+pub use config_file::{ConfigFile, WorkingDir};
+pub use launch::LaunchOptions;
 pub use main_config::Config;
@@ -9,34 +9,126 @@ pub enum Error {
     Config(#[from] confy::ConfyError),
     #[error("no handlers found for '{0}'")]
     NotFound(String),
+    #[error("'{0}' was not found in any of: {}", .1.join(", "))]
+    NotFoundIn(String, Vec<String>),
     #[error("could not figure out the mime type of '{0}'")]
     Ambiguous(std::path::PathBuf),
     #[error(transparent)]
     BadMimeType(#[from] mime::FromStrError),
     #[error("bad mime: {0}")]
     InvalidMime(mime::Mime),
+    #[error("mime_overrides[\"{pattern}\"] is not a valid mime: '{mime}'")]
+    BadMimeOverride { pattern: String, mime: String },
+    #[error("no mime is known for the extension '{0}', give a mime like `application/pdf` instead")]
+    UnknownExtension(String),
+    #[error("more than one application runs '{0}', give one of their desktop files instead: {}", .1.join(", "))]
+    AmbiguousCommand(String, Vec<String>),
+    #[error("only hidden applications can open '{0}' ({}); pass --include-hidden or set it explicitly", .1.join(", "))]
+    OnlyHidden(String, Vec<String>),
+    #[error("'{0}' is not a valid desktop file name, which must be UTF-8 without ';', '=', '/', or line breaks")]
+    BadHandlerName(String),
+    #[error("{0} mime(s) differ from the associations to import")]
+    Drift(usize),
+    #[error("doctor found {0} error(s)")]
+    DoctorFailed(usize),
+    #[error("no handler was found for {0} path(s)")]
+    Unresolved(usize),
+    #[error("{0} failed ({1})")]
+    HandlerFailed(String, std::process::ExitStatus),
     #[error("malformed desktop entry at {0}")]
     BadEntry(std::path::PathBuf),
+    #[error("{0} is a {1} entry, not an application")]
+    NotApplication(String, crate::common::EntryType),
     #[error(transparent)]
     BadRegex(#[from] regex::Error),
+    #[error(
+        "invalid regex at handlers[{handler}].{field}[{pattern}]: {source}"
+    )]
+    BadRegexHandler {
+        handler: usize,
+        field: &'static str,
+        pattern: usize,
+        source: regex::Error,
+    },
+    #[error("handlers[{0}] has exclude_regexes but no regexes")]
+    ExcludeWithoutRegexes(usize),
+    #[error("handlers[{0}] has neither regexes nor mimes, so it would never be used")]
+    NothingToMatch(usize),
+    #[error("handlers[{handler}] is named '{name}' like an earlier handler, but names must be unique")]
+    DuplicateRegexHandlerName { handler: usize, name: String },
+    #[error("handlers[{handler}].exec refers to capture group '{group}', which not every pattern in its regexes has")]
+    UnknownCaptureGroup { handler: usize, group: String },
+    #[error("handlers[{0}] needs either `exec` or `handler`, but not both")]
+    ExecOrHandler(usize),
+    #[error("no regex handler is named '{0}'")]
+    UnknownRegexHandler(String),
+    #[error("invalid launch options for '{handler}': {reason}")]
+    BadLaunchOptions { handler: String, reason: String },
+    #[error(
+        "every handler failed:{}",
+        .0.iter().map(|(handler, e)| format!("\n  {handler}: {e}")).collect::<String>()
+    )]
+    AllHandlersFailed(Vec<(String, Error)>),
+    #[error(
+        "nothing was imported because of these problems:{}",
+        .0.iter().map(|(what, e)| format!("\n  {what}: {e}")).collect::<String>()
+    )]
+    BadImport(Vec<(String, Error)>),
     #[error("error spawning selector process '{0}'")]
     Selector(String),
+    #[error("selector command '{0}' not found, check `selector` in ~/.config/handlr/handlr.toml or `--selector`")]
+    SelectorNotFound(String),
+    #[error("unknown placeholder '{{{0}}}' in `selector`, only {{prompt}} and {{mime}} can be used")]
+    BadSelectorPlaceholder(String),
+    #[error("{handler} has no action '{action}', {}", match .actions.as_slice() {
+        [] => "it has no actions".to_owned(),
+        actions => format!("its actions are: {}", actions.join(", ")),
+    })]
+    UnknownAction {
+        handler: String,
+        action: String,
+        actions: Vec<String>,
+    },
+    #[error("{handler} is unavailable, since the program in its TryExec key, '{program}', cannot be found")]
+    MissingTryExec { handler: String, program: String },
+    #[error("no handler was given for '{0}', give its desktop file after it or pass `--select` to pick one")]
+    NoHandlerGiven(String),
     #[error("selection cancelled")]
     Cancelled,
     #[error("Please specify the default terminal with handlr set x-scheme-handler/terminal")]
     NoTerminal,
+    #[error("no file manager found to open directories with")]
+    NoFileManager,
+    #[error("$XDG_CURRENT_DESKTOP is not set, so there is no desktop-specific mimeapps.list to use")]
+    NoDesktop,
+    #[error("'{0}' is not a valid URL scheme, which must start with a letter followed by letters, digits, '+', '-', or '.'")]
+    BadScheme(String),
+    #[error("{0} was not generated by handlr, so it will not be changed")]
+    NotGenerated(std::path::PathBuf),
+    #[error("'{0}' is not a valid tag, which must only contain letters, digits, '.', '_', or '-'")]
+    BadTag(String),
     #[error("Bad path: {0}")]
     BadPath(String),
     #[error(transparent)]
+    BadGlob(#[from] glob::PatternError),
+    #[error("'{0}' pattern matched no files")]
+    NoGlobMatches(String),
+    #[error(transparent)]
     SerdeJson(#[from] serde_json::Error),
     #[error(transparent)]
     SerdeIniDe(#[from] serde_ini::de::Error),
     #[error(transparent)]
-    SerdeIniSer(#[from] serde_ini::ser::Error),
+    TomlDe(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+    #[error("{0} should run in '{1}', which is not a directory")]
+    BadWorkingDir(String, std::path::PathBuf),
     #[error("Could not split exec command '{0}' in desktop file '{1}' into shell words")]
     BadExec(String, String),
     #[error("Could not split command '{0}' into shell words")]
     BadCmd(String),
+    #[error("Command {0:?} must be on a single line")]
+    MultilineCmd(String),
     #[cfg(test)]
     #[error(transparent)]
     BadUrl(#[from] url::ParseError),
@@ -45,4 +137,187 @@ pub enum Error {
     FromUtf8(#[from] std::string::FromUtf8Error),
 }
 
+/// Advice on how to fix one of the errors users commonly run into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remediation {
+    /// One line version for places with little room, like notifications
+    pub hint: String,
+    /// Steps to take to fix the error
+    pub details: String,
+}
+
+impl Error {
+    /// Get advice on how to fix this error, if it is a common one
+    pub fn remediation(&self) -> Option<Remediation> {
+        let (hint, details) = match self {
+            Self::NotFound(target) if target.parse::<mime::Mime>().is_ok() => (
+                format!("set a handler with `handlr set {target} <handler>.desktop`"),
+                format!(
+                    "No application is associated with {target}. To choose one, run:
+
+    handlr set {target} <handler>.desktop
+
+where <handler>.desktop is the name of the application's desktop file.
+`handlr list --all` shows which applications are associated with what."
+                ),
+            ),
+            Self::NotFound(target) | Self::NotFoundIn(target, _)
+                if target.ends_with(".desktop") =>
+            {
+                (
+                    format!("check that the application for {target} is installed"),
+                    format!(
+                        "handlr could not find {target} in any applications directory \
+(`$XDG_DATA_HOME/applications` or `$XDG_DATA_DIRS/applications`).
+If the application was uninstalled, `handlr clean` removes it from mimeapps.list."
+                    ),
+                )
+            }
+            Self::NoTerminal => (
+                "set a terminal with `handlr set x-scheme-handler/terminal <terminal>.desktop`".to_owned(),
+                "Applications that run in a terminal need a terminal emulator to open them in. To choose one, run:
+
+    handlr set x-scheme-handler/terminal <terminal>.desktop
+
+If the terminal does not run commands with `-e`, also set `term_exec_args` in ~/.config/handlr/handlr.toml.".to_owned(),
+            ),
+            Self::NoFileManager => (
+                "set a file manager with `handlr set inode/directory <file manager>.desktop`".to_owned(),
+                "No application is associated with inode/directory and no installed application is in the FileManager category. To choose one, run:
+
+    handlr set inode/directory <file manager>.desktop
+
+or set `default_file_manager` in ~/.config/handlr/handlr.toml, e.g.
+
+    default_file_manager = \"thunar.desktop\"".to_owned(),
+            ),
+            Self::Selector(selector) | Self::SelectorNotFound(selector) => (
+                "check `selector` in ~/.config/handlr/handlr.toml".to_owned(),
+                format!(
+                    "handlr could not run the selector command `{selector}`.
+Set `selector` in ~/.config/handlr/handlr.toml to a command that reads options from stdin \
+and prints the chosen one, e.g.
+
+    selector = \"rofi -dmenu -i -p 'Open With: '\"
+
+or pass one for a single run with `--selector`."
+                ),
+            ),
+            Self::BadPath(path) => (
+                format!("the file URL's path parsed as '{path}'"),
+                format!(
+                    "The file URL's path parsed as '{path}', which is not a path on this computer.
+File URLs must not name a host other than localhost, e.g. file:///home/user/file.txt \
+rather than file://host/home/user/file.txt."
+                ),
+            ),
+            Self::ExcludeWithoutRegexes(handler) => (
+                format!("add `regexes` to handlers[{handler}] in ~/.config/handlr/handlr.toml"),
+                format!(
+                    "Regex handler {handler} in ~/.config/handlr/handlr.toml only has `exclude_regexes`, \
+which can only stop a handler that `regexes` matched from being used. Add the patterns to match, e.g.
+
+    regexes = ['reddit\\.com']
+    exclude_regexes = ['old\\.reddit\\.com']"
+                ),
+            ),
+            _ => return None,
+        };
+
+        Some(Remediation { hint, details })
+    }
+
+    /// Get the message to show the user for this error
+    /// If `explain` is true, common errors are followed by steps to fix them
+    pub fn report(&self, explain: bool) -> String {
+        match self.remediation() {
+            Some(remediation) if explain => {
+                format!("Error: {self:?}\n\n{}", remediation.details)
+            }
+            _ => format!("Error: {self:?}"),
+        }
+    }
+
+    /// Get the body of the notification shown for this error,
+    /// which includes a one line hint on how to fix common errors
+    pub fn notification_body(&self) -> String {
+        match self.remediation() {
+            Some(remediation) => format!("{self}\n{}", remediation.hint),
+            None => self.to_string(),
+        }
+    }
+
+    /// Get the exit code to use when handlr fails with this error
+    /// 1 is used for general errors and 2 is used by clap for usage errors
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Cancelled => 3,
+            Self::SelectorNotFound(_) => 4,
+            // Pass on the handler's own exit code
+            Self::HandlerFailed(_, status) => status
+                .code()
+                .and_then(|code| u8::try_from(code).ok())
+                .filter(|&code| code != 0)
+                .unwrap_or(1),
+            _ => 1,
+        }
+    }
+
+    /// Check whether to notify about this error when handlr is not run in a terminal
+    /// Failed checks are left out, since they are meant for scripts rather than people,
+    /// as are failed handlers, which were already notified about
+    pub fn should_notify(&self) -> bool {
+        !matches!(
+            self,
+            Self::Drift(_)
+                | Self::DoctorFailed(_)
+                | Self::Unresolved(_)
+                | Self::HandlerFailed(..)
+        )
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explained_errors() {
+        let mut output = String::new();
+
+        for error in [
+            Error::NotFound("text/x-lua".into()),
+            Error::NotFound("nvim.desktop".into()),
+            Error::NotFoundIn(
+                "nvim.desktop".into(),
+                vec!["/usr/share/applications".into()],
+            ),
+            Error::NoTerminal,
+            Error::NoFileManager,
+            Error::SelectorNotFound("fuzzel --dmenu".into()),
+            Error::BadPath("/home/user/file.txt".into()),
+            Error::ExcludeWithoutRegexes(2),
+        ] {
+            output.push_str(&format!(
+                "{}\n\nNotification: {}\n\n",
+                error.report(true),
+                error.notification_body()
+            ));
+        }
+
+        goldie::assert!(output);
+    }
+
+    #[test]
+    fn unexplained_errors() {
+        let error = Error::Cancelled;
+        assert!(error.remediation().is_none());
+        assert_eq!(error.report(true), "Error: Cancelled");
+
+        // Nothing is added unless asked for
+        let error = Error::NoTerminal;
+        assert_eq!(error.report(false), "Error: NoTerminal");
+    }
+}
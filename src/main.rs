@@ -5,60 +5,458 @@ mod config;
 mod error;
 mod utils;
 
+use apps::Reorder;
 use cli::Cmd;
-use common::mime_table;
-use config::Config;
+use common::{expand_globs, mime_table, Globs, MimeOrExtension, Trace};
+use config::{Associations, Config};
 use error::Result;
+use utils::Output;
 
 use clap::{CommandFactory, Parser};
 use clap_complete::CompleteEnv;
+use std::{io::IsTerminal, process::ExitCode, str::FromStr};
 
 #[mutants::skip] // Cannot test directly at the moment
-fn main() -> Result<()> {
-    CompleteEnv::with_factory(|| Cmd::command().name("handlr")).completer("handlr").complete();
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Issue a notification if handlr is not being run in a terminal
+            // Failing to do so is not worth reporting over the error itself
+            if !std::io::stdout().is_terminal() && e.should_notify() {
+                let _ = utils::notify("handlr error", &e.notification_body());
+            }
+            // Explain how to fix common errors to people reading them in a terminal
+            eprintln!("{}", e.report(std::io::stderr().is_terminal()));
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let mut config = Config::new()?;
-    let mut stdout = std::io::stdout().lock();
+#[mutants::skip] // Cannot test directly at the moment
+fn run() -> Result<()> {
+    CompleteEnv::with_factory(|| Cmd::command().name("handlr"))
+        .completer("handlr")
+        .complete();
+
+    let cmd = Cmd::parse();
+
+    let mut config = match cmd {
+        // Doctor looks for whatever would stop the config from loading, so it cannot load it first,
+        // and completion scripts do not depend on it
+        Cmd::Doctor { .. } | Cmd::Completions { .. } => Config::unloaded(&cmd),
+        _ => Config::load(&cmd)?,
+    };
+    // Whether to check mimeapps.list for potential problems once the command is done
+    let mut lint = false;
 
-    let res = match Cmd::parse() {
-        Cmd::Set { mime, handler } => config.set_handler(&mime, &handler),
-        Cmd::Add { mime, handler } => config.add_handler(&mime, &handler),
+    let res = match cmd {
+        Cmd::Set {
+            args,
+            from_command,
+            desktop,
+            no_lint,
+            raw,
+        } => {
+            lint = !no_lint;
+            config.write_to_desktop(desktop);
+            config
+                .mimes_and_handler(
+                    &mut std::io::stderr().lock(),
+                    &args,
+                    from_command.as_deref(),
+                    raw,
+                    true,
+                )
+                .and_then(|(mimes, handler)| {
+                    config.set_handler(&mimes, &handler)
+                })
+        }
+        Cmd::Add {
+            args,
+            from_command,
+            prepend,
+            position,
+            desktop,
+            no_lint,
+            raw,
+            select,
+        } => {
+            lint = !no_lint;
+            config.write_to_desktop(desktop);
+            config
+                .mimes_and_handler(
+                    &mut std::io::stderr().lock(),
+                    &args,
+                    from_command.as_deref(),
+                    raw,
+                    select,
+                )
+                .and_then(|(mimes, handler)| match (prepend, position) {
+                    (true, _) => config.add_handler_front(&mimes, &handler),
+                    (false, Some(index)) => {
+                        config.insert_handler(&mimes, &handler, index)
+                    }
+                    (false, None) => config.add_handler(&mimes, &handler),
+                })
+        }
         Cmd::Launch {
             mime,
+            regex,
             args,
-            selector_args,
+            tag,
+            follow_links,
+            notify_done,
+            wait,
+            dry_run,
+            action,
+            include_hidden,
+            ..
         } => {
-            config.override_selector(selector_args);
-            config.launch_handler(&mime, args)
+            config.set_include_hidden(include_hidden);
+            config.follow_links(follow_links);
+            config.notify_done(notify_done);
+            config.wait(wait);
+            config.dry_run(dry_run);
+            config.set_action(action);
+            config.set_tag(tag).and_then(|_| match regex {
+                Some(name) => config.launch_regex_handler(
+                    &name,
+                    mime.into_iter().chain(args).collect(),
+                ),
+                None => MimeOrExtension::from_str(
+                    mime.as_deref().unwrap_or_default(),
+                )
+                .and_then(|mime| config.launch_handler(&mime, args)),
+            })
         }
         Cmd::Get {
             mime,
             json,
-            selector_args,
+            all,
+            trace,
+            include_hidden,
+            output,
+            ..
         } => {
-            config.override_selector(selector_args);
-            config.show_handler(&mut stdout, &mime, json)
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            config.set_include_hidden(include_hidden);
+            let mut writer = Output::new(output.output.as_deref());
+            if all {
+                config.show_all_handlers(&mut writer, &mime, format)
+            } else {
+                let mut steps = if trace {
+                    Trace::enabled()
+                } else {
+                    Trace::default()
+                };
+                let result =
+                    config.show_handler(&mut writer, &mime, format, &mut steps);
+                // Even failed lookups are worth tracing
+                steps
+                    .steps()
+                    .iter()
+                    .for_each(|step| eprintln!("trace: {step}"));
+                result
+            }
+            .and_then(|_| writer.finish())
         }
         Cmd::Open {
             paths,
-            selector_args,
+            yes,
+            tag,
+            follow_links,
+            no_glob,
+            keep_duplicates,
+            with,
+            if_no_handler,
+            notify_done,
+            wait,
+            dry_run,
+            action,
+            include_hidden,
+            ..
+        } => {
+            config.set_include_hidden(include_hidden);
+            config.follow_links(follow_links);
+            config.keep_duplicates(keep_duplicates);
+            config.notify_done(notify_done);
+            config.wait(wait);
+            config.dry_run(dry_run);
+            config.set_action(action);
+            config.fallback_command(if_no_handler);
+            config
+                .set_tag(tag)
+                .and_then(|_| config.override_handler(with))
+                .and_then(|_| {
+                    if no_glob {
+                        Ok(paths)
+                    } else {
+                        expand_globs(paths)
+                    }
+                })
+                .and_then(|paths| config.open_paths(&paths, yes))
+        }
+        Cmd::Mime {
+            paths,
+            json,
+            provenance,
+            output,
+        } => {
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            let mut writer = Output::new(output.output.as_deref());
+            // Reading the globs is only worth it if they would be shown
+            let globs = (provenance && format.is_json())
+                .then(Globs::load)
+                .transpose();
+            globs
+                .and_then(|globs| {
+                    mime_table(
+                        &mut writer,
+                        &paths,
+                        format,
+                        config.sniff_content(),
+                        config.mime_overrides(),
+                        globs.as_ref(),
+                        config.table_config(),
+                    )
+                })
+                .and_then(|_| writer.finish())
+        }
+        Cmd::Which {
+            path,
+            action,
+            json,
+            trace,
+            output,
+            ..
+        } => {
+            let format = output.format(json, config.terminal_output);
+            config.set_action(action);
+            config.plain_tables(output.plain);
+            let mut writer = Output::new(output.output.as_deref());
+            let mut steps = if trace {
+                Trace::enabled()
+            } else {
+                Trace::default()
+            };
+            let result =
+                config.print_command(&mut writer, &path, format, &mut steps);
+            // Even failed lookups are worth tracing
+            steps
+                .steps()
+                .iter()
+                .for_each(|step| eprintln!("trace: {step}"));
+            result.and_then(|_| writer.finish())
+        }
+        Cmd::TestRegex { path, json, output } => {
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            let mut writer = Output::new(output.output.as_deref());
+            config
+                .print_regex_matches(&mut writer, &path, format)
+                .and_then(|_| writer.finish())
+        }
+        Cmd::Info {
+            handler,
+            json,
+            output,
+        } => {
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            let mut writer = Output::new(output.output.as_deref());
+            config
+                .print_info(&mut writer, &handler, format)
+                .and_then(|_| writer.finish())
+        }
+        Cmd::Apps {
+            json,
+            include_hidden,
+            output,
         } => {
-            config.override_selector(selector_args);
-            config.open_paths(&paths)
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            config.set_include_hidden(include_hidden);
+            let mut writer = Output::new(output.output.as_deref());
+            config
+                .print_apps(&mut writer, format)
+                .and_then(|_| writer.finish())
         }
-        Cmd::Mime { paths, json } => {
-            mime_table(&mut stdout, &paths, json, config.terminal_output)
+        Cmd::Doctor { json, output } => apps::doctor(
+            Output::new(output.output.as_deref()),
+            output.format(json, config.terminal_output),
+            output.plain,
+        ),
+        Cmd::Completions { shell } => {
+            utils::write_completions(&mut std::io::stdout().lock(), shell)
         }
-        Cmd::List { all, json } => config.print(&mut stdout, all, json),
-        Cmd::Unset { mime } => config.unset_handler(&mime),
-        Cmd::Remove { mime, handler } => config.remove_handler(&mime, &handler),
+        Cmd::List {
+            pattern,
+            all,
+            json,
+            orphans,
+            no_names,
+            handler,
+            expand,
+            handlers_of,
+            include_hidden,
+            output,
+        } => {
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            config.set_include_hidden(include_hidden);
+            let mut writer = Output::new(output.output.as_deref());
+            if let Some(mime) = handlers_of {
+                // Used by completions, which should never print anything else
+                config
+                    .print_handlers_of(&mut writer, &mime)
+                    .and_then(|_| writer.finish())
+            } else {
+                if let Some(handler) = handler {
+                    config.print_mimes_of(&mut writer, &handler, expand, format)
+                } else if orphans {
+                    config.print_orphans(&mut writer, format)
+                } else {
+                    config.print(
+                        &mut writer,
+                        all,
+                        pattern.as_deref(),
+                        !no_names,
+                        format,
+                    )
+                }
+                .and_then(|_| writer.finish())
+                .and_then(|_| {
+                    config.print_repeated_keys(&mut std::io::stderr().lock())
+                })
+            }
+        }
+        Cmd::Unset {
+            mime,
+            desktop,
+            no_lint,
+        } => {
+            lint = !no_lint;
+            config.write_to_desktop(desktop);
+            config.unset_handler(&mime)
+        }
+        Cmd::Remove {
+            mime,
+            handler,
+            desktop,
+            system,
+            dry_run,
+            json,
+            no_lint,
+            ..
+        } => {
+            lint = !(no_lint || dry_run);
+            config.write_to_desktop(desktop);
+            match handler {
+                Some(handler) if system => {
+                    config.remove_association(&mime, &handler)
+                }
+                Some(handler) => config.remove_handler(&mime, &handler),
+                None => config.prune_mime(
+                    &mut std::io::stdout().lock(),
+                    &mime,
+                    dry_run,
+                    json,
+                ),
+            }
+        }
+        Cmd::Reorder {
+            mime,
+            handler,
+            to,
+            up,
+            down,
+            desktop,
+            no_lint,
+        } => {
+            lint = !no_lint;
+            config.write_to_desktop(desktop);
+            let to = match (to, up, down) {
+                (Some(index), _, _) => Reorder::To(index),
+                (None, true, _) => Reorder::Up,
+                (None, false, _) => Reorder::Down,
+            };
+            config.reorder_handler(
+                &mut std::io::stdout().lock(),
+                &mime,
+                &handler,
+                to,
+            )
+        }
+        Cmd::Import {
+            file,
+            replace,
+            desktop,
+            no_lint,
+            ..
+        } => {
+            lint = !no_lint;
+            config.write_to_desktop(desktop);
+            Associations::load(&file).and_then(|associations| {
+                config.import(
+                    &mut std::io::stderr().lock(),
+                    &associations,
+                    replace,
+                )
+            })
+        }
+        Cmd::Export { desktop } => {
+            config.write_to_desktop(desktop);
+            config.export(&mut std::io::stdout().lock())
+        }
+        Cmd::Status {
+            check,
+            replace,
+            json,
+            desktop,
+            ..
+        } => {
+            config.write_to_desktop(desktop);
+            Associations::load(&check).and_then(|associations| {
+                config.check_status(
+                    &mut std::io::stdout().lock(),
+                    &associations,
+                    replace,
+                    json,
+                )
+            })
+        }
+        Cmd::Clean {
+            dry_run,
+            json,
+            desktop,
+            no_lint,
+            output,
+        } => {
+            lint = !(no_lint || dry_run);
+            config.write_to_desktop(desktop);
+            let format = output.format(json, config.terminal_output);
+            config.plain_tables(output.plain);
+            let mut writer = Output::new(output.output.as_deref());
+            config
+                .clean(&mut writer, dry_run, format)
+                .and_then(|_| writer.finish())
+        }
+        Cmd::RegisterScheme {
+            scheme,
+            handler,
+            update_database,
+        } => config.register_scheme(&scheme, &handler, update_database),
+        Cmd::UnregisterScheme {
+            scheme,
+            update_database,
+        } => config.unregister_scheme(&scheme, update_database),
     };
 
-    // Issue a notification if handlr is not being run in a terminal
-    if let Err(ref e) = res {
-        if !config.terminal_output {
-            utils::notify("handlr error", &e.to_string())?
-        }
+    if res.is_ok() && lint {
+        config.print_lints(&mut std::io::stderr().lock())?;
     }
 
     res
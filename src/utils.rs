@@ -1,10 +1,609 @@
-use crate::error::Result;
+use crate::{
+    cli::CompletionShell,
+    common::{join_command, Selector},
+    error::{Error, Result},
+};
+use clap_complete::env::Shells;
+use itertools::Itertools;
+use mime::Mime;
+use std::{
+    io::{IsTerminal, StdoutLock, Write},
+    path::{Path, PathBuf},
+};
+
+/// How urgent a notification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Normal,
+    Critical,
+}
 
 /// Issue a notification
 #[mutants::skip] // Cannot test directly, runs command
 pub fn notify(title: &str, msg: &str) -> Result<()> {
+    notify_with(title, msg, Urgency::Normal)
+}
+
+/// Issue a notification with a given urgency
+#[cfg(feature = "notifications")]
+#[mutants::skip] // Cannot test directly, runs command
+pub fn notify_with(title: &str, msg: &str, urgency: Urgency) -> Result<()> {
+    let urgency = match urgency {
+        Urgency::Normal => "normal",
+        Urgency::Critical => "critical",
+    };
     std::process::Command::new("notify-send")
-        .args(["-t", "10000", title, msg])
+        .args(["-t", "10000", "-u", urgency, title, msg])
         .spawn()?;
     Ok(())
 }
+
+/// Write what would have been a notification to stderr,
+/// since notifications need the `notifications` feature
+#[cfg(not(feature = "notifications"))]
+#[mutants::skip] // Cannot test directly, writes to stderr
+pub fn notify_with(title: &str, msg: &str, _: Urgency) -> Result<()> {
+    eprintln!("{title}: {msg}");
+    Ok(())
+}
+
+/// Run the selector with the given program and arguments, e.g. from `Selector::args`,
+/// with the given variables added to its environment
+#[mutants::skip] // Cannot test directly, runs external command
+pub fn select<O: Iterator<Item = String>>(
+    args: &[String],
+    mut opts: O,
+    env: &[(&str, &str)],
+) -> Result<String> {
+    use std::{
+        io::prelude::*,
+        process::{Command, Stdio},
+    };
+
+    let selector = join_command(args);
+
+    let mut process = {
+        let (cmd, args) = args
+            .split_first()
+            .ok_or_else(|| Error::BadCmd(selector.clone()))?;
+        Command::new(cmd)
+            .args(args)
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    Error::SelectorNotFound(selector.clone())
+                }
+                _ => e.into(),
+            })?
+    };
+
+    let output = {
+        // Stdin is dropped after writing so that the selector sees the end of the options
+        let written = process
+            .stdin
+            .take()
+            .ok_or_else(|| Error::Selector(selector.clone()))?
+            .write_all(opts.join("\n").as_bytes());
+
+        // A selector that exits without reading every option is still answered by its output
+        match written {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            written => written?,
+        }
+
+        let mut output = String::with_capacity(24);
+
+        process
+            .stdout
+            .take()
+            .ok_or_else(|| Error::Selector(selector.clone()))?
+            .read_to_string(&mut output)?;
+
+        strip_ansi(&output).trim().to_owned()
+    };
+
+    // Selectors like rofi and fzf exit with an error when the user backs out,
+    // which is a cancellation no matter what they printed
+    if !process.wait()?.success() || output.is_empty() {
+        Err(Error::Cancelled)
+    } else {
+        Ok(output)
+    }
+}
+
+/// Remove ANSI escape sequences, e.g. colors, from a selector's output
+fn strip_ansi(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // Control sequences end with a character from `@` to `~`
+            Some('[') => {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+            // Operating system commands end with BEL or `ESC \`
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next().is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Other escape sequences are a single character long
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Ask the user to confirm something
+/// Prompts in the terminal if there is one,
+/// otherwise issues a notification with the message and asks through the selector
+#[mutants::skip] // Cannot test directly, requires user input
+pub fn confirm(
+    message: &str,
+    mime: &Mime,
+    selector: &Selector,
+) -> Result<bool> {
+    if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        eprint!("{message} [y/N] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    } else {
+        notify("handlr", message)?;
+        Ok(select(
+            &selector.args(message, mime)?,
+            ["No", "Yes"].into_iter().map(String::from),
+            &[],
+        )? == "Yes")
+    }
+}
+
+/// Ask the user to choose one of the given options, getting its index
+/// Shows a numbered prompt in the terminal if there is one, otherwise asks through the selector
+/// Anything other than one of the numbers cancels
+#[mutants::skip] // Cannot test directly, requires user input
+pub fn choose(
+    prompt: &str,
+    options: &[String],
+    mime: &Mime,
+    selector: &Selector,
+) -> Result<usize> {
+    if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        choose_in_terminal(prompt, options)
+    } else {
+        let choice = select(
+            &selector.args(prompt, mime)?,
+            options.iter().cloned(),
+            &[],
+        )?;
+        options
+            .iter()
+            .position(|option| *option == choice)
+            .ok_or(Error::Cancelled)
+    }
+}
+
+/// Ask the user to choose one of the given options without the selector, for when it cannot be run
+/// Shows a numbered prompt in the terminal if there is one,
+/// otherwise picks the first option and issues a notification explaining why
+#[mutants::skip] // Cannot test directly, requires user input
+pub fn choose_without_selector(
+    prompt: &str,
+    options: &[String],
+    reason: &Error,
+) -> Result<usize> {
+    if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        choose_in_terminal(prompt, options)
+    } else {
+        // Failing to explain is no reason to stop the handler from opening
+        let _ = notify(
+            "handlr",
+            &format!("{reason}\nUsing {} instead", options[0]),
+        );
+        Ok(0)
+    }
+}
+
+/// Show a numbered prompt for choosing one of the given options in the terminal
+/// Anything other than one of the numbers cancels
+#[mutants::skip] // Cannot test directly, requires user input
+fn choose_in_terminal(prompt: &str, options: &[String]) -> Result<usize> {
+    for (i, option) in options.iter().enumerate() {
+        eprintln!("{:>3}) {option}", i + 1);
+    }
+    eprint!("{prompt} [1-{}] ", options.len());
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=options.len()).contains(n))
+        .map(|n| n - 1)
+        .ok_or(Error::Cancelled)
+}
+
+/// Destination for a command's output
+pub enum Output {
+    /// Write directly to stdout
+    Stdout(StdoutLock<'static>),
+    /// Buffer output and write it to a file all at once when finished
+    File(PathBuf, Vec<u8>),
+}
+
+impl Output {
+    /// Create a new `Output`, writing to stdout if there is no path or the path is `-`
+    pub fn new(path: Option<&Path>) -> Self {
+        match path {
+            Some(path) if path.as_os_str() != "-" => {
+                Self::File(path.to_owned(), Vec::new())
+            }
+            _ => Self::Stdout(std::io::stdout().lock()),
+        }
+    }
+
+    /// Finish writing output
+    /// If writing to a file, this is when the file is actually written
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::Stdout(mut stdout) => Ok(stdout.flush()?),
+            Self::File(path, buffer) => write_atomic(&path, &buffer),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::File(_, buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::File(_, buffer) => buffer.flush(),
+        }
+    }
+}
+
+/// Write the script that registers handlr's completions with a shell
+/// The script gets completions by running `COMPLETE=<shell> handlr -- <args>`, which `main` answers
+pub fn write_completions<W: Write>(
+    writer: &mut W,
+    shell: CompletionShell,
+) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell.name())
+        .expect("Every CompletionShell should be supported by clap_complete");
+    completer
+        .write_registration("COMPLETE", "handlr", "handlr", "handlr", writer)?;
+    Ok(())
+}
+
+/// Write a value as a single line of JSON and flush it immediately
+/// so that consumers of JSON Lines output can process it as it is produced
+pub fn write_json_line<W: Write, T: serde::Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> Result<()> {
+    writeln!(writer, "{}", serde_json::to_string(value)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the given contents to a file by writing to a temporary file in the same directory
+/// and then moving it over the original, so the file is never left partially written
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    write_atomic_with(path, |file| Ok(file.write_all(contents)?))
+}
+
+/// Create a file to write to, truncating it if it already exists
+/// New files are readable by everyone but only writable by their owner, modulo the umask
+fn create_file(path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o644);
+    }
+
+    Ok(options.open(path)?)
+}
+
+/// Give `file` the same permissions as the file at `original`, if it exists,
+/// except that it is never made writable by everyone
+fn keep_permissions(original: &Path, file: &std::fs::File) -> Result<()> {
+    #[cfg(unix)]
+    if let Ok(metadata) = std::fs::metadata(original) {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777 & !0o002;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = (original, file);
+
+    Ok(())
+}
+
+/// Write to a file using `write` on a temporary file in the same directory
+/// and then move it over the original, so the file is never left partially written
+/// If `write` fails, the original file is left untouched
+fn write_atomic_with<F: FnOnce(&mut std::fs::File) -> Result<()>>(
+    path: &Path,
+    write: F,
+) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp);
+
+    let result = create_file(&temp)
+        .and_then(|mut file| {
+            keep_permissions(path, &file)?;
+            write(&mut file)?;
+            // Make sure the contents are on disk before they replace the original
+            Ok(file.sync_all()?)
+        })
+        .and_then(|_| Ok(std::fs::rename(&temp, path)?));
+
+    if result.is_err() {
+        // Do not leave the temporary file lying around
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// A directory under the system's temporary directory that is removed along with its contents when dropped,
+/// so that tests clean up after themselves even when they fail
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Helper function for testing
+    /// Create a directory named after `name` and the current process, so that tests running at once do not share it
+    pub fn new(name: &str) -> Result<Self> {
+        let path = std::env::temp_dir()
+            .join(format!("handlr-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+    use pretty_assertions::assert_eq;
+
+    /// Split a selector command into its program and arguments
+    fn args(selector: &str) -> Vec<String> {
+        shlex::split(selector).expect("Test selectors should be valid")
+    }
+
+    #[test]
+    fn completion_scripts() -> Result<()> {
+        for shell in CompletionShell::value_variants() {
+            let mut buffer = Vec::new();
+            write_completions(&mut buffer, *shell)?;
+            let script = String::from_utf8(buffer)?;
+            assert!(script.contains("handlr"), "{shell:?}");
+            assert!(script.contains(shell.name()), "{shell:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_not_found() {
+        let result = select(
+            &args("/nonexistent/selector -p 'Open With: '"),
+            ["a", "b"].into_iter().map(String::from),
+            &[],
+        );
+        assert!(matches!(
+            result,
+            Err(Error::SelectorNotFound(cmd)) if cmd == "/nonexistent/selector -p 'Open With: '"
+        ));
+    }
+
+    #[test]
+    fn selector_cancelled() {
+        // Outputs nothing, like a selector that the user closed
+        let result = select(
+            &args("true"),
+            ["a", "b"].into_iter().map(String::from),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn selector_chosen() -> Result<()> {
+        let result = select(
+            &args("head -n 1"),
+            ["a", "b"].into_iter().map(String::from),
+            &[],
+        )?;
+        assert_eq!(result, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn selector_env() -> Result<()> {
+        let result = select(
+            &args(r#"sh -c 'echo "$HANDLR_MIME $HANDLR_PATH"'"#),
+            ["a", "b"].into_iter().map(String::from),
+            &[("HANDLR_MIME", "video/mp4"), ("HANDLR_PATH", "a b.mp4")],
+        )?;
+        assert_eq!(result, "video/mp4 a b.mp4");
+        Ok(())
+    }
+
+    #[test]
+    fn selector_script() -> Result<()> {
+        let options = || ["a", "b c"].into_iter().map(String::from);
+
+        // Colors and surrounding whitespace are not part of the choice
+        let result = select(
+            &args(
+                r#"sh -c 'read a; read b; printf " \033[1m%s\033[0m \n\n" "$b"'"#,
+            ),
+            options(),
+            &[],
+        )?;
+        assert_eq!(result, "b c");
+
+        // Exiting with an error cancels even if something was printed
+        let result = select(
+            &args(r#"sh -c 'read a; echo "$a"; exit 1'"#),
+            options(),
+            &[],
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+
+        // So does printing nothing but whitespace
+        let result =
+            select(&args(r#"sh -c 'cat >/dev/null; echo'"#), options(), &[]);
+        assert!(matches!(result, Err(Error::Cancelled)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ansi_escapes() {
+        assert_eq!(strip_ansi("\x1b[1;31mfirefox\x1b[0m"), "firefox");
+        assert_eq!(strip_ansi("\x1b]0;title\x07helix"), "helix");
+        assert_eq!(strip_ansi("\x1b]0;title\x1b\\helix"), "helix");
+        assert_eq!(strip_ansi("\x1b=mpv"), "mpv");
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+
+    #[test]
+    fn output_to_file() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("handlr-output-{}.txt", std::process::id()));
+        std::fs::write(&path, "old contents")?;
+
+        let mut output = Output::new(Some(&path));
+        write!(output, "new contents")?;
+
+        // Nothing should be written until output is finished
+        assert_eq!(std::fs::read_to_string(&path)?, "old contents");
+
+        output.finish()?;
+
+        assert_eq!(std::fs::read_to_string(&path)?, "new contents");
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_write() -> Result<()> {
+        let dir = TempDir::new("interrupted")?;
+        let path = dir.join("mimeapps.list");
+        let original = "[Default Applications]\ntext/plain=helix.desktop;\n";
+
+        // Simulate being interrupted after writing part of the new contents
+        let interrupted = |file: &mut std::fs::File| -> Result<()> {
+            file.write_all(b"[Default Applications]\ntext/pl")?;
+            Err(Error::Io(std::io::ErrorKind::Interrupted.into()))
+        };
+
+        // Truncating and then writing directly loses the original contents
+        std::fs::write(&path, original)?;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+        assert!(interrupted(&mut file).is_err());
+        assert_ne!(std::fs::read_to_string(&path)?, original);
+
+        // Writing atomically leaves the original untouched
+        std::fs::write(&path, original)?;
+        assert!(write_atomic_with(&path, interrupted).is_err());
+        assert_eq!(std::fs::read_to_string(&path)?, original);
+        // The temporary file should be cleaned up
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn created_file_permissions() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("permissions")?;
+        let path = dir.join("mimeapps.list");
+        let mode = |path: &Path| -> Result<u32> {
+            Ok(std::fs::metadata(path)?.permissions().mode() & 0o777)
+        };
+
+        // New files are at most 0644, depending on the umask
+        write_atomic(&path, b"new")?;
+        assert_eq!(mode(&path)? & !0o644, 0);
+        assert_eq!(mode(&path)? & 0o600, 0o600);
+
+        // Existing files keep their permissions
+        std::fs::set_permissions(
+            &path,
+            std::fs::Permissions::from_mode(0o600),
+        )?;
+        write_atomic(&path, b"private")?;
+        assert_eq!(mode(&path)?, 0o600);
+
+        // But are never left writable by everyone
+        std::fs::set_permissions(
+            &path,
+            std::fs::Permissions::from_mode(0o666),
+        )?;
+        write_atomic(&path, b"shared")?;
+        assert_eq!(mode(&path)?, 0o664);
+
+        Ok(())
+    }
+}